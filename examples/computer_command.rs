@@ -0,0 +1,143 @@
+//! "Computer" Voice Command Demo
+//!
+//! The whole loop the crate is built for: listen for the wake word with a
+//! trained template, confirm it with Whisper, answer with the TOS computer
+//! chime, then record and transcribe the command that follows.
+//!
+//! Usage:
+//! 1. Train a template: cargo run --example train_wake_word
+//! 2. Set WHISPER_ENDPOINT, REPLICATE_API_KEY or OPENAI_API_KEY in .env file
+//! 3. Run: cargo run --example computer_command -- --template computer.json
+//! 4. Say "computer", wait for the chime, then give a command
+//!
+//! The chime plays before the command recording starts, so it isn't in the
+//! audio that gets transcribed.
+
+use anyhow::Result;
+use audio_transcribe_cli::config::load_env_from_args;
+use audio_transcribe_cli::device::select_input;
+use audio_transcribe_cli::events::DetectionEvent;
+use audio_transcribe_cli::mix::ChannelMix;
+use audio_transcribe_cli::pipeline::WakePipeline;
+use audio_transcribe_cli::recording::AudioRecorder;
+use audio_transcribe_cli::shutdown::Shutdown;
+use audio_transcribe_cli::sounds::{self, Sound};
+use audio_transcribe_cli::transcribe::{transcribe_audio, AudioClip, WhisperConfig};
+use audio_transcribe_cli::wake_word::{confidence_precision, format_confidence, WakeWordDetector};
+use clap::Parser;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// How often captured audio is handed to the detector
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Wake word, chime, command
+#[derive(Parser)]
+struct Args {
+    /// Load environment variables from this file instead of ./.env
+    #[arg(long, env = "DOTENV_PATH")]
+    env_file: Option<PathBuf>,
+
+    /// Wake word template file (written by `WakeWordDetector::save_template`)
+    #[arg(long, env = "WAKE_WORD_TEMPLATE", value_name = "PATH")]
+    template: PathBuf,
+
+    /// The wake word Stage 2 looks for in the transcript
+    #[arg(long, default_value = "computer")]
+    wake_word: String,
+
+    /// Similarity needed to trigger (0.0-1.0; lower = more sensitive)
+    #[arg(long, env = "WAKE_WORD_THRESHOLD", default_value_t = 0.65)]
+    threshold: f32,
+
+    /// Seconds of command to record after the chime
+    #[arg(long, value_name = "SECS", default_value_t = 5.0)]
+    command_secs: f32,
+
+    /// Input device to listen on (any part of its name; default device if unset)
+    #[arg(long, env = "AUDIO_DEVICE")]
+    device: Option<String>,
+
+    /// Audio buffer size hint in frames (smaller = lower latency, larger = fewer dropouts)
+    #[arg(long, env = "AUDIO_BUFFER_SIZE", value_name = "FRAMES")]
+    buffer_size: Option<u32>,
+}
+
+fn main() -> Result<()> {
+    load_env_from_args();
+    let args = Args::parse();
+
+    let whisper_config = WhisperConfig::from_env()?;
+    if !whisper_config.is_configured() {
+        anyhow::bail!(
+            "Set WHISPER_ENDPOINT, REPLICATE_API_KEY or OPENAI_API_KEY to confirm and transcribe"
+        );
+    }
+
+    let mut detector = WakeWordDetector::new();
+    detector.load_template(&args.template)?;
+    anyhow::ensure!(
+        detector.is_trained(),
+        "Wake word template is empty; nothing can be detected"
+    );
+    detector.set_threshold(args.threshold);
+
+    let (device, config) = select_input(args.device.as_deref(), false)?;
+    let recorder = AudioRecorder::open(&device, &config, ChannelMix::default(), args.buffer_size)?;
+
+    // Stage 1 resamples to the detector's rate; Stage 2 gets the device's
+    let mut pipeline = WakePipeline::new(detector, &args.wake_word, recorder.sample_rate());
+    pipeline.set_transcriber(whisper_config.transcriber()?);
+    let shutdown = Shutdown::install()?;
+    let command_duration = Duration::from_secs_f32(args.command_secs.max(0.1));
+
+    println!("Listening for \"{}\"... (Ctrl+C to quit)", args.wake_word);
+    recorder.start();
+    while !shutdown.requested() {
+        thread::sleep(POLL_INTERVAL);
+        let Some(mut candidate) = pipeline.push(&recorder.take())? else {
+            continue;
+        };
+
+        print!(
+            "Wake word? ({}) ",
+            format_confidence(candidate.confidence, confidence_precision())
+        );
+        io::stdout().flush()?;
+        match pipeline.confirm(&mut candidate) {
+            Some(DetectionEvent::Transcription {
+                confirmed: true, ..
+            }) => {
+                // Stop listening so neither the chime nor the command reaches the detector
+                recorder.stop();
+                if let Err(e) = sounds::play(Sound::ComputerReady) {
+                    eprintln!("Warning: couldn't play the chime: {:#}", e);
+                }
+                candidate.timing.acknowledge();
+                println!("yes [{}]", candidate.timing.breakdown());
+
+                println!("🎤 Go ahead ({:.0} s)...", command_duration.as_secs_f32());
+                let command = recorder.record(command_duration, || shutdown.requested());
+                match AudioClip::from_samples(&command, recorder.sample_rate())
+                    .and_then(|clip| transcribe_audio(&whisper_config, clip))
+                {
+                    Ok(text) => println!("Command: {}", text.trim()),
+                    Err(e) => eprintln!("Failed to transcribe the command: {:#}", e),
+                }
+                println!("\nListening for \"{}\"...", args.wake_word);
+            }
+            Some(DetectionEvent::Transcription { text, .. }) => println!("no (heard \"{}\")", text),
+            Some(DetectionEvent::Error { message, .. }) => println!("unconfirmed: {}", message),
+            _ => println!(),
+        }
+
+        // Audio captured while Stage 2 or the command ran is stale by now
+        recorder.start();
+    }
+
+    recorder.stop();
+    println!("\nBye.");
+    Ok(())
+}
@@ -0,0 +1,102 @@
+//! Log-Mel Spectrogram to PNG
+//!
+//! Renders what the wake word detector "hears" in a WAV file, for comparing
+//! a good template recording against a bad one or attaching to a bug report.
+//! Time runs left to right, one column per frame (8 ms at 16 kHz); mel bands
+//! run bottom to top, low frequencies at the bottom. Quiet is dark purple,
+//! loud is yellow.
+//!
+//! Usage:
+//!   cargo run --example mel_spectrogram -- recording.wav recording.png
+
+use anyhow::{Context, Result};
+use audio_transcribe_cli::filter::Bandpass;
+use audio_transcribe_cli::resample::resample_linear;
+use audio_transcribe_cli::wake_word::{MfccConfig, WakeWordDetector};
+use audio_transcribe_cli::wav::decode_wav_mono;
+use clap::Parser;
+use image::{Rgb, RgbImage};
+use std::fs;
+use std::path::PathBuf;
+
+/// Render the log-mel spectrogram of a WAV file as a PNG
+#[derive(Parser)]
+struct Args {
+    /// WAV file to analyse (any bit depth or channel count)
+    input: PathBuf,
+
+    /// PNG file to write
+    output: PathBuf,
+
+    /// Size of each frame × band cell in pixels
+    #[arg(long, default_value_t = 4)]
+    scale: u32,
+
+    /// Bandpass the audio first, as the detector would, e.g. "80-8000" (Hz)
+    #[arg(long, env = "BANDPASS", value_name = "LOW-HIGH")]
+    bandpass: Option<Bandpass>,
+}
+
+/// Colormap stops from quiet to loud
+const COLORMAP: [[f32; 3]; 5] = [
+    [0.0, 0.0, 4.0],
+    [87.0, 16.0, 110.0],
+    [188.0, 55.0, 84.0],
+    [249.0, 142.0, 9.0],
+    [252.0, 255.0, 164.0],
+];
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    anyhow::ensure!(args.scale > 0, "--scale must be at least 1");
+
+    let mut detector = WakeWordDetector::new();
+    detector.set_bandpass(args.bandpass);
+    let sample_rate = MfccConfig::default().sample_rate;
+
+    let wav_data = fs::read(&args.input)
+        .with_context(|| format!("Failed to read {}", args.input.display()))?;
+    let (samples, input_rate) = decode_wav_mono(&wav_data)
+        .with_context(|| format!("Failed to decode {}", args.input.display()))?;
+    let samples = resample_linear(&samples, input_rate, sample_rate);
+
+    let mel = detector.extract_log_mel(&samples)?;
+    anyhow::ensure!(
+        mel.nrows() > 0,
+        "{} is too short to analyse",
+        args.input.display()
+    );
+    let (frames, bands) = mel.dim();
+
+    let min = mel.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = mel.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let range = (max - min).max(f32::EPSILON);
+
+    let mut image = RgbImage::new(frames as u32 * args.scale, bands as u32 * args.scale);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let frame = (x / args.scale) as usize;
+        let band = bands - 1 - (y / args.scale) as usize;
+        *pixel = color((mel[[frame, band]] - min) / range);
+    }
+    image
+        .save(&args.output)
+        .with_context(|| format!("Failed to write {}", args.output.display()))?;
+
+    println!(
+        "Wrote {} ({} frames × {} mel bands, {:.2} s)",
+        args.output.display(),
+        frames,
+        bands,
+        samples.len() as f32 / sample_rate as f32
+    );
+    Ok(())
+}
+
+/// Colour for a level between 0.0 (quietest) and 1.0 (loudest)
+fn color(level: f32) -> Rgb<u8> {
+    let position = level.clamp(0.0, 1.0) * (COLORMAP.len() - 1) as f32;
+    let index = (position as usize).min(COLORMAP.len() - 2);
+    let t = position - index as f32;
+    let (from, to) = (COLORMAP[index], COLORMAP[index + 1]);
+    Rgb([0, 1, 2].map(|c| (from[c] + (to[c] - from[c]) * t).round() as u8))
+}
@@ -0,0 +1,127 @@
+//! Concurrent Recording and Transcription
+//!
+//! Records continuously in fixed-length chunks and uploads each chunk to
+//! Replicate while the next one is being recorded, using
+//! `transcribe_replicate_async` on a Tokio runtime. With the blocking API the
+//! microphone sits idle for the whole network round trip; here no audio is
+//! lost between chunks.
+//!
+//! Usage:
+//! 1. Set REPLICATE_API_KEY in .env file
+//! 2. Run: cargo run --example transcribe_async -- --chunk-secs 5
+//!
+//! Transcripts are printed as they arrive, labelled with their chunk number,
+//! so a slow upload may be overtaken by a later one.
+
+use anyhow::{Context, Result};
+use audio_transcribe_cli::config::load_env_from_args;
+use audio_transcribe_cli::device::select_input;
+use audio_transcribe_cli::mix::ChannelMix;
+use audio_transcribe_cli::recording::AudioRecorder;
+use audio_transcribe_cli::shutdown::Shutdown;
+use audio_transcribe_cli::transcribe::{
+    fit_upload, max_upload_bytes_from_env, replicate_timeout_from_env, transcribe_replicate_async,
+    AudioClip, ReplicateOptions, RetryPolicy,
+};
+use clap::Parser;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the recording loop checks for Ctrl+C
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Record and transcribe at the same time
+#[derive(Parser)]
+struct Args {
+    /// Load environment variables from this file instead of ./.env
+    #[arg(long, env = "DOTENV_PATH")]
+    env_file: Option<PathBuf>,
+
+    /// Seconds of audio per uploaded chunk
+    #[arg(long, value_name = "SECS", default_value_t = 5.0)]
+    chunk_secs: f32,
+
+    /// Input device to listen on (any part of its name; default device if unset)
+    #[arg(long, env = "AUDIO_DEVICE")]
+    device: Option<String>,
+
+    /// Audio buffer size hint in frames (smaller = lower latency, larger = fewer dropouts)
+    #[arg(long, env = "AUDIO_BUFFER_SIZE", value_name = "FRAMES")]
+    buffer_size: Option<u32>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    load_env_from_args();
+    let args = Args::parse();
+
+    let api_key = std::env::var("REPLICATE_API_KEY")
+        .context("Set REPLICATE_API_KEY to transcribe on Replicate")?;
+    let max_upload_bytes = max_upload_bytes_from_env()?;
+    let options = Arc::new(ReplicateOptions {
+        timeout: replicate_timeout_from_env()?,
+        retry: RetryPolicy::from_env()?,
+        max_upload_bytes,
+        ..Default::default()
+    });
+    let chunk_duration = Duration::from_secs_f32(args.chunk_secs.max(0.5));
+
+    let (device, config) = select_input(args.device.as_deref(), false)?;
+    let recorder = AudioRecorder::open(&device, &config, ChannelMix::default(), args.buffer_size)?;
+    let shutdown = Shutdown::install()?;
+
+    println!(
+        "Recording in {:.1} s chunks... (Ctrl+C to quit)",
+        chunk_duration.as_secs_f32()
+    );
+    let mut uploads = tokio::task::JoinSet::new();
+    let mut chunk = 0;
+    recorder.start();
+    while !shutdown.requested() {
+        // `take` leaves the recording running, so chunks follow on without a gap
+        let started = Instant::now();
+        while started.elapsed() < chunk_duration && !shutdown.requested() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            // Report finished uploads as they come in
+            while let Some(result) = uploads.try_join_next() {
+                print_result(result?);
+            }
+        }
+        let samples = recorder.take();
+        if samples.is_empty() {
+            continue;
+        }
+
+        chunk += 1;
+        let clip = fit_upload(
+            AudioClip::from_samples(&samples, recorder.sample_rate())?,
+            max_upload_bytes,
+        )?;
+        println!("⏫ Chunk {} ({:.1} s) uploading", chunk, clip.duration_s);
+        let api_key = api_key.clone();
+        let options = Arc::clone(&options);
+        uploads.spawn(async move {
+            let result = transcribe_replicate_async(&api_key, clip.bytes, &options).await;
+            (chunk, result)
+        });
+    }
+
+    recorder.stop();
+    if !uploads.is_empty() {
+        println!("\nWaiting for {} upload(s)...", uploads.len());
+    }
+    while let Some(result) = uploads.join_next().await {
+        print_result(result?);
+    }
+    println!("Bye.");
+    Ok(())
+}
+
+/// Print one chunk's transcript, or why it failed
+fn print_result((chunk, result): (usize, Result<String>)) {
+    match result {
+        Ok(text) => println!("📝 Chunk {}: {}", chunk, text.trim()),
+        Err(e) => eprintln!("❌ Chunk {} failed: {:#}", chunk, e),
+    }
+}
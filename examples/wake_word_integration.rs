@@ -1,194 +1,463 @@
-/// Integrated Wake Word + Transcription Demo
-/// 
-/// This example shows how to combine wake word detection with the existing
-/// Whisper transcription system in a realistic always-on scenario.
-/// 
-/// Usage:
-/// 1. Set REPLICATE_API_KEY in .env file
-/// 2. Run: cargo run --example wake_word_integration
-/// 3. Say "computer" to trigger recording and transcription
-
-use anyhow::{Context, Result};
-use audio_transcribe_cli::wake_word::WakeWordDetector;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use dotenv::dotenv;
-use hound::{WavSpec, WavWriter};
-use reqwest::blocking::multipart;
-use serde::Deserialize;
-use std::collections::VecDeque;
-use std::env;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
-
-/// Circular buffer for audio samples
-struct AudioBuffer {
-    buffer: VecDeque<f32>,
-    max_size: usize,
+//! Integrated Wake Word + Transcription Demo
+//!
+//! This example shows how to combine wake word detection with the existing
+//! Whisper transcription system in a realistic always-on scenario.
+//!
+//! Usage:
+//! 1. Set REPLICATE_API_KEY in .env file
+//! 2. Run: cargo run --example wake_word_integration
+//! 3. Say "computer" to trigger recording and transcription
+//!
+//! Add `--confirm-phrase "confirm delete"` to guard commands: after the wake
+//! word is confirmed, the next few seconds must contain that phrase.
+//!
+//! Pass `--format json` to get newline-delimited JSON events on stdout
+//! instead of the human-readable output, for consumption by other programs.
+//!
+//! Built with `--features websocket`, `--serve 127.0.0.1:9001` also publishes
+//! the events to WebSocket clients, which can send `{"command":"start_recording"}`
+//! to record and transcribe without the wake word (`stop_recording` ends early).
+
+use anyhow::Result;
+use audio_transcribe_cli::config::load_env_from_args;
+use audio_transcribe_cli::device::{find_input_device, input_config};
+use audio_transcribe_cli::events::{write_ndjson, DetectionEvent, DEFAULT_EMBED_AUDIO_MAX_BYTES};
+use audio_transcribe_cli::filter::Bandpass;
+use audio_transcribe_cli::mix::ChannelMix;
+use audio_transcribe_cli::pipeline::{Candidate, WakePipeline};
+use audio_transcribe_cli::recording::stream_samples;
+#[cfg(feature = "websocket")]
+use audio_transcribe_cli::segments::NO_SPEECH_MESSAGE;
+#[cfg(feature = "websocket")]
+use audio_transcribe_cli::server::{Command, EventServer, DEFAULT_MAX_CONCURRENT};
+use audio_transcribe_cli::shutdown::Shutdown;
+use audio_transcribe_cli::transcribe::{resolve_replicate_model, WhisperConfig, REPLICATE_MODELS};
+#[cfg(feature = "websocket")]
+use audio_transcribe_cli::transcribe::{transcribe_audio, AudioClip};
+use audio_transcribe_cli::vad::{SpeechGate, DEFAULT_HANGOVER_MS, DEFAULT_VAD_THRESHOLD};
+use audio_transcribe_cli::wake_word::{
+    confidence_precision, confidence_to_db, db_to_confidence, format_confidence, WakeWordDetector,
+};
+use clap::{Parser, ValueEnum};
+use cpal::traits::DeviceTrait;
+use std::io::Write;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+/// Always-on wake word detection with Whisper confirmation
+#[derive(Parser)]
+struct Args {
+    /// Load settings from this .env file instead of ./.env
+    #[arg(long, env = "DOTENV_PATH")]
+    env_file: Option<std::path::PathBuf>,
+
+    /// Output format: human-readable text, or NDJSON events on stdout
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Replicate model: a name from --list-models or a raw owner/name:version
+    #[arg(long, env = "REPLICATE_MODEL")]
+    model: Option<String>,
+
+    /// Initial prompt to bias Stage 2 transcription (names, jargon, spelling)
+    #[arg(long, env = "WHISPER_PROMPT")]
+    prompt: Option<String>,
+
+    /// Wake word template file (written by `train_wake_word`); a synthetic demo template if unset
+    #[arg(long, env = "WAKE_WORD_TEMPLATE", value_name = "PATH")]
+    template: Option<std::path::PathBuf>,
+
+    /// Another wake word to listen for, from its template file, e.g. cancel=cancel.json (repeatable)
+    #[arg(long = "word", value_name = "NAME=PATH", value_parser = parse_word)]
+    words: Vec<(String, std::path::PathBuf)>,
+
+    /// Stage 1 similarity needed to trigger (0.0-1.0; lower = more sensitive)
+    #[arg(long, env = "WAKE_WORD_THRESHOLD", default_value_t = 0.65)]
+    threshold: f32,
+
+    /// The threshold as a match level in dB (20 = 0.9, 40 = 0.99); overrides --threshold
+    #[arg(long, env = "WAKE_WORD_THRESHOLD_DB")]
+    threshold_db: Option<f32>,
+
+    /// Phrase that must follow the confirmed wake word, e.g. "confirm delete"
+    #[arg(long, env = "CONFIRM_PHRASE")]
+    confirm_phrase: Option<String>,
+
+    /// Seconds recorded after the wake word to listen for --confirm-phrase
+    #[arg(
+        long,
+        env = "CONFIRM_WINDOW",
+        value_name = "SECS",
+        default_value_t = 3.0
+    )]
+    confirm_window: f32,
+
+    /// Bandpass audio to this range before extracting features, e.g. "80-8000" (Hz)
+    #[arg(long, env = "BANDPASS", value_name = "LOW-HIGH")]
+    bandpass: Option<Bandpass>,
+
+    /// Run detection every N MFCC frames (N × 8 ms at 16 kHz) instead of every 100 ms
+    #[arg(long, env = "DETECT_EVERY_FRAMES", value_name = "N")]
+    detect_every_frames: Option<usize>,
+
+    /// RMS level that counts as speech; Stage 1 only runs after recent speech (0 = always run)
+    #[arg(long, env = "VAD_THRESHOLD", default_value_t = DEFAULT_VAD_THRESHOLD)]
+    vad_threshold: f32,
+
+    /// How long Stage 1 keeps running after speech stops, in ms
+    #[arg(long, env = "VAD_HANGOVER_MS", value_name = "MS", default_value_t = DEFAULT_HANGOVER_MS)]
+    vad_hangover_ms: u32,
+
+    /// Include the triggering audio in detection events, as a base64 WAV (audio_wav_base64)
+    #[arg(long)]
+    embed_audio: bool,
+
+    /// Largest WAV embedded with --embed-audio; longer audio keeps its newest part
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_EMBED_AUDIO_MAX_BYTES, requires = "embed_audio")]
+    embed_audio_max_bytes: usize,
+
+    /// Evaluation only: transcribe every utterance, to count the wake words Stage 1 misses
+    #[arg(long)]
+    bypass_stage1: bool,
+
+    /// Input device to listen on (any part of its name; default device if unset)
+    #[arg(long, env = "AUDIO_DEVICE")]
+    device: Option<String>,
+
+    /// Audio buffer size hint in frames (smaller = lower latency, larger = fewer dropouts)
+    #[arg(long, env = "AUDIO_BUFFER_SIZE", value_name = "FRAMES")]
+    buffer_size: Option<u32>,
+
+    /// List the friendly Replicate model names and exit
+    #[arg(long)]
+    list_models: bool,
+
+    /// Publish events to WebSocket clients on this address, e.g. 127.0.0.1:9001
+    #[cfg(feature = "websocket")]
+    #[arg(long, env = "WS_ADDR", value_name = "ADDR")]
+    serve: Option<String>,
+
+    /// Longest recording a client's start_recording command makes, in seconds
+    #[cfg(feature = "websocket")]
+    #[arg(long, default_value_t = 10.0)]
+    record_secs: f32,
+
+    /// Most POST /transcribe uploads transcribed at once; the rest get 503 with Retry-After
+    #[cfg(feature = "websocket")]
+    #[arg(long, env = "MAX_CONCURRENT", value_name = "N", default_value_t = DEFAULT_MAX_CONCURRENT)]
+    max_concurrent: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
 }
 
-impl AudioBuffer {
-    fn new(duration_secs: usize, sample_rate: usize) -> Self {
-        let max_size = duration_secs * sample_rate;
-        Self {
-            buffer: VecDeque::with_capacity(max_size),
-            max_size,
+/// Where events go besides the console
+struct Publisher {
+    json: bool,                 // NDJSON on stdout
+    embed_audio: Option<usize>, // Cap on the WAV added to detections, if any
+    #[cfg(feature = "websocket")]
+    server: Option<EventServer>,
+}
+
+impl Publisher {
+    /// The Stage 1 event for `candidate`, with its audio if --embed-audio is on
+    fn detection(&self, candidate: &Candidate, sample_rate: u32) -> DetectionEvent {
+        let Some(max_bytes) = self.embed_audio else {
+            return candidate.event();
+        };
+        candidate
+            .event_with_audio(sample_rate, max_bytes)
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to embed the detection audio: {:#}", e);
+                candidate.event()
+            })
+    }
+
+    fn publish(&self, event: &DetectionEvent) {
+        if self.json {
+            emit(event);
+        }
+        #[cfg(feature = "websocket")]
+        if let Some(ref server) = self.server {
+            server.publish(event);
         }
     }
-    
-    fn push(&mut self, samples: &[f32]) {
-        for &sample in samples {
-            if self.buffer.len() >= self.max_size {
-                self.buffer.pop_front();
+}
+
+/// A recording started by a WebSocket client's command
+#[cfg(feature = "websocket")]
+struct ClientRecording {
+    whisper_config: WhisperConfig,
+    sample_rate: u32,
+    max_samples: usize,
+    audio: Option<Vec<f32>>, // Some while recording
+}
+
+#[cfg(feature = "websocket")]
+impl ClientRecording {
+    /// Act on a command; returns the transcription if it ended a recording
+    fn handle(&mut self, command: Command) -> Option<DetectionEvent> {
+        match command {
+            Command::StartRecording => {
+                self.audio.get_or_insert_with(Vec::new);
+                None
             }
-            self.buffer.push_back(sample);
+            Command::StopRecording => self.finish(),
         }
     }
-    
-    fn get_samples(&self) -> Vec<f32> {
-        self.buffer.iter().copied().collect()
+
+    fn is_recording(&self) -> bool {
+        self.audio.is_some()
     }
-    
-    fn len(&self) -> usize {
-        self.buffer.len()
+
+    /// Add captured audio; returns the transcription once the recording is full
+    fn push(&mut self, data: &[f32]) -> Option<DetectionEvent> {
+        let audio = self.audio.as_mut()?;
+        audio.extend_from_slice(data);
+        if audio.len() < self.max_samples {
+            return None;
+        }
+        self.finish()
     }
-}
 
-/// Configuration for Whisper transcription service
-struct WhisperConfig {
-    endpoint: Option<String>,  // Local Fast Whisper endpoint
-    api_key: Option<String>,   // Replicate API key
+    fn finish(&mut self) -> Option<DetectionEvent> {
+        let audio = self.audio.take()?;
+        let result = AudioClip::from_samples(&audio, self.sample_rate)
+            .and_then(|clip| transcribe_audio(&self.whisper_config, clip));
+        Some(match result {
+            Ok(text) => DetectionEvent::Recorded {
+                no_speech: text.trim().is_empty(),
+                text: text.trim().to_string(),
+            },
+            Err(e) => DetectionEvent::Error {
+                detection_id: None,
+                message: format!("{:#}", e),
+            },
+        })
+    }
 }
 
-/// Response from local Fast Whisper endpoint
-#[derive(Debug, Deserialize)]
-struct WhisperResponse {
-    text: String,
-    #[allow(dead_code)]
-    duration_s: Option<f32>,
+/// Counts reported when the session ends
+#[derive(Default)]
+struct Session {
+    detections: usize,
+    confirmations: usize,
+    stage1_passed: usize, // Confirmations Stage 1 fired on, when it's bypassed
 }
 
-/// Create WAV file bytes from audio samples
-fn create_wav_bytes(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>> {
-    let spec = WavSpec {
-        channels: 1,
+const WAKE_WORD: &str = "computer";
+
+fn main() -> Result<()> {
+    // Load environment variables
+    load_env_from_args();
+    let args = Args::parse();
+    let format = args.format;
+
+    if args.list_models {
+        for (name, model) in REPLICATE_MODELS {
+            println!("{:10} {}", name, model);
+        }
+        return Ok(());
+    }
+
+    // Check which transcription service to use
+    let mut whisper_config = WhisperConfig::from_env()?;
+    if let Some(ref model) = args.model {
+        whisper_config.replicate_model = resolve_replicate_model(model).to_string();
+    }
+    if args.prompt.is_some() {
+        whisper_config.prompt = args.prompt.clone();
+    }
+
+    let mut detector = WakeWordDetector::new();
+    detector.set_bandpass(args.bandpass);
+
+    if let Some(ref path) = args.template {
+        // The template brings its own band, replacing --bandpass
+        detector.load_template(path)?;
+    } else {
+        // Train a simple template for "computer"
+        // In production, you would record actual samples of the wake word
+        let training_samples = generate_training_samples(3);
+        detector.train_template(&training_samples)?;
+    }
+    anyhow::ensure!(
+        detector.is_trained(),
+        "Wake word template is empty; nothing can be detected"
+    );
+    for (name, path) in &args.words {
+        detector.load_word(name, path)?;
+    }
+
+    // Set threshold (tune this based on testing)
+    detector.set_threshold(
+        args.threshold_db
+            .map(db_to_confidence)
+            .unwrap_or(args.threshold),
+    );
+
+    if format == OutputFormat::Text {
+        print_intro(
+            &whisper_config,
+            detector.threshold(),
+            args.template.as_deref(),
+        );
+    }
+
+    let publisher = Publisher {
+        json: format == OutputFormat::Json,
+        embed_audio: args.embed_audio.then_some(args.embed_audio_max_bytes),
+        #[cfg(feature = "websocket")]
+        server: args.serve.as_deref().map(EventServer::bind).transpose()?,
+    };
+    #[cfg(feature = "websocket")]
+    if let Some(ref server) = publisher.server {
+        server.set_max_concurrent(args.max_concurrent);
+        eprintln!("Serving events on ws://{}/", server.local_addr());
+    }
+
+    // Setup audio capture
+    let host = cpal::default_host();
+    let device = find_input_device(&host, args.device.as_deref())?;
+
+    let config = input_config(&device)?;
+    let sample_rate = config.sample_rate().0;
+    let channels = config.channels();
+
+    if format == OutputFormat::Text {
+        println!("Using input device: {}", device.name()?);
+        println!("Sample rate: {} Hz, Channels: {}", sample_rate, channels);
+        println!();
+        println!("🎤 Listening for wake word \"{}\"...", WAKE_WORD);
+        println!("   (Press Ctrl+C to exit)");
+        println!();
+    }
+
+    #[cfg(feature = "websocket")]
+    let mut recording = ClientRecording {
+        whisper_config: whisper_config.clone(),
         sample_rate,
-        bits_per_sample: 16,
-        sample_format: hound::SampleFormat::Int,
+        max_samples: (args.record_secs.max(0.0) * sample_rate as f32) as usize,
+        audio: None,
     };
-    
-    let mut cursor = std::io::Cursor::new(Vec::new());
-    {
-        let mut writer = WavWriter::new(&mut cursor, spec)?;
-        for &sample in samples {
-            let sample_i16 = (sample * i16::MAX as f32) as i16;
-            writer.write_sample(sample_i16)?;
+
+    let mut pipeline = WakePipeline::new(detector, WAKE_WORD, sample_rate);
+    if let Some(frames) = args.detect_every_frames {
+        pipeline.set_detect_every_frames(frames);
+    }
+    // No point matching the template against silence
+    if args.vad_threshold > 0.0 {
+        let mut gate = SpeechGate::new(sample_rate, args.vad_threshold);
+        gate.set_hangover_ms(args.vad_hangover_ms);
+        pipeline.set_speech_gate(Some(gate));
+    }
+    if whisper_config.is_configured() {
+        pipeline.set_transcriber(whisper_config.transcriber()?);
+        pipeline.set_confirm_phrase(
+            args.confirm_phrase.as_deref(),
+            Duration::from_secs_f32(args.confirm_window.max(0.0)),
+        );
+    } else if args.confirm_phrase.is_some() {
+        eprintln!("Warning: --confirm-phrase needs Stage 2 transcription; ignoring it");
+    }
+    // Ready only while Stage 2 is on and its service is configured
+    #[cfg(feature = "websocket")]
+    if let Some(ref server) = publisher.server {
+        if pipeline.confirms() {
+            server.set_transcriber(Some(whisper_config.transcriber()?));
         }
-        writer.finalize()?;
     }
-    
-    Ok(cursor.into_inner())
-}
+    if args.bypass_stage1 {
+        anyhow::ensure!(
+            pipeline.confirms(),
+            "--bypass-stage1 needs Stage 2 transcription"
+        );
+        eprintln!(
+            "⚠️  Stage 1 is bypassed: every utterance is sent to Stage 2. This is for \
+             measuring Stage 1's recall, and every utterance is a (possibly billed) transcription."
+        );
+        pipeline.set_bypass_stage1(true);
+    }
 
-/// Transcribe audio using configured Whisper service
-fn transcribe_audio(config: &WhisperConfig, audio_data: Vec<u8>) -> Result<String> {
-    if let Some(ref endpoint) = config.endpoint {
-        transcribe_local_whisper(endpoint, audio_data)
-    } else if let Some(ref api_key) = config.api_key {
-        transcribe_replicate(api_key, audio_data)
-    } else {
-        Err(anyhow::anyhow!("No transcription service configured"))
+    // The audio callback only downmixes and hands samples over; detection and
+    // the Stage 2 network call run on this thread so they can't stall capture
+    let (tx, rx) = mpsc::channel();
+    let stream = stream_samples(
+        &device,
+        &config,
+        ChannelMix::default(),
+        args.buffer_size,
+        tx,
+    )?;
+
+    let shutdown = Shutdown::install()?;
+
+    // Keep running until Ctrl+C or SIGTERM
+    let mut session = Session::default();
+    while !shutdown.requested() {
+        #[cfg(feature = "websocket")]
+        if let Some(ref server) = publisher.server {
+            while let Some(command) = server.try_command() {
+                if let Some(event) = recording.handle(command) {
+                    report_recording(&event, &publisher, format);
+                }
+            }
+        }
+
+        let data = match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(data) => data,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        // A client's recording takes the audio instead of Stage 1
+        #[cfg(feature = "websocket")]
+        if recording.is_recording() {
+            if let Some(event) = recording.push(&data) {
+                report_recording(&event, &publisher, format);
+                rx.try_iter().for_each(drop);
+            }
+            continue;
+        }
+
+        if process_audio_frame(&data, &mut pipeline, &mut session, format, &publisher) {
+            // Audio that queued up while Stage 2 ran is stale
+            rx.try_iter().for_each(drop);
+        }
+        if let Some(event) = pipeline.check_confirm_phrase() {
+            report_confirm_phrase(&event, &publisher, format);
+            rx.try_iter().for_each(drop);
+        }
     }
-}
 
-/// Transcribe using local Fast Whisper endpoint
-fn transcribe_local_whisper(endpoint: &str, audio_data: Vec<u8>) -> Result<String> {
-    let client = reqwest::blocking::Client::new();
-    
-    let part = multipart::Part::bytes(audio_data)
-        .file_name("audio.wav")
-        .mime_str("audio/wav")?;
-    
-    let form = multipart::Form::new().part("file", part);
-    
-    let url = format!("{}/transcribe", endpoint);
-    
-    let response = client
-        .post(&url)
-        .multipart(form)
-        .send()
-        .context("Failed to send request to local Whisper endpoint")?;
-    
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response.text().unwrap_or_default();
-        return Err(anyhow::anyhow!(
-            "Local Whisper API error ({}): {}",
-            status,
-            error_text
-        ));
-    }
-    
-    let result: WhisperResponse = response.json()?;
-    Ok(result.text)
-}
+    // Stop capturing, then flush the event log
+    drop(stream);
+    std::io::stdout().flush().ok();
 
-/// Transcribe using Replicate API
-fn transcribe_replicate(api_key: &str, audio_data: Vec<u8>) -> Result<String> {
-    let client = reqwest::blocking::Client::new();
-    
-    let part = multipart::Part::bytes(audio_data)
-        .file_name("audio.wav")
-        .mime_str("audio/wav")?;
-    
-    let form = multipart::Form::new().part("file", part);
-    
-    let whisper_version = "vaibhavs10/incredibly-fast-whisper:3ab86df6c8f54c11309d4d1f930ac292bad43ace52d10c80d87eb258b3c9f79c";
-    let url = format!(
-        "https://api.replicate.com/v1/models/{}/predictions",
-        whisper_version
+    // Keep stdout pure NDJSON in json mode
+    let mut summary = format!(
+        "Session ended: {} detection(s), {} confirmed by Whisper",
+        session.detections, session.confirmations
     );
-    
-    let response = client
-        .post(&url)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .multipart(form)
-        .send()
-        .context("Failed to send request to Replicate")?;
-    
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response.text().unwrap_or_default();
-        return Err(anyhow::anyhow!(
-            "Replicate API error ({}): {}",
-            status,
-            error_text
-        ));
-    }
-    
-    let result: serde_json::Value = response.json()?;
-    
-    // Extract text from various possible response formats
-    let text = if let Some(text) = result.get("text").and_then(|v| v.as_str()) {
-        text.to_string()
-    } else if let Some(output) = result.get("output") {
-        if let Some(text) = output.get("text").and_then(|v| v.as_str()) {
-            text.to_string()
-        } else if let Some(text_str) = output.as_str() {
-            text_str.to_string()
-        } else {
-            serde_json::to_string_pretty(&output)?
-        }
+    if pipeline.bypasses_stage1() {
+        summary += &format!(
+            "; Stage 1 would have caught {} of the {} confirmed",
+            session.stage1_passed, session.confirmations
+        );
+    }
+    if format == OutputFormat::Json {
+        eprintln!("{}", summary);
     } else {
-        "(No transcription returned)".to_string()
-    };
-    
-    Ok(text)
+        println!("\n\n{}", summary);
+    }
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    // Load environment variables
-    dotenv().ok();
-    
+/// Print the human-readable banner and configuration summary
+fn print_intro(whisper_config: &WhisperConfig, threshold: f32, template: Option<&std::path::Path>) {
     println!("╔══════════════════════════════════════════════════════════╗");
     println!("║   Wake Word Detection + Transcription Demo              ║");
     println!("╚══════════════════════════════════════════════════════════╝");
@@ -197,229 +466,229 @@ fn main() -> Result<()> {
     println!("  Stage 1: Lightweight local pattern matching (MFCC + DTW)");
     println!("  Stage 2: Whisper confirmation");
     println!();
-    
-    // Check which transcription service to use
-    let whisper_endpoint = env::var("WHISPER_ENDPOINT").ok();
-    let api_key = env::var("REPLICATE_API_KEY").ok();
-    
-    let stage2_enabled = whisper_endpoint.is_some() || api_key.is_some();
-    
-    if !stage2_enabled {
-        println!("⚠️  Note: Neither WHISPER_ENDPOINT nor REPLICATE_API_KEY found");
+
+    if !whisper_config.is_configured() {
+        println!("⚠️  Note: None of WHISPER_ENDPOINT, REPLICATE_API_KEY or OPENAI_API_KEY found");
         println!("   Stage 2 confirmation disabled - only Stage 1 detection will run");
         println!();
         println!("   To enable Stage 2 confirmation, set one of:");
         println!("   - WHISPER_ENDPOINT=http://your-server:8085 (local Fast Whisper)");
         println!("   - REPLICATE_API_KEY=your_key (Replicate API)");
+        println!("   - OPENAI_API_KEY=your_key (OpenAI Whisper API)");
         println!();
-    } else if let Some(ref endpoint) = whisper_endpoint {
+    } else if let Some(ref endpoint) = whisper_config.endpoint {
         println!("✓ Using local Fast Whisper endpoint: {}", endpoint);
         println!();
+    } else if whisper_config.api_key.is_some() {
+        println!(
+            "✓ Using Replicate API for transcription ({})",
+            whisper_config.replicate_model
+        );
+        println!();
     } else {
-        println!("✓ Using Replicate API for transcription");
+        println!("✓ Using OpenAI Whisper API for transcription");
         println!();
     }
-    
-    println!("Setting up wake word detector...");
-    let mut detector = WakeWordDetector::new();
-    
-    // Train a simple template for "computer"
-    // In production, you would record actual samples of the wake word
-    println!("  Training template (synthetic audio for demo)...");
-    let training_samples = generate_training_samples(3);
-    detector.train_template(&training_samples)?;
-    println!("  ✓ Template trained");
-    
-    // Set threshold (tune this based on testing)
-    detector.set_threshold(0.65);
-    println!("  Detection threshold: 0.65");
-    
-    println!("\nStarting audio capture...");
-    println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
-    println!();
-    
-    // Setup audio capture
-    let host = cpal::default_host();
-    let device = host
-        .default_input_device()
-        .context("No input device available")?;
-    
-    println!("Using input device: {}", device.name()?);
-    
-    let config = device.default_input_config()?;
-    let sample_rate = config.sample_rate().0;
-    let channels = config.channels() as u16;
-    
-    println!("Sample rate: {} Hz, Channels: {}", sample_rate, channels);
+
+    match template {
+        Some(path) => println!("Wake word detector ready (template {})", path.display()),
+        None => println!("Wake word detector ready (synthetic template for demo)"),
+    }
+    println!(
+        "  Detection threshold: {:.2} ({:.1} dB; --threshold or --threshold-db to change)",
+        threshold,
+        confidence_to_db(threshold)
+    );
     println!();
-    println!("🎤 Listening for wake word \"computer\"...");
-    println!("   (Press Ctrl+C to exit)");
+    println!("Starting audio capture...");
+    println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
     println!();
-    
-    // Shared state
-    let audio_buffer = Arc::new(Mutex::new(AudioBuffer::new(2, sample_rate as usize)));
-    let detector = Arc::new(Mutex::new(detector));
-    let last_detection = Arc::new(Mutex::new(Instant::now()));
-    let whisper_config = Arc::new(WhisperConfig {
-        endpoint: whisper_endpoint,
-        api_key,
-    });
-    
-    // Clone for audio callback
-    let audio_buffer_clone = Arc::clone(&audio_buffer);
-    let detector_clone = Arc::clone(&detector);
-    let last_detection_clone = Arc::clone(&last_detection);
-    let whisper_config_clone = Arc::clone(&whisper_config);
-    
-    // Error callback
-    let err_fn = |err| eprintln!("Audio stream error: {}", err);
-    
-    // Build audio stream
-    let stream = match config.sample_format() {
-        cpal::SampleFormat::F32 => device.build_input_stream(
-            &config.into(),
-            move |data: &[f32], _: &_| {
-                process_audio_frame(
-                    data,
-                    &audio_buffer_clone,
-                    &detector_clone,
-                    &last_detection_clone,
-                    &whisper_config_clone,
-                    sample_rate,
-                );
-            },
-            err_fn,
-            None,
-        )?,
-        cpal::SampleFormat::I16 => {
-            let audio_buffer_clone = Arc::clone(&audio_buffer);
-            let detector_clone = Arc::clone(&detector);
-            let last_detection_clone = Arc::clone(&last_detection);
-            let whisper_config_clone = Arc::clone(&whisper_config);
-            
-            device.build_input_stream(
-                &config.into(),
-                move |data: &[i16], _: &_| {
-                    // Convert i16 to f32
-                    let float_data: Vec<f32> = data.iter()
-                        .map(|&s| s as f32 / i16::MAX as f32)
-                        .collect();
-                    process_audio_frame(
-                        &float_data,
-                        &audio_buffer_clone,
-                        &detector_clone,
-                        &last_detection_clone,
-                        &whisper_config_clone,
-                        sample_rate,
-                    );
-                },
-                err_fn,
-                None,
-            )?
-        }
-        _ => return Err(anyhow::anyhow!("Unsupported sample format")),
-    };
-    
-    stream.play()?;
-    
-    // Keep running
-    loop {
-        std::thread::sleep(Duration::from_secs(1));
-    }
 }
 
-/// Process each audio frame for wake word detection
+/// Process each captured chunk; returns true if Stage 2 ran
 fn process_audio_frame(
     data: &[f32],
-    audio_buffer: &Arc<Mutex<AudioBuffer>>,
-    detector: &Arc<Mutex<WakeWordDetector>>,
-    last_detection: &Arc<Mutex<Instant>>,
-    whisper_config: &Arc<WhisperConfig>,
-    sample_rate: u32,
-) {
-    // Add samples to buffer
-    let mut buffer = audio_buffer.lock().unwrap();
-    buffer.push(data);
+    pipeline: &mut WakePipeline,
+    session: &mut Session,
+    format: OutputFormat,
+    publisher: &Publisher,
+) -> bool {
+    let json = format == OutputFormat::Json;
 
     // Show live sound level (simple ASCII bar)
-    let rms = (data.iter().map(|x| x * x).sum::<f32>() / data.len().max(1) as f32).sqrt();
-    let bar_len = (rms * 40.0).min(40.0) as usize;
-    let bar = "|".repeat(bar_len);
-    print!("\r[{:40}] RMS: {:.3}   ", bar, rms);
-    use std::io::Write;
-    std::io::stdout().flush().ok();
-
-    // Only check every 100ms to reduce CPU usage
-    if buffer.len() < sample_rate as usize / 10 {
-        return;
+    if !json {
+        let rms = (data.iter().map(|x| x * x).sum::<f32>() / data.len().max(1) as f32).sqrt();
+        let bar_len = (rms * 40.0).min(40.0) as usize;
+        let bar = "|".repeat(bar_len);
+        print!("\r[{:40}] RMS: {:.3}   ", bar, rms);
+        std::io::stdout().flush().ok();
     }
 
-    // Prevent rapid re-triggering
-    let mut last_det = last_detection.lock().unwrap();
-    if last_det.elapsed() < Duration::from_secs(3) {
-        return;
+    // Stage 1 (checked every 100ms, not during the cooldown)
+    let result = pipeline.push(data);
+    let repairs = pipeline.take_repairs();
+    if !repairs.is_empty() {
+        eprintln!("\nWarning: {}", repairs);
     }
+    let mut candidate = match result {
+        Ok(Some(candidate)) => candidate,
+        Ok(None) => return false,
+        Err(e) => {
+            eprintln!("\n[DEBUG] Detection error: {}", e);
+            return false;
+        }
+    };
+    session.detections += 1;
 
-    // Get samples for detection (last 1 second)
-    let samples = buffer.get_samples();
-
-    // Run detection
-    let detector = detector.lock().unwrap();
-    match detector.detect(&samples) {
-        Ok((detected, confidence)) => {
-            if detected {
-                *last_det = Instant::now();
-                drop(last_det);
-                drop(detector);
-
-                println!("\n\n🎯 Candidate detected! (confidence: {:.1}%)", confidence * 100.0);
-                println!("   Stage 1: ✓ Local pattern match successful");
-
-                // Stage 2: Send to Whisper for confirmation
-                if whisper_config.endpoint.is_some() || whisper_config.api_key.is_some() {
-                    println!("   Stage 2: Sending to Whisper for confirmation...");
-
-                    // Get full buffer for transcription (2 seconds)
-                    let transcription_samples = buffer.get_samples();
-                    drop(buffer);
-
-                    // Convert to WAV and transcribe
-                    match create_wav_bytes(&transcription_samples, sample_rate) {
-                        Ok(wav_data) => {
-                            match transcribe_audio(whisper_config, wav_data) {
-                                Ok(text) => {
-                                    let text_lower = text.to_lowercase();
-                                    let contains_wake_word = text_lower.contains("computer");
-
-                                    println!("   Stage 2: Transcription: \"{}\"", text.trim());
-
-                                    if contains_wake_word {
-                                        println!("   Stage 2: ✓ Wake word CONFIRMED!");
-                                        println!("🎉 WAKE WORD VERIFIED - Ready for command");
-                                    } else {
-                                        println!("   Stage 2: ✗ False positive - wake word not in transcription");
-                                    }
-                                }
-                                Err(e) => {
-                                    eprintln!("   Stage 2: Transcription error: {}", e);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("   Stage 2: WAV creation error: {}", e);
-                        }
-                    }
+    publisher.publish(&publisher.detection(&candidate, pipeline.sample_rate()));
+    if !json {
+        println!(
+            "\n\n🎯 Detection #{}: candidate \"{}\" (confidence: {}, {:.1} dB)",
+            candidate.id,
+            candidate.word,
+            format_confidence(candidate.confidence, confidence_precision()),
+            confidence_to_db(candidate.confidence)
+        );
+        if pipeline.bypasses_stage1() {
+            println!(
+                "   Stage 1: bypassed ({})",
+                if candidate.stage1_passed {
+                    "would have fired"
                 } else {
-                    drop(buffer);
-                    println!("   Stage 2: Confirmation disabled (no endpoint configured)");
+                    "would have missed it"
                 }
+            );
+        } else {
+            println!("   Stage 1: ✓ Local pattern match successful");
+        }
+        if pipeline.confirms() {
+            println!("   Stage 2: Sending to Whisper for confirmation...");
+        }
+    }
+
+    // Stage 2: Send to Whisper for confirmation
+    let event = pipeline.confirm(&mut candidate);
+    if let Some(DetectionEvent::Transcription {
+        confirmed: true, ..
+    }) = event
+    {
+        session.confirmations += 1;
+        if candidate.stage1_passed {
+            session.stage1_passed += 1;
+        }
+    }
+
+    if let Some(ref event) = event {
+        publisher.publish(event);
+    }
+    if json {
+        return event.is_some();
+    }
 
-                println!("🎤 Listening for wake word \"computer\"...");
+    match event {
+        Some(DetectionEvent::Transcription {
+            ref text,
+            confirmed,
+            ..
+        }) => {
+            println!("   Stage 2: Transcription: \"{}\"", text);
+            if confirmed {
+                println!("   Stage 2: ✓ Wake word CONFIRMED!");
+                println!("🎉 WAKE WORD VERIFIED - Ready for command");
+                if pipeline.awaiting_phrase() {
+                    println!("🔐 Listening for the confirm phrase...");
+                }
+            } else {
+                println!("   Stage 2: ✗ False positive - wake word not in transcription");
             }
         }
-        Err(e) => {
-            println!("\n[DEBUG] Detection error: {}", e);
+        Some(DetectionEvent::Error { ref message, .. }) => {
+            eprintln!("   Stage 2: Transcription error: {}", message);
+        }
+        Some(
+            DetectionEvent::Detection { .. }
+            | DetectionEvent::Recorded { .. }
+            | DetectionEvent::ConfirmPhrase { .. },
+        ) => {}
+        None => println!("   Stage 2: Confirmation disabled (no endpoint configured)"),
+    }
+    candidate.timing.acknowledge();
+    println!("   ⏱  {}", candidate.timing.breakdown());
+    if !pipeline.awaiting_phrase() {
+        println!("🎤 Listening for wake word \"{}\"...", WAKE_WORD);
+    }
+    event.is_some()
+}
+
+/// Publish the confirm phrase check, and show it in text mode
+fn report_confirm_phrase(event: &DetectionEvent, publisher: &Publisher, format: OutputFormat) {
+    publisher.publish(event);
+    if format != OutputFormat::Text {
+        return;
+    }
+    match event {
+        DetectionEvent::ConfirmPhrase {
+            text,
+            matched: true,
+            ..
+        } => {
+            println!("🔓 Confirm phrase heard (\"{}\") - proceeding", text);
+        }
+        DetectionEvent::ConfirmPhrase {
+            text,
+            matched: false,
+            ..
+        } => {
+            println!(
+                "🔒 Confirm phrase not heard (\"{}\") - command cancelled",
+                text
+            );
         }
+        DetectionEvent::Error { message, .. } => eprintln!("   Confirm phrase error: {}", message),
+        _ => {}
+    }
+    println!("🎤 Listening for wake word \"{}\"...", WAKE_WORD);
+}
+
+/// Publish the outcome of a client's recording, and show it in text mode
+#[cfg(feature = "websocket")]
+fn report_recording(event: &DetectionEvent, publisher: &Publisher, format: OutputFormat) {
+    publisher.publish(event);
+    if format == OutputFormat::Text {
+        match event {
+            DetectionEvent::Recorded {
+                no_speech: true, ..
+            } => {
+                println!("\n📝 Client recording: {}", NO_SPEECH_MESSAGE)
+            }
+            DetectionEvent::Recorded { text, .. } => {
+                println!("\n📝 Client recording: \"{}\"", text)
+            }
+            DetectionEvent::Error { message, .. } => {
+                eprintln!("\nClient recording failed: {}", message)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parse a `--word` value: `NAME=PATH`
+fn parse_word(value: &str) -> Result<(String, std::path::PathBuf)> {
+    match value.split_once('=') {
+        Some((name, path)) if !name.trim().is_empty() && !path.trim().is_empty() => {
+            Ok((name.trim().to_string(), path.trim().into()))
+        }
+        _ => Err(anyhow::anyhow!(
+            "Expected NAME=PATH, e.g. cancel=cancel.json"
+        )),
+    }
+}
+
+/// Write an NDJSON event to stdout
+fn emit(event: &DetectionEvent) {
+    let stdout = std::io::stdout();
+    if let Err(e) = write_ndjson(&mut stdout.lock(), event) {
+        eprintln!("Failed to write event: {}", e);
     }
 }
 
@@ -428,45 +697,45 @@ fn process_audio_frame(
 fn generate_training_samples(count: usize) -> Vec<Vec<f32>> {
     let sample_rate = 16000;
     let mut samples = Vec::new();
-    
+
     for i in 0..count {
         let duration = 1.0 + (i as f32 * 0.05);
         let pitch_mult = 1.0 - (i as f32 * 0.03);
-        
+
         // Simulate "computer" with multiple frequency components
         // This is a simplified representation
         let sample: Vec<f32> = (0..(sample_rate as f32 * duration) as usize)
             .map(|idx| {
                 let t = idx as f32 / sample_rate as f32;
                 let phase_shift = i as f32 * 0.1;
-                
+
                 // "com" - lower frequencies
                 let com = if t < 0.3 {
                     (300.0 * pitch_mult * t * 2.0 * std::f32::consts::PI + phase_shift).sin() * 0.4
                 } else {
                     0.0
                 };
-                
+
                 // "pu" - middle frequencies
-                let pu = if t >= 0.3 && t < 0.6 {
+                let pu = if (0.3..0.6).contains(&t) {
                     (800.0 * pitch_mult * t * 2.0 * std::f32::consts::PI + phase_shift).sin() * 0.3
                 } else {
                     0.0
                 };
-                
+
                 // "ter" - higher frequencies
                 let ter = if t >= 0.6 {
                     (1500.0 * pitch_mult * t * 2.0 * std::f32::consts::PI + phase_shift).sin() * 0.3
                 } else {
                     0.0
                 };
-                
+
                 (com + pu + ter) * (1.0 - t) // Decay envelope
             })
             .collect();
-        
+
         samples.push(sample);
     }
-    
+
     samples
 }
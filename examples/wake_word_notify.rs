@@ -0,0 +1,226 @@
+//! Wake Word Desktop Notification Demo
+//!
+//! Listens for the wake word and, once Stage 2 confirms it, shows a desktop
+//! notification with the transcribed text. Detection runs on its own thread
+//! and reports `DetectionEvent`s over a channel; the main thread only turns
+//! those events into notifications, so the listening loop never waits on UI.
+//!
+//! Usage:
+//! 1. Set WHISPER_ENDPOINT, REPLICATE_API_KEY or OPENAI_API_KEY in .env file
+//! 2. Run: cargo run --example wake_word_notify
+//! 3. Say "computer" followed by a command
+//!
+//! Where desktop notifications aren't available the text is printed instead.
+
+use anyhow::Result;
+use audio_transcribe_cli::device::{
+    buffer_frames_from_env, device_name_from_env, find_input_device, input_config,
+};
+use audio_transcribe_cli::events::DetectionEvent;
+use audio_transcribe_cli::mix::ChannelMix;
+use audio_transcribe_cli::recording::stream_samples;
+use audio_transcribe_cli::resample::resample_linear;
+use audio_transcribe_cli::transcribe::{transcribe_audio, AudioClip, WhisperConfig};
+use audio_transcribe_cli::wake_word::{confidence_precision, format_confidence, WakeWordDetector};
+use cpal::traits::DeviceTrait;
+use dotenv::dotenv;
+use notify_rust::Notification;
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WAKE_WORD: &str = "computer";
+
+fn main() -> Result<()> {
+    dotenv().ok();
+
+    println!("Wake Word Notification Demo");
+    println!("===========================\n");
+
+    let whisper_config = WhisperConfig::from_env()?;
+    if !whisper_config.is_configured() {
+        anyhow::bail!(
+            "Set WHISPER_ENDPOINT, REPLICATE_API_KEY or OPENAI_API_KEY so detections can be confirmed"
+        );
+    }
+
+    // Synthetic template for the demo; use a recorded template in practice
+    let mut detector = WakeWordDetector::new();
+    detector.train_template(&[generate_chirp(16000)])?;
+    anyhow::ensure!(
+        detector.is_trained(),
+        "Wake word template is empty; nothing can be detected"
+    );
+    detector.set_threshold(0.65);
+
+    let host = cpal::default_host();
+    let device = find_input_device(&host, device_name_from_env().as_deref())?;
+    println!("Using input device: {}", device.name()?);
+
+    let config = input_config(&device)?;
+    let sample_rate = config.sample_rate().0;
+
+    let (audio_tx, audio_rx) = mpsc::channel();
+    let _stream = stream_samples(
+        &device,
+        &config,
+        ChannelMix::default(),
+        buffer_frames_from_env()?,
+        audio_tx,
+    )?;
+
+    // Detection runs on its own thread and only talks to us through events
+    let (event_tx, event_rx) = mpsc::channel();
+    thread::spawn(move || {
+        detection_loop(audio_rx, detector, whisper_config, sample_rate, event_tx);
+    });
+
+    println!(
+        "🎤 Listening for wake word \"{}\"... (Ctrl+C to exit)\n",
+        WAKE_WORD
+    );
+
+    for event in event_rx {
+        match event {
+            DetectionEvent::Detection {
+                detection_id,
+                confidence,
+                ..
+            } => {
+                println!(
+                    "🎯 Detection #{}: candidate (confidence: {})",
+                    detection_id,
+                    format_confidence(confidence, confidence_precision())
+                );
+            }
+            DetectionEvent::Transcription {
+                text,
+                confirmed: true,
+                ..
+            }
+            | DetectionEvent::Recorded { text, .. } => notify(&text),
+            DetectionEvent::Transcription {
+                detection_id,
+                text,
+                confirmed: false,
+            } => {
+                println!("   ✗ #{} false positive: \"{}\"", detection_id, text.trim());
+            }
+            DetectionEvent::Error { message, .. } => eprintln!("Stage 2 error: {}", message),
+            DetectionEvent::ConfirmPhrase { .. } => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Show a desktop notification, falling back to stdout
+fn notify(text: &str) {
+    let shown = Notification::new()
+        .summary("Wake word confirmed")
+        .body(text.trim())
+        .show();
+
+    match shown {
+        Ok(_) => println!("🔔 Notified: \"{}\"", text.trim()),
+        Err(e) => {
+            println!("🔔 Wake word confirmed: \"{}\"", text.trim());
+            println!("   (desktop notification unavailable: {})", e);
+        }
+    }
+}
+
+/// Rolling-buffer detection with Stage 2 confirmation, reporting via `events`
+fn detection_loop(
+    audio: Receiver<Vec<f32>>,
+    detector: WakeWordDetector,
+    whisper_config: WhisperConfig,
+    sample_rate: u32,
+    events: Sender<DetectionEvent>,
+) {
+    let window = 2 * sample_rate as usize;
+    let check_every = sample_rate as usize / 10;
+    let mut buffer: VecDeque<f32> = VecDeque::with_capacity(window);
+    let mut since_check = 0;
+    let mut last_detection: Option<Instant> = None;
+    let mut detection_id = 0;
+
+    for chunk in audio {
+        buffer.extend(chunk.iter().copied());
+        while buffer.len() > window {
+            buffer.pop_front();
+        }
+
+        // Only check every 100ms, and not during the cooldown
+        since_check += chunk.len();
+        if since_check < check_every {
+            continue;
+        }
+        since_check = 0;
+        if last_detection.is_some_and(|t| t.elapsed() < Duration::from_secs(3)) {
+            continue;
+        }
+
+        let samples: Vec<f32> = buffer.iter().copied().collect();
+        let features_input = resample_linear(&samples, sample_rate, detector.sample_rate());
+        let confidence = match detector.detect(&features_input) {
+            Ok(Some((_, confidence))) => confidence,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Detection error: {}", e);
+                continue;
+            }
+        };
+        last_detection = Some(Instant::now());
+        detection_id += 1;
+
+        let detection = DetectionEvent::Detection {
+            detection_id,
+            confidence,
+            audio_wav_base64: None,
+        };
+        if events.send(detection).is_err() {
+            return;
+        }
+
+        let text = match AudioClip::from_samples(&samples, sample_rate)
+            .and_then(|clip| transcribe_audio(&whisper_config, clip))
+        {
+            Ok(text) => text,
+            Err(e) => {
+                let message = format!("{:#}", e);
+                let error = DetectionEvent::Error {
+                    detection_id: Some(detection_id),
+                    message,
+                };
+                if events.send(error).is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+        let confirmed = text.to_lowercase().contains(WAKE_WORD);
+        if events
+            .send(DetectionEvent::Transcription {
+                detection_id,
+                text,
+                confirmed,
+            })
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Frequency sweep standing in for a recorded wake word
+fn generate_chirp(sample_rate: usize) -> Vec<f32> {
+    (0..sample_rate)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            let freq = 300.0 + 1200.0 * t;
+            (2.0 * std::f32::consts::PI * freq * t).sin() * 0.5
+        })
+        .collect()
+}
@@ -1,53 +1,267 @@
 //! Wake Word TUI Demo
 //! Multi-pane TUI: status + live sound level + debug widgets
+//!
+//! Start with `--template wake.json` (or WAKE_WORD_TEMPLATE) and
+//! `--threshold 0.85` (or WAKE_WORD_THRESHOLD), then fine-tune live with the
+//! up/down arrow keys. Without a template only the sound level is shown.
+//!
+//! The status pane shows the pitch of the latest 40 ms of audio while
+//! someone is speaking, handy for checking the microphone hears a voice.
+//!
+//! The level gauge marks the recent peak; `--peak-decay 1.0` holds the
+//! maximum instead, for calibration, and `r` resets it.
+//!
+//! `--replay recording.wav` streams a file through the detector instead of
+//! the microphone, at real time or `--replay-speed` times faster, updating
+//! the panes as if it were live. Press `p` to play it again. The microphone
+//! isn't opened, so no input device is needed.
 
+use std::fs;
 use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::{Duration, Instant};
 
-use audio_transcribe_cli::wake_word::WakeWordDetector;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use anyhow::Context;
+use audio_transcribe_cli::convert::{to_mono, ToF32};
+use audio_transcribe_cli::device::{find_input_device, input_config, stream_config};
+use audio_transcribe_cli::pitch::estimate_pitch;
+use audio_transcribe_cli::resample::{resample_linear, StreamResampler};
+use audio_transcribe_cli::wake_word::{
+    confidence_precision, format_confidence, MfccConfig, WakeWordDetector,
+};
+use audio_transcribe_cli::wav::decode_wav_mono;
+use clap::Parser;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::SizedSample;
 use crossterm::event::{self, Event, KeyCode};
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
 use ratatui::backend::CrosstermBackend;
-use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
 use ratatui::style::{Color, Style};
-use ratatui::widgets::{Block, Borders, Paragraph, Gauge};
+use ratatui::widgets::{Block, Borders, Gauge, Paragraph};
 use ratatui::Terminal;
 
+/// Gap between the trigger threshold and the re-arm threshold
+const RELEASE_GAP: f32 = 0.1;
+
+/// Threshold change per arrow key press
+const THRESHOLD_STEP: f32 = 0.01;
+
+/// Audio kept for detection between checks
+const MAX_BUFFER_SAMPLES: usize = 16000 * 2; // 2 seconds
+
+/// Audio the pitch readout is estimated from
+const PITCH_FRAME_SAMPLES: usize = 16000 * 40 / 1000; // 40 ms
+
+/// Length of each block fed in during a replay, like one audio callback
+const REPLAY_BLOCK: Duration = Duration::from_millis(100);
+
+/// Highest sound level seen, decaying towards the current level
+struct PeakMeter {
+    level: f32,
+    decay: f32, // Factor applied per audio callback; 1.0 holds the peak
+}
+
+impl PeakMeter {
+    fn new(decay: f32) -> Self {
+        Self { level: 0.0, decay }
+    }
+
+    /// Take in the level of the latest block of audio
+    fn update(&mut self, rms: f32) {
+        self.level = (self.level * self.decay).max(rms);
+    }
+
+    fn reset(&mut self) {
+        self.level = 0.0;
+    }
+}
+
+/// Live wake word detection status and sound level
+#[derive(Parser)]
+struct Args {
+    /// Similarity needed to trigger (0.0-1.0; lower = more sensitive)
+    #[arg(long, env = "WAKE_WORD_THRESHOLD", default_value_t = 0.9)]
+    threshold: f32,
+
+    /// Peak meter decay per audio block (0.0-1.0; 1.0 holds the maximum until reset with `r`)
+    #[arg(long, env = "PEAK_DECAY", default_value_t = 0.95)]
+    peak_decay: f32,
+
+    /// Wake word template file (written by `WakeWordDetector::save_template`)
+    #[arg(long, env = "WAKE_WORD_TEMPLATE", value_name = "PATH")]
+    template: Option<PathBuf>,
+
+    /// Input device to listen on (any part of its name; default device if unset)
+    #[arg(long, env = "AUDIO_DEVICE")]
+    device: Option<String>,
+
+    /// Audio buffer size hint in frames (smaller = lower latency, larger = fewer dropouts)
+    #[arg(long, env = "AUDIO_BUFFER_SIZE", value_name = "FRAMES")]
+    buffer_size: Option<u32>,
+
+    /// Stream this WAV file through the detector instead of the microphone (p replays it)
+    #[arg(long, value_name = "PATH")]
+    replay: Option<PathBuf>,
+
+    /// Replay speed (1.0 = real time, 4.0 = four times faster)
+    #[arg(long, default_value_t = 1.0)]
+    replay_speed: f32,
+}
+
+/// Feeds a WAV file to the detector in place of the microphone
+struct Replay {
+    path: Option<PathBuf>,
+    speed: f32,
+    live: Arc<AtomicBool>, // False while replaying; the microphone is ignored
+    source: Arc<Mutex<String>>,
+    idle_source: &'static str, // Shown between replays
+    current_rms: Arc<Mutex<f32>>,
+    peak_rms: Arc<Mutex<PeakMeter>>,
+    audio_buffer: Arc<Mutex<Vec<f32>>>,
+}
+
+impl Replay {
+    /// Start replaying on a background thread, unless already replaying
+    fn start(&self) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        if !self.live.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let speed = self.speed;
+        let live = Arc::clone(&self.live);
+        let source = Arc::clone(&self.source);
+        let idle_source = self.idle_source;
+        let current_rms = Arc::clone(&self.current_rms);
+        let peak_rms = Arc::clone(&self.peak_rms);
+        let audio_buffer = Arc::clone(&self.audio_buffer);
+        thread::spawn(move || {
+            let result = replay_file(
+                &path,
+                speed,
+                &source,
+                &current_rms,
+                &peak_rms,
+                &audio_buffer,
+            );
+            *source.lock().unwrap() = match result {
+                Ok(()) => idle_source.to_string(),
+                Err(e) => format!("{} (replay failed: {:#})", idle_source, e),
+            };
+            live.store(true, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Feed `path` into the buffer and level meters block by block, in (scaled) real time
+fn replay_file(
+    path: &PathBuf,
+    speed: f32,
+    source: &Mutex<String>,
+    current_rms: &Mutex<f32>,
+    peak_rms: &Mutex<PeakMeter>,
+    audio_buffer: &Mutex<Vec<f32>>,
+) -> anyhow::Result<()> {
+    let wav_data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let (samples, sample_rate) = decode_wav_mono(&wav_data)?;
+    let rate = MfccConfig::default().sample_rate;
+    let samples = resample_linear(&samples, sample_rate, rate);
+
+    let block_len = (rate as f32 * REPLAY_BLOCK.as_secs_f32()) as usize;
+    let total_s = samples.len() as f32 / rate as f32;
+    for (i, block) in samples.chunks(block_len).enumerate() {
+        append_capped(&mut audio_buffer.lock().unwrap(), block);
+        let rms = (block.iter().map(|s| s * s).sum::<f32>() / block.len() as f32).sqrt();
+        *current_rms.lock().unwrap() = rms;
+        peak_rms.lock().unwrap().update(rms);
+        *source.lock().unwrap() = format!(
+            "Replaying {} ({:.1}/{:.1} s at {}x)",
+            path.display(),
+            ((i + 1) * block_len) as f32 / rate as f32,
+            total_s,
+            speed
+        );
+        thread::sleep(REPLAY_BLOCK.div_f32(speed));
+    }
+    Ok(())
+}
+
+/// Append to the detection buffer, dropping the oldest audio beyond its cap
+fn append_capped(buffer: &mut Vec<f32>, samples: &[f32]) {
+    buffer.extend_from_slice(samples);
+    let excess = buffer.len().saturating_sub(MAX_BUFFER_SAMPLES);
+    buffer.drain(..excess);
+}
+
 fn main() -> Result<(), io::Error> {
+    dotenv::dotenv().ok();
+    let args = Args::parse();
+
     // Shared state between audio callback and UI
     let current_rms = Arc::new(Mutex::new(0f32));
-    let peak_rms = Arc::new(Mutex::new(0f32));
-    let status_text = Arc::new(Mutex::new(String::from("Listening...")));
+    let peak_rms = Arc::new(Mutex::new(PeakMeter::new(args.peak_decay.clamp(0.0, 1.0))));
     let audio_buffer = Arc::new(Mutex::new(Vec::new()));
+    let live = Arc::new(AtomicBool::new(true));
 
     // Wake Word Detector
     let mut detector = WakeWordDetector::new();
-    // For demonstration, we'll create a dummy template.
-    // In a real application, you would load a pre-trained template.
-    let dummy_template_features = ndarray::Array2::zeros((50, 13));
-    detector.set_template(dummy_template_features);
-    detector.set_threshold(0.9); // High threshold for dummy template
+    if let Some(ref path) = args.template {
+        detector
+            .load_template(path)
+            .map_err(|e| io::Error::other(format!("{:#}", e)))?;
+    }
+    // Without a template nothing can match, so say so instead of "Listening..."
+    let trained = detector.is_trained();
+    let status_text = Arc::new(Mutex::new(String::from(if trained {
+        "Listening..."
+    } else {
+        "No wake word template loaded - detection is off (pass --template)"
+    })));
+    // Re-arm once similarity drops a little below the trigger threshold
+    detector.set_thresholds(args.threshold, args.threshold - RELEASE_GAP);
     let detector = Arc::new(Mutex::new(detector));
 
-    // Spawn audio capture stream and keep stream in scope so it isn't dropped
-    let _stream = match start_audio_stream(
-        Arc::clone(&current_rms),
-        Arc::clone(&peak_rms),
-        Arc::clone(&audio_buffer),
-    ) {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("Failed to start audio stream: {}", e);
-            // continue without stream
-            // create a dummy stream via None equivalent - we'll just continue
-            // but return early would stop demo; we continue with zero levels
-            // Use Option<Stream>? but to keep minimal changes, just continue
-            // by not having a stream.
-            // For simplicity, just panic to surface the error.
-            panic!("Failed to start audio stream: {}", e);
-        }
+    // Spawn audio capture stream and keep stream in scope so it isn't dropped.
+    // A replay doesn't need the microphone, so it isn't opened at all
+    let stream = match args.replay {
+        Some(_) => None,
+        None => Some(
+            start_audio_stream(
+                Arc::clone(&current_rms),
+                Arc::clone(&peak_rms),
+                Arc::clone(&audio_buffer),
+                Arc::clone(&live),
+                args.device.as_deref(),
+                args.buffer_size,
+            )
+            .map_err(|e| io::Error::other(format!("Failed to start audio stream: {:#}", e)))?,
+        ),
+    };
+    let idle_source = if stream.is_some() {
+        "Microphone"
+    } else {
+        "None"
+    };
+
+    let replay = Replay {
+        path: args.replay.clone(),
+        speed: args.replay_speed.max(0.1),
+        live,
+        source: Arc::new(Mutex::new(idle_source.to_string())),
+        idle_source,
+        current_rms: Arc::clone(&current_rms),
+        peak_rms: Arc::clone(&peak_rms),
+        audio_buffer: Arc::clone(&audio_buffer),
+    };
+    replay.start();
+    let replay_hint = match args.replay {
+        Some(ref path) => format!("\nPress p to replay {}", path.display()),
+        None => String::new(),
     };
 
     enable_raw_mode()?;
@@ -57,8 +271,22 @@ fn main() -> Result<(), io::Error> {
 
     let mut last_draw = Instant::now();
     let mut last_detection = Instant::now();
+    let sample_rate = MfccConfig::default().sample_rate;
+    let mut pitch = None;
 
     loop {
+        // Kept while detection has just emptied the buffer, until it refills
+        {
+            let buffer = audio_buffer.lock().unwrap();
+            if buffer.len() >= PITCH_FRAME_SAMPLES {
+                pitch = estimate_pitch(&buffer[buffer.len() - PITCH_FRAME_SAMPLES..], sample_rate);
+            }
+        }
+        let pitch_text = match pitch {
+            Some(hz) => format!("{:.0} Hz", hz),
+            None => "-".to_string(),
+        };
+
         // draw UI
         terminal.draw(|f| {
             let size = f.size();
@@ -69,40 +297,67 @@ fn main() -> Result<(), io::Error> {
 
             // Left: status / logs
             let status_block = Block::default().title("Status").borders(Borders::ALL);
-            let status = status_text.lock().unwrap().clone();
+            let threshold = detector.lock().unwrap().threshold();
+            let status = format!(
+                "{}\n\nSource: {}\nPitch: {}\nThreshold: {} (up/down to adjust)\nPress r to reset the peak{}",
+                status_text.lock().unwrap(),
+                replay.source.lock().unwrap(),
+                pitch_text,
+                format_confidence(threshold, confidence_precision()),
+                replay_hint
+            );
             let paragraph = Paragraph::new(status).block(status_block);
             f.render_widget(paragraph, cols[0]);
 
             // Right: sound level gauge
             let level_block = Block::default().title("Sound Level").borders(Borders::ALL);
+            let gauge_area = level_block.inner(cols[1]);
             let rms = *current_rms.lock().unwrap();
+            let peak = peak_rms.lock().unwrap().level;
             let value = rms;
             let percent = (value.clamp(0.0, 1.0) * 100.0) as u16;
-            let label = format!("{:.2}", value);
+            let label = format!("{:.2} (peak {:.2})", value, peak);
             let gauge = Gauge::default()
                 .block(level_block)
                 .gauge_style(Style::default().fg(Color::Green))
                 .percent(percent)
                 .label(label);
             f.render_widget(gauge, cols[1]);
+
+            // Peak marker: a bar across the gauge at the peak level
+            if gauge_area.width > 0 {
+                let offset = (peak.clamp(0.0, 1.0) * (gauge_area.width - 1) as f32) as u16;
+                let marker_area = Rect {
+                    x: gauge_area.x + offset,
+                    width: 1,
+                    ..gauge_area
+                };
+                let marker = Paragraph::new(vec!["│".into(); gauge_area.height as usize])
+                    .style(Style::default().fg(Color::Red));
+                f.render_widget(marker, marker_area);
+            }
         })?;
 
         // Wake word detection logic
         if last_detection.elapsed() > Duration::from_millis(500) {
             let mut buffer = audio_buffer.lock().unwrap();
-            if !buffer.is_empty() {
-                let audio_data = buffer.clone();
-                buffer.clear();
-
+            let audio_data = std::mem::take(&mut *buffer);
+            if trained && !audio_data.is_empty() {
                 let mut detector = detector.lock().unwrap();
                 let mut status = status_text.lock().unwrap();
 
-                match detector.detect(&audio_data) {
+                match detector.process(&audio_data) {
                     Ok((detected, similarity)) => {
                         if detected {
-                            *status = format!("Wake Word DETECTED! (Similarity: {:.2})", similarity);
+                            *status = format!(
+                                "Wake Word DETECTED! (Similarity: {})",
+                                format_confidence(similarity, confidence_precision())
+                            );
                         } else {
-                            *status = format!("Listening... (Similarity: {:.2})", similarity);
+                            *status = format!(
+                                "Listening... (Similarity: {})",
+                                format_confidence(similarity, confidence_precision())
+                            );
                         }
                     }
                     Err(e) => {
@@ -121,6 +376,13 @@ fn main() -> Result<(), io::Error> {
                     if key.code == KeyCode::Char('q') {
                         break;
                     }
+                    adjust_threshold(&detector, key.code);
+                    if key.code == KeyCode::Char('r') {
+                        peak_rms.lock().unwrap().reset();
+                    }
+                    if key.code == KeyCode::Char('p') {
+                        replay.start();
+                    }
                     if key.code == KeyCode::Char('d') {
                         let mut s = status_text.lock().unwrap();
                         *s = "Wake word candidate detected!".to_string();
@@ -142,6 +404,13 @@ fn main() -> Result<(), io::Error> {
                 if key.code == KeyCode::Char('q') {
                     break;
                 }
+                adjust_threshold(&detector, key.code);
+                if key.code == KeyCode::Char('r') {
+                    peak_rms.lock().unwrap().reset();
+                }
+                if key.code == KeyCode::Char('p') {
+                    replay.start();
+                }
             }
         }
     }
@@ -149,188 +418,109 @@ fn main() -> Result<(), io::Error> {
     disable_raw_mode()
 }
 
+/// Raise or lower the detection threshold on the up/down arrow keys
+fn adjust_threshold(detector: &Mutex<WakeWordDetector>, key: KeyCode) {
+    let delta = match key {
+        KeyCode::Up => THRESHOLD_STEP,
+        KeyCode::Down => -THRESHOLD_STEP,
+        _ => return,
+    };
+    let mut detector = detector.lock().unwrap();
+    let threshold = detector.threshold() + delta;
+    detector.set_thresholds(threshold, threshold - RELEASE_GAP);
+}
+
 fn start_audio_stream(
     current_rms: Arc<Mutex<f32>>,
-    peak_rms: Arc<Mutex<f32>>,
+    peak_rms: Arc<Mutex<PeakMeter>>,
     audio_buffer: Arc<Mutex<Vec<f32>>>,
+    live: Arc<AtomicBool>,
+    device_name: Option<&str>,
+    buffer_frames: Option<u32>,
 ) -> Result<cpal::Stream, anyhow::Error> {
     let host = cpal::default_host();
-    let device = host
-        .default_input_device()
-        .ok_or_else(|| anyhow::anyhow!("No input device available"))?;
-    let config = device.default_input_config()?;
+    let device = find_input_device(&host, device_name)?;
+    let config = input_config(&device)?;
+    let stream_config = stream_config(&config, buffer_frames);
+    // The detector gets audio at its own rate, like a replay
+    let resampler = StreamResampler::new(config.sample_rate().0, MfccConfig::default().sample_rate);
     // Create the stream according to sample format and return it; caller will keep it alive
     let stream = match config.sample_format() {
-        cpal::SampleFormat::F32 => {
-            build_input_stream_f32(&device, &config.into(), current_rms, peak_rms, audio_buffer)?
-        }
-        cpal::SampleFormat::I16 => {
-            build_input_stream_i16(&device, &config.into(), current_rms, peak_rms, audio_buffer)?
-        }
-        cpal::SampleFormat::U16 => {
-            build_input_stream_u16(&device, &config.into(), current_rms, peak_rms, audio_buffer)?
-        }
-        _ => build_input_stream_f32(&device, &config.into(), current_rms, peak_rms, audio_buffer)?,
+        cpal::SampleFormat::F32 => build_input_stream::<f32>(
+            &device,
+            &stream_config,
+            current_rms,
+            peak_rms,
+            audio_buffer,
+            resampler,
+            live,
+        )?,
+        cpal::SampleFormat::I16 => build_input_stream::<i16>(
+            &device,
+            &stream_config,
+            current_rms,
+            peak_rms,
+            audio_buffer,
+            resampler,
+            live,
+        )?,
+        cpal::SampleFormat::U16 => build_input_stream::<u16>(
+            &device,
+            &stream_config,
+            current_rms,
+            peak_rms,
+            audio_buffer,
+            resampler,
+            live,
+        )?,
+        _ => build_input_stream::<f32>(
+            &device,
+            &stream_config,
+            current_rms,
+            peak_rms,
+            audio_buffer,
+            resampler,
+            live,
+        )?,
     };
 
     stream.play()?;
 
     Ok(stream)
 }
-fn build_input_stream_f32(
-    device: &cpal::Device,
-    config: &cpal::StreamConfig,
-    current_rms: Arc<Mutex<f32>>,
-    peak_rms: Arc<Mutex<f32>>,
-    audio_buffer: Arc<Mutex<Vec<f32>>>,
-) -> Result<cpal::Stream, anyhow::Error> {
-    let err_fn = |err| eprintln!("Audio stream error: {}", err);
-    let channels = config.channels as usize;
-    let stream = device.build_input_stream(
-        config,
-        move |data: &[f32], _| {
-            // Append to buffer for wake word detection
-            if let Ok(mut buffer) = audio_buffer.lock() {
-                buffer.extend_from_slice(data);
-                // Optional: limit buffer size to avoid memory issues
-                const MAX_BUFFER_SAMPLES: usize = 16000 * 2; // 2 seconds
-                if buffer.len() > MAX_BUFFER_SAMPLES {
-                    buffer.drain(0..buffer.len() - MAX_BUFFER_SAMPLES);
-                }
-            }
-
-            let mut sum = 0f32;
-            let mut count = 0usize;
-            for frame in data.chunks(channels) {
-                if let Some(&s) = frame.get(0) {
-                    sum += s * s;
-                    count += 1;
-                }
-            }
-            if count > 0 {
-                let rms = (sum / count as f32).sqrt();
-                {
-                    let mut cur = current_rms.lock().unwrap();
-                    *cur = rms;
-                }
-                {
-                    let mut peak = peak_rms.lock().unwrap();
-                    if rms > *peak {
-                        *peak = rms;
-                    } else {
-                        *peak *= 0.95;
-                    }
-                }
-            }
-        },
-        err_fn,
-        None,
-    )?;
-    Ok(stream)
-}
-
-fn build_input_stream_i16(
+/// Build an input stream for samples of type `T`, feeding the buffer and meters with mono audio
+fn build_input_stream<T: SizedSample + ToF32>(
     device: &cpal::Device,
     config: &cpal::StreamConfig,
     current_rms: Arc<Mutex<f32>>,
-    peak_rms: Arc<Mutex<f32>>,
+    peak_rms: Arc<Mutex<PeakMeter>>,
     audio_buffer: Arc<Mutex<Vec<f32>>>,
+    mut resampler: StreamResampler,
+    live: Arc<AtomicBool>,
 ) -> Result<cpal::Stream, anyhow::Error> {
     let err_fn = |err| eprintln!("Audio stream error: {}", err);
     let channels = config.channels as usize;
     let stream = device.build_input_stream(
         config,
-        move |data: &[i16], _| {
-            // Convert and append to buffer
-            let f32_data: Vec<f32> = data.iter().map(|&s| s as f32 / i16::MAX as f32).collect();
-            if let Ok(mut buffer) = audio_buffer.lock() {
-                buffer.extend_from_slice(&f32_data);
-                const MAX_BUFFER_SAMPLES: usize = 16000 * 2; // 2 seconds
-                if buffer.len() > MAX_BUFFER_SAMPLES {
-                    buffer.drain(0..buffer.len() - MAX_BUFFER_SAMPLES);
-                }
-            }
-
-            let mut sum = 0f32;
-            let mut count = 0usize;
-            for frame in data.chunks(channels) {
-                if let Some(&s) = frame.get(0) {
-                    let f = s as f32 / i16::MAX as f32;
-                    sum += f * f;
-                    count += 1;
-                }
+        move |data: &[T], _| {
+            // A replay is feeding the buffer and meters instead
+            if !live.load(Ordering::Relaxed) {
+                return;
             }
-            if count > 0 {
-                let rms = (sum / count as f32).sqrt();
-                {
-                    let mut cur = current_rms.lock().unwrap();
-                    *cur = rms;
-                }
-                {
-                    let mut peak = peak_rms.lock().unwrap();
-                    if rms > *peak {
-                        *peak = rms;
-                    } else {
-                        *peak *= 0.95;
-                    }
-                }
-            }
-        },
-        err_fn,
-        None,
-    )?;
-    Ok(stream)
-}
-
-fn build_input_stream_u16(
-    device: &cpal::Device,
-    config: &cpal::StreamConfig,
-    current_rms: Arc<Mutex<f32>>,
-    peak_rms: Arc<Mutex<f32>>,
-    audio_buffer: Arc<Mutex<Vec<f32>>>,
-) -> Result<cpal::Stream, anyhow::Error> {
-    let err_fn = |err| eprintln!("Audio stream error: {}", err);
-    let channels = config.channels as usize;
-    let stream = device.build_input_stream(
-        config,
-        move |data: &[u16], _| {
-            // Convert and append to buffer
-            let f32_data: Vec<f32> = data
-                .iter()
-                .map(|&s| (s as f32 / u16::MAX as f32) * 2.0 - 1.0)
-                .collect();
+            let samples: Vec<f32> = data.iter().map(|&s| s.to_f32()).collect();
+            let mono = to_mono(&samples, channels);
+            // Append to buffer for wake word detection
             if let Ok(mut buffer) = audio_buffer.lock() {
-                buffer.extend_from_slice(&f32_data);
-                const MAX_BUFFER_SAMPLES: usize = 16000 * 2; // 2 seconds
-                if buffer.len() > MAX_BUFFER_SAMPLES {
-                    buffer.drain(0..buffer.len() - MAX_BUFFER_SAMPLES);
-                }
+                append_capped(&mut buffer, &resampler.process(&mono));
             }
 
-            let mut sum = 0f32;
-            let mut count = 0usize;
-            for frame in data.chunks(channels) {
-                if let Some(&s) = frame.get(0) {
-                    // u16 is 0..65535, convert to -1.0..1.0
-                    let f = (s as f32 / u16::MAX as f32) * 2.0 - 1.0;
-                    sum += f * f;
-                    count += 1;
-                }
-            }
-            if count > 0 {
-                let rms = (sum / count as f32).sqrt();
+            if !mono.is_empty() {
+                let rms = (mono.iter().map(|s| s * s).sum::<f32>() / mono.len() as f32).sqrt();
                 {
                     let mut cur = current_rms.lock().unwrap();
                     *cur = rms;
                 }
-                {
-                    let mut peak = peak_rms.lock().unwrap();
-                    if rms > *peak {
-                        *peak = rms;
-                    } else {
-                        *peak *= 0.95;
-                    }
-                }
+                peak_rms.lock().unwrap().update(rms);
             }
         },
         err_fn,
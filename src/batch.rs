@@ -0,0 +1,93 @@
+//! Resumable batch transcription
+//!
+//! A batch run records each file it finishes in a manifest, so an
+//! interrupted run can pick up where it stopped instead of starting over.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Default manifest file name, in the current directory
+pub const DEFAULT_MANIFEST: &str = "batch-manifest.json";
+
+/// Files a batch run has finished, with where their transcripts went
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BatchManifest {
+    completed: BTreeMap<PathBuf, PathBuf>, // Input file -> transcript file
+}
+
+impl BatchManifest {
+    /// Load a manifest, or start an empty one if `path` doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&json).with_context(|| format!("Invalid manifest {}", path.display()))
+    }
+
+    /// Write the manifest atomically
+    ///
+    /// The JSON goes to a temporary file that is then renamed over `path`, so
+    /// a crash mid-write leaves the previous manifest intact.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+    }
+
+    /// Whether `input` was transcribed and its transcript is still there
+    pub fn is_done(&self, input: &Path) -> bool {
+        self.completed
+            .get(input)
+            .is_some_and(|output| output.exists())
+    }
+
+    /// Record that `input` was transcribed to `output`
+    pub fn record(&mut self, input: &Path, output: &Path) {
+        self.completed
+            .insert(input.to_path_buf(), output.to_path_buf());
+    }
+}
+
+/// Where the transcript of `input` is written: next to it, with a `.txt` extension
+pub fn transcript_path(input: &Path) -> PathBuf {
+    input.with_extension("txt")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_resumes_completed_files() {
+        let dir = std::env::temp_dir().join(format!("batch-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let manifest_path = dir.join(DEFAULT_MANIFEST);
+        let (first, second) = (dir.join("a.wav"), dir.join("b.wav"));
+
+        let mut manifest = BatchManifest::load(&manifest_path).unwrap();
+        assert!(!manifest.is_done(&first));
+        fs::write(transcript_path(&first), "hello").unwrap();
+        manifest.record(&first, &transcript_path(&first));
+        manifest.save(&manifest_path).unwrap();
+
+        // A re-run skips the finished file only
+        let reloaded = BatchManifest::load(&manifest_path).unwrap();
+        assert_eq!(reloaded, manifest);
+        assert!(reloaded.is_done(&first));
+        assert!(!reloaded.is_done(&second));
+        assert!(!dir.join(format!("{}.tmp", DEFAULT_MANIFEST)).exists());
+
+        // A deleted transcript means the file is redone
+        fs::remove_file(transcript_path(&first)).unwrap();
+        assert!(!reloaded.is_done(&first));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
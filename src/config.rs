@@ -0,0 +1,75 @@
+//! Loading of `.env` configuration files.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Environment variable naming the `.env` file to load
+pub const DOTENV_PATH_VAR: &str = "DOTENV_PATH";
+
+/// Find the value of `--env-file <path>` or `--env-file=<path>` in raw arguments
+///
+/// The env file has to be loaded before clap parses the arguments, because
+/// clap reads env-backed defaults at parse time, so it is picked out early.
+pub fn env_file_arg<I: IntoIterator<Item = String>>(args: I) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--env-file" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--env-file=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// Load environment variables from a `.env` file
+///
+/// Uses `path` if given, otherwise `DOTENV_PATH`, otherwise `./.env`.
+/// Warns when an explicitly requested file doesn't exist instead of silently
+/// carrying on without it.
+pub fn load_env_file(path: Option<&Path>) {
+    let path = path
+        .map(Path::to_path_buf)
+        .or_else(|| env::var_os(DOTENV_PATH_VAR).map(PathBuf::from));
+
+    match path {
+        Some(path) => {
+            if !path.exists() {
+                eprintln!("Warning: env file {} not found", path.display());
+            } else if let Err(e) = dotenv::from_path(&path) {
+                eprintln!("Warning: failed to load env file {}: {}", path.display(), e);
+            }
+        }
+        None => {
+            dotenv::dotenv().ok();
+        }
+    }
+}
+
+/// Load the `.env` file named by `--env-file` in the process arguments
+pub fn load_env_from_args() {
+    load_env_file(env_file_arg(env::args()).as_deref());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_env_file_arg() {
+        assert_eq!(
+            env_file_arg(args(&["cli", "--env-file", "/etc/app.env"])),
+            Some(PathBuf::from("/etc/app.env"))
+        );
+        assert_eq!(
+            env_file_arg(args(&["cli", "--quiet", "--env-file=prod.env"])),
+            Some(PathBuf::from("prod.env"))
+        );
+        assert_eq!(env_file_arg(args(&["cli", "--quiet"])), None);
+    }
+}
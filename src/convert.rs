@@ -0,0 +1,132 @@
+//! Sample format and channel conversions
+//!
+//! One definition of each, matching cpal's: integer samples are scaled by
+//! 32768, so `i16::MIN` is exactly -1.0 and `i16::MAX` just under 1.0, and
+//! u16 samples are offset binary, with 32768 as silence. Use these rather
+//! than converting by hand, so every device format ends up on one scale.
+
+/// An i16 sample as f32 (-1.0..1.0)
+pub fn i16_to_f32(sample: i16) -> f32 {
+    sample as f32 / 32768.0
+}
+
+/// An f32 sample as i16, rounded to the nearest step and clipped to range
+pub fn f32_to_i16(sample: f32) -> i16 {
+    f32_to_int(sample, 16) as i16
+}
+
+/// An f32 sample as i16, with the fraction dropped (towards zero) instead of rounded
+pub fn f32_to_i16_truncated(sample: f32) -> i16 {
+    (sample * 32768.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+/// An f32 sample as a `bits`-bit integer (16, 24 or 32), rounded and clipped
+///
+/// On the same scale as `f32_to_i16`, so an i16 sample shifted up to the
+/// wider depth is the same value.
+pub fn f32_to_int(sample: f32, bits: u16) -> i32 {
+    // f64 keeps 32-bit scaling exact
+    let scale = (1i64 << (bits - 1)) as f64;
+    (sample as f64 * scale).round().clamp(-scale, scale - 1.0) as i32
+}
+
+/// A u16 sample as i16: the same level, moved from offset binary to signed
+pub fn u16_to_i16(sample: u16) -> i16 {
+    (sample as i32 - 32768) as i16
+}
+
+/// A u16 sample as f32 (-1.0..1.0)
+pub fn u16_to_f32(sample: u16) -> f32 {
+    i16_to_f32(u16_to_i16(sample))
+}
+
+/// A device sample type that converts to f32
+pub trait ToF32: Copy {
+    fn to_f32(self) -> f32;
+}
+
+impl ToF32 for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+
+impl ToF32 for i16 {
+    fn to_f32(self) -> f32 {
+        i16_to_f32(self)
+    }
+}
+
+impl ToF32 for u16 {
+    fn to_f32(self) -> f32 {
+        u16_to_f32(self)
+    }
+}
+
+/// Average interleaved `channels`-channel samples into mono
+pub fn to_mono(interleaved: &[f32], channels: usize) -> Vec<f32> {
+    let channels = channels.max(1);
+    interleaved
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect()
+}
+
+/// Split interleaved `channels`-channel samples into one track per channel
+pub fn deinterleave(interleaved: &[f32], channels: usize) -> Vec<Vec<f32>> {
+    let channels = channels.max(1);
+    let mut tracks = vec![Vec::with_capacity(interleaved.len() / channels); channels];
+    for frame in interleaved.chunks(channels) {
+        for (track, &sample) in tracks.iter_mut().zip(frame) {
+            track.push(sample);
+        }
+    }
+    tracks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::Sample;
+
+    #[test]
+    fn test_i16_round_trip() {
+        for sample in i16::MIN..=i16::MAX {
+            assert_eq!(f32_to_i16(i16_to_f32(sample)), sample);
+            assert_eq!(i16_to_f32(sample), sample.to_sample::<f32>());
+        }
+        assert_eq!(i16_to_f32(i16::MIN), -1.0);
+        assert_eq!(f32_to_i16(1.5), i16::MAX);
+        assert_eq!(f32_to_i16(-1.5), i16::MIN);
+        assert_eq!(f32_to_i16_truncated(-1.5), i16::MIN);
+        assert_eq!(f32_to_i16_truncated(2.9 / 32768.0), 2);
+        assert_eq!(f32_to_i16_truncated(-2.9 / 32768.0), -2);
+    }
+
+    #[test]
+    fn test_u16_conversions_agree() {
+        for sample in 0..=u16::MAX {
+            // Recording went via i16 while the TUI scaled 0..65535 onto
+            // -1.0..1.0; that was off by up to a step, and 32768 wasn't silence
+            let via_i16 = i16_to_f32(u16_to_i16(sample));
+            assert_eq!(u16_to_f32(sample), via_i16);
+            assert_eq!(sample.to_f32(), sample.to_sample::<f32>());
+            let old_tui = (sample as f32 / u16::MAX as f32) * 2.0 - 1.0;
+            assert!((old_tui - via_i16).abs() < 1.5 / 32768.0);
+        }
+        assert_eq!(u16_to_f32(32768), 0.0);
+        assert_eq!(u16_to_f32(0), -1.0);
+        assert_eq!(u16_to_i16(u16::MAX), i16::MAX);
+    }
+
+    #[test]
+    fn test_channel_conversions() {
+        let interleaved = [0.5, 0.0, -0.5, -0.5, 1.0, 0.0];
+        assert_eq!(to_mono(&interleaved, 2), vec![0.25, -0.5, 0.5]);
+        assert_eq!(to_mono(&interleaved, 1), interleaved.to_vec());
+        assert_eq!(
+            deinterleave(&interleaved, 2),
+            vec![vec![0.5, -0.5, 1.0], vec![0.0, -0.5, 0.0]]
+        );
+    }
+}
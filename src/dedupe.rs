@@ -0,0 +1,94 @@
+//! Suppress repeated transcripts
+//!
+//! With overlapping clips, or a wake word firing again on the same
+//! utterance, the continuous modes can transcribe the same words twice in a
+//! row. `DuplicateFilter` remembers the last transcript shown and flags a
+//! result as a duplicate when it (nearly) matches it within a short window.
+
+use crate::pipeline::similarity;
+use std::time::{Duration, Instant};
+
+/// How long after a transcript an identical one counts as a repeat
+pub const DEFAULT_DUPLICATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Similarity (0.0-1.0) at which two transcripts count as the same
+pub const DUPLICATE_THRESHOLD: f32 = 0.9;
+
+/// Remembers the last transcript to spot immediate repeats
+pub struct DuplicateFilter {
+    window: Duration,
+    last: Option<(String, Instant)>, // Normalized text and when it was shown
+}
+
+impl DuplicateFilter {
+    /// A zero `window` never suppresses anything
+    pub fn new(window: Duration) -> Self {
+        Self { window, last: None }
+    }
+
+    /// Whether `text` repeats the previous transcript, recording it if not
+    pub fn is_duplicate(&mut self, text: &str) -> bool {
+        self.is_duplicate_at(text, Instant::now())
+    }
+
+    /// `is_duplicate` for a transcript that arrived at `now`
+    ///
+    /// Case, punctuation and spacing are ignored, and a few character edits
+    /// are tolerated, so "Turn on the light." repeats "turn on the lights".
+    /// A duplicate doesn't restart the window, so a phrase that keeps
+    /// coming back is shown again once the window has passed.
+    pub fn is_duplicate_at(&mut self, text: &str, now: Instant) -> bool {
+        let text = normalize(text);
+        let duplicate = self.last.as_ref().is_some_and(|(last, at)| {
+            now.saturating_duration_since(*at) < self.window
+                && similarity(last, &text) >= DUPLICATE_THRESHOLD
+        });
+        if !duplicate {
+            self.last = Some((text, now));
+        }
+        duplicate
+    }
+}
+
+/// Lowercase words without punctuation, single-spaced
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consecutive_duplicates_are_suppressed() {
+        let mut filter = DuplicateFilter::new(DEFAULT_DUPLICATE_WINDOW);
+        let start = Instant::now();
+        assert!(!filter.is_duplicate_at("Turn on the lights.", start));
+        assert!(filter.is_duplicate_at(" turn on the lights", start + Duration::from_secs(1)));
+        assert!(filter.is_duplicate_at("Turn on the light.", start + Duration::from_secs(2)));
+
+        // Something else is shown, and becomes the one to compare with
+        assert!(!filter.is_duplicate_at("What time is it?", start + Duration::from_secs(3)));
+        assert!(!filter.is_duplicate_at("Turn on the lights.", start + Duration::from_secs(4)));
+
+        // Outside the window a repeat is shown again
+        assert!(!filter.is_duplicate_at("Turn on the lights.", start + Duration::from_secs(15)));
+    }
+
+    #[test]
+    fn test_zero_window_never_suppresses() {
+        let mut filter = DuplicateFilter::new(Duration::ZERO);
+        let now = Instant::now();
+        assert!(!filter.is_duplicate_at("Hello.", now));
+        assert!(!filter.is_duplicate_at("Hello.", now));
+    }
+}
@@ -0,0 +1,233 @@
+//! Spectral-subtraction noise reduction.
+//!
+//! Steady background noise (fans, hum, room tone) is described by its
+//! average magnitude spectrum, the noise profile. Denoising subtracts that
+//! spectrum from each short frame of the audio, keeping the phase, and
+//! overlap-adds the frames back together.
+//!
+//! A profile recorded from the room itself (`--record-noise-profile`) works
+//! best. Without one, the first 300 ms of each clip are assumed to be noise,
+//! which fails when speech starts straight away.
+
+use anyhow::{Context, Result};
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+
+/// Seconds of background noise recorded for a profile
+pub const PROFILE_RECORD_SECS: u64 = 3;
+
+/// Samples per analysis frame
+const FRAME_SIZE: usize = 512;
+
+/// Step between frames (50% overlap)
+const HOP_SIZE: usize = FRAME_SIZE / 2;
+
+/// Leading audio treated as noise when there is no recorded profile
+const ESTIMATE_MS: usize = 300;
+
+/// How much of the noise spectrum is subtracted (above 1 removes more, with more artifacts)
+const OVER_SUBTRACTION: f32 = 2.0;
+
+/// Fraction of each bin's magnitude always kept, so bins never go silent
+/// (fully emptied bins are what makes "musical noise")
+const SPECTRAL_FLOOR: f32 = 0.05;
+
+/// Average magnitude spectrum of background noise
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NoiseProfile {
+    pub sample_rate: u32,
+    pub frame_size: usize,
+    pub magnitudes: Vec<f32>, // One per frequency bin, DC to Nyquist
+}
+
+impl NoiseProfile {
+    /// Build a profile from a recording of background noise only
+    pub fn from_samples(samples: &[f32], sample_rate: u32) -> Result<Self> {
+        if samples.len() < FRAME_SIZE {
+            anyhow::bail!(
+                "Need at least {} samples of noise, got {}",
+                FRAME_SIZE,
+                samples.len()
+            );
+        }
+        let fft = FftPlanner::new().plan_fft_forward(FRAME_SIZE);
+        let window = sqrt_hann(FRAME_SIZE);
+        let mut magnitudes = vec![0.0; FRAME_SIZE / 2 + 1];
+        let mut frames = 0;
+        for start in (0..=samples.len() - FRAME_SIZE).step_by(HOP_SIZE) {
+            let mut buffer: Vec<Complex<f32>> = samples[start..start + FRAME_SIZE]
+                .iter()
+                .zip(&window)
+                .map(|(&s, &w)| Complex::new(s * w, 0.0))
+                .collect();
+            fft.process(&mut buffer);
+            for (sum, bin) in magnitudes.iter_mut().zip(&buffer) {
+                *sum += bin.norm();
+            }
+            frames += 1;
+        }
+        for magnitude in &mut magnitudes {
+            *magnitude /= frames as f32;
+        }
+        Ok(Self {
+            sample_rate,
+            frame_size: FRAME_SIZE,
+            magnitudes,
+        })
+    }
+
+    /// Estimate a profile from the first 300 ms of a clip
+    pub fn estimate(samples: &[f32], sample_rate: u32) -> Result<Self> {
+        let len = (sample_rate as usize * ESTIMATE_MS / 1000).max(FRAME_SIZE);
+        Self::from_samples(&samples[..len.min(samples.len())], sample_rate)
+    }
+
+    /// Save the profile as JSON
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string(self)?;
+        fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Load a profile written by `save`
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let profile: Self = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid noise profile {}", path.display()))?;
+        if profile.frame_size != FRAME_SIZE || profile.magnitudes.len() != FRAME_SIZE / 2 + 1 {
+            anyhow::bail!(
+                "Noise profile {} has frame size {}, expected {}",
+                path.display(),
+                profile.frame_size,
+                FRAME_SIZE
+            );
+        }
+        Ok(profile)
+    }
+}
+
+/// Remove the noise described by `profile` from `samples`
+///
+/// The output has the same length as the input. The profile must have been
+/// recorded at the same sample rate, since its bins are frequencies at that
+/// rate.
+pub fn denoise(samples: &[f32], sample_rate: u32, profile: &NoiseProfile) -> Result<Vec<f32>> {
+    if profile.sample_rate != sample_rate {
+        anyhow::bail!(
+            "Noise profile was recorded at {} Hz but the audio is {} Hz",
+            profile.sample_rate,
+            sample_rate
+        );
+    }
+
+    let mut planner = FftPlanner::new();
+    let forward = planner.plan_fft_forward(FRAME_SIZE);
+    let inverse = planner.plan_fft_inverse(FRAME_SIZE);
+    let window = sqrt_hann(FRAME_SIZE);
+
+    // Pad by a hop on each side so every sample is covered by two frames,
+    // whose windows then sum to exactly one
+    let mut padded = vec![0.0; HOP_SIZE];
+    padded.extend_from_slice(samples);
+    padded.resize(padded.len() + FRAME_SIZE, 0.0);
+    let mut output = vec![0.0; padded.len()];
+
+    for start in (0..=padded.len() - FRAME_SIZE).step_by(HOP_SIZE) {
+        let mut buffer: Vec<Complex<f32>> = padded[start..start + FRAME_SIZE]
+            .iter()
+            .zip(&window)
+            .map(|(&s, &w)| Complex::new(s * w, 0.0))
+            .collect();
+        forward.process(&mut buffer);
+
+        for (k, bin) in buffer.iter_mut().enumerate() {
+            // Bins above Nyquist mirror those below
+            let noise = profile.magnitudes[k.min(FRAME_SIZE - k)];
+            let magnitude = bin.norm();
+            if magnitude > 0.0 {
+                let cleaned =
+                    (magnitude - OVER_SUBTRACTION * noise).max(SPECTRAL_FLOOR * magnitude);
+                *bin *= cleaned / magnitude;
+            }
+        }
+
+        inverse.process(&mut buffer);
+        for ((out, bin), &w) in output[start..start + FRAME_SIZE]
+            .iter_mut()
+            .zip(&buffer)
+            .zip(&window)
+        {
+            *out += bin.re / FRAME_SIZE as f32 * w;
+        }
+    }
+
+    Ok(output[HOP_SIZE..HOP_SIZE + samples.len()].to_vec())
+}
+
+/// Square root of a periodic Hann window
+///
+/// Used for both analysis and synthesis, so each frame is weighted by a full
+/// Hann window, and Hann windows at 50% overlap add up to one.
+fn sqrt_hann(len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| (0.5 - 0.5 * (2.0 * PI * i as f32 / len as f32).cos()).sqrt())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic white noise in -amplitude..amplitude
+    fn noise(len: usize, seed: u32, amplitude: f32) -> Vec<f32> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                ((state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0) * amplitude
+            })
+            .collect()
+    }
+
+    fn rms_error(a: &[f32], b: &[f32]) -> f32 {
+        let sum: f32 = a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum();
+        (sum / a.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_denoise_removes_profiled_noise() {
+        let tone: Vec<f32> = (0..16000)
+            .map(|i| (2.0 * PI * 440.0 * i as f32 / 16000.0).sin() * 0.5)
+            .collect();
+        let noisy: Vec<f32> = tone
+            .iter()
+            .zip(noise(16000, 1, 0.1))
+            .map(|(s, n)| s + n)
+            .collect();
+
+        // The profile comes from a separate stretch of the same room noise
+        let profile = NoiseProfile::from_samples(&noise(48000, 2, 0.1), 16000).unwrap();
+        let cleaned = denoise(&noisy, 16000, &profile).unwrap();
+        assert_eq!(cleaned.len(), noisy.len());
+        assert!(rms_error(&cleaned, &tone) < rms_error(&noisy, &tone) / 2.0);
+
+        assert!(denoise(&noisy, 48000, &profile).is_err());
+    }
+
+    #[test]
+    fn test_silent_profile_leaves_audio_intact() {
+        let audio = noise(5000, 3, 0.5);
+        let profile = NoiseProfile::from_samples(&[0.0; 4096], 16000).unwrap();
+        let cleaned = denoise(&audio, 16000, &profile).unwrap();
+        assert!(rms_error(&cleaned, &audio) < 1e-4);
+
+        let path = std::env::temp_dir().join(format!("noise-test-{}.json", std::process::id()));
+        profile.save(&path).unwrap();
+        let loaded = NoiseProfile::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(loaded, profile);
+    }
+}
@@ -0,0 +1,509 @@
+//! Audio input device selection.
+//!
+//! Besides microphones, the input can be the computer's own playback
+//! ("loopback"), for transcribing video calls or podcasts. Platform support:
+//!
+//! - Windows: WASAPI loopback. `--loopback` records from an output device
+//!   (the default one, or the one named with `--device`).
+//! - Linux: PulseAudio/PipeWire expose playback as "Monitor of ..." sources.
+//!   `--loopback` picks a monitor source if the audio backend lists one;
+//!   otherwise set `PULSE_SOURCE=<sink>.monitor` and use `--device pulse`.
+//! - macOS: no built-in loopback. Install a virtual device such as BlackHole
+//!   and select it with `--device`.
+//!
+//! `DeviceInfo` reports what a device supports, for picking a `--device`
+//! and `--buffer-size` that will work.
+
+use anyhow::{Context, Result};
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{BufferSize, SampleFormat, SampleRate, SupportedBufferSize, SupportedStreamConfigRange};
+use serde::Serialize;
+use std::env;
+
+/// Environment variable naming the input device (any part of its name)
+pub const DEVICE_VAR: &str = "AUDIO_DEVICE";
+
+/// Environment variable holding the stream buffer size hint, in frames
+pub const BUFFER_SIZE_VAR: &str = "AUDIO_BUFFER_SIZE";
+
+/// Sample rates tried, best first, when picking a config by hand
+const PREFERRED_RATES: [u32; 3] = [48000, 44100, 16000];
+
+/// Find the index of the device matching `query` in a list of device names
+///
+/// An exact (case-insensitive) match wins; otherwise the first name that
+/// contains `query` is used, so `--device usb` finds "USB Audio Device".
+pub fn match_device_name(names: &[String], query: &str) -> Option<usize> {
+    let query = query.to_lowercase();
+    names
+        .iter()
+        .position(|name| name.to_lowercase() == query)
+        .or_else(|| {
+            names
+                .iter()
+                .position(|name| name.to_lowercase().contains(&query))
+        })
+}
+
+/// Whether a device name looks like a PulseAudio/PipeWire monitor source
+pub fn is_monitor_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.starts_with("monitor of") || name.ends_with(".monitor")
+}
+
+/// Pick the device named by `query` from `devices`, or explain what exists
+fn find_by_name(devices: Vec<cpal::Device>, query: &str, kind: &str) -> Result<cpal::Device> {
+    let names: Vec<String> = devices
+        .iter()
+        .map(|d| d.name().unwrap_or_default())
+        .collect();
+
+    match match_device_name(&names, query) {
+        Some(index) => Ok(devices.into_iter().nth(index).unwrap()),
+        None => Err(anyhow::anyhow!(
+            "No {} device matching \"{}\". Available: {}",
+            kind,
+            query,
+            names.join(", ")
+        )),
+    }
+}
+
+/// The input device matching `name` (see `match_device_name`), or the default one
+///
+/// A name that matches nothing is an error listing the devices there are,
+/// rather than a silent fallback to the default.
+pub fn find_input_device(host: &cpal::Host, name: Option<&str>) -> Result<cpal::Device> {
+    match name {
+        Some(name) => find_by_name(host.input_devices()?.collect(), name, "input"),
+        None => host
+            .default_input_device()
+            .context("No input device available"),
+    }
+}
+
+/// Names of the input devices, each with whether it is the default
+pub fn list_input_devices(host: &cpal::Host) -> Result<Vec<(String, bool)>> {
+    let default = host.default_input_device().and_then(|d| d.name().ok());
+    host.input_devices()?
+        .map(|device| {
+            let name = device.name()?;
+            let is_default = default.as_deref() == Some(name.as_str());
+            Ok((name, is_default))
+        })
+        .collect()
+}
+
+/// One kind of input stream a device can open
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigInfo {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32, // Same as the minimum for a single config
+    pub sample_format: String,
+    pub min_buffer_frames: Option<u32>, // None if the driver doesn't say
+    pub max_buffer_frames: Option<u32>,
+}
+
+impl ConfigInfo {
+    fn new(
+        channels: u16,
+        (min_sample_rate, max_sample_rate): (u32, u32),
+        sample_format: SampleFormat,
+        buffer_size: &SupportedBufferSize,
+    ) -> Self {
+        let (min_buffer_frames, max_buffer_frames) = match buffer_size {
+            SupportedBufferSize::Range { min, max } => (Some(*min), Some(*max)),
+            SupportedBufferSize::Unknown => (None, None),
+        };
+        Self {
+            channels,
+            min_sample_rate,
+            max_sample_rate,
+            sample_format: sample_format.to_string(),
+            min_buffer_frames,
+            max_buffer_frames,
+        }
+    }
+
+    /// One line, e.g. `2 ch, 44100-48000 Hz, f32, buffer 64-4096 frames`
+    pub fn summary(&self) -> String {
+        let rates = if self.min_sample_rate == self.max_sample_rate {
+            self.min_sample_rate.to_string()
+        } else {
+            format!("{}-{}", self.min_sample_rate, self.max_sample_rate)
+        };
+        let buffer = match (self.min_buffer_frames, self.max_buffer_frames) {
+            (Some(min), Some(max)) => format!("buffer {}-{} frames", min, max),
+            _ => "buffer size unknown".to_string(),
+        };
+        format!(
+            "{} ch, {} Hz, {}, {}",
+            self.channels, rates, self.sample_format, buffer
+        )
+    }
+}
+
+impl From<&SupportedStreamConfigRange> for ConfigInfo {
+    fn from(range: &SupportedStreamConfigRange) -> Self {
+        Self::new(
+            range.channels(),
+            (range.min_sample_rate().0, range.max_sample_rate().0),
+            range.sample_format(),
+            range.buffer_size(),
+        )
+    }
+}
+
+impl From<&cpal::SupportedStreamConfig> for ConfigInfo {
+    fn from(config: &cpal::SupportedStreamConfig) -> Self {
+        let rate = config.sample_rate().0;
+        Self::new(
+            config.channels(),
+            (rate, rate),
+            config.sample_format(),
+            config.buffer_size(),
+        )
+    }
+}
+
+/// What an input device supports, as printed by `--device-info`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub default_config: Option<ConfigInfo>, // None if the driver reports no default
+    pub supported_configs: Vec<ConfigInfo>,
+}
+
+impl DeviceInfo {
+    /// Describe a device from its default config and supported ranges
+    pub fn new(
+        name: &str,
+        default_config: Option<&cpal::SupportedStreamConfig>,
+        ranges: &[SupportedStreamConfigRange],
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            default_config: default_config.map(ConfigInfo::from),
+            supported_configs: ranges.iter().map(ConfigInfo::from).collect(),
+        }
+    }
+
+    /// Ask the driver what `device` supports
+    pub fn query(device: &cpal::Device) -> Result<Self> {
+        let ranges: Vec<SupportedStreamConfigRange> = device
+            .supported_input_configs()
+            .context("Failed to list the device's supported input configs")?
+            .collect();
+        Ok(Self::new(
+            &device.name()?,
+            device.default_input_config().ok().as_ref(),
+            &ranges,
+        ))
+    }
+
+    /// Human-readable report: the name, default config and supported ones
+    pub fn summary(&self) -> String {
+        let mut lines = vec![format!("Device: {}", self.name)];
+        lines.push(match &self.default_config {
+            Some(config) => format!("Default: {}", config.summary()),
+            None => "Default: none reported".to_string(),
+        });
+        lines.push("Supported input configs:".to_string());
+        if self.supported_configs.is_empty() {
+            lines.push("  (none)".to_string());
+        }
+        for config in &self.supported_configs {
+            lines.push(format!("  {}", config.summary()));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Input device name from `AUDIO_DEVICE`, if set
+pub fn device_name_from_env() -> Option<String> {
+    env::var(DEVICE_VAR)
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Select the device to record from and its default stream config
+///
+/// `name` picks a device by (partial) name instead of the default one.
+/// With `loopback` the computer's playback is captured instead of a
+/// microphone; this fails with an explanation on platforms where the audio
+/// backend can't provide it.
+pub fn select_input(
+    name: Option<&str>,
+    loopback: bool,
+) -> Result<(cpal::Device, cpal::SupportedStreamConfig)> {
+    let host = cpal::default_host();
+
+    if loopback {
+        return select_loopback(&host, name);
+    }
+
+    let device = find_input_device(&host, name)?;
+    let config = input_config(&device)?;
+    Ok((device, config))
+}
+
+/// The device's default input config, or the best supported one if that fails
+///
+/// Some drivers fail to report a default even though they list working
+/// configs. The fallback prefers f32, then i16, samples at a common rate,
+/// and says which config it chose.
+pub fn input_config(device: &cpal::Device) -> Result<cpal::SupportedStreamConfig> {
+    let default_err = match device.default_input_config() {
+        Ok(config) => return Ok(config),
+        Err(e) => e,
+    };
+    let ranges: Vec<SupportedStreamConfigRange> = device
+        .supported_input_configs()
+        .with_context(|| format!("No default input config ({})", default_err))?
+        .collect();
+    let config = best_config(&ranges).with_context(|| {
+        format!(
+            "No default input config ({}) and no supported f32/i16/u16 config",
+            default_err
+        )
+    })?;
+    eprintln!(
+        "Default input config unavailable ({}); using {} channel(s) at {} Hz, {:?}",
+        default_err,
+        config.channels(),
+        config.sample_rate().0,
+        config.sample_format()
+    );
+    Ok(config)
+}
+
+/// The highest-quality config we can record from among `ranges`
+///
+/// Ranks by sample format (f32, then i16, then u16; others can't be
+/// recorded), then by sample rate (48 kHz, 44.1 kHz, 16 kHz, else the rate
+/// in range closest to 48 kHz), then prefers mono or stereo.
+fn best_config(ranges: &[SupportedStreamConfigRange]) -> Option<cpal::SupportedStreamConfig> {
+    let format_rank = |format: SampleFormat| match format {
+        SampleFormat::F32 => Some(3),
+        SampleFormat::I16 => Some(2),
+        SampleFormat::U16 => Some(1),
+        _ => None,
+    };
+    ranges
+        .iter()
+        .filter_map(|range| {
+            let format = format_rank(range.sample_format())?;
+            let (min, max) = (range.min_sample_rate().0, range.max_sample_rate().0);
+            let (rate, rate_rank) = PREFERRED_RATES
+                .iter()
+                .enumerate()
+                .find(|(_, rate)| (min..=max).contains(*rate))
+                .map(|(i, &rate)| (rate, PREFERRED_RATES.len() - i))
+                .unwrap_or((PREFERRED_RATES[0].clamp(min, max), 0));
+            let few_channels = range.channels() <= 2;
+            let config = range.with_sample_rate(SampleRate(rate));
+            Some(((format, rate_rank, few_channels), config))
+        })
+        .max_by_key(|(rank, _)| *rank)
+        .map(|(_, config)| config)
+}
+
+/// WASAPI records playback by opening an input stream on an output device
+#[cfg(target_os = "windows")]
+fn select_loopback(
+    host: &cpal::Host,
+    name: Option<&str>,
+) -> Result<(cpal::Device, cpal::SupportedStreamConfig)> {
+    let device = match name {
+        Some(name) => find_by_name(host.output_devices()?.collect(), name, "output")?,
+        None => host
+            .default_output_device()
+            .context("No output device available for loopback capture")?,
+    };
+    let config = device.default_output_config()?;
+    Ok((device, config))
+}
+
+/// Elsewhere loopback is only possible through a monitor source
+#[cfg(not(target_os = "windows"))]
+fn select_loopback(
+    host: &cpal::Host,
+    name: Option<&str>,
+) -> Result<(cpal::Device, cpal::SupportedStreamConfig)> {
+    let monitors: Vec<cpal::Device> = host
+        .input_devices()?
+        .filter(|d| d.name().map(|n| is_monitor_name(&n)).unwrap_or(false))
+        .collect();
+
+    let device = match name {
+        Some(name) => find_by_name(monitors, name, "monitor")?,
+        None => monitors.into_iter().next().with_context(|| {
+            if cfg!(target_os = "macos") {
+                "Loopback capture isn't built into macOS; install a virtual device \
+                 such as BlackHole and select it with --device"
+            } else {
+                "No monitor source found for loopback capture. With PulseAudio or \
+                 PipeWire, set PULSE_SOURCE=<sink>.monitor and use --device pulse"
+            }
+        })?,
+    };
+    let config = input_config(&device)?;
+    Ok((device, config))
+}
+
+/// Buffer size hint from `AUDIO_BUFFER_SIZE`, if set
+pub fn buffer_frames_from_env() -> Result<Option<u32>> {
+    match env::var(BUFFER_SIZE_VAR) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .with_context(|| format!("{} must be a number of frames", BUFFER_SIZE_VAR)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Check a requested buffer size against what the device supports
+fn fixed_buffer_size(supported: &SupportedBufferSize, frames: u32) -> Result<BufferSize, String> {
+    match supported {
+        SupportedBufferSize::Range { min, max } if (*min..=*max).contains(&frames) => {
+            Ok(BufferSize::Fixed(frames))
+        }
+        SupportedBufferSize::Range { min, max } => Err(format!(
+            "buffer size {} is outside the device's range of {}-{} frames",
+            frames, min, max
+        )),
+        SupportedBufferSize::Unknown => Err(format!(
+            "the device doesn't report which buffer sizes it supports, so {} frames can't be checked",
+            frames
+        )),
+    }
+}
+
+/// Stream config for `config`, with a fixed buffer of `buffer_frames` if given
+///
+/// Smaller buffers lower latency; larger ones help on hardware that drops
+/// out. A size the device doesn't support falls back to the default buffer
+/// with a warning rather than failing to open the stream.
+pub fn stream_config(
+    config: &cpal::SupportedStreamConfig,
+    buffer_frames: Option<u32>,
+) -> cpal::StreamConfig {
+    let mut stream_config: cpal::StreamConfig = config.clone().into();
+    if let Some(frames) = buffer_frames {
+        match fixed_buffer_size(config.buffer_size(), frames) {
+            Ok(buffer_size) => stream_config.buffer_size = buffer_size,
+            Err(reason) => eprintln!("Warning: {}; using the default buffer size", reason),
+        }
+    }
+    stream_config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_device_name() {
+        let names: Vec<String> = ["default", "USB Audio Device", "Monitor of Speakers"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(match_device_name(&names, "DEFAULT"), Some(0));
+        assert_eq!(match_device_name(&names, "usb"), Some(1));
+        assert_eq!(match_device_name(&names, "speakers"), Some(2));
+        assert_eq!(match_device_name(&names, "bluetooth"), None);
+    }
+
+    #[test]
+    fn test_is_monitor_name() {
+        assert!(is_monitor_name("Monitor of Built-in Audio Analog Stereo"));
+        assert!(is_monitor_name(
+            "alsa_output.pci-0000_00_1f.3.analog-stereo.monitor"
+        ));
+        assert!(!is_monitor_name("USB Microphone"));
+    }
+
+    #[test]
+    fn test_fixed_buffer_size_validates_range() {
+        let range = SupportedBufferSize::Range { min: 64, max: 4096 };
+        assert_eq!(fixed_buffer_size(&range, 256), Ok(BufferSize::Fixed(256)));
+        assert!(fixed_buffer_size(&range, 32).is_err());
+        assert!(fixed_buffer_size(&range, 8192).is_err());
+        assert!(fixed_buffer_size(&SupportedBufferSize::Unknown, 256).is_err());
+    }
+
+    #[test]
+    fn test_best_config_prefers_quality() {
+        let range = |channels, min, max, format| {
+            SupportedStreamConfigRange::new(
+                channels,
+                SampleRate(min),
+                SampleRate(max),
+                SupportedBufferSize::Unknown,
+                format,
+            )
+        };
+        let ranges = [
+            range(2, 8000, 16000, SampleFormat::F32),
+            range(2, 8000, 96000, SampleFormat::I16),
+            range(1, 8000, 96000, SampleFormat::I32),
+        ];
+        let config = best_config(&ranges).unwrap();
+        assert_eq!(config.sample_format(), SampleFormat::F32);
+        assert_eq!(config.sample_rate(), SampleRate(16000));
+
+        // Same format: the common rate wins, then fewer channels
+        let ranges = [
+            range(8, 8000, 96000, SampleFormat::I16),
+            range(2, 8000, 22050, SampleFormat::I16),
+            range(2, 8000, 96000, SampleFormat::I16),
+        ];
+        let config = best_config(&ranges).unwrap();
+        assert_eq!((config.channels(), config.sample_rate().0), (2, 48000));
+
+        // An odd range gets its rate closest to 48 kHz
+        let config = best_config(&[range(1, 22050, 32000, SampleFormat::U16)]).unwrap();
+        assert_eq!(config.sample_rate(), SampleRate(32000));
+        assert!(best_config(&[range(1, 8000, 48000, SampleFormat::I32)]).is_none());
+    }
+
+    #[test]
+    fn test_device_info_report() {
+        let ranges = [
+            SupportedStreamConfigRange::new(
+                2,
+                SampleRate(44100),
+                SampleRate(48000),
+                SupportedBufferSize::Range { min: 64, max: 4096 },
+                SampleFormat::F32,
+            ),
+            SupportedStreamConfigRange::new(
+                1,
+                SampleRate(16000),
+                SampleRate(16000),
+                SupportedBufferSize::Unknown,
+                SampleFormat::I16,
+            ),
+        ];
+        let default = ranges[0].with_sample_rate(SampleRate(48000));
+        let info = DeviceInfo::new("Fake Mic", Some(&default), &ranges);
+
+        assert_eq!(
+            info.summary(),
+            "Device: Fake Mic\n\
+             Default: 2 ch, 48000 Hz, f32, buffer 64-4096 frames\n\
+             Supported input configs:\n  \
+             2 ch, 44100-48000 Hz, f32, buffer 64-4096 frames\n  \
+             1 ch, 16000 Hz, i16, buffer size unknown"
+        );
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["name"], "Fake Mic");
+        assert_eq!(json["default_config"]["max_sample_rate"], 48000);
+        assert_eq!(json["supported_configs"][1]["sample_format"], "i16");
+        assert!(json["supported_configs"][1]["min_buffer_frames"].is_null());
+    }
+}
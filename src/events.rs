@@ -0,0 +1,192 @@
+//! Detection events passed from the listening loop to consumers
+//!
+//! The detection loop sends these down a channel so side effects (printing,
+//! notifications, logging) stay decoupled from the audio path.
+//!
+//! Each Stage 1 detection gets a `detection_id`, which the Stage 2 result and
+//! confirm phrase check that follow it carry too, so a log can be read as
+//! one sequence per detection: #42 fired at 0.8, #42 confirmed.
+//!
+//! A detection can carry the audio that triggered it as a base64 WAV, so a
+//! consumer gets metadata and audio in the one stream.
+
+use crate::wav::samples_to_wav;
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// Default cap on the WAV embedded in a detection: 2 s of 48 kHz audio fits
+pub const DEFAULT_EMBED_AUDIO_MAX_BYTES: usize = 256 * 1024;
+
+/// Size of the header `samples_to_wav` writes before the samples
+const WAV_HEADER_BYTES: usize = 44;
+
+/// Something the wake word pipeline observed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DetectionEvent {
+    /// Stage 1 matched the wake word template
+    Detection {
+        detection_id: u64,
+        confidence: f32,
+        /// The triggering audio as a base64 16-bit WAV; only when asked for
+        #[serde(skip_serializing_if = "Option::is_none")]
+        audio_wav_base64: Option<String>,
+    },
+    /// Stage 2 transcribed the candidate audio
+    Transcription {
+        detection_id: u64,
+        text: String,
+        confirmed: bool,
+    },
+    /// The follow-up after a confirmed wake word was checked for the confirm phrase
+    ConfirmPhrase {
+        detection_id: u64,
+        text: String,
+        matched: bool,
+    },
+    /// A recording requested directly (not via the wake word) was transcribed
+    Recorded {
+        text: String,
+        /// Nothing was said; only present when true
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        no_speech: bool,
+    },
+    /// Stage 2 failed; the pipeline has gone back to listening
+    Error {
+        /// The detection being checked; absent for errors outside the pipeline
+        #[serde(skip_serializing_if = "Option::is_none")]
+        detection_id: Option<u64>,
+        message: String,
+    },
+}
+
+impl DetectionEvent {
+    /// The Stage 1 detection this event belongs to, if any
+    pub fn detection_id(&self) -> Option<u64> {
+        match *self {
+            DetectionEvent::Detection { detection_id, .. }
+            | DetectionEvent::Transcription { detection_id, .. }
+            | DetectionEvent::ConfirmPhrase { detection_id, .. } => Some(detection_id),
+            DetectionEvent::Error { detection_id, .. } => detection_id,
+            DetectionEvent::Recorded { .. } => None,
+        }
+    }
+}
+
+/// Encode mono samples as a base64 16-bit WAV, for `audio_wav_base64`
+///
+/// Only the newest samples that fit in a WAV of `max_bytes` are kept, since
+/// the wake word is at the end of the window and an event is a single line.
+pub fn wav_base64(samples: &[f32], sample_rate: u32, max_bytes: usize) -> Result<String> {
+    let fits = max_bytes.saturating_sub(WAV_HEADER_BYTES) / 2;
+    let newest = &samples[samples.len().saturating_sub(fits)..];
+    Ok(BASE64.encode(samples_to_wav(newest, sample_rate)?))
+}
+
+/// Write an event as one line of JSON (NDJSON) and flush immediately
+///
+/// Flushing per event lets a consuming process react in real time even when
+/// stdout is a pipe.
+pub fn write_ndjson<W: Write>(out: &mut W, event: &DetectionEvent) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, event)?;
+    out.write_all(b"\n")?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_ndjson_emits_one_line_per_event() {
+        let mut out = Vec::new();
+        for (detection_id, confidence) in [(1, 0.5), (2, 0.75)] {
+            let event = DetectionEvent::Detection {
+                detection_id,
+                confidence,
+                audio_wav_base64: None,
+            };
+            write_ndjson(&mut out, &event).unwrap();
+        }
+
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"event":"detection","detection_id":1,"confidence":0.5}"#,
+                r#"{"event":"detection","detection_id":2,"confidence":0.75}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_embedded_audio_is_the_captured_wav() {
+        let samples: Vec<f32> = (0..8000).map(|i| (i as f32 * 0.05).sin() * 0.5).collect();
+        let event = DetectionEvent::Detection {
+            detection_id: 7,
+            confidence: 0.9,
+            audio_wav_base64: Some(
+                wav_base64(&samples, 16000, DEFAULT_EMBED_AUDIO_MAX_BYTES).unwrap(),
+            ),
+        };
+        let json: serde_json::Value = serde_json::to_value(&event).unwrap();
+        let wav = BASE64
+            .decode(json["audio_wav_base64"].as_str().unwrap())
+            .unwrap();
+
+        let mut reader = hound::WavReader::new(std::io::Cursor::new(&wav)).unwrap();
+        assert_eq!(reader.spec().sample_rate, 16000);
+        assert_eq!(reader.spec().channels, 1);
+        let decoded: Vec<i16> = reader.samples::<i16>().map(Result::unwrap).collect();
+        assert_eq!(decoded.len(), samples.len());
+        for (&got, &sent) in decoded.iter().zip(&samples) {
+            // Within the rounding (and dither) of 16-bit quantization
+            assert!((got as f32 / 32767.0 - sent).abs() < 2.0 / 32767.0);
+        }
+
+        // Over the cap, only the newest samples are kept
+        let capped = BASE64
+            .decode(wav_base64(&samples, 16000, 1044).unwrap())
+            .unwrap();
+        assert_eq!(capped.len(), 1044);
+        let (tail, _) = crate::wav::decode_wav_mono(&capped).unwrap();
+        assert_eq!(tail.len(), 500);
+        assert!((tail[499] - samples[7999]).abs() < 2.0 / 32767.0);
+    }
+
+    #[test]
+    fn test_event_json_shape() {
+        let event = DetectionEvent::Transcription {
+            detection_id: 42,
+            text: "computer".to_string(),
+            confirmed: true,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"transcription","detection_id":42,"text":"computer","confirmed":true}"#
+        );
+        assert_eq!(event.detection_id(), Some(42));
+
+        let error = DetectionEvent::Error {
+            detection_id: None,
+            message: "bad command".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"event":"error","message":"bad command"}"#
+        );
+
+        let silent = DetectionEvent::Recorded {
+            text: String::new(),
+            no_speech: true,
+        };
+        assert_eq!(
+            serde_json::to_string(&silent).unwrap(),
+            r#"{"event":"recorded","text":"","no_speech":true}"#
+        );
+    }
+}
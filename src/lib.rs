@@ -0,0 +1,32 @@
+//! Audio transcription library
+//!
+//! Shared building blocks for the CLI and the examples.
+
+pub mod batch;
+pub mod config;
+pub mod convert;
+pub mod dedupe;
+pub mod denoise;
+pub mod device;
+pub mod events;
+pub mod filter;
+pub mod loudness;
+pub mod mix;
+pub mod pipeline;
+pub mod pitch;
+pub mod progress;
+pub mod recording;
+pub mod resample;
+pub mod segments;
+pub mod self_test;
+#[cfg(feature = "websocket")]
+pub mod server;
+pub mod shutdown;
+pub mod sounds;
+pub mod template_bank;
+pub mod transcribe;
+pub mod transcriber;
+pub mod transcript_log;
+pub mod vad;
+pub mod wake_word;
+pub mod wav;
@@ -1,163 +1,1126 @@
 use anyhow::{Context, Result};
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use dotenv::dotenv;
-use hound::{WavSpec, WavWriter};
-use reqwest::blocking::multipart;
-use std::env;
+use audio_transcribe_cli::batch::{transcript_path, BatchManifest, DEFAULT_MANIFEST};
+use audio_transcribe_cli::config::load_env_file;
+use audio_transcribe_cli::dedupe::{DuplicateFilter, DEFAULT_DUPLICATE_WINDOW};
+use audio_transcribe_cli::denoise::{denoise, NoiseProfile, PROFILE_RECORD_SECS};
+use audio_transcribe_cli::device::{
+    find_input_device, list_input_devices, select_input, DeviceInfo, DEVICE_VAR,
+};
+use audio_transcribe_cli::filter::Bandpass;
+use audio_transcribe_cli::mix::ChannelMix;
+use audio_transcribe_cli::progress::Spinner;
+use audio_transcribe_cli::recording::{
+    parse_record_duration, record_wav, stream_samples, AudioRecorder, RecordingStats, WavOptions,
+    DEFAULT_RECORD_DURATION, RECORD_DURATION_VAR,
+};
+use audio_transcribe_cli::segments::{
+    align_segments, default_channel_labels, merge_channels, parse_text_pointer, to_markdown,
+    to_subtitles, SubtitleFormat, TranscriptReport, Transcription, DEFAULT_NO_SPEECH_THRESHOLD,
+    NO_SPEECH_MESSAGE,
+};
+use audio_transcribe_cli::self_test::run_self_test;
+use audio_transcribe_cli::shutdown::Shutdown;
+use audio_transcribe_cli::sounds::{self, Sound};
+use audio_transcribe_cli::transcribe::{
+    download_audio, save_failed_audio, transcribe_audio_cancellable, AudioClip, Cancelled,
+    WhisperConfig, FAILED_AUDIO_DIR, MAX_UPLOAD_BYTES_VAR,
+};
+use audio_transcribe_cli::transcript_log::TranscriptLog;
+use audio_transcribe_cli::vad::{
+    UtteranceSplitter, DEFAULT_VAD_THRESHOLD, END_SILENCE_MS, MIN_UTTERANCE_MS,
+};
+use audio_transcribe_cli::wake_word::{confidence_precision, format_confidence, WakeWordDetector};
+use audio_transcribe_cli::wav::{
+    decode_wav_channels, decode_wav_mono, raw_pcm_layout, samples_to_wav_as, to_pcm16, write_piped,
+    PipeFormat, Quantization, WavEncoding,
+};
+use clap::builder::BoolishValueParser;
+use clap::{Parser, ValueEnum};
+use cpal::traits::DeviceTrait;
 use std::fs;
-use std::sync::{Arc, Mutex};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 
-// Export wake word module for examples and library usage
-pub mod wake_word;
-
-fn record_audio(duration_secs: u64) -> Result<Vec<u8>> {
-    println!("Recording audio for {} seconds...", duration_secs);
-    
-    let host = cpal::default_host();
-    let device = host
-        .default_input_device()
-        .context("No input device available")?;
-    
-    println!("Using input device: {}", device.name()?);
-    
-    let config = device.default_input_config()?;
-    println!("Default input config: {:?}", config);
-    
-    let sample_rate = config.sample_rate().0;
-    let channels = config.channels() as u16;
-    
-    let spec = WavSpec {
-        channels,
-        sample_rate,
-        bits_per_sample: 16,
-        sample_format: hound::SampleFormat::Int,
+/// Local endpoint used when no transcription service is configured
+const DEFAULT_WHISPER_ENDPOINT: &str = "http://tc3.local:8085";
+
+/// How a finished transcript is printed (and written by --batch)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Plain text
+    Text,
+    /// Markdown notes: timestamped bullets, grouped by speaker
+    Markdown,
+    /// One JSON object: text, no_speech, duration_secs, device and sample_rate
+    Json,
+}
+
+impl OutputFormat {
+    /// File extension for --batch transcripts
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Text => "txt",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Json => "json",
+        }
+    }
+}
+
+/// Record audio from the microphone and transcribe it with Whisper
+#[derive(Parser, Debug, Clone)]
+#[command(version, about)]
+struct Args {
+    /// Load settings from this .env file instead of ./.env
+    #[arg(long, env = "DOTENV_PATH")]
+    env_file: Option<PathBuf>,
+
+    /// Record from the input device whose name contains this text
+    #[arg(long, env = DEVICE_VAR)]
+    device: Option<String>,
+
+    /// Print the names of the input devices (for --device) and exit
+    #[arg(long)]
+    list_devices: bool,
+
+    /// Print the configs the --device (or default) input supports, then exit
+    #[arg(long)]
+    device_info: bool,
+
+    /// Audio buffer size hint in frames (smaller = lower latency, larger = fewer dropouts)
+    #[arg(long, env = "AUDIO_BUFFER_SIZE", value_name = "FRAMES")]
+    buffer_size: Option<u32>,
+
+    /// Capture the computer's playback (loopback/monitor) instead of a microphone
+    #[arg(long, env = "LOOPBACK", value_parser = BoolishValueParser::new())]
+    loopback: bool,
+
+    /// Initial prompt to bias transcription, e.g. names and jargon with their spelling
+    #[arg(long, env = "WHISPER_PROMPT")]
+    prompt: Option<String>,
+
+    /// Refuse to upload recordings bigger than this, even after re-encoding (default 25 MiB)
+    #[arg(long, env = MAX_UPLOAD_BYTES_VAR, value_name = "BYTES")]
+    max_upload_bytes: Option<usize>,
+
+    /// Where the transcript is in the server's JSON response, e.g. "/result/text"
+    #[arg(long, env = "WHISPER_TEXT_POINTER", value_name = "POINTER", value_parser = parse_text_pointer)]
+    text_pointer: Option<String>,
+
+    /// Shown instead of the transcript when nothing was said ("" for a blank line)
+    #[arg(long, env = "NO_SPEECH_TEXT", value_name = "TEXT", default_value = NO_SPEECH_MESSAGE)]
+    no_speech_text: String,
+
+    /// How sure the backend must be that a segment is silence (0.0-1.0) to call it no speech
+    #[arg(long, env = "NO_SPEECH_THRESHOLD", default_value_t = DEFAULT_NO_SPEECH_THRESHOLD)]
+    no_speech_threshold: f32,
+
+    /// Bandpass audio to this range before transcribing it, e.g. "80-8000" (Hz)
+    #[arg(long, env = "BANDPASS", value_name = "LOW-HIGH")]
+    bandpass: Option<Bandpass>,
+
+    /// Reduce steady background noise before transcribing (spectral subtraction)
+    #[arg(long, env = "DENOISE", value_parser = BoolishValueParser::new())]
+    denoise: bool,
+
+    /// Noise profile for denoising, from --record-noise-profile (implies --denoise)
+    #[arg(long, env = "NOISE_PROFILE", value_name = "PATH")]
+    noise_profile: Option<PathBuf>,
+
+    /// Record a few seconds of room noise, save its profile here, then exit
+    #[arg(long, value_name = "PATH")]
+    record_noise_profile: Option<PathBuf>,
+
+    /// Keep the recording in failed/ when transcription fails
+    #[arg(long, env = "KEEP_FAILED_AUDIO", value_parser = BoolishValueParser::new())]
+    keep_failed_audio: bool,
+
+    /// Suppress the progress spinner while waiting for the transcription
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Say when a transcript is left out as a duplicate
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Seconds within which a repeat of the last phrase is left out in --split and --loop (0 keeps all)
+    #[arg(long, value_name = "SECS", default_value_t = DEFAULT_DUPLICATE_WINDOW.as_secs_f64())]
+    duplicate_window: f64,
+
+    /// Append each phrase transcribed in --split and --loop to this file, one per line
+    #[arg(long, env = "TRANSCRIPT_LOG", value_name = "PATH")]
+    transcript_log: Option<PathBuf>,
+
+    /// Sync the --transcript-log to disk after every phrase, not just flush it
+    #[arg(long, requires = "transcript_log")]
+    durable: bool,
+
+    /// Keep listening and transcribe each phrase separately, splitting on pauses
+    #[arg(long)]
+    split: bool,
+
+    /// Count down this many seconds, then beep, before each recording starts
+    #[arg(long, env = "COUNTDOWN", value_name = "N", default_value_t = 0)]
+    countdown: u32,
+
+    /// Record one clip and write it to stdout as WAV instead of transcribing it (messages go to stderr)
+    #[arg(
+        long,
+        conflicts_with_all = ["loop_mode", "split", "batch", "detect_file", "self_test", "record_noise_profile", "input_url"]
+    )]
+    to_stdout: bool,
+
+    /// What --to-stdout writes: wav, or raw for bare little-endian samples
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "wav",
+        requires = "to_stdout"
+    )]
+    stdout_format: PipeFormat,
+
+    /// Seconds to record, e.g. 1.5 (default 5)
+    #[arg(long, env = RECORD_DURATION_VAR, value_name = "SECS", value_parser = parse_record_duration)]
+    duration: Option<Duration>,
+
+    /// Where --keep-audio saves the recording (default: recording.wav in /tmp, or C:/tmp on Windows)
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Save the recording to --output
+    #[arg(long)]
+    keep_audio: bool,
+
+    /// Keep recording and transcribing --duration clips until stopped
+    #[arg(long = "loop")]
+    loop_mode: bool,
+
+    /// Weight of each input channel when mixing to mono, e.g. "1,0" (default: average)
+    #[arg(long, env = "CHANNEL_WEIGHTS", value_name = "W1,W2,...")]
+    channel_weights: Option<ChannelMix>,
+
+    /// Bit depth of recordings kept on disk: 16, 24 or 32
+    #[arg(long, env = "BIT_DEPTH", default_value_t = 16)]
+    bit_depth: u16,
+
+    /// Keep recordings as floating-point samples (requires --bit-depth 32)
+    #[arg(long)]
+    float: bool,
+
+    /// How captured audio is quantized to 16 bits: round-dither (default) or truncate
+    #[arg(long, env = "QUANTIZATION", value_name = "MODE")]
+    quantization: Option<Quantization>,
+
+    /// Check the detection and WAV pipeline offline with synthetic audio, then exit
+    #[arg(long)]
+    self_test: bool,
+
+    /// Transcribe these WAV files instead of recording, writing each transcript to <file>.txt (or .md)
+    #[arg(long, value_name = "PATH", num_args = 1..)]
+    batch: Vec<PathBuf>,
+
+    /// Transcribe the WAV file at this URL instead of recording (Replicate fetches it directly)
+    #[arg(long, value_name = "URL", conflicts_with_all = ["batch", "split", "loop_mode"])]
+    input_url: Option<String>,
+
+    /// Manifest of finished files, so an interrupted --batch run can resume
+    #[arg(long, value_name = "PATH", default_value = DEFAULT_MANIFEST)]
+    manifest: PathBuf,
+
+    /// Re-transcribe files the --batch manifest lists as done
+    #[arg(long)]
+    force: bool,
+
+    /// Transcribe each channel of a --batch file separately and label the results
+    #[arg(long)]
+    split_channels: bool,
+
+    /// Labels for --split-channels, in channel order (default: Left,Right for stereo)
+    #[arg(
+        long,
+        value_name = "L1,L2,...",
+        value_delimiter = ',',
+        requires = "split_channels"
+    )]
+    channel_labels: Vec<String>,
+
+    /// Transcript format for the recording and --batch files (json also applies to --device-info)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Title heading for --format markdown
+    #[arg(long)]
+    title: Option<String>,
+
+    /// Write the transcript's segments, with their sample ranges in the recording, as JSON
+    #[arg(long, value_name = "PATH")]
+    segments_json: Option<PathBuf>,
+
+    /// Write the transcript as subtitles: WebVTT for a .vtt path, otherwise SRT
+    #[arg(long, value_name = "PATH")]
+    subtitles: Option<PathBuf>,
+
+    /// Scan this WAV file for the wake word instead of recording
+    #[arg(long, value_name = "PATH")]
+    detect_file: Option<PathBuf>,
+
+    /// Wake word template file (written by `WakeWordDetector::save_template`)
+    #[arg(long, env = "WAKE_WORD_TEMPLATE", value_name = "PATH")]
+    template: Option<PathBuf>,
+
+    /// RMS level above which audio counts as speech in --split mode
+    #[arg(long, env = "VAD_THRESHOLD", default_value_t = DEFAULT_VAD_THRESHOLD)]
+    vad_threshold: f32,
+
+    /// Pause (ms) that ends a phrase in --split mode; shorter dips stay in the phrase
+    #[arg(long, env = "MIN_SILENCE_GAP_MS", default_value_t = END_SILENCE_MS)]
+    min_silence_gap_ms: u32,
+
+    /// Phrases with less speech than this (ms) are ignored in --split mode
+    #[arg(long, env = "MIN_UTTERANCE_MS", default_value_t = MIN_UTTERANCE_MS)]
+    min_utterance_ms: u32,
+}
+
+impl Args {
+    /// Encoding for recordings kept on disk, from --bit-depth and --float
+    fn wav_encoding(&self) -> Result<WavEncoding> {
+        WavEncoding::new(self.bit_depth, self.float)
+    }
+
+    /// The --transcript-log, if one was asked for
+    fn transcript_log(&self) -> Result<Option<TranscriptLog>> {
+        self.transcript_log
+            .as_deref()
+            .map(|path| TranscriptLog::open(path, self.durable))
+            .transpose()
+    }
+
+    /// Filter for repeated phrases in the continuous modes, per --duplicate-window
+    fn duplicate_filter(&self) -> DuplicateFilter {
+        DuplicateFilter::new(Duration::from_secs_f64(self.duplicate_window.max(0.0)))
+    }
+
+    /// Where progress messages go: stdout, or stderr when stdout carries the audio or JSON
+    fn console(&self) -> Box<dyn Write> {
+        if self.to_stdout || self.format == OutputFormat::Json {
+            Box::new(io::stderr())
+        } else {
+            Box::new(io::stdout())
+        }
+    }
+
+    /// A finished transcript of `audio` in the chosen --format
+    fn render(&self, transcription: &Transcription, audio: &AudioDetails) -> Result<String> {
+        if self.format == OutputFormat::Json {
+            let report = TranscriptReport::new(
+                transcription,
+                self.no_speech_threshold,
+                audio.duration_secs,
+                audio.sample_rate,
+                audio.device.clone(),
+            );
+            return Ok(serde_json::to_string(&report)?);
+        }
+        Ok(self.render_text(transcription))
+    }
+
+    /// A finished transcript as text or markdown
+    fn render_text(&self, transcription: &Transcription) -> String {
+        if transcription.is_no_speech(self.no_speech_threshold) {
+            return self.no_speech_text.clone();
+        }
+        match self.format {
+            OutputFormat::Markdown => to_markdown(transcription, self.title.as_deref()),
+            _ => transcription.text.trim().to_string(),
+        }
+    }
+
+    /// Whether the audio itself is needed, not just its transcript
+    fn needs_audio(&self) -> bool {
+        self.bandpass.is_some()
+            || self.denoises()
+            || self.keep_failed_audio
+            || self.segments_json.is_some()
+            || self.subtitles.is_some()
+            || self.format == OutputFormat::Json
+    }
+
+    /// Whether audio is denoised before transcription
+    fn denoises(&self) -> bool {
+        self.denoise || self.noise_profile.is_some()
+    }
+}
+
+/// The audio behind a transcript, for --format json
+struct AudioDetails {
+    device: Option<String>, // Only for live recordings
+    duration_secs: f32,
+    sample_rate: u32,
+}
+
+impl AudioDetails {
+    /// Read the length and rate from a WAV file's header
+    fn from_wav(wav_data: &[u8], device: Option<String>) -> Result<Self> {
+        let reader = hound::WavReader::new(io::Cursor::new(wav_data))?;
+        let sample_rate = reader.spec().sample_rate;
+        Ok(Self {
+            device,
+            duration_secs: reader.duration() as f32 / sample_rate as f32,
+            sample_rate,
+        })
+    }
+}
+
+/// Record a clip; returns the WAV data and the name of the device it came from
+fn record_audio(
+    args: &Args,
+    duration: Duration,
+    encoding: WavEncoding,
+) -> Result<(Vec<u8>, String)> {
+    let mut console = args.console();
+    writeln!(
+        console,
+        "Recording audio for {} seconds...",
+        duration.as_secs_f64()
+    )?;
+
+    let (device, config) = select_input(args.device.as_deref(), args.loopback)?;
+    let device_name = device.name()?;
+
+    writeln!(console, "Using input device: {}", device_name)?;
+
+    writeln!(console, "Default input config: {:?}", config)?;
+    countdown(args);
+
+    // Always mono: Whisper and the wake word code expect one channel
+    let options = WavOptions {
+        mix: Some(args.channel_weights.clone().unwrap_or_default()),
+        encoding,
+        buffer_frames: args.buffer_size,
     };
-    
-    // Use a platform-appropriate temporary file
+
+    writeln!(console, "Recording...")?;
+    let wav_data = record_wav(&device, &config, duration, &options)?;
+    writeln!(console, "Recording complete!")?;
+
+    if args.keep_audio {
+        let path = recording_path(args)?;
+        fs::write(&path, &wav_data)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        writeln!(console, "Recording kept at {}", path.display())?;
+    }
+
+    let (samples, sample_rate) = decode_wav_mono(&wav_data)?;
+    writeln!(
+        console,
+        "Levels: {}",
+        RecordingStats::measure(&samples, sample_rate).summary()
+    )?;
+
+    Ok((wav_data, device_name))
+}
+
+/// Where `record_audio` keeps the recording: --output, or a platform-appropriate temporary file
+///
+/// Creates the directory if it doesn't exist.
+fn recording_path(args: &Args) -> Result<PathBuf> {
     #[cfg(target_os = "windows")]
     let temp_dir = "C:/tmp";
     #[cfg(not(target_os = "windows"))]
     let temp_dir = "/tmp";
-    // Create the temp directory if it doesn't exist
-    std::fs::create_dir_all(temp_dir)?;
-    let temp_path = format!("{}/recording.wav", temp_dir);
-    let writer = Arc::new(Mutex::new(WavWriter::create(&temp_path, spec)?));
-    let writer_clone = Arc::clone(&writer);
-    
-    let err_fn = |err| eprintln!("An error occurred on stream: {}", err);
-    
-    let stream = match config.sample_format() {
-        cpal::SampleFormat::F32 => device.build_input_stream(
-            &config.into(),
-            move |data: &[f32], _: &_| {
-                let mut writer = writer_clone.lock().unwrap();
-                for &sample in data {
-                    let sample = (sample * i16::MAX as f32) as i16;
-                    writer.write_sample(sample).unwrap();
-                }
-            },
-            err_fn,
+    let path = match args.output {
+        Some(ref path) => path.clone(),
+        None => Path::new(temp_dir).join("recording.wav"),
+    };
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    Ok(path)
+}
+
+/// Print what the selected input device supports
+fn run_device_info(args: &Args) -> Result<()> {
+    let device = find_input_device(&cpal::default_host(), args.device.as_deref())?;
+    let info = DeviceInfo::query(&device)?;
+    if args.format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else {
+        println!("{}", info.summary());
+    }
+    Ok(())
+}
+
+/// Print the input devices --device can select
+fn run_list_devices() -> Result<()> {
+    let devices = list_input_devices(&cpal::default_host())?;
+    if devices.is_empty() {
+        println!("No input devices found");
+    }
+    for (name, is_default) in devices {
+        println!("{}{}", name, if is_default { " (default)" } else { "" });
+    }
+    Ok(())
+}
+
+/// Record one clip and write it to stdout as WAV, for piping into other tools
+fn record_to_stdout(args: &Args, duration: Duration, encoding: WavEncoding) -> Result<()> {
+    let (wav_data, _) = record_audio(args, duration, encoding)?;
+    let mut stdout = io::stdout().lock();
+    let written = write_piped(&mut stdout, &wav_data, args.stdout_format)
+        .context("Failed to write the recording to stdout")?;
+    stdout.flush()?;
+    match args.stdout_format {
+        PipeFormat::Wav => eprintln!("Wrote {} bytes of WAV to stdout", written),
+        PipeFormat::Raw => eprintln!(
+            "Wrote {} bytes of raw PCM ({}) to stdout",
+            written,
+            raw_pcm_layout(&wav_data)?
+        ),
+    }
+    Ok(())
+}
+
+/// Count down --countdown seconds and beep, so the start of a recording isn't missed
+///
+/// The beep has finished playing when this returns, so it isn't recorded.
+fn countdown(args: &Args) {
+    if args.countdown == 0 {
+        return;
+    }
+    let mut console = args.console();
+    for n in (1..=args.countdown).rev() {
+        write!(console, "{}... ", n).ok();
+        console.flush().ok();
+        std::thread::sleep(Duration::from_secs(1));
+    }
+    writeln!(console).ok();
+    if let Err(e) = sounds::play(Sound::Beep) {
+        eprintln!("Warning: couldn't play the start beep: {}", e);
+    }
+}
+
+/// Listen continuously, transcribing each utterance as it ends
+fn run_split_mode(args: &Args) -> Result<()> {
+    let (device, config) = select_input(args.device.as_deref(), args.loopback)?;
+    let device_name = device.name()?;
+    let mut console = args.console();
+
+    writeln!(console, "Using input device: {}", device_name)?;
+
+    let sample_rate = config.sample_rate().0;
+
+    let (tx, rx) = mpsc::channel();
+    let mix = args.channel_weights.clone().unwrap_or_default();
+    let stream = stream_samples(&device, &config, mix, args.buffer_size, tx)?;
+
+    writeln!(
+        console,
+        "Listening... pause between phrases to transcribe them (Ctrl+C to stop)"
+    )?;
+
+    let shutdown = Shutdown::install()?;
+    let skip = SkipKey::listen();
+    let mut output = PhraseOutput::new(args, device_name)?;
+    let mut splitter = UtteranceSplitter::new(sample_rate, args.vad_threshold);
+    splitter.set_min_silence_gap_ms(args.min_silence_gap_ms);
+    splitter.set_min_utterance_ms(args.min_utterance_ms);
+    let (mut transcribed, mut failed) = (0, 0);
+    let mut tally = |outcome: Option<bool>| match outcome {
+        Some(true) => transcribed += 1,
+        Some(false) => failed += 1,
+        None => {} // Skipped
+    };
+    while !shutdown.requested() {
+        let chunk = match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(chunk) => chunk,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        for utterance in splitter.push(&chunk) {
+            tally(transcribe_utterance(
+                args,
+                &utterance,
+                sample_rate,
+                &skip,
+                Some(&shutdown),
+                &mut output,
+            )?);
+        }
+    }
+
+    // Stop capturing, then transcribe the phrase that was still in progress
+    drop(stream);
+    if let Some(utterance) = splitter.flush() {
+        tally(transcribe_utterance(
+            args,
+            &utterance,
+            sample_rate,
+            &skip,
             None,
-        )?,
-        cpal::SampleFormat::I16 => device.build_input_stream(
-            &config.into(),
-            move |data: &[i16], _: &_| {
-                let mut writer = writer_clone.lock().unwrap();
-                for &sample in data {
-                    writer.write_sample(sample).unwrap();
+            &mut output,
+        )?);
+    }
+
+    writeln!(
+        console,
+        "\nStopped: {} phrase(s) transcribed, {} failed",
+        transcribed, failed
+    )?;
+    Ok(())
+}
+
+/// Record and transcribe fixed-length clips until Ctrl+C, on one open stream
+fn run_loop_mode(args: &Args, duration: Duration) -> Result<()> {
+    let (device, config) = select_input(args.device.as_deref(), args.loopback)?;
+    let device_name = device.name()?;
+    let mut console = args.console();
+    writeln!(console, "Using input device: {}", device_name)?;
+
+    let mix = args.channel_weights.clone().unwrap_or_default();
+    let recorder = AudioRecorder::open(&device, &config, mix, args.buffer_size)?;
+    let shutdown = Shutdown::install()?;
+    let skip = SkipKey::listen();
+    let mut output = PhraseOutput::new(args, device_name)?;
+    let (mut transcribed, mut failed) = (0, 0);
+    while !shutdown.requested() {
+        writeln!(
+            console,
+            "\nRecording audio for {} seconds... (Ctrl+C to stop)",
+            duration.as_secs_f64()
+        )?;
+        countdown(args);
+        let samples = recorder.record(duration, || shutdown.requested());
+        if samples.is_empty() {
+            continue;
+        }
+        let sample_rate = recorder.sample_rate();
+        let outcome = transcribe_utterance(
+            args,
+            &samples,
+            sample_rate,
+            &skip,
+            Some(&shutdown),
+            &mut output,
+        )?;
+        match outcome {
+            Some(true) => transcribed += 1,
+            Some(false) => failed += 1,
+            None => {} // Skipped
+        }
+    }
+
+    writeln!(
+        console,
+        "\nStopped: {} recording(s) transcribed, {} failed",
+        transcribed, failed
+    )?;
+    Ok(())
+}
+
+/// Transcribe each --batch file, skipping those a previous run finished
+fn run_batch_mode(args: &Args) -> Result<()> {
+    let mut manifest = if args.force {
+        BatchManifest::default()
+    } else {
+        BatchManifest::load(&args.manifest)?
+    };
+    let shutdown = Shutdown::install()?;
+    let (mut transcribed, mut skipped, mut failed) = (0, 0, 0);
+    for input in &args.batch {
+        if shutdown.requested() {
+            break;
+        }
+        if manifest.is_done(input) {
+            println!("Skipping {} (already transcribed)", input.display());
+            skipped += 1;
+            continue;
+        }
+
+        println!("Transcribing {}...", input.display());
+        let output = transcript_path(input).with_extension(args.format.extension());
+        let result = fs::read(input)
+            .with_context(|| format!("Failed to read {}", input.display()))
+            .and_then(|wav_data| {
+                let cancelled = || shutdown.requested();
+                let transcription = if args.split_channels {
+                    transcribe_channels(args, &wav_data, &cancelled)
+                } else {
+                    recording_clip(args, &wav_data)
+                        .and_then(|clip| transcribe_audio(clip, args, &cancelled))
+                }?;
+                Ok((transcription, AudioDetails::from_wav(&wav_data, None)?))
+            })
+            .and_then(|(transcription, audio)| {
+                fs::write(&output, args.render(&transcription, &audio)?)
+                    .with_context(|| format!("Failed to write {}", output.display()))
+            });
+        match result {
+            Ok(()) => {
+                // Save after every file so a crash loses at most the one in flight
+                manifest.record(input, &output);
+                manifest.save(&args.manifest)?;
+                println!("  -> {}", output.display());
+                transcribed += 1;
+            }
+            Err(e) if e.is::<Cancelled>() => println!("  Cancelled"),
+            Err(e) => {
+                eprintln!("  Failed: {:#}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "\nBatch finished: {} transcribed, {} skipped, {} failed",
+        transcribed, skipped, failed
+    );
+    if failed > 0 {
+        anyhow::bail!("{} file(s) failed; run again to retry them", failed);
+    }
+    Ok(())
+}
+
+/// Transcribe each channel of a WAV file on its own and merge the labelled results
+///
+/// The channels are transcribed concurrently, one request per channel,
+/// until `cancelled` returns true.
+fn transcribe_channels(
+    args: &Args,
+    wav_data: &[u8],
+    cancelled: &(dyn Fn() -> bool + Sync),
+) -> Result<Transcription> {
+    let (tracks, sample_rate) = decode_wav_channels(wav_data)?;
+    let labels = if args.channel_labels.is_empty() {
+        default_channel_labels(tracks.len())
+    } else if args.channel_labels.len() == tracks.len() {
+        args.channel_labels.clone()
+    } else {
+        anyhow::bail!(
+            "{} channel labels given but the file has {} channels",
+            args.channel_labels.len(),
+            tracks.len()
+        );
+    };
+
+    let results: Vec<Result<Transcription>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = tracks
+            .iter()
+            .map(|track| {
+                scope.spawn(move || {
+                    let clip = samples_clip(args, track, sample_rate)?;
+                    transcribe_audio(clip, args, cancelled)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("transcription thread panicked"))
+            .collect()
+    });
+
+    let mut channels = Vec::with_capacity(results.len());
+    for (label, result) in labels.into_iter().zip(results) {
+        let transcription = result.with_context(|| format!("Channel \"{}\" failed", label))?;
+        channels.push((label, transcription));
+    }
+    Ok(merge_channels(channels))
+}
+
+/// Record background noise and save its profile for --noise-profile
+fn run_record_noise_profile(args: &Args, path: &Path) -> Result<()> {
+    let (device, config) = select_input(args.device.as_deref(), args.loopback)?;
+    println!("Using input device: {}", device.name()?);
+
+    let mix = args.channel_weights.clone().unwrap_or_default();
+    let recorder = AudioRecorder::open(&device, &config, mix, args.buffer_size)?;
+    println!(
+        "Recording {} seconds of background noise... stay quiet",
+        PROFILE_RECORD_SECS
+    );
+    // A profile cut short by Ctrl+C could miss part of the noise, so don't keep it
+    let shutdown = Shutdown::install()?;
+    let samples = recorder.record(Duration::from_secs(PROFILE_RECORD_SECS), || {
+        shutdown.requested()
+    });
+    if shutdown.requested() {
+        println!("Cancelled; no noise profile saved");
+        return Ok(());
+    }
+    let profile = NoiseProfile::from_samples(&samples, recorder.sample_rate())?;
+    profile.save(path)?;
+    println!("Noise profile saved to {}", path.display());
+    println!("Use it with --noise-profile {}", path.display());
+    Ok(())
+}
+
+/// Run the offline self-test, printing PASS/FAIL per stage
+fn run_self_test_mode() -> Result<()> {
+    let stages = run_self_test();
+    for stage in &stages {
+        match &stage.result {
+            Ok(()) => println!("PASS  {}", stage.name),
+            Err(e) => println!("FAIL  {}: {}", stage.name, e),
+        }
+    }
+    let failed = stages.iter().filter(|s| s.result.is_err()).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} self-test stages failed", failed, stages.len());
+    }
+    println!("All {} self-test stages passed", stages.len());
+    Ok(())
+}
+
+/// Scan a WAV file with a saved template and print every detection
+fn run_detect_file(args: &Args, path: &Path) -> Result<()> {
+    let template = args
+        .template
+        .as_deref()
+        .context("--detect-file needs --template")?;
+    let mut detector = WakeWordDetector::new();
+    detector.load_template(template)?;
+
+    let hits = detector.scan_file(path)?;
+    let repairs = detector.take_repairs();
+    if !repairs.is_empty() {
+        eprintln!("Warning: {}", repairs);
+    }
+    let precision = confidence_precision();
+    for hit in &hits {
+        println!(
+            "{:>8.2}s  confidence {}",
+            hit.time_s,
+            format_confidence(hit.confidence, precision)
+        );
+    }
+    if hits.is_empty() {
+        anyhow::bail!("No wake word detected in {}", path.display());
+    }
+    println!("{} detection(s) in {}", hits.len(), path.display());
+    Ok(())
+}
+
+/// Enter on the terminal, which skips the transcription in flight
+///
+/// In the continuous modes a slow transcription would otherwise hold up
+/// everything after it.
+struct SkipKey(Arc<AtomicBool>);
+
+impl SkipKey {
+    /// Start watching stdin for Enter
+    fn listen() -> Self {
+        let pressed = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&pressed);
+        thread::spawn(move || {
+            for _ in io::stdin().lines() {
+                flag.store(true, Ordering::SeqCst);
+            }
+        });
+        Self(pressed)
+    }
+
+    /// Forget earlier presses, before a new transcription starts
+    fn arm(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    fn pressed(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Where the continuous modes' phrases go besides the terminal
+struct PhraseOutput {
+    dedupe: DuplicateFilter,
+    log: Option<TranscriptLog>,
+    device: String, // For --format json
+}
+
+impl PhraseOutput {
+    fn new(args: &Args, device: String) -> Result<Self> {
+        Ok(Self {
+            dedupe: args.duplicate_filter(),
+            log: args.transcript_log()?,
+            device,
+        })
+    }
+
+    /// Append a phrase to the --transcript-log; a failed write is only a warning
+    fn log(&mut self, text: &str) {
+        if let Some(ref mut log) = self.log {
+            if let Err(e) = log.append(text.trim()) {
+                eprintln!("Warning: {:#}", e);
+            }
+        }
+    }
+}
+
+/// Transcribe one utterance and print it, unless it repeats the last one
+///
+/// With --format json each utterance is printed as one line of JSON, and
+/// everything else goes to stderr.
+/// Enter, or Ctrl+C when `shutdown` is given, cancels the transcription.
+/// Returns whether transcription succeeded, or `None` if it was skipped.
+fn transcribe_utterance(
+    args: &Args,
+    utterance: &[f32],
+    sample_rate: u32,
+    skip: &SkipKey,
+    shutdown: Option<&Shutdown>,
+    output: &mut PhraseOutput,
+) -> Result<Option<bool>> {
+    let clip = samples_clip(args, utterance, sample_rate)?;
+    let backup = if args.keep_failed_audio {
+        Some(samples_to_wav_as(
+            utterance,
+            sample_rate,
+            args.wav_encoding()?,
+            args.quantization.unwrap_or_default(),
+        )?)
+    } else {
+        None
+    };
+    let mut spinner = Spinner::start("Waiting for transcription (Enter to skip)", !args.quiet);
+    skip.arm();
+    let cancelled = || skip.pressed() || shutdown.is_some_and(Shutdown::requested);
+    let result = transcribe_audio(clip, args, &cancelled);
+    spinner.finish();
+    match result {
+        Ok(transcription) => {
+            let no_speech = transcription.is_no_speech(args.no_speech_threshold);
+            if !no_speech && output.dedupe.is_duplicate(&transcription.text) {
+                if args.verbose {
+                    writeln!(args.console(), "[duplicate suppressed]")?;
                 }
-            },
-            err_fn,
-            None,
-        )?,
-        cpal::SampleFormat::U16 => device.build_input_stream(
-            &config.into(),
-            move |data: &[u16], _: &_| {
-                let mut writer = writer_clone.lock().unwrap();
-                for &sample in data {
-                    let sample = (sample as i32 - 32768) as i16;
-                    writer.write_sample(sample).unwrap();
+                return Ok(Some(true));
+            }
+            if args.format == OutputFormat::Json {
+                let audio = AudioDetails {
+                    device: Some(output.device.clone()),
+                    duration_secs: utterance.len() as f32 / sample_rate.max(1) as f32,
+                    sample_rate,
+                };
+                println!("{}", args.render(&transcription, &audio)?);
+            } else if no_speech {
+                println!("> {}", args.no_speech_text);
+            } else {
+                println!("> {}", transcription.text.trim());
+            }
+            if !no_speech {
+                output.log(&transcription.text);
+            }
+            Ok(Some(true))
+        }
+        Err(e) if e.is::<Cancelled>() => {
+            writeln!(args.console(), "(skipped)")?;
+            Ok(None)
+        }
+        Err(e) => {
+            eprintln!("Transcription error: {}", e);
+            if let Some(wav_data) = backup {
+                match save_failed_audio(Path::new(FAILED_AUDIO_DIR), &wav_data) {
+                    Ok(path) => eprintln!("Recording saved to {}", path.display()),
+                    Err(save_err) => eprintln!("Failed to save recording: {}", save_err),
                 }
-            },
-            err_fn,
-            None,
-        )?,
-        _ => return Err(anyhow::anyhow!("Unsupported sample format")),
-    };
-    
-    stream.play()?;
-    
-    println!("Recording...");
-    std::thread::sleep(Duration::from_secs(duration_secs));
-    
-    drop(stream);
-    println!("Recording complete!");
-    
-    // Finalize the writer
-    let writer = Arc::try_unwrap(writer)
-        .map_err(|_| anyhow::anyhow!("Failed to unwrap writer"))?
-        .into_inner()
-        .unwrap();
-    
-    writer.finalize()?;
-    
-    // Read the file back
-        let wav_data = fs::read(&temp_path)?;
-    
-    // Clean up
-        fs::remove_file(&temp_path).ok();
-    
-    Ok(wav_data)
-}
-
-fn transcribe_audio(audio_data: Vec<u8>) -> Result<String> {
-    println!("Sending audio to local Whisper for transcription...");
-    let client = reqwest::blocking::Client::new();
-    let part = multipart::Part::bytes(audio_data)
-        .file_name("audio.wav")
-        .mime_str("audio/wav")?;
-    let form = multipart::Form::new().part("file", part);
-    let url = "http://tc3.local:8085/transcribe";
-    let response = client
-        .post(url)
-        .multipart(form)
-        .send()
-        .context("Failed to send request to local Whisper API")?;
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response.text().unwrap_or_default();
-        return Err(anyhow::anyhow!(
-            "Local Whisper API error ({}): {}",
-            status,
-            error_text
-        ));
-    }
-    let result: serde_json::Value = response.json()?;
-    let text = result.get("text")
-        .and_then(|v| v.as_str())
-        .unwrap_or("(No transcription returned)")
-        .to_string();
-    Ok(text)
+            }
+            Ok(Some(false))
+        }
+    }
+}
+
+/// 16-bit clip of a recording for transcription, cleaned up as configured
+fn recording_clip(args: &Args, wav_data: &[u8]) -> Result<AudioClip> {
+    if args.bandpass.is_none() && !args.denoises() {
+        return to_pcm16(wav_data).and_then(AudioClip::from_wav);
+    }
+    let (samples, sample_rate) = decode_wav_mono(wav_data)?;
+    samples_clip(args, &samples, sample_rate)
+}
+
+/// Clip of mono samples for transcription, denoised and bandpassed as configured
+fn samples_clip(args: &Args, samples: &[f32], sample_rate: u32) -> Result<AudioClip> {
+    let mut samples = samples.to_vec();
+    if args.denoises() {
+        // Without a recorded profile, assume the clip starts with noise only
+        let profile = match args.noise_profile {
+            Some(ref path) => NoiseProfile::load(path)?,
+            None => NoiseProfile::estimate(&samples, sample_rate)?,
+        };
+        samples = denoise(&samples, sample_rate, &profile)?;
+    }
+    if let Some(band) = args.bandpass {
+        samples = band.apply(&samples, sample_rate);
+    }
+    AudioClip::from_samples_quantized(&samples, sample_rate, args.quantization.unwrap_or_default())
+}
+
+/// The transcription service: `WHISPER_ENDPOINT`, else Replicate with
+/// `REPLICATE_API_KEY`, else OpenAI with `OPENAI_API_KEY`, else the default
+/// local endpoint
+fn whisper_config(args: &Args) -> Result<WhisperConfig> {
+    let mut config = WhisperConfig::from_env()?;
+    if !config.is_configured() {
+        config.endpoint = Some(DEFAULT_WHISPER_ENDPOINT.to_string());
+    }
+    config.prompt = args.prompt.clone();
+    config.text_pointer = args.text_pointer.clone();
+    if let Some(max_upload_bytes) = args.max_upload_bytes {
+        config.max_upload_bytes = max_upload_bytes;
+    }
+    Ok(config)
+}
+
+/// Transcribe a clip with the configured service, until `cancelled` returns true
+fn transcribe_audio(
+    clip: AudioClip,
+    args: &Args,
+    cancelled: &(dyn Fn() -> bool + Sync),
+) -> Result<Transcription> {
+    let config = whisper_config(args)?;
+    writeln!(
+        args.console(),
+        "Sending {:.1}s of audio to {} for transcription...",
+        clip.duration_s,
+        config.transcriber()?.name()
+    )?;
+    transcribe_audio_cancellable(&config, clip, cancelled)
 }
 
 fn main() -> Result<()> {
-    // Load .env file
-    dotenv().ok();
-    
-    println!("Audio Transcription CLI (Local Whisper)");
-    println!("======================");
+    // Load the .env file named by --env-file, then parse again so it can
+    // supply env-backed options
+    load_env_file(Args::parse().env_file.as_deref());
+    let args = Args::parse();
+    let encoding = args.wav_encoding()?;
+
+    // With --to-stdout the banner would corrupt the WAV stream
+    writeln!(args.console(), "Audio Transcription CLI (Local Whisper)")?;
+    writeln!(args.console(), "======================")?;
+    if args.list_devices {
+        return run_list_devices();
+    }
+    if args.device_info {
+        return run_device_info(&args);
+    }
+    if args.self_test {
+        return run_self_test_mode();
+    }
+    if let Some(ref path) = args.record_noise_profile {
+        return run_record_noise_profile(&args, path);
+    }
+    if !args.batch.is_empty() {
+        return run_batch_mode(&args);
+    }
+    if let Some(ref path) = args.detect_file {
+        return run_detect_file(&args, path);
+    }
+    if let Some(ref url) = args.input_url {
+        return run_input_url(&args, url);
+    }
+    if args.split {
+        return run_split_mode(&args);
+    }
     // Record 5 seconds of audio by default
-    let duration = env::var("RECORD_DURATION")
-        .ok()
-        .and_then(|d| d.parse().ok())
-        .unwrap_or(5);
-    let audio_data = record_audio(duration)?;
-    println!("Audio recorded: {} bytes", audio_data.len());
-    let transcription = transcribe_audio(audio_data)?;
-    println!("\n======================");
-    println!("Transcription Result:");
-    println!("======================");
-    println!("{}", transcription);
+    let duration = args.duration.unwrap_or(DEFAULT_RECORD_DURATION);
+    if args.loop_mode {
+        return run_loop_mode(&args, duration);
+    }
+    if args.to_stdout {
+        return record_to_stdout(&args, duration, encoding);
+    }
+    let (audio_data, device_name) = record_audio(&args, duration, encoding)?;
+    writeln!(args.console(), "Audio recorded: {} bytes", audio_data.len())?;
+    transcribe_recording(&args, audio_data, Some(device_name))
+}
+
+/// Transcribe the audio at --input-url
+///
+/// Replicate is handed the URL to fetch itself, unless the audio is needed
+/// here (to clean it up, keep it, or time the transcript); every other
+/// backend gets it downloaded and uploaded like a recording.
+fn run_input_url(args: &Args, url: &str) -> Result<()> {
+    let mut console = args.console();
+    let replicate = whisper_config(args)?.replicate_transcriber();
+    if let Some(replicate) = replicate.filter(|_| !args.needs_audio()) {
+        writeln!(console, "Passing {} to Replicate for transcription...", url)?;
+        let mut spinner = Spinner::start("Waiting for transcription", !args.quiet);
+        let result = replicate.transcribe_url(url);
+        spinner.finish();
+        let transcription = result?;
+        writeln!(console, "\n======================")?;
+        writeln!(console, "Transcription Result:")?;
+        writeln!(console, "======================")?;
+        println!("{}", args.render_text(&transcription));
+        return Ok(());
+    }
+    writeln!(console, "Downloading {}...", url)?;
+    let audio_data = download_audio(url)?;
+    writeln!(console, "Audio downloaded: {} bytes", audio_data.len())?;
+    transcribe_recording(args, audio_data, None)
+}
+
+/// Transcribe a WAV recording and write out the transcript as configured
+fn transcribe_recording(args: &Args, audio_data: Vec<u8>, device: Option<String>) -> Result<()> {
+    let mut console = args.console();
+    let audio = AudioDetails::from_wav(&audio_data, device)?;
+    // Only hold on to a copy of the audio when we may need to save it
+    let backup = args.keep_failed_audio.then(|| audio_data.clone());
+    // Ctrl+C cancels the transcription, on Replicate as well
+    let shutdown = Shutdown::install()?;
+    let cancelled = || shutdown.requested();
+    let mut spinner = Spinner::start("Waiting for transcription", !args.quiet);
+    // The transcription services get 16-bit PCM whatever depth was recorded
+    let result = recording_clip(args, &audio_data).and_then(|clip| {
+        // Kept to map segment times onto the recording
+        let sample_rate = clip.sample_rate;
+        let total_samples = (clip.duration_s as f64 * sample_rate as f64).round() as u64;
+        let transcription = transcribe_audio(clip, args, &cancelled)?;
+        Ok((transcription, sample_rate, total_samples))
+    });
+    spinner.finish();
+    let (transcription, sample_rate, total_samples) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            // Like a skipped phrase, a cancelled recording isn't kept
+            if let Some(wav_data) = backup.filter(|_| !e.is::<Cancelled>()) {
+                match save_failed_audio(Path::new(FAILED_AUDIO_DIR), &wav_data) {
+                    Ok(path) => eprintln!("Recording saved to {}", path.display()),
+                    Err(save_err) => eprintln!("Failed to save recording: {}", save_err),
+                }
+            }
+            return Err(e);
+        }
+    };
+    writeln!(console, "\n======================")?;
+    writeln!(console, "Transcription Result:")?;
+    writeln!(console, "======================")?;
+    println!("{}", args.render(&transcription, &audio)?);
+
+    if let Some(ref path) = args.segments_json {
+        let aligned = align_segments(&transcription.segments, sample_rate, total_samples);
+        fs::write(path, serde_json::to_string_pretty(&aligned)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        writeln!(
+            console,
+            "{} segment(s) written to {}",
+            aligned.len(),
+            path.display()
+        )?;
+    }
+
+    if let Some(ref path) = args.subtitles {
+        let duration_secs = total_samples as f32 / sample_rate as f32;
+        let subtitles = to_subtitles(
+            &transcription,
+            duration_secs,
+            SubtitleFormat::from_path(path),
+        );
+        fs::write(path, subtitles)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        writeln!(console, "Subtitles written to {}", path.display())?;
+    }
     Ok(())
 }
@@ -0,0 +1,87 @@
+//! Downmixing multichannel audio to mono.
+
+use crate::convert::to_mono;
+use anyhow::{Context, Result};
+use std::str::FromStr;
+
+/// How interleaved channels are combined into one
+///
+/// By default channels are averaged. With explicit weights each output
+/// sample is `sum(weight[c] * sample[c])`, so `1,0` keeps only the first
+/// channel and `1,1` sums two channels.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChannelMix {
+    weights: Option<Vec<f32>>,
+}
+
+impl ChannelMix {
+    /// Mix with one weight per channel
+    pub fn weighted(weights: Vec<f32>) -> Self {
+        Self {
+            weights: Some(weights),
+        }
+    }
+
+    /// Check the weights match the device's channel count
+    pub fn validate(&self, channels: usize) -> Result<()> {
+        match &self.weights {
+            Some(weights) if weights.len() != channels => anyhow::bail!(
+                "{} channel weights given but the device has {} channels",
+                weights.len(),
+                channels
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Combine interleaved `channels`-channel samples into mono
+    pub fn downmix(&self, interleaved: &[f32], channels: usize) -> Vec<f32> {
+        match &self.weights {
+            Some(weights) => interleaved
+                .chunks(channels.max(1))
+                .map(|frame| frame.iter().zip(weights).map(|(s, w)| s * w).sum())
+                .collect(),
+            None => to_mono(interleaved, channels),
+        }
+    }
+}
+
+impl FromStr for ChannelMix {
+    type Err = anyhow::Error;
+
+    /// Parse comma-separated weights, e.g. `0.7,0.3`
+    fn from_str(spec: &str) -> Result<Self> {
+        let weights = spec
+            .split(',')
+            .map(|w| {
+                w.trim()
+                    .parse::<f32>()
+                    .with_context(|| format!("Invalid channel weight \"{}\"", w.trim()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::weighted(weights))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downmix() {
+        let stereo = [0.5, 0.1, -0.2, 0.4];
+        assert_eq!(ChannelMix::default().downmix(&stereo, 2), vec![0.3, 0.1]);
+
+        let left_only: ChannelMix = "1, 0".parse().unwrap();
+        assert_eq!(left_only.downmix(&stereo, 2), vec![0.5, -0.2]);
+    }
+
+    #[test]
+    fn test_weights_must_match_channels() {
+        let mix: ChannelMix = "0.7,0.3".parse().unwrap();
+        assert!(mix.validate(2).is_ok());
+        assert!(mix.validate(4).is_err());
+        assert!(ChannelMix::default().validate(4).is_ok());
+        assert!("1,x".parse::<ChannelMix>().is_err());
+    }
+}
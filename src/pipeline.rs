@@ -0,0 +1,819 @@
+//! Two-stage wake word pipeline
+//!
+//! Stage 1 runs the local detector over a rolling window of captured audio;
+//! each candidate is then handed to Stage 2, a transcription call that checks
+//! the wake word was really spoken. The pipeline owns the window and the
+//! re-trigger cooldown so callers only feed audio and report the results.
+//!
+//! For guarded commands a confirm phrase can be required as well: once the
+//! wake word is confirmed, the next few seconds are recorded and transcribed,
+//! and the command only goes ahead if the phrase was said.
+//!
+//! To measure how many wake words Stage 1 misses, it can be bypassed: every
+//! utterance then goes to Stage 2, and each candidate records whether Stage 1
+//! would have passed it. That costs a transcription per utterance, so it is
+//! for evaluation only.
+
+use crate::events::{wav_base64, DetectionEvent};
+use crate::resample::StreamResampler;
+use crate::transcribe::AudioClip;
+use crate::transcriber::Transcriber;
+use crate::vad::SpeechGate;
+use crate::wake_word::{FrameCache, Repairs, WakeWordDetector};
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Audio kept for detection and sent to Stage 2
+const WINDOW_SECS: usize = 2;
+
+/// Time after a detection during which no new candidate is raised
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(3);
+
+/// Follow-up recorded for the confirm phrase
+pub const DEFAULT_CONFIRM_WINDOW: Duration = Duration::from_secs(3);
+
+/// Similarity (0.0-1.0) a stretch of transcript needs to count as the confirm phrase
+pub const PHRASE_MATCH_THRESHOLD: f32 = 0.8;
+
+/// Audio that passed Stage 1
+pub struct Candidate {
+    pub id: u64,      // Numbers the pipeline's detections from 1; carried by its events
+    pub word: String, // The wake word that fired: the pipeline's, or a named one
+    pub confidence: f32,
+    pub stage1_passed: bool, // Only false when Stage 1 is bypassed
+    pub audio: Vec<f32>,
+    pub timing: DetectionTiming,
+}
+
+impl Candidate {
+    /// The Stage 1 event for this candidate
+    pub fn event(&self) -> DetectionEvent {
+        DetectionEvent::Detection {
+            detection_id: self.id,
+            confidence: self.confidence,
+            audio_wav_base64: None,
+        }
+    }
+
+    /// The Stage 1 event with the candidate's audio (at `sample_rate`) embedded
+    ///
+    /// The WAV is capped at `max_bytes`; see `wav_base64`.
+    pub fn event_with_audio(&self, sample_rate: u32, max_bytes: usize) -> Result<DetectionEvent> {
+        Ok(DetectionEvent::Detection {
+            detection_id: self.id,
+            confidence: self.confidence,
+            audio_wav_base64: Some(wav_base64(&self.audio, sample_rate, max_bytes)?),
+        })
+    }
+}
+
+/// When each step between detection and acknowledgement happened
+///
+/// `WakePipeline` fills in detection and the Stage 2 bounds; the caller
+/// calls `acknowledge` once it has responded (beep, "ready" message), so
+/// `breakdown` covers the whole wake-to-response path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetectionTiming {
+    pub detected: Instant,
+    pub stage2_started: Option<Instant>,
+    pub stage2_finished: Option<Instant>,
+    pub acknowledged: Option<Instant>,
+}
+
+impl DetectionTiming {
+    /// Start timing at a detection
+    pub fn new(detected: Instant) -> Self {
+        Self {
+            detected,
+            stage2_started: None,
+            stage2_finished: None,
+            acknowledged: None,
+        }
+    }
+
+    /// Mark the response to the wake word as given
+    pub fn acknowledge(&mut self) {
+        self.acknowledged = Some(Instant::now());
+    }
+
+    /// Time from detection to acknowledgement, once acknowledged
+    pub fn total(&self) -> Option<Duration> {
+        Some(self.acknowledged? - self.detected)
+    }
+
+    /// One-line summary, e.g. `queue 1 ms | Stage 2 412 ms | ack 0 ms | total 413 ms`
+    ///
+    /// Steps that didn't happen (no Stage 2, not acknowledged yet) are left out.
+    pub fn breakdown(&self) -> String {
+        let ms = |from: Instant, to: Instant| format!("{} ms", (to - from).as_millis());
+        let mut parts = Vec::new();
+        let mut last = self.detected;
+        if let (Some(start), Some(end)) = (self.stage2_started, self.stage2_finished) {
+            parts.push(format!("queue {}", ms(self.detected, start)));
+            parts.push(format!("Stage 2 {}", ms(start, end)));
+            last = end;
+        }
+        if let Some(ack) = self.acknowledged {
+            parts.push(format!("ack {}", ms(last, ack)));
+            parts.push(format!("total {}", ms(self.detected, ack)));
+        }
+        parts.join(" | ")
+    }
+}
+
+/// Rolling-window wake word detection with optional Stage 2 confirmation
+///
+/// Stage 1 matches the newest template-length stretch of audio, using MFCC
+/// frames cached as the audio arrives rather than recomputed every check.
+pub struct WakePipeline {
+    detector: WakeWordDetector,
+    wake_word: String,
+    sample_rate: u32,
+    transcriber: Option<Box<dyn Transcriber>>,
+    window: VecDeque<f32>,
+    window_len: usize,
+    resampler: StreamResampler, // To the detector's rate, for Stage 1 only
+    frames: FrameCache,         // MFCC frames of the stream, so each is computed once
+    check_every: usize,         // Run Stage 1 every 100 ms of audio...
+    since_check: usize,
+    detect_every_frames: Option<usize>, // ...or every this many MFCC frames
+    frames_since_check: usize,
+    speech_gate: Option<SpeechGate>, // Skips Stage 1 while nobody speaks
+    bypass_stage1: bool,
+    was_speaking: bool,       // Whether the gate was open after the last push
+    since_candidate: usize,   // Samples since the last candidate, for bypass without a gate
+    stage1_best: (bool, f32), // Best Stage 1 result during the bypassed utterance
+    cooldown: Duration,
+    last_detection: Option<Instant>,
+    confirm_phrase: Option<String>,
+    confirm_window: usize,       // Follow-up length in samples
+    follow_up: Option<FollowUp>, // Some while recording the follow-up
+    detections: u64,             // Id of the latest candidate
+}
+
+/// The confirm phrase follow-up to a confirmed wake word
+struct FollowUp {
+    detection_id: u64, // The wake word it follows
+    audio: Vec<f32>,
+}
+
+impl WakePipeline {
+    /// Create a pipeline for mono audio at `sample_rate`, without Stage 2
+    ///
+    /// Audio at a rate other than the detector's (say, 48 kHz from the
+    /// device) is resampled for Stage 1; Stage 2 still gets it at
+    /// `sample_rate`.
+    pub fn new(mut detector: WakeWordDetector, wake_word: &str, sample_rate: u32) -> Self {
+        // Stage 1 reports the main template under the wake word
+        detector.set_name(&wake_word.to_lowercase());
+        let window_len = WINDOW_SECS * sample_rate as usize;
+        let resampler = StreamResampler::new(sample_rate, detector.sample_rate());
+        let frames = detector.frame_cache();
+        Self {
+            detector,
+            wake_word: wake_word.to_lowercase(),
+            sample_rate,
+            transcriber: None,
+            window: VecDeque::with_capacity(window_len),
+            window_len,
+            resampler,
+            frames,
+            check_every: (sample_rate as usize / 10).max(1),
+            since_check: 0,
+            detect_every_frames: None,
+            frames_since_check: 0,
+            speech_gate: None,
+            bypass_stage1: false,
+            was_speaking: false,
+            since_candidate: 0,
+            stage1_best: (false, 0.0),
+            cooldown: DEFAULT_COOLDOWN,
+            last_detection: None,
+            confirm_phrase: None,
+            confirm_window: 0,
+            follow_up: None,
+            detections: 0,
+        }
+    }
+
+    /// Enable Stage 2 confirmation through `transcriber`
+    ///
+    /// Usually `WhisperConfig::transcriber`, the configured service, which
+    /// holds Stage 2 audio to the upload limit; wrap any other transcriber
+    /// in `UploadLimit` to do the same.
+    pub fn set_transcriber(&mut self, transcriber: Box<dyn Transcriber>) {
+        self.transcriber = Some(transcriber);
+    }
+
+    /// Rate of the audio fed to `push`, and so of each candidate's audio
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// NaN and infinite input repaired since the last call
+    ///
+    /// See `WakeWordDetector::take_repairs`; nothing is printed otherwise.
+    pub fn take_repairs(&self) -> Repairs {
+        self.detector.take_repairs()
+    }
+
+    /// Whether candidates are confirmed by Stage 2
+    pub fn confirms(&self) -> bool {
+        self.transcriber.is_some()
+    }
+
+    /// Set how long to ignore the wake word after a detection
+    pub fn set_cooldown(&mut self, cooldown: Duration) {
+        self.cooldown = cooldown;
+    }
+
+    /// Require `phrase` to be said within `window` after a confirmed wake word
+    ///
+    /// The follow-up is transcribed with the Stage 2 transcriber, so this
+    /// has no effect without one. `None` turns the requirement off.
+    pub fn set_confirm_phrase(&mut self, phrase: Option<&str>, window: Duration) {
+        self.confirm_phrase = phrase.map(str::to_string);
+        self.confirm_window = (window.as_secs_f32() * self.sample_rate as f32) as usize;
+        self.follow_up = None;
+    }
+
+    /// Whether the follow-up for the confirm phrase is being recorded
+    pub fn awaiting_phrase(&self) -> bool {
+        self.follow_up.is_some()
+    }
+
+    /// Run Stage 1 every `frames` MFCC frames instead of every 100 ms
+    ///
+    /// A frame is cut every `hop_size` samples, so the interval is
+    /// `frames * hop_size / sample_rate` seconds: 8 frames with the default
+    /// 128-sample hop is 64 ms at 16 kHz. Counting frames keeps the cadence
+    /// tied to the features DTW sees rather than to how the audio happens to
+    /// arrive in chunks.
+    pub fn set_detect_every_frames(&mut self, frames: usize) {
+        self.detect_every_frames = Some(frames.max(1));
+    }
+
+    /// Only run Stage 1 while `gate` (for audio at the pipeline's rate) is open
+    ///
+    /// MFCC frames are still cached as audio arrives, since that is cheap and
+    /// a match needs the frames from before the gate opened; the DTW match,
+    /// the expensive part, is skipped during silence. `None` checks every time.
+    pub fn set_speech_gate(&mut self, gate: Option<SpeechGate>) {
+        self.speech_gate = gate;
+    }
+
+    /// Send every utterance to Stage 2, whatever Stage 1 makes of it
+    ///
+    /// For measuring Stage 1's recall: each utterance becomes a candidate
+    /// once the speech gate closes after it (without a gate, every window of
+    /// audio does). Stage 1 still runs on its usual schedule during the
+    /// utterance; the candidate's `confidence` is its best score and
+    /// `stage1_passed` whether it would have fired. Every candidate is a
+    /// transcription call, so this is far more expensive than normal
+    /// listening.
+    pub fn set_bypass_stage1(&mut self, bypass: bool) {
+        self.bypass_stage1 = bypass;
+    }
+
+    /// Whether Stage 1 is bypassed
+    pub fn bypasses_stage1(&self) -> bool {
+        self.bypass_stage1
+    }
+
+    /// Feed captured samples; returns a candidate when Stage 1 fires
+    ///
+    /// While the confirm phrase follow-up is recording, the samples go to it
+    /// instead and Stage 1 doesn't run.
+    pub fn push(&mut self, samples: &[f32]) -> Result<Option<Candidate>> {
+        if let Some(ref mut follow_up) = self.follow_up {
+            follow_up.audio.extend_from_slice(samples);
+            return Ok(None);
+        }
+        self.window.extend(samples.iter().copied());
+        let excess = self.window.len().saturating_sub(self.window_len);
+        self.window.drain(..excess);
+        let resampled;
+        let features_input = if self.resampler.is_passthrough() {
+            samples
+        } else {
+            resampled = self.resampler.process(samples);
+            &resampled
+        };
+        let new_frames = self.detector.update_cache(&mut self.frames, features_input);
+
+        let speaking = match self.speech_gate {
+            Some(ref mut gate) => gate.push(samples),
+            None => true,
+        };
+        self.since_candidate += samples.len();
+        if self.bypass_stage1 {
+            if speaking && self.check_due(samples.len(), new_frames) {
+                let confidence = self.detector.confidence_cached(&self.frames)?;
+                self.stage1_best.0 |= confidence >= self.detector.threshold();
+                self.stage1_best.1 = self.stage1_best.1.max(confidence);
+            }
+            return Ok(self.bypass_candidate(speaking));
+        }
+
+        if !self.check_due(samples.len(), new_frames) || self.in_cooldown() || !speaking {
+            return Ok(None);
+        }
+
+        // The main template and any named wake words; the most confident wins
+        let Some((word, confidence)) = self.detector.detect_cached(&self.frames)? else {
+            return Ok(None);
+        };
+        Ok(Some(self.candidate(word, confidence, true)))
+    }
+
+    /// With Stage 1 bypassed, a candidate for each utterance once it ends
+    fn bypass_candidate(&mut self, speaking: bool) -> Option<Candidate> {
+        let ended = if self.speech_gate.is_some() {
+            let ended = self.was_speaking && !speaking;
+            self.was_speaking = speaking;
+            ended
+        } else {
+            self.since_candidate >= self.window_len
+        };
+        if !ended {
+            return None;
+        }
+        let (passed, confidence) = std::mem::replace(&mut self.stage1_best, (false, 0.0));
+        let word = self.wake_word.clone();
+        Some(self.candidate(word, confidence, passed))
+    }
+
+    /// Number a new candidate holding the current window
+    fn candidate(&mut self, word: String, confidence: f32, stage1_passed: bool) -> Candidate {
+        let detected = Instant::now();
+        self.last_detection = Some(detected);
+        self.detections += 1;
+        self.since_candidate = 0;
+        Candidate {
+            id: self.detections,
+            word,
+            confidence,
+            stage1_passed,
+            audio: self.window.iter().copied().collect(),
+            timing: DetectionTiming::new(detected),
+        }
+    }
+
+    /// Run Stage 2 on a candidate; `None` when confirmation is disabled
+    ///
+    /// The start and end of Stage 2 are recorded in `candidate.timing`.
+    /// Whatever the outcome, the cooldown restarts once Stage 2 returns and
+    /// audio captured while it ran is discarded, so a slow or failing
+    /// endpoint drops straight back to listening instead of re-triggering on
+    /// stale audio.
+    pub fn confirm(&mut self, candidate: &mut Candidate) -> Option<DetectionEvent> {
+        let transcriber = self.transcriber.as_ref()?;
+        candidate.timing.stage2_started = Some(Instant::now());
+        let result = AudioClip::from_samples(&candidate.audio, self.sample_rate)
+            .and_then(|clip| transcriber.transcribe(clip.bytes));
+        let finished = Instant::now();
+        candidate.timing.stage2_finished = Some(finished);
+
+        self.last_detection = Some(finished);
+        self.window.clear();
+        self.frames.clear();
+        self.resampler.reset();
+        if let Some(ref mut gate) = self.speech_gate {
+            gate.reset();
+        }
+        self.was_speaking = false;
+        self.since_check = 0;
+        self.frames_since_check = 0;
+
+        Some(match result {
+            Ok(text) => {
+                let confirmed = text.to_lowercase().contains(&candidate.word.to_lowercase());
+                if confirmed && self.confirm_phrase.is_some() {
+                    self.follow_up = Some(FollowUp {
+                        detection_id: candidate.id,
+                        audio: Vec::with_capacity(self.confirm_window),
+                    });
+                }
+                DetectionEvent::Transcription {
+                    detection_id: candidate.id,
+                    text: text.trim().to_string(),
+                    confirmed,
+                }
+            }
+            Err(e) => DetectionEvent::Error {
+                detection_id: Some(candidate.id),
+                message: format!("{:#}", e),
+            },
+        })
+    }
+
+    /// Check the confirm phrase once its follow-up window is full
+    ///
+    /// Call after each `push`. Returns `None` until the whole window has been
+    /// recorded; then transcribes it and reports whether the phrase was in
+    /// it, and the pipeline goes back to listening after the cooldown.
+    pub fn check_confirm_phrase(&mut self) -> Option<DetectionEvent> {
+        if self.follow_up.as_ref()?.audio.len() < self.confirm_window {
+            return None;
+        }
+        let follow_up = self.follow_up.take()?;
+        let phrase = self.confirm_phrase.as_deref()?;
+        let transcriber = self.transcriber.as_ref()?;
+        let result = AudioClip::from_samples(&follow_up.audio, self.sample_rate)
+            .and_then(|clip| transcriber.transcribe(clip.bytes));
+
+        self.last_detection = Some(Instant::now());
+        self.frames.clear();
+        self.resampler.reset();
+        if let Some(ref mut gate) = self.speech_gate {
+            gate.reset();
+        }
+
+        Some(match result {
+            Ok(text) => DetectionEvent::ConfirmPhrase {
+                detection_id: follow_up.detection_id,
+                matched: phrase_matches(&text, phrase),
+                text: text.trim().to_string(),
+            },
+            Err(e) => DetectionEvent::Error {
+                detection_id: Some(follow_up.detection_id),
+                message: format!("{:#}", e),
+            },
+        })
+    }
+
+    /// Count new audio towards the next check; true when a check is due
+    fn check_due(&mut self, new_samples: usize, new_frames: usize) -> bool {
+        match self.detect_every_frames {
+            Some(every) => {
+                self.frames_since_check += new_frames;
+                if self.frames_since_check < every {
+                    return false;
+                }
+                self.frames_since_check = 0;
+            }
+            None => {
+                self.since_check += new_samples;
+                if self.since_check < self.check_every {
+                    return false;
+                }
+                self.since_check = 0;
+            }
+        }
+        true
+    }
+
+    fn in_cooldown(&self) -> bool {
+        self.last_detection
+            .is_some_and(|t| t.elapsed() < self.cooldown)
+    }
+}
+
+/// Whether `phrase` was said in `transcript`, allowing for small
+/// transcription errors
+///
+/// Case and punctuation are ignored. Each run of words about as long as the
+/// phrase is compared to it by edit distance, so "confirm delete" matches
+/// "Confirmed, delete." but not "cancel".
+pub fn phrase_matches(transcript: &str, phrase: &str) -> bool {
+    let words = |text: &str| -> Vec<String> {
+        text.split_whitespace()
+            .map(|word| {
+                word.chars()
+                    .filter(|c| c.is_alphanumeric())
+                    .flat_map(char::to_lowercase)
+                    .collect::<String>()
+            })
+            .filter(|word| !word.is_empty())
+            .collect()
+    };
+    let transcript = words(transcript);
+    let phrase = words(phrase).join(" ");
+    if phrase.is_empty() {
+        return true;
+    }
+    let len = phrase.split(' ').count();
+
+    // Also try one word more or less, for words split or merged in transcription
+    (len.saturating_sub(1).max(1)..=len + 1).any(|n| {
+        transcript
+            .windows(n.min(transcript.len()).max(1))
+            .any(|run| similarity(&run.join(" "), &phrase) >= PHRASE_MATCH_THRESHOLD)
+    })
+}
+
+/// 1.0 for identical strings, falling with each character edit
+pub(crate) fn similarity(a: &str, b: &str) -> f32 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    // Levenshtein distance, one row at a time
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    1.0 - row[b.len()] as f32 / longest as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcriber::{MockTranscriber, UploadLimit};
+    use crate::wake_word::chirp;
+
+    /// A second of silence then a one second chirp, filling the 2 s window
+    fn utterance() -> Vec<f32> {
+        std::iter::repeat_n(0.0, 16000)
+            .chain(chirp(300.0, 1500.0, 1.0))
+            .collect()
+    }
+
+    #[test]
+    fn test_timing_breakdown() {
+        let detected = Instant::now();
+        let at = |ms: u64| detected + Duration::from_millis(ms);
+        let mut timing = DetectionTiming::new(detected);
+        assert_eq!(timing.breakdown(), "");
+
+        timing.stage2_started = Some(at(2));
+        timing.stage2_finished = Some(at(402));
+        timing.acknowledged = Some(at(405));
+        assert_eq!(
+            timing.breakdown(),
+            "queue 2 ms | Stage 2 400 ms | ack 3 ms | total 405 ms"
+        );
+        assert_eq!(timing.total(), Some(Duration::from_millis(405)));
+    }
+
+    #[test]
+    fn test_detect_every_frames_sets_cadence() {
+        let mut pipeline = WakePipeline::new(WakeWordDetector::new(), "computer", 16000);
+
+        // By default a check is due every 100 ms of audio
+        assert!(!pipeline.check_due(1000, 7));
+        assert!(pipeline.check_due(600, 5));
+
+        // In frames, independent of how the samples arrive
+        pipeline.set_detect_every_frames(8);
+        let due: Vec<bool> = (0..16).map(|_| pipeline.check_due(128, 1)).collect();
+        assert_eq!(due.iter().filter(|&&d| d).count(), 2);
+        assert!(due[7] && due[15]);
+        assert!(!pipeline.check_due(4000, 0));
+    }
+
+    #[test]
+    fn test_speech_gate_skips_stage1_on_silence() {
+        let wake_word = utterance();
+        let detector = || {
+            let mut detector = WakeWordDetector::new();
+            detector
+                .train_template(std::slice::from_ref(&wake_word))
+                .unwrap();
+            // Any check at all would fire
+            detector.set_threshold(0.0);
+            detector
+        };
+        let hiss: Vec<f32> = (0..32000)
+            .map(|i| ((i * 7919) % 200) as f32 / 200.0 * 0.002 - 0.001)
+            .collect();
+
+        let mut ungated = WakePipeline::new(detector(), "computer", 16000);
+        assert!(ungated.push(&hiss).unwrap().is_some());
+
+        let mut pipeline = WakePipeline::new(detector(), "computer", 16000);
+        pipeline.set_speech_gate(Some(SpeechGate::new(16000, 0.02)));
+        for block in hiss.chunks(160) {
+            assert!(pipeline.push(block).unwrap().is_none());
+        }
+        // Speech opens the gate
+        assert!(wake_word
+            .chunks(160)
+            .any(|block| pipeline.push(block).unwrap().is_some()));
+    }
+
+    #[test]
+    fn test_device_rate_audio_is_resampled() {
+        let wake_word = utterance();
+        let mut detector = WakeWordDetector::new();
+        detector
+            .train_template(std::slice::from_ref(&wake_word))
+            .unwrap();
+        detector.set_threshold(0.5);
+
+        // The same words from a 48 kHz device, in 10 ms blocks
+        let captured = crate::resample::resample_linear(&wake_word, 16000, 48000);
+        let mut pipeline = WakePipeline::new(detector, "computer", 48000);
+        let candidate = captured
+            .chunks(480)
+            .find_map(|block| pipeline.push(block).unwrap())
+            .expect("stage 1 fires");
+        assert!(candidate.confidence > 0.5);
+        // Stage 2 gets the audio at the device rate
+        assert_eq!(candidate.audio.len(), WINDOW_SECS * 48000);
+    }
+
+    #[test]
+    fn test_listening_resumes_after_stage2_error() {
+        let wake_word = utterance();
+        let mut detector = WakeWordDetector::new();
+        detector
+            .train_template(std::slice::from_ref(&wake_word))
+            .unwrap();
+        detector.set_threshold(0.5);
+
+        // The first call fails, the next succeeds
+        let mut pipeline = WakePipeline::new(detector, "Computer", 16000);
+        pipeline.set_transcriber(Box::new(MockTranscriber::new([
+            Err("endpoint unreachable"),
+            Ok(" Computer, lights on. "),
+        ])));
+
+        let mut candidate = pipeline.push(&wake_word).unwrap().expect("stage 1 fires");
+        assert!(matches!(
+            pipeline.confirm(&mut candidate),
+            Some(DetectionEvent::Error {
+                detection_id: Some(1),
+                ..
+            })
+        ));
+
+        // The failure still applies the cooldown...
+        assert!(pipeline.push(&wake_word).unwrap().is_none());
+
+        // ...and once it has passed the pipeline is listening again
+        pipeline.set_cooldown(Duration::ZERO);
+        let mut candidate = pipeline
+            .push(&wake_word)
+            .unwrap()
+            .expect("stage 1 fires again");
+        assert_eq!(
+            pipeline.confirm(&mut candidate),
+            Some(DetectionEvent::Transcription {
+                detection_id: 2,
+                text: "Computer, lights on.".to_string(),
+                confirmed: true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_stage2_respects_upload_limit() {
+        let wake_word = utterance();
+        let mut detector = WakeWordDetector::new();
+        detector
+            .train_template(std::slice::from_ref(&wake_word))
+            .unwrap();
+        detector.set_threshold(0.5);
+
+        // Far too small for the window, even re-encoded at 16 kHz
+        let mut pipeline = WakePipeline::new(detector, "computer", 16000);
+        pipeline.set_transcriber(Box::new(UploadLimit::new(
+            Box::new(MockTranscriber::new([Ok("Computer")])),
+            1000,
+        )));
+
+        let mut candidate = pipeline.push(&wake_word).unwrap().expect("stage 1 fires");
+        match pipeline.confirm(&mut candidate) {
+            Some(DetectionEvent::Error {
+                detection_id: Some(1),
+                message,
+            }) => assert!(message.contains("Recording too large"), "{}", message),
+            other => panic!("expected an upload error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_bypass_sends_speech_to_stage2() {
+        let mut detector = WakeWordDetector::new();
+        detector.train_template(&[utterance()]).unwrap();
+        // Nothing real gets past Stage 1
+        detector.set_threshold(0.99);
+
+        // Half a second of a tone that isn't the template, then silence
+        let tone = (0..8000).map(|i| (i as f32 * 0.17).sin() * 0.5);
+        let speech: Vec<f32> = tone.chain(std::iter::repeat_n(0.0, 16000)).collect();
+
+        let mut pipeline = WakePipeline::new(detector, "computer", 16000);
+        pipeline.set_speech_gate(Some(SpeechGate::new(16000, 0.02)));
+        assert!(speech
+            .chunks(160)
+            .all(|block| pipeline.push(block).unwrap().is_none()));
+
+        pipeline.set_transcriber(Box::new(MockTranscriber::new([Ok("Computer?")])));
+        pipeline.set_bypass_stage1(true);
+        let candidates: Vec<Candidate> = speech
+            .chunks(160)
+            .filter_map(|block| pipeline.push(block).unwrap())
+            .collect();
+        assert_eq!(candidates.len(), 1, "one candidate per utterance");
+        let mut candidate = candidates.into_iter().next().unwrap();
+        assert!(!candidate.stage1_passed);
+        assert!(candidate.confidence < 0.99);
+
+        // Stage 2 and its confirmation logic run as usual
+        assert_eq!(
+            pipeline.confirm(&mut candidate),
+            Some(DetectionEvent::Transcription {
+                detection_id: 1,
+                text: "Computer?".to_string(),
+                confirmed: true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_phrase_matching_is_fuzzy() {
+        assert!(phrase_matches("Confirm delete.", "confirm delete"));
+        assert!(phrase_matches(
+            "Yes, confirmed delete please",
+            "confirm delete"
+        ));
+        assert!(phrase_matches("confirm de lete", "confirm delete"));
+        assert!(!phrase_matches("cancel", "confirm delete"));
+        assert!(!phrase_matches("confirm", "confirm delete"));
+        assert!(!phrase_matches("", "confirm delete"));
+        assert!(similarity("kitten", "sitting") < 0.6);
+    }
+
+    #[test]
+    fn test_confirm_phrase_follows_the_wake_word() {
+        let wake_word = utterance();
+        let mut detector = WakeWordDetector::new();
+        detector
+            .train_template(std::slice::from_ref(&wake_word))
+            .unwrap();
+        detector.set_threshold(0.5);
+
+        // The wake word, then the follow-up
+        let mut pipeline = WakePipeline::new(detector, "computer", 16000);
+        pipeline.set_transcriber(Box::new(MockTranscriber::new([
+            Ok("Computer"),
+            Ok("Confirm, delete."),
+        ])));
+        pipeline.set_confirm_phrase(Some("confirm delete"), Duration::from_secs(1));
+
+        // Every stage reports the same detection
+        let mut candidate = pipeline.push(&wake_word).unwrap().expect("stage 1 fires");
+        let detection = candidate.event();
+        assert_eq!(detection.detection_id(), Some(1));
+        let stage2 = pipeline.confirm(&mut candidate).unwrap();
+        assert_eq!(stage2.detection_id(), detection.detection_id());
+        assert!(pipeline.awaiting_phrase());
+
+        // Nothing is reported until the whole window has been recorded
+        assert!(pipeline.push(&[0.0; 8000]).unwrap().is_none());
+        assert_eq!(pipeline.check_confirm_phrase(), None);
+        pipeline.push(&[0.0; 8000]).unwrap();
+        assert_eq!(
+            pipeline.check_confirm_phrase(),
+            Some(DetectionEvent::ConfirmPhrase {
+                detection_id: 1,
+                text: "Confirm, delete.".to_string(),
+                matched: true,
+            })
+        );
+        assert!(!pipeline.awaiting_phrase());
+    }
+
+    #[test]
+    fn test_confirm_phrase_reports_the_wake_word_it_follows() {
+        let wake_word = utterance();
+        let mut detector = WakeWordDetector::new();
+        detector
+            .train_template(std::slice::from_ref(&wake_word))
+            .unwrap();
+        detector.set_threshold(0.5);
+        let mut pipeline = WakePipeline::new(detector, "computer", 16000);
+        pipeline.set_transcriber(Box::new(MockTranscriber::new([
+            Ok("Computer"),
+            Ok("Confirm delete"),
+        ])));
+        pipeline.set_confirm_phrase(Some("confirm delete"), Duration::from_secs(1));
+
+        // A second candidate comes along before the first is confirmed
+        pipeline.set_cooldown(Duration::ZERO);
+        let mut first = pipeline.push(&wake_word).unwrap().expect("stage 1 fires");
+        let second = pipeline
+            .push(&wake_word)
+            .unwrap()
+            .expect("stage 1 fires again");
+        assert_eq!((first.id, second.id), (1, 2));
+        pipeline.confirm(&mut first).unwrap();
+
+        pipeline.push(&[0.0; 16000]).unwrap();
+        assert_eq!(
+            pipeline.check_confirm_phrase().unwrap().detection_id(),
+            Some(1)
+        );
+    }
+}
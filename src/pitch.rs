@@ -0,0 +1,126 @@
+//! Pitch (F0) estimation by autocorrelation.
+//!
+//! A voiced frame repeats itself every pitch period, so its normalized
+//! autocorrelation peaks at that lag. Only lags for 80–400 Hz are searched,
+//! which covers most speaking voices; the shortest lag whose peak is nearly
+//! as strong as the best is taken, so a period that repeats twice isn't
+//! mistaken for one an octave lower. Silence, noise and unvoiced consonants
+//! have no strong peak and give no pitch.
+
+/// Lowest and highest pitch searched, in Hz
+pub const MIN_PITCH_HZ: f32 = 80.0;
+pub const MAX_PITCH_HZ: f32 = 400.0;
+
+/// Frames quieter than this RMS (-60 dBFS) are silence
+const SILENCE_RMS: f32 = 1e-3;
+
+/// Weakest normalized autocorrelation peak that counts as voiced
+const VOICING_THRESHOLD: f32 = 0.5;
+
+/// A peak this close to the strongest counts as just as good, so shorter
+/// lags win over their multiples
+const OCTAVE_TOLERANCE: f32 = 0.9;
+
+/// The dominant pitch of `frame` in Hz, or `None` if it is silent or unvoiced
+///
+/// The frame needs at least two periods of the lowest pitch (25 ms);
+/// shorter frames give `None`.
+pub fn estimate_pitch(frame: &[f32], sample_rate: u32) -> Option<f32> {
+    let min_lag = (sample_rate as f32 / MAX_PITCH_HZ).floor().max(1.0) as usize;
+    let max_lag = (sample_rate as f32 / MIN_PITCH_HZ).ceil() as usize;
+    if frame.len() < 2 * max_lag {
+        return None;
+    }
+
+    let mean = frame.iter().sum::<f32>() / frame.len() as f32;
+    let centered: Vec<f32> = frame.iter().map(|x| x - mean).collect();
+    let energy = centered.iter().map(|x| x * x).sum::<f32>();
+    if (energy / centered.len() as f32).sqrt() < SILENCE_RMS {
+        return None;
+    }
+
+    // Normalized by the energy of both overlapping parts, so longer lags
+    // (with less overlap) aren't penalized
+    let correlation = |lag: usize| {
+        let (head, tail) = (&centered[..centered.len() - lag], &centered[lag..]);
+        let product: f32 = head.iter().zip(tail).map(|(a, b)| a * b).sum();
+        let norm = (head.iter().map(|x| x * x).sum::<f32>()
+            * tail.iter().map(|x| x * x).sum::<f32>())
+        .sqrt();
+        if norm > 0.0 {
+            product / norm
+        } else {
+            0.0
+        }
+    };
+    // One lag either side of the range, for finding peaks at its ends
+    let values: Vec<f32> = (min_lag - 1..=max_lag + 1).map(correlation).collect();
+    let value = |lag: usize| values[lag + 1 - min_lag];
+
+    let peaks: Vec<usize> = (min_lag..=max_lag)
+        .filter(|&lag| value(lag) >= value(lag - 1) && value(lag) >= value(lag + 1))
+        .collect();
+    let best = peaks.iter().map(|&lag| value(lag)).fold(f32::MIN, f32::max);
+    if best < VOICING_THRESHOLD {
+        return None;
+    }
+    let lag = *peaks
+        .iter()
+        .find(|&&lag| value(lag) >= best * OCTAVE_TOLERANCE)?;
+
+    // Fit a parabola through the peak and its neighbours for a fractional lag
+    let (before, at, after) = (value(lag - 1), value(lag), value(lag + 1));
+    let curvature = before - 2.0 * at + after;
+    let offset = if curvature < 0.0 {
+        (0.5 * (before - after) / curvature).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+    Some(sample_rate as f32 / (lag as f32 + offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn tone(hz: f32, sample_rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| 0.3 * (2.0 * PI * hz * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_estimate_pitch() {
+        let pitch = estimate_pitch(&tone(150.0, 16000, 640), 16000).unwrap();
+        assert!((pitch - 150.0).abs() < 1.5, "{}", pitch);
+        let pitch = estimate_pitch(&tone(220.0, 44100, 2048), 44100).unwrap();
+        assert!((pitch - 220.0).abs() < 1.5, "{}", pitch);
+
+        // A voice with strong harmonics is still heard at its fundamental
+        let voice: Vec<f32> = tone(120.0, 16000, 800)
+            .iter()
+            .zip(tone(240.0, 16000, 800))
+            .zip(tone(360.0, 16000, 800))
+            .map(|((a, b), c)| a + b + c)
+            .collect();
+        let pitch = estimate_pitch(&voice, 16000).unwrap();
+        assert!((pitch - 120.0).abs() < 1.5, "{}", pitch);
+    }
+
+    #[test]
+    fn test_no_pitch_without_voicing() {
+        // A fixed LCG, so the noise is the same every run
+        let mut state = 12345u32;
+        let noise: Vec<f32> = (0..640)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+            })
+            .collect();
+        assert_eq!(estimate_pitch(&noise, 16000), None);
+        assert_eq!(estimate_pitch(&[0.0; 640], 16000), None);
+        // Too short to hold two periods of 80 Hz
+        assert_eq!(estimate_pitch(&tone(150.0, 16000, 300), 16000), None);
+    }
+}
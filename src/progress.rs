@@ -0,0 +1,71 @@
+//! Terminal progress feedback for long-running network calls.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Spinner with an elapsed-time counter drawn on stderr
+///
+/// The spinner runs on a background thread until it is dropped (or
+/// `finish` is called), at which point the line is cleared. It draws nothing
+/// when disabled or when stderr is not a terminal, so it never pollutes logs.
+pub struct Spinner {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    /// Start a spinner showing `message`, unless `enabled` is false
+    pub fn start(message: &str, enabled: bool) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+
+        if !enabled || !io::stderr().is_terminal() {
+            return Self { stop, handle: None };
+        }
+
+        let stop_clone = Arc::clone(&stop);
+        let message = message.to_string();
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let mut frame = 0;
+            while !stop_clone.load(Ordering::Relaxed) {
+                eprint!(
+                    "\r{} {} ({:.1}s)",
+                    FRAMES[frame % FRAMES.len()],
+                    message,
+                    start.elapsed().as_secs_f32()
+                );
+                io::stderr().flush().ok();
+                frame += 1;
+                thread::sleep(TICK_INTERVAL);
+            }
+            // Clear the spinner line
+            eprint!("\r{}\r", " ".repeat(message.len() + 16));
+            io::stderr().flush().ok();
+        });
+
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Stop the spinner and clear its line
+    pub fn finish(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
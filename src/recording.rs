@@ -0,0 +1,564 @@
+//! Audio capture from an input device
+
+use crate::convert::{i16_to_f32, u16_to_i16, ToF32};
+use crate::device::stream_config;
+use crate::loudness::integrated_loudness;
+use crate::mix::ChannelMix;
+use crate::wav::{write_i16_as, write_sample_as, WavEncoding};
+use anyhow::{Context, Result};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::SizedSample;
+use hound::WavWriter;
+use std::io::{Cursor, Seek, Write};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Environment variable with the recording length in seconds, e.g. `1.5`
+pub const RECORD_DURATION_VAR: &str = "RECORD_DURATION";
+
+/// Recording length when `RECORD_DURATION` isn't set
+pub const DEFAULT_RECORD_DURATION: Duration = Duration::from_secs(5);
+
+/// Longest recording allowed
+pub const MAX_RECORD_DURATION: Duration = Duration::from_secs(3600);
+
+/// Parse a recording length in (possibly fractional) seconds
+///
+/// Must be positive and at most `MAX_RECORD_DURATION`.
+pub fn parse_record_duration(secs: &str) -> Result<Duration> {
+    let secs: f64 = secs
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid duration \"{}\", expected seconds", secs.trim()))?;
+    if !(secs > 0.0 && secs <= MAX_RECORD_DURATION.as_secs_f64()) {
+        anyhow::bail!(
+            "Duration must be above 0 and at most {} seconds, got {}",
+            MAX_RECORD_DURATION.as_secs(),
+            secs
+        );
+    }
+    Ok(Duration::from_secs_f64(secs))
+}
+
+/// Levels of a recording
+///
+/// Peak and RMS are in linear full-scale units (1.0 = 0 dBFS). Loudness is
+/// the integrated loudness in LUFS (see `loudness`), which follows what the
+/// ear hears and is what to normalize to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordingStats {
+    pub duration: Duration,
+    pub peak: f32,
+    pub rms: f32,
+    pub loudness_lufs: Option<f32>, // None for silence
+}
+
+impl RecordingStats {
+    /// Measure mono `samples` at `sample_rate`
+    pub fn measure(samples: &[f32], sample_rate: u32) -> Self {
+        let power =
+            samples.iter().map(|&x| x as f64 * x as f64).sum::<f64>() / samples.len().max(1) as f64;
+        Self {
+            duration: Duration::from_secs_f64(samples.len() as f64 / sample_rate as f64),
+            peak: samples.iter().fold(0.0, |peak, x| x.abs().max(peak)),
+            rms: power.sqrt() as f32,
+            loudness_lufs: integrated_loudness(samples, sample_rate),
+        }
+    }
+
+    /// Gain in dB that brings the recording to `target_lufs`, e.g. -23.0
+    pub fn gain_to(&self, target_lufs: f32) -> Option<f32> {
+        self.loudness_lufs.map(|loudness| target_lufs - loudness)
+    }
+
+    /// One line for logs, e.g. `peak -3.1 dBFS, RMS -18.6 dBFS, loudness -17.9 LUFS`
+    pub fn summary(&self) -> String {
+        let loudness = match self.loudness_lufs {
+            Some(lufs) => format!("{:.1} LUFS", lufs),
+            None => "silent".to_string(),
+        };
+        format!(
+            "peak {:.1} dBFS, RMS {:.1} dBFS, loudness {}",
+            20.0 * self.peak.log10(),
+            20.0 * self.rms.log10(),
+            loudness
+        )
+    }
+}
+
+/// Samples collected between `start` and `stop`
+#[derive(Debug, Default)]
+struct Capture {
+    active: bool,
+    samples: Vec<f32>,
+}
+
+impl Capture {
+    fn start(&mut self) {
+        // Anything left from a previous capture must not bleed into this one
+        self.samples.clear();
+        self.active = true;
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        if self.active {
+            self.samples.extend_from_slice(samples);
+        }
+    }
+
+    fn take(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    fn stop(&mut self) -> Vec<f32> {
+        self.active = false;
+        self.take()
+    }
+}
+
+/// An input stream that stays open across recordings
+///
+/// Opening and closing a stream for every recording causes audible
+/// glitches and extra latency on some drivers. The recorder opens the device
+/// once; `start` and `stop` only mark where each recording begins and ends.
+/// Audio between recordings is discarded.
+pub struct AudioRecorder {
+    _stream: cpal::Stream,
+    capture: Arc<Mutex<Capture>>,
+    sample_rate: u32,
+}
+
+impl AudioRecorder {
+    /// Open `device` and start streaming, mixing to mono with `mix`
+    ///
+    /// `buffer_frames` is an optional buffer size hint (see `device::stream_config`).
+    pub fn open(
+        device: &cpal::Device,
+        config: &cpal::SupportedStreamConfig,
+        mix: ChannelMix,
+        buffer_frames: Option<u32>,
+    ) -> Result<Self> {
+        let capture = Arc::new(Mutex::new(Capture::default()));
+        let sink = Arc::clone(&capture);
+        let stream = open_mono_stream(device, config, mix, buffer_frames, move |mono| {
+            sink.lock().unwrap().push(&mono)
+        })?;
+
+        Ok(Self {
+            _stream: stream,
+            capture,
+            sample_rate: config.sample_rate().0,
+        })
+    }
+
+    /// Sample rate of the recorded (mono) audio
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Begin a new recording, discarding anything captured before
+    pub fn start(&self) {
+        self.capture.lock().unwrap().start();
+    }
+
+    /// End the recording and return its mono samples
+    pub fn stop(&self) -> Vec<f32> {
+        self.capture.lock().unwrap().stop()
+    }
+
+    /// The samples captured since `start` or the last `take`, without stopping
+    ///
+    /// For listening continuously, e.g. feeding a wake word detector.
+    pub fn take(&self) -> Vec<f32> {
+        self.capture.lock().unwrap().take()
+    }
+
+    /// Record for `duration`, or until `cancelled` returns true
+    pub fn record(&self, duration: Duration, cancelled: impl Fn() -> bool) -> Vec<f32> {
+        self.start();
+        let started = Instant::now();
+        while started.elapsed() < duration && !cancelled() {
+            thread::sleep(
+                Duration::from_millis(50).min(duration.saturating_sub(started.elapsed())),
+            );
+        }
+        self.stop()
+    }
+}
+
+/// Stream mono audio from `device`, sending each block down `tx` as it arrives
+///
+/// For listening continuously at the lowest latency, e.g. splitting speech
+/// into utterances. The stream runs until the returned handle is dropped.
+pub fn stream_samples(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    mix: ChannelMix,
+    buffer_frames: Option<u32>,
+    tx: Sender<Vec<f32>>,
+) -> Result<cpal::Stream> {
+    open_mono_stream(device, config, mix, buffer_frames, move |mono| {
+        tx.send(mono).ok();
+    })
+}
+
+/// Record `duration` of mono audio from `device`, mixing channels with `mix`
+///
+/// Use `device::select_input(None, false)` for the default input device.
+pub fn record_samples(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    mix: ChannelMix,
+    buffer_frames: Option<u32>,
+    duration: Duration,
+) -> Result<Vec<f32>> {
+    Ok(AudioRecorder::open(device, config, mix, buffer_frames)?.record(duration, || false))
+}
+
+/// How `record_wav` captures and encodes a recording
+#[derive(Debug, Clone, Default)]
+pub struct WavOptions {
+    /// Mix down to mono with these weights; every channel is kept if `None`
+    pub mix: Option<ChannelMix>,
+    /// Bit depth and sample type of the file
+    pub encoding: WavEncoding,
+    /// Buffer size hint in frames (see `device::stream_config`)
+    pub buffer_frames: Option<u32>,
+}
+
+/// Record `duration` from `device` as 16-bit WAV with every channel the device has
+///
+/// Use `device::select_input(None, false)` for the default input device.
+pub fn record_to_wav(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    duration: Duration,
+) -> Result<Vec<u8>> {
+    record_wav(device, config, duration, &WavOptions::default())
+}
+
+/// Record `duration` from `device` as WAV, captured and encoded as in `options`
+///
+/// Each block is encoded as it arrives, so only the WAV file is held in
+/// memory, not the raw samples as well.
+pub fn record_wav(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    duration: Duration,
+    options: &WavOptions,
+) -> Result<Vec<u8>> {
+    let stream_config = stream_config(config, options.buffer_frames);
+    if let Some(ref mix) = options.mix {
+        mix.validate(stream_config.channels as usize)?;
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::F32 => build_block_stream::<f32>(device, &stream_config, tx, |data| {
+            DeviceSamples::Float(data.to_vec())
+        })?,
+        cpal::SampleFormat::I16 => build_block_stream::<i16>(device, &stream_config, tx, |data| {
+            DeviceSamples::Int(data.to_vec())
+        })?,
+        cpal::SampleFormat::U16 => build_block_stream::<u16>(device, &stream_config, tx, |data| {
+            DeviceSamples::Int(data.iter().map(|&s| u16_to_i16(s)).collect())
+        })?,
+        _ => return Err(anyhow::anyhow!("Unsupported sample format")),
+    };
+
+    let mut cursor = Cursor::new(Vec::new());
+    {
+        let mut encoder = WavEncoder::new(
+            &mut cursor,
+            stream_config.channels,
+            config.sample_rate().0,
+            options,
+        )?;
+        stream.play()?;
+        let deadline = Instant::now() + duration;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            match rx.recv_timeout(remaining) {
+                Ok(block) => encoder.push(&block)?,
+                Err(_) => break,
+            }
+        }
+        // Keep whatever arrived before the stream stopped
+        drop(stream);
+        for block in rx.try_iter() {
+            encoder.push(&block)?;
+        }
+        encoder.finish()?;
+    }
+
+    Ok(cursor.into_inner())
+}
+
+/// A block of interleaved samples as the device delivered them
+enum DeviceSamples {
+    Float(Vec<f32>),
+    /// From integer devices, kept as-is so they can be written exactly
+    Int(Vec<i16>),
+}
+
+/// Writes a recording as WAV block by block, mixing it down to mono if the options say so
+///
+/// Integer samples are written exactly when nothing needs mixing, so a
+/// 16-bit device gives a bit-identical 16-bit file.
+struct WavEncoder<W: Write + Seek> {
+    writer: WavWriter<W>,
+    channels: u16,
+    mix: Option<ChannelMix>, // Only set when there is more than one channel
+    encoding: WavEncoding,
+}
+
+impl<W: Write + Seek> WavEncoder<W> {
+    fn new(writer: W, channels: u16, sample_rate: u32, options: &WavOptions) -> Result<Self> {
+        let spec = options.encoding.spec(
+            if options.mix.is_some() { 1 } else { channels },
+            sample_rate,
+        );
+        Ok(Self {
+            writer: WavWriter::new(writer, spec)?,
+            channels,
+            // Mono input is already mixed
+            mix: options.mix.clone().filter(|_| channels > 1),
+            encoding: options.encoding,
+        })
+    }
+
+    /// Write a block of whole frames
+    fn push(&mut self, block: &DeviceSamples) -> Result<()> {
+        let channels = self.channels as usize;
+        match (block, &self.mix) {
+            (DeviceSamples::Int(samples), None) => {
+                for &sample in samples {
+                    write_i16_as(&mut self.writer, sample, self.encoding)?;
+                }
+            }
+            (DeviceSamples::Float(samples), None) => {
+                for &sample in samples {
+                    write_sample_as(&mut self.writer, sample, self.encoding)?;
+                }
+            }
+            (DeviceSamples::Float(samples), Some(mix)) => {
+                for sample in mix.downmix(samples, channels) {
+                    write_sample_as(&mut self.writer, sample, self.encoding)?;
+                }
+            }
+            (DeviceSamples::Int(samples), Some(mix)) => {
+                let samples: Vec<f32> = samples.iter().map(|&s| i16_to_f32(s)).collect();
+                for sample in mix.downmix(&samples, channels) {
+                    write_sample_as(&mut self.writer, sample, self.encoding)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill in the header now that the length is known
+    fn finish(self) -> Result<()> {
+        self.writer.finalize()?;
+        Ok(())
+    }
+}
+
+/// Build an input stream that sends each block down `tx`, converted by `block`
+fn build_block_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    tx: Sender<DeviceSamples>,
+    block: impl Fn(&[T]) -> DeviceSamples + Send + 'static,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample + Send + 'static,
+{
+    let err_fn = |err| eprintln!("An error occurred on stream: {}", err);
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &_| {
+            tx.send(block(data)).ok();
+        },
+        err_fn,
+        None,
+    )?;
+    Ok(stream)
+}
+
+/// Open `device` and start streaming, passing each block to `sink` mixed to mono
+fn open_mono_stream(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    mix: ChannelMix,
+    buffer_frames: Option<u32>,
+    sink: impl FnMut(Vec<f32>) + Send + 'static,
+) -> Result<cpal::Stream> {
+    let stream_config = stream_config(config, buffer_frames);
+    mix.validate(stream_config.channels as usize)?;
+
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::F32 => build_mono_stream::<f32>(device, &stream_config, mix, sink)?,
+        cpal::SampleFormat::I16 => build_mono_stream::<i16>(device, &stream_config, mix, sink)?,
+        cpal::SampleFormat::U16 => build_mono_stream::<u16>(device, &stream_config, mix, sink)?,
+        _ => return Err(anyhow::anyhow!("Unsupported sample format")),
+    };
+    stream.play()?;
+    Ok(stream)
+}
+
+/// Build an input stream that passes mono blocks to `sink`
+fn build_mono_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mix: ChannelMix,
+    mut sink: impl FnMut(Vec<f32>) + Send + 'static,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample + ToF32,
+{
+    let channels = config.channels as usize;
+    let err_fn = |err| eprintln!("An error occurred on stream: {}", err);
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &_| {
+            let samples: Vec<f32> = data.iter().map(|&s| s.to_f32()).collect();
+            sink(mix.downmix(&samples, channels));
+        },
+        err_fn,
+        None,
+    )?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_stats() {
+        let tone: Vec<f32> = (0..32000)
+            .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 16000.0).sin())
+            .collect();
+        let stats = RecordingStats::measure(&tone, 16000);
+        assert_eq!(stats.duration, Duration::from_secs(2));
+        assert!((stats.peak - 0.5).abs() < 1e-3);
+        assert!((stats.rms - 0.5 / 2f32.sqrt()).abs() < 1e-3);
+        let gain = stats.gain_to(-23.0).unwrap();
+        assert!((gain + 14.0).abs() < 0.2, "{}", gain);
+        assert!(stats.summary().starts_with("peak -6.0 dBFS, RMS -9.0 dBFS"));
+
+        let silence = RecordingStats::measure(&[0.0; 1600], 16000);
+        assert_eq!(silence.gain_to(-23.0), None);
+        assert!(silence.summary().ends_with("loudness silent"));
+    }
+
+    #[test]
+    fn test_capture_keeps_recordings_separate() {
+        let mut capture = Capture::default();
+
+        // Audio before the first start is dropped
+        capture.push(&[9.0; 4]);
+        capture.start();
+        capture.push(&[1.0, 2.0]);
+        assert_eq!(capture.stop(), vec![1.0, 2.0]);
+
+        // Nor does audio between recordings reach the next one
+        capture.push(&[9.0; 4]);
+        capture.start();
+        capture.push(&[3.0]);
+        assert_eq!(capture.stop(), vec![3.0]);
+        assert!(capture.stop().is_empty());
+
+        // Taking drains the recording but keeps it going
+        capture.start();
+        capture.push(&[4.0]);
+        assert_eq!(capture.take(), vec![4.0]);
+        capture.push(&[5.0]);
+        assert_eq!(capture.stop(), vec![5.0]);
+    }
+
+    /// Encode `blocks` the way `record_wav` does
+    fn encode_wav(
+        blocks: &[DeviceSamples],
+        channels: u16,
+        sample_rate: u32,
+        options: &WavOptions,
+    ) -> Vec<u8> {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut encoder = WavEncoder::new(&mut cursor, channels, sample_rate, options).unwrap();
+        for block in blocks {
+            encoder.push(block).unwrap();
+        }
+        encoder.finish().unwrap();
+        cursor.into_inner()
+    }
+
+    #[test]
+    fn test_encode_wav() {
+        // Two blocks, as they would arrive from the device
+        let stereo = [
+            DeviceSamples::Int(vec![100, 300, -7, 7]),
+            DeviceSamples::Int(vec![i16::MIN, i16::MAX]),
+        ];
+
+        // Every channel, bit for bit
+        let wav = encode_wav(&stereo, 2, 16000, &WavOptions::default());
+        let mut reader = hound::WavReader::new(Cursor::new(wav)).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        let samples: Vec<i16> = reader.samples().map(|s| s.unwrap()).collect();
+        assert_eq!(samples, vec![100, 300, -7, 7, i16::MIN, i16::MAX]);
+
+        // Or mixed down to one
+        let options = WavOptions {
+            mix: Some(ChannelMix::default()),
+            ..Default::default()
+        };
+        let wav = encode_wav(&stereo, 2, 16000, &options);
+        let mut reader = hound::WavReader::new(Cursor::new(wav)).unwrap();
+        assert_eq!(reader.spec().channels, 1);
+        let samples: Vec<i16> = reader.samples().map(|s| s.unwrap()).collect();
+        assert_eq!(samples.len(), 3);
+        assert!((samples[0] - 200).abs() <= 1 && samples[1] == 0 && samples[2] <= 0);
+    }
+
+    #[test]
+    fn test_encode_wav_averages_stereo_to_mono() {
+        let stereo = [
+            DeviceSamples::Float(vec![0.5, 0.25, -1.0, 1.0]),
+            DeviceSamples::Float(vec![0.2, 0.4, -0.5, -0.3]),
+        ];
+        let options = WavOptions {
+            mix: Some(ChannelMix::default()),
+            encoding: WavEncoding::new(32, true).unwrap(),
+            ..Default::default()
+        };
+        let wav = encode_wav(&stereo, 2, 48000, &options);
+
+        let mut reader = hound::WavReader::new(Cursor::new(wav)).unwrap();
+        assert_eq!(reader.spec().channels, 1);
+        assert_eq!(reader.spec().sample_rate, 48000);
+        let mono: Vec<f32> = reader.samples().map(|s| s.unwrap()).collect();
+        assert_eq!(mono.len(), 4);
+        for (got, want) in mono.iter().zip([0.375, 0.0, 0.3, -0.4]) {
+            assert!((got - want).abs() < 1e-6, "{:?}", mono);
+        }
+    }
+
+    #[test]
+    fn test_parse_record_duration() {
+        assert_eq!(
+            parse_record_duration("1.5").unwrap(),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(
+            parse_record_duration(" 10 ").unwrap(),
+            Duration::from_secs(10)
+        );
+        assert!(parse_record_duration("0").is_err());
+        assert!(parse_record_duration("-2").is_err());
+        assert!(parse_record_duration("NaN").is_err());
+        assert!(parse_record_duration("3601").is_err());
+        assert!(parse_record_duration("five").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! Sample rate conversion.
+//!
+//! The wake word detector's mel filterbank assumes its configured sample
+//! rate, so audio from devices or files at other rates is converted first.
+//! `resample_linear` converts a whole clip; `StreamResampler` converts
+//! audio as it is captured, in blocks of any size.
+
+/// Resample `input` from `from` Hz to `to` Hz by linear interpolation
+///
+/// Good enough for speech features; no anti-aliasing filter is applied when
+/// downsampling.
+pub fn resample_linear(input: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || input.is_empty() || from == 0 || to == 0 {
+        return input.to_vec();
+    }
+
+    let out_len = (input.len() as u64 * to as u64 / from as u64) as usize;
+    let step = from as f64 / to as f64;
+    (0..out_len)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let idx = pos as usize;
+            let frac = (pos - idx as f64) as f32;
+            let a = input[idx.min(input.len() - 1)];
+            let b = input[(idx + 1).min(input.len() - 1)];
+            a + (b - a) * frac
+        })
+        .collect()
+}
+
+/// Linear resampling of a stream that arrives in blocks
+///
+/// Keeps its place between blocks, so the output is the same however the
+/// input is split up. Resampling each block with `resample_linear` instead
+/// would drop the fraction of a sample at the end of every block and let the
+/// timing drift.
+#[derive(Debug, Clone)]
+pub struct StreamResampler {
+    step: f64,         // Input samples per output sample
+    pos: f64,          // Position of the next output, relative to the next block
+    last: Option<f32>, // Final sample of the previous block
+}
+
+impl StreamResampler {
+    /// Convert from `from` Hz to `to` Hz
+    pub fn new(from: u32, to: u32) -> Self {
+        let step = if from == 0 || to == 0 {
+            1.0
+        } else {
+            from as f64 / to as f64
+        };
+        Self {
+            step,
+            pos: 0.0,
+            last: None,
+        }
+    }
+
+    /// Whether the rates are the same, so audio passes through untouched
+    pub fn is_passthrough(&self) -> bool {
+        self.step == 1.0
+    }
+
+    /// Resample the next block
+    ///
+    /// The last output before the end of a block needs the block after it,
+    /// so it comes out with that one.
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        if self.is_passthrough() || input.is_empty() {
+            return input.to_vec();
+        }
+        let sample = |i: isize| match usize::try_from(i) {
+            Ok(i) => input[i],
+            Err(_) => self.last.unwrap_or(input[0]),
+        };
+
+        let mut output = Vec::with_capacity((input.len() as f64 / self.step) as usize + 1);
+        while (self.pos.floor() as isize) + 1 < input.len() as isize {
+            let idx = self.pos.floor() as isize;
+            let frac = (self.pos - idx as f64) as f32;
+            let (a, b) = (sample(idx), sample(idx + 1));
+            output.push(a + (b - a) * frac);
+            self.pos += self.step;
+        }
+        self.pos -= input.len() as f64;
+        self.last = input.last().copied();
+        output
+    }
+
+    /// Start afresh, e.g. after skipping audio
+    pub fn reset(&mut self) {
+        self.pos = 0.0;
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustfft::{num_complex::Complex, FftPlanner};
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_resample_linear_interpolates() {
+        let input = vec![0.0, 1.0, 0.0, -1.0];
+        assert_eq!(resample_linear(&input, 16000, 16000), input);
+        assert_eq!(
+            resample_linear(&input, 8000, 16000),
+            vec![0.0, 0.5, 1.0, 0.5, 0.0, -0.5, -1.0, -1.0]
+        );
+        assert_eq!(resample_linear(&input, 16000, 8000), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_48k_sine_to_16k() {
+        // One second of a 1 kHz tone from a 48 kHz device
+        let input: Vec<f32> = (0..48000)
+            .map(|i| (2.0 * PI * 1000.0 * i as f32 / 48000.0).sin())
+            .collect();
+        let output = resample_linear(&input, 48000, 16000);
+        assert_eq!(output.len(), 16000);
+
+        // One-hertz bins, so the peak bin is the frequency
+        let mut spectrum: Vec<Complex<f32>> =
+            output.iter().map(|&x| Complex::new(x, 0.0)).collect();
+        FftPlanner::new()
+            .plan_fft_forward(spectrum.len())
+            .process(&mut spectrum);
+        let peak = (0..spectrum.len() / 2)
+            .max_by(|&a, &b| spectrum[a].norm().total_cmp(&spectrum[b].norm()))
+            .unwrap();
+        assert_eq!(peak, 1000);
+
+        // In odd-sized blocks, the stream version gives the same samples
+        let mut stream = StreamResampler::new(48000, 16000);
+        let streamed: Vec<f32> = input
+            .chunks(441)
+            .flat_map(|block| stream.process(block))
+            .collect();
+        assert!(
+            (15999..=16000).contains(&streamed.len()),
+            "{}",
+            streamed.len()
+        );
+        for (a, b) in streamed.iter().zip(&output) {
+            assert!((a - b).abs() < 1e-4);
+        }
+
+        let mut upsampler = StreamResampler::new(8000, 16000);
+        let upsampled: Vec<f32> = [0.0, 1.0, 0.0, -1.0]
+            .chunks(1)
+            .flat_map(|block| upsampler.process(block))
+            .collect();
+        assert_eq!(upsampled, vec![0.0, 0.5, 1.0, 0.5, 0.0, -0.5]);
+        assert!(StreamResampler::new(16000, 16000).is_passthrough());
+    }
+}
@@ -0,0 +1,729 @@
+//! Timed transcript segments and speaker-turn formatting
+//!
+//! Some backends return the transcript as a list of timed segments, and
+//! diarizing ones label each segment with the speaker. These helpers parse
+//! that list, lay it out as one line per speaker turn, and map segment times
+//! to sample positions in the source audio. Recordings with one speaker per
+//! channel get the same layout by transcribing each channel separately and
+//! merging the results. Timed transcripts can also be written as SRT or
+//! WebVTT subtitles.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Where `find_text` looks for the transcript by default, in order
+///
+/// Covers the local endpoint (`text`), other self-hosted servers
+/// (`transcription`, `result.text`) and the Replicate models (`output`).
+pub const DEFAULT_TEXT_POINTERS: &[&str] = &[
+    "/text",
+    "/transcription",
+    "/result/text",
+    "/output/text",
+    "/output/transcription",
+    "/output",
+];
+
+/// Whisper's own cut-off: segments at least this likely to be silence aren't speech
+pub const DEFAULT_NO_SPEECH_THRESHOLD: f32 = 0.6;
+
+/// Shown instead of a blank transcript when nothing was said
+pub const NO_SPEECH_MESSAGE: &str = "(no speech detected)";
+
+/// A stretch of transcribed speech
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    #[serde(default)]
+    pub start: f32, // Seconds from the start of the audio
+    #[serde(default)]
+    pub end: f32,
+    pub text: String,
+    #[serde(default)]
+    pub speaker: Option<String>, // Only set by diarizing backends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_speech_prob: Option<f32>, // Whisper's estimate that this is silence
+}
+
+/// A transcript with its timed segments, when the backend provides them
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Transcription {
+    pub text: String,
+    pub segments: Vec<Segment>, // Empty if the backend only returned text
+}
+
+impl Transcription {
+    /// Build from a backend's JSON response (text and optional `segments`)
+    ///
+    /// A diarized response is laid out as speaker turns. Otherwise the text
+    /// is looked up with `find_text`, falling back to the segments when the
+    /// default paths find nothing.
+    pub fn from_response(response: &serde_json::Value, text_pointer: Option<&str>) -> Result<Self> {
+        let segments = parse_segments(response).unwrap_or_default();
+        let text = if has_speakers(&segments) {
+            format_speaker_turns(&segments)
+        } else {
+            match find_text(response, text_pointer) {
+                Ok(text) => text,
+                Err(_) if text_pointer.is_none() && !segments.is_empty() => {
+                    format_speaker_turns(&segments)
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        Ok(Self { text, segments })
+    }
+
+    /// Whether the audio turned out to hold no speech
+    ///
+    /// True for a blank transcript, and when the backend rated every segment
+    /// at least `threshold` likely to be silence: Whisper tends to fill
+    /// silence with a stray "Thank you." rather than nothing.
+    pub fn is_no_speech(&self, threshold: f32) -> bool {
+        let silent = |segment: &Segment| segment.no_speech_prob.is_some_and(|p| p >= threshold);
+        self.text.trim().is_empty()
+            || (!self.segments.is_empty() && self.segments.iter().all(silent))
+    }
+}
+
+/// A segment with the range of source samples it covers
+///
+/// `start_sample..end_sample` indexes the audio that was transcribed, so a
+/// player can seek to (or highlight) exactly the samples behind the text.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AlignedSegment {
+    #[serde(flatten)]
+    pub segment: Segment,
+    pub start_sample: u64,
+    pub end_sample: u64,
+}
+
+/// Add sample ranges to `segments` for audio at `sample_rate`
+///
+/// Times are rounded to the nearest sample and clamped to the
+/// `total_samples` of the clip, since backends may report an end time a
+/// little past the audio.
+pub fn align_segments(
+    segments: &[Segment],
+    sample_rate: u32,
+    total_samples: u64,
+) -> Vec<AlignedSegment> {
+    let to_sample = |seconds: f32| {
+        let sample = (seconds.max(0.0) as f64 * sample_rate as f64).round() as u64;
+        sample.min(total_samples)
+    };
+    segments
+        .iter()
+        .map(|segment| {
+            let start_sample = to_sample(segment.start);
+            AlignedSegment {
+                segment: segment.clone(),
+                start_sample,
+                end_sample: to_sample(segment.end).max(start_sample),
+            }
+        })
+        .collect()
+}
+
+/// A timed piece of transcript as `incredibly-fast-whisper` returns it
+#[derive(Deserialize)]
+struct Chunk {
+    timestamp: (Option<f32>, Option<f32>), // The last chunk may have no end
+    text: String,
+}
+
+/// Parse the `segments` array of a backend response, if it has one
+///
+/// Also takes a `chunks` array of `{ "timestamp": [start, end], "text" }`
+/// entries. A chunk without an end time ends where it starts.
+pub fn parse_segments(response: &serde_json::Value) -> Option<Vec<Segment>> {
+    if let Some(segments) = response.get("segments") {
+        return serde_json::from_value(segments.clone()).ok();
+    }
+    let chunks: Vec<Chunk> = serde_json::from_value(response.get("chunks")?.clone()).ok()?;
+    Some(
+        chunks
+            .into_iter()
+            .map(|chunk| {
+                let start = chunk.timestamp.0.unwrap_or(0.0);
+                Segment {
+                    start,
+                    end: chunk.timestamp.1.unwrap_or(start),
+                    text: chunk.text,
+                    speaker: None,
+                    no_speech_prob: None,
+                }
+            })
+            .collect(),
+    )
+}
+
+/// The transcript text in a backend's JSON response
+///
+/// `pointer` is a JSON pointer such as `/output/text`; without one, the
+/// `DEFAULT_TEXT_POINTERS` are tried in turn. Fails, listing the paths
+/// tried, when none of them leads to a string.
+pub fn find_text(response: &serde_json::Value, pointer: Option<&str>) -> Result<String> {
+    let pointers = match pointer {
+        Some(ref pointer) => std::slice::from_ref(pointer),
+        None => DEFAULT_TEXT_POINTERS,
+    };
+    pointers
+        .iter()
+        .find_map(|pointer| response.pointer(pointer)?.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "No transcript text in the response (tried {})",
+                pointers.join(", ")
+            )
+        })
+}
+
+/// Check a JSON pointer for `find_text`, e.g. `/output/text`
+pub fn parse_text_pointer(pointer: &str) -> Result<String> {
+    let pointer = pointer.trim();
+    if !pointer.starts_with('/') {
+        anyhow::bail!(
+            "\"{}\" is not a JSON pointer; it must start with '/', e.g. /output/text",
+            pointer
+        );
+    }
+    Ok(pointer.to_string())
+}
+
+/// Whether any segment is labelled with a speaker
+pub fn has_speakers(segments: &[Segment]) -> bool {
+    segments.iter().any(|segment| segment.speaker.is_some())
+}
+
+/// Display name for a speaker label: `SPEAKER_01` becomes `Speaker 1`
+fn speaker_name(label: &str) -> String {
+    match label
+        .strip_prefix("SPEAKER_")
+        .and_then(|n| n.parse::<u32>().ok())
+    {
+        Some(n) => format!("Speaker {}", n),
+        None => label.to_string(),
+    }
+}
+
+/// Join segments into a transcript with one line per speaker turn
+///
+/// Consecutive segments from the same speaker are merged into a single
+/// `Speaker 0: ...` line. Segments without speaker labels are joined into
+/// plain text.
+pub fn format_speaker_turns(segments: &[Segment]) -> String {
+    let mut turns: Vec<(Option<&str>, Vec<&str>)> = Vec::new();
+    for segment in segments {
+        let text = segment.text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let speaker = segment.speaker.as_deref();
+        match turns.last_mut() {
+            Some((last, texts)) if *last == speaker => texts.push(text),
+            _ => turns.push((speaker, vec![text])),
+        }
+    }
+
+    turns
+        .iter()
+        .map(|(speaker, texts)| match speaker {
+            Some(label) => format!("{}: {}", speaker_name(label), texts.join(" ")),
+            None => texts.join(" "),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Format a time in seconds as `MM:SS`, or `H:MM:SS` from an hour on
+fn timestamp(seconds: f32) -> String {
+    let total = seconds.max(0.0) as u32;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}", minutes, seconds)
+    }
+}
+
+/// A transcript with details of the audio behind it, as printed by `--format json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptReport {
+    pub text: String,    // Empty when `no_speech`
+    pub no_speech: bool, // Nothing was said (see `Transcription::is_no_speech`)
+    pub duration_secs: f32,
+    pub device: Option<String>, // Input device, for live recordings
+    pub sample_rate: u32,
+}
+
+impl TranscriptReport {
+    /// Report `transcription` of `duration_secs` of audio at `sample_rate`
+    ///
+    /// `no_speech_threshold` is as for `Transcription::is_no_speech`.
+    pub fn new(
+        transcription: &Transcription,
+        no_speech_threshold: f32,
+        duration_secs: f32,
+        sample_rate: u32,
+        device: Option<String>,
+    ) -> Self {
+        let no_speech = transcription.is_no_speech(no_speech_threshold);
+        Self {
+            text: if no_speech {
+                String::new()
+            } else {
+                transcription.text.trim().to_string()
+            },
+            no_speech,
+            duration_secs,
+            device,
+            sample_rate,
+        }
+    }
+}
+
+/// Lay out a transcription as Markdown notes
+///
+/// An optional `# title`, then one bullet per segment prefixed with its
+/// start time as `**[MM:SS]**`. With speaker labels, each speaker turn gets
+/// a `### Speaker N` heading over its bullets. Without segments the text is
+/// a single paragraph.
+pub fn to_markdown(transcription: &Transcription, title: Option<&str>) -> String {
+    let mut blocks = Vec::new();
+    if let Some(title) = title {
+        blocks.push(format!("# {}", title.trim()));
+    }
+
+    if transcription.segments.is_empty() {
+        blocks.push(
+            transcription
+                .text
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+    } else {
+        let mut speaker: Option<&str> = None;
+        let mut bullets: Vec<String> = Vec::new();
+        for segment in &transcription.segments {
+            let text = segment.text.trim();
+            if text.is_empty() {
+                continue;
+            }
+            if let Some(label) = segment.speaker.as_deref() {
+                if speaker != Some(label) {
+                    if !bullets.is_empty() {
+                        blocks.push(bullets.join("\n"));
+                        bullets.clear();
+                    }
+                    blocks.push(format!("### {}", speaker_name(label)));
+                    speaker = Some(label);
+                }
+            }
+            bullets.push(format!("- **[{}]** {}", timestamp(segment.start), text));
+        }
+        if !bullets.is_empty() {
+            blocks.push(bullets.join("\n"));
+        }
+    }
+    blocks.join("\n\n") + "\n"
+}
+
+/// Subtitle file formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    Srt,
+    Vtt, // WebVTT
+}
+
+impl SubtitleFormat {
+    /// The format for a file name: WebVTT for `.vtt`, otherwise SRT
+    pub fn from_path(path: &std::path::Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("vtt") => SubtitleFormat::Vtt,
+            _ => SubtitleFormat::Srt,
+        }
+    }
+
+    /// Format a time in seconds as a cue timestamp
+    ///
+    /// SRT uses `HH:MM:SS,mmm` and WebVTT `HH:MM:SS.mmm`.
+    pub fn timestamp(self, seconds: f32) -> String {
+        let millis = (seconds.max(0.0) as f64 * 1000.0).round() as u64;
+        let (hours, minutes) = (millis / 3_600_000, millis / 60_000 % 60);
+        let (secs, millis) = (millis / 1000 % 60, millis % 1000);
+        let separator = match self {
+            SubtitleFormat::Srt => ',',
+            SubtitleFormat::Vtt => '.',
+        };
+        format!(
+            "{:02}:{:02}:{:02}{}{:03}",
+            hours, minutes, secs, separator, millis
+        )
+    }
+}
+
+/// Lay out a transcription as subtitles, one cue per segment
+///
+/// A segment without a proper end runs until the next one starts (or the
+/// end of the recording). Without segments, the whole text is a single cue
+/// spanning the `duration_secs` of the recording.
+pub fn to_subtitles(
+    transcription: &Transcription,
+    duration_secs: f32,
+    format: SubtitleFormat,
+) -> String {
+    let whole = [Segment {
+        start: 0.0,
+        end: duration_secs,
+        text: transcription.text.clone(),
+        speaker: None,
+        no_speech_prob: None,
+    }];
+    let segments: &[Segment] = if transcription.segments.is_empty() {
+        &whole
+    } else {
+        &transcription.segments
+    };
+
+    let mut cues = Vec::new();
+    for (i, segment) in segments.iter().enumerate() {
+        let text = segment.text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let end = if segment.end > segment.start {
+            segment.end
+        } else {
+            segments
+                .get(i + 1)
+                .map_or(duration_secs, |next| next.start)
+                .max(segment.start)
+        };
+        let mut cue = String::new();
+        if format == SubtitleFormat::Srt {
+            cue.push_str(&format!("{}\n", cues.len() + 1));
+        }
+        cue.push_str(&format!(
+            "{} --> {}\n{}\n",
+            format.timestamp(segment.start),
+            format.timestamp(end),
+            text
+        ));
+        cues.push(cue);
+    }
+
+    let body = cues.join("\n");
+    match format {
+        SubtitleFormat::Srt => body,
+        SubtitleFormat::Vtt if body.is_empty() => "WEBVTT\n".to_string(),
+        SubtitleFormat::Vtt => format!("WEBVTT\n\n{}", body),
+    }
+}
+
+/// Labels for `channels` tracks when none are given: `Left`/`Right` for stereo,
+/// otherwise `Channel 1`, `Channel 2`, ...
+pub fn default_channel_labels(channels: usize) -> Vec<String> {
+    match channels {
+        2 => vec!["Left".to_string(), "Right".to_string()],
+        n => (1..=n).map(|i| format!("Channel {}", i)).collect(),
+    }
+}
+
+/// Combine the transcriptions of separately transcribed channels
+///
+/// Each channel's label becomes the speaker of its segments. When every
+/// channel has timed segments they are interleaved by start time, so the
+/// transcript reads as a conversation; otherwise each channel's text is
+/// given on its own `Label: ...` line.
+pub fn merge_channels(channels: Vec<(String, Transcription)>) -> Transcription {
+    let timed = channels.iter().all(|(_, t)| !t.segments.is_empty());
+    let lines: Vec<String> = channels
+        .iter()
+        .filter(|(_, t)| !t.text.trim().is_empty())
+        .map(|(label, t)| format!("{}: {}", label, t.text.trim()))
+        .collect();
+
+    let mut segments: Vec<Segment> = channels
+        .into_iter()
+        .flat_map(|(label, t)| {
+            t.segments.into_iter().map(move |segment| Segment {
+                speaker: Some(label.clone()),
+                ..segment
+            })
+        })
+        .collect();
+    // Stable, so segments starting together stay in channel order
+    segments.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+    let text = if timed {
+        format_speaker_turns(&segments)
+    } else {
+        lines.join("\n")
+    };
+    Transcription { text, segments }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_groups_consecutive_speaker_segments() {
+        let response = serde_json::json!({
+            "segments": [
+                { "start": 0.0, "end": 1.2, "text": " Hi there.", "speaker": "SPEAKER_00" },
+                { "start": 1.2, "end": 2.0, "text": " How are you?", "speaker": "SPEAKER_00" },
+                { "start": 2.1, "end": 3.0, "text": " Fine, thanks.", "speaker": "SPEAKER_01" },
+                { "start": 3.2, "end": 4.0, "text": " Good.", "speaker": "SPEAKER_00" }
+            ],
+            "num_speakers": 2
+        });
+        let segments = parse_segments(&response).unwrap();
+        assert!(has_speakers(&segments));
+        assert_eq!(
+            format_speaker_turns(&segments),
+            "Speaker 0: Hi there. How are you?\nSpeaker 1: Fine, thanks.\nSpeaker 0: Good."
+        );
+    }
+
+    #[test]
+    fn test_format_without_speakers_is_plain_text() {
+        let response = serde_json::json!({
+            "segments": [{ "text": " One." }, { "text": " Two." }]
+        });
+        let segments = parse_segments(&response).unwrap();
+        assert!(!has_speakers(&segments));
+        assert_eq!(format_speaker_turns(&segments), "One. Two.");
+        assert!(parse_segments(&serde_json::json!({ "text": "x" })).is_none());
+    }
+
+    #[test]
+    fn test_find_text_paths() {
+        let nested = serde_json::json!({ "result": { "text": "Nested." }, "output": null });
+        assert_eq!(find_text(&nested, None).unwrap(), "Nested.");
+        let replicate = serde_json::json!({ "output": { "transcription": "Hi." } });
+        assert_eq!(find_text(&replicate, None).unwrap(), "Hi.");
+        assert_eq!(
+            find_text(&replicate, Some("/output/transcription")).unwrap(),
+            "Hi."
+        );
+
+        // A custom path is the only one tried
+        let custom = serde_json::json!({ "text": "Wrong.", "data": [{ "utterance": "Right." }] });
+        assert_eq!(
+            find_text(&custom, Some("/data/0/utterance")).unwrap(),
+            "Right."
+        );
+        let error = find_text(&custom, Some("/data/0/text")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No transcript text in the response (tried /data/0/text)"
+        );
+
+        let error = find_text(&serde_json::json!({ "output": 42 }), None).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("/text, /transcription, /result/text"));
+        assert!(Transcription::from_response(&serde_json::json!({}), None).is_err());
+
+        assert_eq!(
+            parse_text_pointer(" /output/text ").unwrap(),
+            "/output/text"
+        );
+        assert!(parse_text_pointer("output.text").is_err());
+    }
+
+    #[test]
+    fn test_no_speech() {
+        let transcription =
+            |response: serde_json::Value| Transcription::from_response(&response, None).unwrap();
+        let threshold = DEFAULT_NO_SPEECH_THRESHOLD;
+        assert!(transcription(serde_json::json!({ "text": " \n" })).is_no_speech(threshold));
+        assert!(!transcription(serde_json::json!({ "text": "Hi." })).is_no_speech(threshold));
+
+        let hallucinated = transcription(serde_json::json!({
+            "text": " Thank you.",
+            "segments": [{ "text": " Thank you.", "no_speech_prob": 0.92 }]
+        }));
+        assert!(hallucinated.is_no_speech(threshold));
+        assert!(!hallucinated.is_no_speech(0.95));
+
+        // One segment with speech is enough
+        let mixed = transcription(serde_json::json!({
+            "text": " Hello.",
+            "segments": [
+                { "text": " Hello.", "no_speech_prob": 0.1 },
+                { "text": " Bye.", "no_speech_prob": 0.9 }
+            ]
+        }));
+        assert!(!mixed.is_no_speech(threshold));
+    }
+
+    #[test]
+    fn test_transcript_report_json() {
+        let transcription =
+            Transcription::from_response(&serde_json::json!({ "text": " Hello there. " }), None)
+                .unwrap();
+        let report = TranscriptReport::new(
+            &transcription,
+            DEFAULT_NO_SPEECH_THRESHOLD,
+            5.0,
+            48000,
+            Some("USB Mic".into()),
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap();
+        assert_eq!(json["text"], "Hello there.");
+        assert_eq!(json["no_speech"], false);
+        assert_eq!(json["duration_secs"], 5.0);
+        assert_eq!(json["device"], "USB Mic");
+        assert_eq!(json["sample_rate"], 48000);
+
+        let silence =
+            Transcription::from_response(&serde_json::json!({ "text": " " }), None).unwrap();
+        let report = TranscriptReport::new(&silence, DEFAULT_NO_SPEECH_THRESHOLD, 1.5, 16000, None);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["no_speech"], true);
+        assert_eq!(json["text"], "");
+        assert!(json["device"].is_null());
+    }
+
+    #[test]
+    fn test_align_segments_clamps_to_clip() {
+        let response = serde_json::json!({
+            "text": "One. Two.",
+            "segments": [
+                { "start": 0.0, "end": 1.25, "text": " One." },
+                { "start": 1.25, "end": 2.7, "text": " Two." }
+            ]
+        });
+        let transcription = Transcription::from_response(&response, None).unwrap();
+        assert_eq!(transcription.text, "One. Two.");
+
+        // A 2.5 s clip at 16 kHz; the last segment overshoots it
+        let aligned = align_segments(&transcription.segments, 16000, 40000);
+        let ranges: Vec<(u64, u64)> = aligned
+            .iter()
+            .map(|s| (s.start_sample, s.end_sample))
+            .collect();
+        assert_eq!(ranges, vec![(0, 20000), (20000, 40000)]);
+
+        let json = serde_json::to_value(&aligned[1]).unwrap();
+        assert_eq!(json["text"], " Two.");
+        assert_eq!(json["start_sample"], 20000);
+    }
+
+    #[test]
+    fn test_subtitle_timestamps() {
+        assert_eq!(SubtitleFormat::Srt.timestamp(0.0), "00:00:00,000");
+        assert_eq!(SubtitleFormat::Srt.timestamp(65.4321), "00:01:05,432");
+        assert_eq!(SubtitleFormat::Srt.timestamp(3723.5), "01:02:03,500");
+        assert_eq!(SubtitleFormat::Vtt.timestamp(65.4321), "00:01:05.432");
+        assert_eq!(SubtitleFormat::Vtt.timestamp(-1.0), "00:00:00.000");
+        // Rounding can carry into the seconds
+        assert_eq!(SubtitleFormat::Vtt.timestamp(59.9996), "00:01:00.000");
+
+        let path = std::path::Path::new;
+        assert_eq!(
+            SubtitleFormat::from_path(path("out.VTT")),
+            SubtitleFormat::Vtt
+        );
+        assert_eq!(
+            SubtitleFormat::from_path(path("out.srt")),
+            SubtitleFormat::Srt
+        );
+    }
+
+    #[test]
+    fn test_subtitles_from_chunks() {
+        let response = serde_json::json!({
+            "text": " One. Two.",
+            "chunks": [
+                { "timestamp": [0.0, 1.5], "text": " One." },
+                { "timestamp": [1.5, null], "text": " Two." }
+            ]
+        });
+        let transcription = Transcription::from_response(&response, None).unwrap();
+        assert_eq!(transcription.segments.len(), 2);
+
+        let srt = to_subtitles(&transcription, 3.0, SubtitleFormat::Srt);
+        assert_eq!(
+            srt,
+            "1\n00:00:00,000 --> 00:00:01,500\nOne.\n\n\
+             2\n00:00:01,500 --> 00:00:03,000\nTwo.\n"
+        );
+
+        // Without timestamps, one cue covers the recording
+        let plain = Transcription {
+            text: " Hello. ".to_string(),
+            segments: Vec::new(),
+        };
+        let vtt = to_subtitles(&plain, 2.25, SubtitleFormat::Vtt);
+        assert_eq!(vtt, "WEBVTT\n\n00:00:00.000 --> 00:00:02.250\nHello.\n");
+    }
+
+    #[test]
+    fn test_markdown_groups_by_speaker() {
+        let response = serde_json::json!({
+            "segments": [
+                { "start": 0.0, "end": 1.2, "text": " Hi there.", "speaker": "SPEAKER_00" },
+                { "start": 65.4, "end": 66.0, "text": " Still here?", "speaker": "SPEAKER_00" },
+                { "start": 3725.0, "end": 3726.0, "text": " Yes.", "speaker": "SPEAKER_01" }
+            ]
+        });
+        let markdown = to_markdown(
+            &Transcription::from_response(&response, None).unwrap(),
+            Some("Standup"),
+        );
+        assert_eq!(
+            markdown,
+            "# Standup\n\n### Speaker 0\n\n- **[00:00]** Hi there.\n- **[01:05]** Still here?\n\n\
+             ### Speaker 1\n\n- **[1:02:05]** Yes.\n"
+        );
+
+        // Without segments, one paragraph
+        let plain =
+            Transcription::from_response(&serde_json::json!({ "text": " One.\n Two. " }), None)
+                .unwrap();
+        assert_eq!(to_markdown(&plain, None), "One. Two.\n");
+    }
+
+    #[test]
+    fn test_merge_channels_interleaves_by_time() {
+        let transcription = |segments: serde_json::Value| {
+            Transcription::from_response(&serde_json::json!({ "segments": segments }), None)
+                .unwrap()
+        };
+        let left = transcription(serde_json::json!([
+            { "start": 0.0, "end": 1.0, "text": " How was the trip?" },
+            { "start": 3.0, "end": 4.0, "text": " Good." }
+        ]));
+        let right = transcription(serde_json::json!([
+            { "start": 1.5, "end": 2.5, "text": " Long, but fine." }
+        ]));
+        let labels = default_channel_labels(2);
+        let merged = merge_channels(vec![
+            (labels[0].clone(), left.clone()),
+            (labels[1].clone(), right),
+        ]);
+        assert_eq!(
+            merged.text,
+            "Left: How was the trip?\nRight: Long, but fine.\nLeft: Good."
+        );
+        assert_eq!(merged.segments.len(), 3);
+
+        // Without timings, one line per channel
+        let untimed = Transcription {
+            text: " Hello.".to_string(),
+            segments: Vec::new(),
+        };
+        let merged = merge_channels(vec![
+            ("Host".to_string(), left),
+            ("Guest".to_string(), untimed),
+        ]);
+        assert_eq!(merged.text, "Host: How was the trip? Good.\nGuest: Hello.");
+        assert_eq!(default_channel_labels(3)[2], "Channel 3");
+    }
+}
@@ -0,0 +1,107 @@
+//! Offline end-to-end check of the detection and WAV pipeline.
+//!
+//! Uses synthetic audio only, so it needs no microphone or network and can
+//! run in CI or on first install.
+
+use anyhow::{ensure, Result};
+
+use crate::wake_word::WakeWordDetector;
+use crate::wav::{decode_wav_mono, samples_to_wav};
+
+const SAMPLE_RATE: u32 = 16000;
+
+/// Outcome of one self-test stage
+pub struct StageResult {
+    pub name: &'static str,
+    pub result: Result<()>,
+}
+
+/// Run every stage, continuing past failures so all results are reported
+pub fn run_self_test() -> Vec<StageResult> {
+    let chirp = chirp(SAMPLE_RATE as usize);
+    let noise = noise(SAMPLE_RATE as usize);
+    let mut detector = WakeWordDetector::new();
+
+    vec![
+        stage("MFCC extraction", || {
+            let features = detector.extract_mfcc(&chirp)?;
+            ensure!(features.nrows() > 0, "no frames extracted");
+            ensure!(
+                features.iter().all(|x| x.is_finite()),
+                "non-finite coefficients"
+            );
+            Ok(())
+        }),
+        stage("Template training", || {
+            detector.train_template(std::slice::from_ref(&chirp))
+        }),
+        stage("Detection on the trained signal", || {
+            let confidence = detector.confidence(&chirp)?;
+            ensure!(
+                detector.detect(&chirp)?.is_some(),
+                "missed (confidence {:.2})",
+                confidence
+            );
+            Ok(())
+        }),
+        stage("Rejection of noise", || {
+            let confidence = detector.confidence(&noise)?;
+            ensure!(
+                detector.detect(&noise)?.is_none(),
+                "false positive (confidence {:.2})",
+                confidence
+            );
+            Ok(())
+        }),
+        stage("WAV round trip", || {
+            let wav = samples_to_wav(&chirp, SAMPLE_RATE)?;
+            let (decoded, sample_rate) = decode_wav_mono(&wav)?;
+            ensure!(sample_rate == SAMPLE_RATE, "sample rate {}", sample_rate);
+            ensure!(decoded.len() == chirp.len(), "length {}", decoded.len());
+            let max_error = chirp
+                .iter()
+                .zip(&decoded)
+                .map(|(a, b)| (a - b).abs())
+                .fold(0.0, f32::max);
+            ensure!(max_error < 1e-3, "max sample error {}", max_error);
+            Ok(())
+        }),
+    ]
+}
+
+fn stage(name: &'static str, f: impl FnOnce() -> Result<()>) -> StageResult {
+    StageResult { name, result: f() }
+}
+
+/// Frequency sweep standing in for a spoken wake word
+fn chirp(len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            (2.0 * std::f32::consts::PI * (300.0 + 1200.0 * t) * t).sin() * 0.5
+        })
+        .collect()
+}
+
+/// Deterministic white noise (a small LCG, so results are reproducible)
+fn noise(len: usize) -> Vec<f32> {
+    let mut state: u32 = 0x1234_5678;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 8) as f32 / (1 << 24) as f32 - 0.5
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test_passes() {
+        for stage in run_self_test() {
+            assert!(stage.result.is_ok(), "{}: {:?}", stage.name, stage.result);
+        }
+    }
+}
@@ -0,0 +1,617 @@
+//! WebSocket server publishing detection events (feature `websocket`).
+//!
+//! Clients connect to `ws://ADDR/` and receive every `DetectionEvent` as a
+//! JSON text message, in the same shape as the NDJSON output. They can send
+//! commands back, e.g. `{"command":"start_recording"}`; a message that isn't
+//! a command is answered with an `error` event.
+//!
+//! The same port answers plain HTTP health checks, for load balancers and
+//! Kubernetes probes: `GET /health` is 200 while the server is running, and
+//! `GET /ready` is 200 while the transcriber given to `set_transcriber`
+//! passes its `check_ready` (an API key is set, say), 503 otherwise. Both
+//! reply with a small JSON status and don't contact the backend, so
+//! they're cheap to poll. Each connection's
+//! request head is read in full before it is routed, so a request that
+//! arrives in pieces is still recognised.
+//!
+//! `POST /transcribe` with a WAV body transcribes it with the same
+//! transcriber and replies `{"text":"..."}`. At most `set_max_concurrent`
+//! of these run at once; another arriving while they're all busy gets 503
+//! with `Retry-After` before its body is read, so a burst of uploads can't
+//! pile recordings up in memory or run into the backend's rate limit.
+//!
+//! The server runs its own tokio runtime on a background thread, so the
+//! listening loop stays synchronous: it calls `publish` for each event and
+//! polls `try_command` between audio chunks.
+
+use crate::events::DetectionEvent;
+use crate::transcribe::ConcurrencyLimit;
+use crate::transcriber::Transcriber;
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// Events buffered per client before a slow client starts missing them
+const EVENT_BUFFER: usize = 64;
+
+/// Longest request head (request line and headers) read before giving up
+const MAX_REQUEST_HEAD: usize = 8192;
+
+/// Largest WAV body `POST /transcribe` accepts, in bytes
+const MAX_UPLOAD_BODY: usize = 100 * 1024 * 1024;
+
+/// How many `POST /transcribe` requests run at once unless `set_max_concurrent` says otherwise
+pub const DEFAULT_MAX_CONCURRENT: usize = 4;
+
+/// Seconds a request turned away by the concurrency limit is told to wait
+const RETRY_AFTER_SECS: u64 = 2;
+
+/// What the connections share with the `EventServer` handle
+struct ServerState {
+    transcriber: Mutex<Option<Arc<dyn Transcriber>>>, // Checked by `/ready`, used by `/transcribe`
+    limit: Mutex<Arc<ConcurrencyLimit>>,
+}
+
+impl ServerState {
+    fn new() -> Self {
+        Self {
+            transcriber: Mutex::new(None),
+            limit: Mutex::new(Arc::new(ConcurrencyLimit::new(DEFAULT_MAX_CONCURRENT))),
+        }
+    }
+}
+
+/// A request sent by a client
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Record and transcribe, without waiting for the wake word
+    StartRecording,
+    /// End a recording started by `StartRecording` early
+    StopRecording,
+}
+
+impl Command {
+    /// Parse a client message, e.g. `{"command":"start_recording"}`
+    pub fn parse(text: &str) -> Result<Self> {
+        serde_json::from_str(text).with_context(|| format!("Unknown command {}", text.trim()))
+    }
+}
+
+/// Handle to a running server
+pub struct EventServer {
+    events: broadcast::Sender<String>,
+    commands: mpsc::Receiver<Command>,
+    state: Arc<ServerState>,
+    local_addr: SocketAddr,
+}
+
+impl EventServer {
+    /// Listen on `addr` (e.g. `127.0.0.1:9001`) and serve clients in the background
+    pub fn bind(addr: &str) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()?;
+        let listener = runtime
+            .block_on(TcpListener::bind(addr))
+            .with_context(|| format!("Failed to listen on {}", addr))?;
+        let local_addr = listener.local_addr()?;
+
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let (command_tx, commands) = mpsc::channel();
+        let state = Arc::new(ServerState::new());
+        let server_events = events.clone();
+        let server_state = state.clone();
+        thread::spawn(move || {
+            runtime.block_on(accept_loop(
+                listener,
+                server_events,
+                command_tx,
+                server_state,
+            ))
+        });
+
+        Ok(Self {
+            events,
+            commands,
+            state,
+            local_addr,
+        })
+    }
+
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Send an event to every connected client
+    pub fn publish(&self, event: &DetectionEvent) {
+        match serde_json::to_string(event) {
+            // Fails only when no client is connected, which is fine
+            Ok(json) => drop(self.events.send(json)),
+            Err(e) => eprintln!("Failed to encode event: {}", e),
+        }
+    }
+
+    /// The next command from a client, if one has arrived
+    pub fn try_command(&self) -> Option<Command> {
+        self.commands.try_recv().ok()
+    }
+
+    /// The transcriber whose `check_ready` `/ready` reports and which
+    /// `/transcribe` uses, e.g. the Stage 2 service; both answer 503 while
+    /// there is none
+    pub fn set_transcriber(&self, transcriber: Option<Box<dyn Transcriber>>) {
+        *self.state.transcriber.lock().unwrap() = transcriber.map(Arc::from);
+    }
+
+    /// Run at most `max` `/transcribe` requests at once (at least one);
+    /// requests already running finish either way
+    pub fn set_max_concurrent(&self, max: usize) {
+        *self.state.limit.lock().unwrap() = Arc::new(ConcurrencyLimit::new(max));
+    }
+}
+
+/// The request line and headers of an HTTP request
+#[derive(Debug, Default)]
+struct RequestHead {
+    method: String,
+    path: String, // Without the query string
+    headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    /// Parse a head read by `read_head`; anything malformed is left empty
+    fn parse(head: &[u8]) -> Self {
+        let head = String::from_utf8_lossy(head);
+        let mut lines = head.lines();
+        let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+        let method = request_line.next().unwrap_or_default().to_string();
+        let target = request_line.next().unwrap_or_default();
+        let path = target.split('?').next().unwrap_or(target).to_string();
+        let headers = lines
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+        Self {
+            method,
+            path,
+            headers,
+        }
+    }
+
+    /// The value of header `name`, ignoring case
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The `Sec-WebSocket-Key` of a WebSocket upgrade request
+    fn websocket_key(&self) -> Option<&str> {
+        let upgrade = self.header("Upgrade")?;
+        if self.method != "GET" || !upgrade.eq_ignore_ascii_case("websocket") {
+            return None;
+        }
+        self.header("Sec-WebSocket-Key")
+    }
+}
+
+/// Read up to the blank line ending the request head
+///
+/// A request can arrive over several reads, so this keeps reading until
+/// the head is complete, the client stops sending, or `MAX_REQUEST_HEAD`
+/// bytes have arrived. Anything sent after the head stays buffered in
+/// `stream`.
+async fn read_head(stream: &mut BufReader<TcpStream>) -> Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut limited = stream.take(MAX_REQUEST_HEAD as u64);
+    while !head.ends_with(b"\r\n\r\n") {
+        if limited.read_until(b'\n', &mut head).await? == 0 {
+            break;
+        }
+    }
+    Ok(head)
+}
+
+/// The health check a request asks for, if it is one
+fn health_check(request: &RequestHead, state: &ServerState) -> Option<(&'static str, String)> {
+    if request.method != "GET" {
+        return None;
+    }
+    let reply = match request.path.as_str() {
+        "/health" => ("200 OK", serde_json::json!({ "status": "ok" })),
+        "/ready" => match state.transcriber.lock().unwrap().clone() {
+            Some(transcriber) => match transcriber.check_ready() {
+                Ok(()) => (
+                    "200 OK",
+                    serde_json::json!({ "status": "ready", "backend": transcriber.name() }),
+                ),
+                Err(e) => (
+                    "503 Service Unavailable",
+                    serde_json::json!({
+                        "status": "unavailable",
+                        "backend": transcriber.name(),
+                        "reason": format!("{:#}", e),
+                    }),
+                ),
+            },
+            None => (
+                "503 Service Unavailable",
+                serde_json::json!({
+                    "status": "unavailable",
+                    "reason": "No transcription service configured",
+                }),
+            ),
+        },
+        _ => return None,
+    };
+    Some((reply.0, reply.1.to_string()))
+}
+
+/// Answer a plain HTTP request with `status`, any extra `headers` and a
+/// JSON `body`, then hang up
+async fn answer_http(
+    mut stream: BufReader<TcpStream>,
+    status: &str,
+    headers: &[(&str, String)],
+    body: &str,
+) -> Result<()> {
+    let headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect();
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        headers,
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// The JSON body of a request that was turned away
+fn refusal(reason: &str) -> String {
+    serde_json::json!({ "status": "unavailable", "reason": reason }).to_string()
+}
+
+/// Answer `POST /transcribe`: transcribe the WAV body, if there's room
+///
+/// A slot is taken from the concurrency limit before the body is read, so
+/// a request over the limit is turned away without buffering its
+/// recording. The transcriber runs on tokio's blocking pool.
+async fn transcribe_upload(
+    mut stream: BufReader<TcpStream>,
+    request: &RequestHead,
+    state: &ServerState,
+) -> Result<()> {
+    let transcriber = state.transcriber.lock().unwrap().clone();
+    let Some(transcriber) = transcriber else {
+        let body = refusal("No transcription service configured");
+        return answer_http(stream, "503 Service Unavailable", &[], &body).await;
+    };
+    let length = match request.header("Content-Length").map(str::parse::<usize>) {
+        Some(Ok(length)) if length <= MAX_UPLOAD_BODY => length,
+        Some(Ok(length)) => {
+            let body = refusal(&format!(
+                "Recording too large ({} bytes > {} limit)",
+                length, MAX_UPLOAD_BODY
+            ));
+            return answer_http(stream, "413 Payload Too Large", &[], &body).await;
+        }
+        _ => {
+            let body = refusal("Send the WAV with a Content-Length");
+            return answer_http(stream, "411 Length Required", &[], &body).await;
+        }
+    };
+
+    let limit = state.limit.lock().unwrap().clone();
+    let Some(permit) = limit.try_acquire() else {
+        let body = refusal(&format!(
+            "{} transcription(s) already in flight",
+            limit.max()
+        ));
+        let retry_after = [("Retry-After", RETRY_AFTER_SECS.to_string())];
+        return answer_http(stream, "503 Service Unavailable", &retry_after, &body).await;
+    };
+    let continue_first = request
+        .header("Expect")
+        .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"));
+    if continue_first {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+    }
+    let mut wav = vec![0; length];
+    stream.read_exact(&mut wav).await?;
+
+    let result = tokio::task::spawn_blocking(move || transcriber.transcribe(wav)).await?;
+    // Free the slot before replying, so the client's next request finds it free
+    drop(permit);
+    let (status, body) = match result {
+        Ok(text) => ("200 OK", serde_json::json!({ "text": text })),
+        Err(e) => (
+            "502 Bad Gateway",
+            serde_json::json!({ "status": "failed", "reason": format!("{:#}", e) }),
+        ),
+    };
+    answer_http(stream, status, &[], &body.to_string()).await
+}
+
+/// Route one connection by its request head
+async fn handle_connection(
+    stream: TcpStream,
+    events: broadcast::Receiver<String>,
+    commands: mpsc::Sender<Command>,
+    state: Arc<ServerState>,
+) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let head = RequestHead::parse(&read_head(&mut stream).await?);
+    if let Some((status, body)) = health_check(&head, &state) {
+        return answer_http(stream, status, &[], &body).await;
+    }
+    if head.method == "POST" && head.path == "/transcribe" {
+        return transcribe_upload(stream, &head, &state).await;
+    }
+    let Some(key) = head.websocket_key() else {
+        let body = serde_json::json!({ "status": "not found" }).to_string();
+        return answer_http(stream, "404 Not Found", &[], &body).await;
+    };
+    // The head has been read already, so finish the handshake by hand
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes())
+    );
+    stream.write_all(response.as_bytes()).await?;
+    let socket = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+    serve_client(socket, events, commands).await
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    events: broadcast::Sender<String>,
+    commands: mpsc::Sender<Command>,
+    state: Arc<ServerState>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                eprintln!("WebSocket accept failed: {}", e);
+                continue;
+            }
+        };
+        // Subscribe before the handshake so no event is missed once it completes
+        let client_events = events.subscribe();
+        let client_commands = commands.clone();
+        let client_state = state.clone();
+        tokio::spawn(async move {
+            let result =
+                handle_connection(stream, client_events, client_commands, client_state).await;
+            if let Err(e) = result {
+                eprintln!("WebSocket client {}: {:#}", peer, e);
+            }
+        });
+    }
+}
+
+/// Forward events to one client and its commands back, until it disconnects
+async fn serve_client(
+    socket: WebSocketStream<BufReader<TcpStream>>,
+    mut events: broadcast::Receiver<String>,
+    commands: mpsc::Sender<Command>,
+) -> Result<()> {
+    let (mut outgoing, mut incoming) = socket.split();
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(json) => outgoing.send(Message::Text(json)).await?,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    eprintln!("WebSocket client fell behind; dropped {} event(s)", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            message = incoming.next() => match message {
+                Some(Ok(Message::Text(text))) => match Command::parse(&text) {
+                    Ok(command) => {
+                        if commands.send(command).is_err() {
+                            break; // The listening loop has stopped
+                        }
+                    }
+                    Err(e) => {
+                        let reply = DetectionEvent::Error {
+                            detection_id: None,
+                            message: format!("{:#}", e),
+                        };
+                        outgoing.send(Message::Text(serde_json::to_string(&reply)?)).await?;
+                    }
+                },
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {} // Pings are answered by tungstenite itself
+                Some(Err(e)) => return Err(e.into()),
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcriber::ReplicateTranscriber;
+    use std::io::{Read, Write};
+    use std::time::{Duration, Instant};
+    use tokio_tungstenite::tungstenite;
+
+    #[test]
+    fn test_command_parsing() {
+        assert_eq!(
+            Command::parse(r#"{"command":"start_recording"}"#).unwrap(),
+            Command::StartRecording
+        );
+        assert_eq!(
+            Command::parse(r#"{"command":"stop_recording"}"#).unwrap(),
+            Command::StopRecording
+        );
+        assert!(Command::parse(r#"{"command":"self_destruct"}"#).is_err());
+        assert!(Command::parse("start recording").is_err());
+    }
+
+    #[test]
+    fn test_events_and_commands_round_trip() {
+        let server = EventServer::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/", server.local_addr());
+        let (mut client, _) = tungstenite::connect(url).unwrap();
+
+        client
+            .send(Message::Text(r#"{"command":"start_recording"}"#.into()))
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let command = loop {
+            if let Some(command) = server.try_command() {
+                break command;
+            }
+            assert!(Instant::now() < deadline, "command never arrived");
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(command, Command::StartRecording);
+
+        server.publish(&DetectionEvent::Detection {
+            detection_id: 1,
+            confidence: 0.5,
+            audio_wav_base64: None,
+        });
+        assert_eq!(
+            client.read().unwrap(),
+            Message::Text(r#"{"event":"detection","detection_id":1,"confidence":0.5}"#.into())
+        );
+    }
+
+    /// Send a raw HTTP request and read the status, head and JSON body of the reply
+    fn exchange(addr: SocketAddr, request: &[u8]) -> (String, String, serde_json::Value) {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        // Sent in pieces, as a slow client or a busy network might
+        for piece in request.chunks(5) {
+            stream.write_all(piece).unwrap();
+            stream.flush().unwrap();
+            thread::sleep(Duration::from_millis(2));
+        }
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().to_string();
+        (
+            status,
+            head.to_string(),
+            serde_json::from_str(body).unwrap(),
+        )
+    }
+
+    /// A transcriber that holds each call until the test lets it go
+    struct Held {
+        started: Mutex<mpsc::Sender<()>>,
+        release: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl Transcriber for Held {
+        fn transcribe(&self, wav: Vec<u8>) -> Result<String> {
+            self.started.lock().unwrap().send(())?;
+            self.release.lock().unwrap().recv()?;
+            Ok(format!("{} bytes", wav.len()))
+        }
+    }
+
+    #[test]
+    fn test_health_checks() {
+        let server = EventServer::bind("127.0.0.1:0").unwrap();
+        let get = |path: &str| {
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            let (status, _, body) = exchange(server.local_addr(), request.as_bytes());
+            (status, body)
+        };
+
+        assert_eq!(get("/health").0, "200");
+        let (status, body) = get("/ready");
+        assert_eq!(status, "503");
+        assert_eq!(body["status"], "unavailable");
+
+        // A backend that isn't configured isn't ready either
+        server.set_transcriber(Some(Box::new(ReplicateTranscriber::new(""))));
+        let (status, body) = get("/ready");
+        assert_eq!(status, "503");
+        assert_eq!(body["reason"], "No Replicate API key");
+
+        server.set_transcriber(Some(Box::new(ReplicateTranscriber::new("r8_key"))));
+        let (status, body) = get("/ready?verbose=1");
+        assert_eq!(status, "200");
+        assert_eq!(body["backend"], "Replicate");
+
+        // Other plain requests are turned away, not mistaken for WebSockets
+        assert_eq!(get("/metrics").0, "404");
+
+        // WebSocket clients still get through on the same port
+        let url = format!("ws://{}/", server.local_addr());
+        assert!(tungstenite::connect(url).is_ok());
+    }
+
+    #[test]
+    fn test_transcribe_concurrency_limit() {
+        let server = EventServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr();
+        let post = move |wav: &[u8]| {
+            let mut request = format!(
+                "POST /transcribe HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+                wav.len()
+            )
+            .into_bytes();
+            request.extend_from_slice(wav);
+            exchange(addr, &request)
+        };
+        assert_eq!(post(b"RIFF").0, "503");
+
+        let (started_tx, started) = mpsc::channel();
+        let (release, release_rx) = mpsc::channel();
+        server.set_transcriber(Some(Box::new(Held {
+            started: Mutex::new(started_tx),
+            release: Mutex::new(release_rx),
+        })));
+        server.set_max_concurrent(1);
+
+        let first = thread::spawn(move || post(b"RIFF...."));
+        started.recv_timeout(Duration::from_secs(5)).unwrap();
+        // A second request while the first is in flight is turned away
+        let (status, head, body) = post(b"RIFF");
+        assert_eq!(status, "503");
+        assert!(head.contains("Retry-After: 2"), "{}", head);
+        assert_eq!(body["reason"], "1 transcription(s) already in flight");
+
+        release.send(()).unwrap();
+        let (status, _, body) = first.join().unwrap();
+        assert_eq!(status, "200");
+        assert_eq!(body["text"], "8 bytes");
+
+        // One at a time, every request gets through
+        for _ in 0..2 {
+            release.send(()).unwrap();
+            let (status, _, body) = post(b"RIFF");
+            assert_eq!(status, "200");
+            assert_eq!(body["text"], "4 bytes");
+        }
+
+        let request = b"POST /transcribe HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert_eq!(exchange(addr, request).0, "411");
+    }
+}
@@ -0,0 +1,30 @@
+//! Graceful shutdown on Ctrl+C and SIGTERM.
+//!
+//! Long-running modes poll the flag in their main loop so they can flush
+//! output and print a summary instead of being killed mid-session.
+
+use anyhow::{Context, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Flag set once the process has been asked to stop
+#[derive(Clone)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    /// Install the Ctrl+C / SIGTERM handler and return the flag it sets
+    ///
+    /// Only one handler can be installed per process.
+    pub fn install() -> Result<Self> {
+        let shutdown = Self(Arc::new(AtomicBool::new(false)));
+        let flag = Arc::clone(&shutdown.0);
+        ctrlc::set_handler(move || flag.store(true, Ordering::SeqCst))
+            .context("Failed to install the shutdown signal handler")?;
+        Ok(shutdown)
+    }
+
+    /// Whether a shutdown has been requested
+    pub fn requested(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
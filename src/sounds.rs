@@ -0,0 +1,148 @@
+//! Short cue sounds played through the default output device.
+//!
+//! The tones are generated on the fly (no sound files), and `play` blocks
+//! until the sound has finished, so a caller that starts recording right
+//! afterwards doesn't capture its own cue.
+
+use anyhow::{Context, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SizedSample};
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Extra wait after the last sample, for the device to drain its buffer
+const DRAIN_MS: u64 = 100;
+
+/// Available cue sounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sound {
+    /// Short plain beep, e.g. to mark the start of a recording
+    Beep,
+    /// Two-tone chime, TOS-style computer acknowledgement
+    ComputerReady,
+    /// Sweeping chirp, TNG-style communicator
+    CommunicatorChirp,
+}
+
+impl Sound {
+    /// Length of the sound
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(match self {
+            Sound::Beep => 150,
+            Sound::ComputerReady => 200,
+            Sound::CommunicatorChirp => 400,
+        })
+    }
+
+    /// The sound as mono samples (-1.0..1.0) at `sample_rate`
+    pub fn samples(&self, sample_rate: u32) -> Vec<f32> {
+        let len = (sample_rate as u64 * self.duration().as_millis() as u64 / 1000) as usize;
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                let progress = i as f32 / len as f32;
+                match self {
+                    Sound::Beep => {
+                        // 5 ms ramps at either end so it doesn't click
+                        let ramp = (t / 0.005).min((len - i) as f32 / sample_rate as f32 / 0.005);
+                        0.3 * ramp.min(1.0) * (2.0 * PI * 880.0 * t).sin()
+                    }
+                    Sound::ComputerReady => {
+                        let volume = 0.3 * (1.0 - progress).powi(2); // Fade out
+                        volume
+                            * ((2.0 * PI * 440.0 * t).sin() * 0.6
+                                + (2.0 * PI * 660.0 * t).sin() * 0.4)
+                    }
+                    Sound::CommunicatorChirp => {
+                        let sweep_freq = 800.0 + 400.0 * (t * 8.0).sin();
+                        let chirp_freq = 1200.0 + 800.0 * (t * 12.0).sin();
+                        let click_freq = if i % 100 < 2 { 2000.0 } else { 0.0 };
+                        // Sharp attack, gentle decay, quick release
+                        let envelope = if t < 0.05 {
+                            t / 0.05
+                        } else if t < 0.3 {
+                            1.0 - ((t - 0.05) / 0.25).powi(2)
+                        } else {
+                            (1.0 - (t - 0.3) / 0.1).max(0.0)
+                        };
+                        envelope
+                            * 0.3
+                            * ((2.0 * PI * sweep_freq * t).sin() * 0.5
+                                + (2.0 * PI * chirp_freq * t).sin() * 0.3
+                                + (2.0 * PI * click_freq * t).sin() * 0.2)
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// Play `sound` on the default output device and wait until it has finished
+pub fn play(sound: Sound) -> Result<()> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .context("No output device available")?;
+    let config = device.default_output_config()?;
+    let samples = Arc::new(sound.samples(config.sample_rate().0));
+    let stream_config: cpal::StreamConfig = config.clone().into();
+
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::F32 => build_output_stream::<f32>(&device, &stream_config, samples)?,
+        cpal::SampleFormat::I16 => build_output_stream::<i16>(&device, &stream_config, samples)?,
+        cpal::SampleFormat::U16 => build_output_stream::<u16>(&device, &stream_config, samples)?,
+        _ => anyhow::bail!("Unsupported output sample format"),
+    };
+    stream.play()?;
+    thread::sleep(sound.duration() + Duration::from_millis(DRAIN_MS));
+    Ok(())
+}
+
+/// Output stream that plays `samples` on every channel, then silence
+fn build_output_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    samples: Arc<Vec<f32>>,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let position = AtomicUsize::new(0);
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &_| {
+            for frame in data.chunks_mut(channels) {
+                let i = position.fetch_add(1, Ordering::Relaxed);
+                let value = T::from_sample(samples.get(i).copied().unwrap_or(0.0));
+                frame.fill(value);
+            }
+        },
+        |err| eprintln!("An error occurred on the output stream: {}", err),
+        None,
+    )?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sounds_are_quiet_and_end_silent() {
+        for sound in [Sound::Beep, Sound::ComputerReady, Sound::CommunicatorChirp] {
+            let samples = sound.samples(48000);
+            assert_eq!(
+                samples.len() as u128,
+                48 * sound.duration().as_millis(),
+                "{:?}",
+                sound
+            );
+            assert!(samples.iter().all(|s| s.abs() <= 0.3), "{:?}", sound);
+            // No click at the end
+            assert!(samples.last().unwrap().abs() < 0.01, "{:?}", sound);
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! Several wake word templates, picked between by their tags.
+//!
+//! A template trained in one room matches best in that room. The bank holds
+//! templates from different environments (each tagged, e.g. `room = office`)
+//! and, given the tags of the current environment, checks the matching
+//! templates first, or only those.
+//!
+//! The same bank can hold templates for different wake words. When two of
+//! them sound alike, `set_min_margin` stops the bank firing on whichever
+//! happens to score a little higher.
+
+use crate::wake_word::{TemplateTags, WakeWordDetector};
+use anyhow::Result;
+use std::cmp::Ordering;
+use std::path::Path;
+
+/// The outcome of `TemplateBank::detect`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BankMatch {
+    pub index: usize, // Which detector in the bank matched best
+    pub detected: bool,
+    pub confidence: f32,
+    pub runner_up: Option<RunnerUp>, // The next best template, if another was checked
+    pub ambiguous: bool,             // Best fired, but not clear enough of the runner-up
+}
+
+/// The second-best template in a `BankMatch`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunnerUp {
+    pub index: usize,
+    pub confidence: f32,
+}
+
+/// A set of detectors, each with its own template and tags
+#[derive(Default)]
+pub struct TemplateBank {
+    detectors: Vec<WakeWordDetector>,
+    active_tags: TemplateTags,
+    exclusive: bool, // Only check templates with the active tags
+    min_margin: f32,
+}
+
+impl TemplateBank {
+    /// An empty bank
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a detector; returns its index
+    pub fn add(&mut self, detector: WakeWordDetector) -> usize {
+        self.detectors.push(detector);
+        self.detectors.len() - 1
+    }
+
+    /// Add a template written by `WakeWordDetector::save_template`
+    ///
+    /// The detector gets default settings apart from what the template
+    /// file carries (normalization, bandpass, tags); use `add` for more
+    /// control.
+    pub fn load_template(&mut self, path: &Path) -> Result<usize> {
+        let mut detector = WakeWordDetector::new();
+        detector.load_template(path)?;
+        Ok(self.add(detector))
+    }
+
+    /// The detectors, in the order they were added
+    pub fn detectors(&self) -> &[WakeWordDetector] {
+        &self.detectors
+    }
+
+    /// Mutable access to the detectors, e.g. to set thresholds
+    pub fn detectors_mut(&mut self) -> &mut [WakeWordDetector] {
+        &mut self.detectors
+    }
+
+    /// Check templates tagged with all of `tags` before the others
+    pub fn prefer_tags(&mut self, tags: TemplateTags) {
+        self.active_tags = tags;
+        self.exclusive = false;
+    }
+
+    /// Check only templates tagged with all of `tags`
+    pub fn select_tags(&mut self, tags: TemplateTags) {
+        self.active_tags = tags;
+        self.exclusive = true;
+    }
+
+    /// Require the best match to beat the runner-up by `margin` confidence
+    ///
+    /// A closer result is reported as ambiguous and not detected. With a
+    /// margin set, every candidate is checked, not just the active-tagged
+    /// ones, since the runner-up may be among the others. 0.0 (the default)
+    /// turns the check off.
+    pub fn set_min_margin(&mut self, margin: f32) {
+        self.min_margin = margin.max(0.0);
+    }
+
+    /// A name for the template at `index`, from its tags, for logs
+    ///
+    /// E.g. `room=office,word=computer`, or `#2` for an untagged template.
+    pub fn label(&self, index: usize) -> String {
+        let tags = self.detectors[index].tags();
+        if tags.is_empty() {
+            return format!("#{}", index);
+        }
+        let pairs: Vec<String> = tags.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        pairs.join(",")
+    }
+
+    /// Whether `detector`'s template has every active tag
+    fn is_active(&self, detector: &WakeWordDetector) -> bool {
+        self.active_tags
+            .iter()
+            .all(|(key, value)| detector.tags().get(key) == Some(value))
+    }
+
+    /// Indices of the detectors `detect` checks, active-tagged ones first
+    pub fn candidates(&self) -> Vec<usize> {
+        self.groups().concat()
+    }
+
+    /// Indices of the active-tagged detectors, then of the other checked ones
+    fn groups(&self) -> [Vec<usize>; 2] {
+        let (active, mut others): (Vec<usize>, Vec<usize>) =
+            (0..self.detectors.len()).partition(|&i| self.is_active(&self.detectors[i]));
+        if self.exclusive {
+            others.clear();
+        }
+        [active, others]
+    }
+
+    /// Match `audio` against the bank's templates
+    ///
+    /// If an active-tagged template fires, the others aren't checked (unless
+    /// a minimum margin is set). The result is the best match among the
+    /// templates that were checked, or `None` when there were none.
+    pub fn detect(&self, audio: &[f32]) -> Result<Option<BankMatch>> {
+        let mut scores = Vec::new();
+        for group in self.groups() {
+            for index in group {
+                let detector = &self.detectors[index];
+                let confidence = detector.confidence(audio)?;
+                scores.push((index, confidence >= detector.threshold(), confidence));
+            }
+            if self.min_margin == 0.0 && scores.iter().any(|&(_, detected, _)| detected) {
+                break;
+            }
+        }
+
+        // Detections first, then by confidence; ties keep the check order
+        scores.sort_by(|a, b| {
+            (b.1, b.2)
+                .partial_cmp(&(a.1, a.2))
+                .unwrap_or(Ordering::Equal)
+        });
+        let mut ranked = scores.into_iter();
+        let Some((index, detected, confidence)) = ranked.next() else {
+            return Ok(None);
+        };
+        let runner_up = ranked
+            .next()
+            .map(|(index, _, confidence)| RunnerUp { index, confidence });
+        let ambiguous =
+            detected && runner_up.is_some_and(|r| confidence - r.confidence < self.min_margin);
+        Ok(Some(BankMatch {
+            index,
+            detected: detected && !ambiguous,
+            confidence,
+            runner_up,
+            ambiguous,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wake_word::chirp;
+
+    fn tagged(audio: &[f32], room: &str) -> WakeWordDetector {
+        let mut detector = WakeWordDetector::new();
+        detector.train_template(&[audio.to_vec()]).unwrap();
+        detector.set_threshold(0.5);
+        detector.set_tag("room", room);
+        detector
+    }
+
+    fn room(name: &str) -> TemplateTags {
+        TemplateTags::from([("room".to_string(), name.to_string())])
+    }
+
+    #[test]
+    fn test_active_tags_are_checked_first() {
+        let office = chirp(300.0, 1500.0, 1.0);
+        let kitchen = chirp(1500.0, 300.0, 1.0);
+        let mut bank = TemplateBank::new();
+        bank.add(tagged(&office, "office"));
+        bank.add(tagged(&kitchen, "kitchen"));
+
+        bank.prefer_tags(room("kitchen"));
+        assert_eq!(bank.candidates(), vec![1, 0]);
+        let found = bank.detect(&office).unwrap().unwrap();
+        assert_eq!((found.index, found.detected), (0, true));
+
+        // Restricted to the kitchen, the office recording no longer matches
+        bank.select_tags(room("kitchen"));
+        assert_eq!(bank.candidates(), vec![1]);
+        let found = bank.detect(&office).unwrap().unwrap();
+        assert_eq!(found.index, 1);
+        assert!(!found.detected);
+
+        bank.select_tags(room("garage"));
+        assert_eq!(bank.detect(&office).unwrap(), None);
+    }
+
+    #[test]
+    fn test_tags_are_stored_with_template() {
+        let path = std::env::temp_dir().join(format!("bank-test-{}.json", std::process::id()));
+        tagged(&chirp(300.0, 1500.0, 1.0), "office")
+            .save_template(&path)
+            .unwrap();
+        let mut bank = TemplateBank::new();
+        let index = bank.load_template(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(bank.detectors()[index].tags(), &room("office"));
+    }
+
+    #[test]
+    fn test_min_margin_rejects_close_calls() {
+        let office = chirp(300.0, 1500.0, 1.0);
+        let mut computer = tagged(&office, "office");
+        computer.set_tag("word", "computer");
+        let mut commodore = tagged(&office, "office");
+        commodore.set_tag("word", "commodore");
+        let mut bank = TemplateBank::new();
+        bank.add(computer);
+        bank.add(commodore);
+        bank.add(tagged(&chirp(1500.0, 300.0, 1.0), "kitchen"));
+        assert_eq!(bank.label(1), "room=office,word=commodore");
+
+        // Identical scores: without a margin the first one wins
+        let found = bank.detect(&office).unwrap().unwrap();
+        assert_eq!(
+            (found.index, found.detected, found.ambiguous),
+            (0, true, false)
+        );
+        let runner_up = found.runner_up.unwrap();
+        assert_eq!(runner_up.index, 1);
+        assert_eq!(runner_up.confidence, found.confidence);
+
+        bank.set_min_margin(0.1);
+        let found = bank.detect(&office).unwrap().unwrap();
+        assert_eq!(
+            (found.index, found.detected, found.ambiguous),
+            (0, false, true)
+        );
+
+        // Preferring one doesn't hide its rival once a margin is set
+        bank.prefer_tags(TemplateTags::from([(
+            "word".to_string(),
+            "computer".to_string(),
+        )]));
+        assert!(bank.detect(&office).unwrap().unwrap().ambiguous);
+
+        // A clear winner still fires
+        let found = bank.detect(&chirp(1500.0, 300.0, 1.0)).unwrap().unwrap();
+        assert_eq!((found.index, found.detected), (2, true));
+        assert!(found.confidence - found.runner_up.unwrap().confidence >= 0.1);
+    }
+}
@@ -0,0 +1,1563 @@
+//! Transcription helpers shared by the CLI and the examples.
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::resample::resample_linear;
+use crate::segments::{
+    find_text, format_speaker_turns, has_speakers, parse_segments, parse_text_pointer,
+    Transcription,
+};
+use crate::transcriber::{LocalWhisperTranscriber, ReplicateTranscriber, Transcriber, UploadLimit};
+use crate::wav::{decode_wav_mono, samples_to_wav_quantized, Quantization};
+
+/// Extra headers for requests to the local Whisper endpoint, e.g. `X-API-Key: abc`
+pub const WHISPER_HEADERS_VAR: &str = "WHISPER_HEADERS";
+
+/// Extra headers for requests to the Replicate API
+pub const REPLICATE_HEADERS_VAR: &str = "REPLICATE_HEADERS";
+
+/// JSON pointer to the transcript in a backend's response, e.g. `/output/text`
+pub const WHISPER_TEXT_POINTER_VAR: &str = "WHISPER_TEXT_POINTER";
+
+/// Friendly names for the supported Replicate Whisper models
+///
+/// - `fast`: `vaibhavs10/incredibly-fast-whisper`, large-v3 with batching (default)
+/// - `large-v3`: `openai/whisper`, the reference large-v3 model
+/// - `diarize`: `thomasmol/whisper-diarization`, adds speaker labels
+///
+/// Each maps to a pinned `owner/name:version` so results stay reproducible.
+pub const REPLICATE_MODELS: &[(&str, &str)] = &[
+    (
+        "fast",
+        "vaibhavs10/incredibly-fast-whisper:3ab86df6c8f54c11309d4d1f930ac292bad43ace52d10c80d87eb258b3c9f79c",
+    ),
+    (
+        "large-v3",
+        "openai/whisper:8099696689d249cf8b122d833c36ac3f75505c666a395ca40ef26f68e7d3d16e",
+    ),
+    (
+        "diarize",
+        "thomasmol/whisper-diarization:cbd15da9f839c5f932742f86ce7def3a03c22e2b4171d42823e83e314547003f",
+    ),
+];
+
+/// Model used when none is chosen
+pub const DEFAULT_REPLICATE_MODEL: &str = "fast";
+
+/// Resolve a friendly model name to its Replicate identifier
+///
+/// Unknown names are passed through unchanged, so a raw
+/// `owner/name:version` can always be used directly.
+pub fn resolve_replicate_model(name: &str) -> &str {
+    REPLICATE_MODELS
+        .iter()
+        .find(|(friendly, _)| *friendly == name)
+        .map(|(_, model)| *model)
+        .unwrap_or(name)
+}
+
+/// Default upload limit, matching the 25 MB cap of common Whisper APIs
+pub const DEFAULT_MAX_UPLOAD_BYTES: usize = 25 * 1024 * 1024;
+
+/// Environment variable overriding the upload limit, in bytes
+pub const MAX_UPLOAD_BYTES_VAR: &str = "MAX_UPLOAD_BYTES";
+
+/// Sample rate recordings are re-encoded to when they're over the limit
+const UPLOAD_FALLBACK_RATE: u32 = 16000;
+
+/// Read the upload limit from `MAX_UPLOAD_BYTES`, or use the default
+pub fn max_upload_bytes_from_env() -> Result<usize> {
+    match env::var(MAX_UPLOAD_BYTES_VAR) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid {} \"{}\"", MAX_UPLOAD_BYTES_VAR, value)),
+        Err(_) => Ok(DEFAULT_MAX_UPLOAD_BYTES),
+    }
+}
+
+/// Recorded audio on its way to a transcription service
+///
+/// Carries the duration and sample rate alongside the WAV bytes so size
+/// checks and logging can reason about the audio without decoding it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioClip {
+    pub bytes: Vec<u8>, // WAV file contents
+    pub duration_s: f32,
+    pub sample_rate: u32,
+}
+
+impl AudioClip {
+    /// Wrap WAV bytes, reading the duration and sample rate from the header
+    pub fn from_wav(bytes: Vec<u8>) -> Result<Self> {
+        let reader = hound::WavReader::new(Cursor::new(&bytes)).context("Invalid WAV data")?;
+        let sample_rate = reader.spec().sample_rate;
+        let duration_s = reader.duration() as f32 / sample_rate.max(1) as f32;
+        Ok(Self {
+            bytes,
+            duration_s,
+            sample_rate,
+        })
+    }
+
+    /// Encode mono f32 samples as a 16-bit WAV clip
+    pub fn from_samples(samples: &[f32], sample_rate: u32) -> Result<Self> {
+        Self::from_samples_quantized(samples, sample_rate, Quantization::default())
+    }
+
+    /// Encode mono f32 samples as a 16-bit WAV clip, quantized with `mode`
+    pub fn from_samples_quantized(
+        samples: &[f32],
+        sample_rate: u32,
+        mode: Quantization,
+    ) -> Result<Self> {
+        Ok(Self {
+            bytes: samples_to_wav_quantized(samples, sample_rate, mode)?,
+            duration_s: samples.len() as f32 / sample_rate.max(1) as f32,
+            sample_rate,
+        })
+    }
+}
+
+/// Make sure a clip fits in `max_bytes` before uploading it
+///
+/// A clip over the limit is re-encoded as 16 kHz mono 16-bit, which is
+/// all Whisper uses anyway. If that is still too big, this fails with the
+/// size and the clip's duration, so nothing is uploaded: a runaway
+/// recording can't end up billed by a paid API.
+pub fn fit_upload(clip: AudioClip, max_bytes: usize) -> Result<AudioClip> {
+    if clip.bytes.len() <= max_bytes {
+        return Ok(clip);
+    }
+
+    let (samples, sample_rate) = decode_wav_mono(&clip.bytes)?;
+    let target_rate = UPLOAD_FALLBACK_RATE.min(sample_rate);
+    let samples = resample_linear(&samples, sample_rate, target_rate);
+    let reencoded = AudioClip::from_samples(&samples, target_rate)?;
+    if reencoded.bytes.len() <= max_bytes {
+        return Ok(reencoded);
+    }
+
+    anyhow::bail!(
+        "Recording too large ({} bytes > {} limit): {:.1}s of audio, even as 16 kHz mono. \
+         Raise the limit with --max-upload-bytes or {}",
+        reencoded.bytes.len(),
+        max_bytes,
+        clip.duration_s,
+        MAX_UPLOAD_BYTES_VAR
+    )
+}
+
+/// The WAV bytes to upload in place of `wav`, fitted to `max_bytes`
+///
+/// Bytes already under the limit are passed through without decoding.
+pub(crate) fn fit_wav(wav: Vec<u8>, max_bytes: usize) -> Result<Vec<u8>> {
+    if wav.len() <= max_bytes {
+        return Ok(wav);
+    }
+    Ok(fit_upload(AudioClip::from_wav(wav)?, max_bytes)?.bytes)
+}
+
+/// Environment variable limiting how long to wait for a Replicate prediction, in seconds
+pub const REPLICATE_TIMEOUT_VAR: &str = "REPLICATE_TIMEOUT";
+
+/// How long to wait for a Replicate prediction when `REPLICATE_TIMEOUT` is unset
+pub const DEFAULT_REPLICATE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Read the Replicate timeout from `REPLICATE_TIMEOUT`, or use the default
+pub fn replicate_timeout_from_env() -> Result<Duration> {
+    match env::var(REPLICATE_TIMEOUT_VAR) {
+        Ok(value) => {
+            let secs: f64 = value.trim().parse().unwrap_or(f64::NAN);
+            if !(secs > 0.0 && secs.is_finite()) {
+                anyhow::bail!(
+                    "Invalid {} \"{}\", expected a positive number of seconds",
+                    REPLICATE_TIMEOUT_VAR,
+                    value
+                );
+            }
+            Ok(Duration::from_secs_f64(secs))
+        }
+        Err(_) => Ok(DEFAULT_REPLICATE_TIMEOUT),
+    }
+}
+
+/// Environment variable with how many times a failed Replicate request is retried
+pub const REPLICATE_MAX_RETRIES_VAR: &str = "REPLICATE_MAX_RETRIES";
+
+/// Environment variable with the delay before the first retry, in milliseconds
+pub const REPLICATE_RETRY_DELAY_VAR: &str = "REPLICATE_RETRY_DELAY_MS";
+
+/// How to retry requests that failed for reasons likely to pass
+///
+/// Connection errors, timeouts, 429 (rate limited) and 5xx responses are
+/// retried up to `max_retries` times. The delay starts at `base_delay` and
+/// doubles on each retry, with ±50% jitter so clients that failed together
+/// don't all retry at the same moment. Other 4xx errors fail at once, since
+/// sending the same request again won't help.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub const NONE: Self = Self {
+        max_retries: 0,
+        base_delay: Duration::ZERO,
+    };
+
+    /// Read the policy from `REPLICATE_MAX_RETRIES` and `REPLICATE_RETRY_DELAY_MS`,
+    /// using the defaults for whichever is unset
+    pub fn from_env() -> Result<Self> {
+        let mut policy = Self::default();
+        if let Ok(value) = env::var(REPLICATE_MAX_RETRIES_VAR) {
+            policy.max_retries = value
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid {} \"{}\"", REPLICATE_MAX_RETRIES_VAR, value))?;
+        }
+        if let Ok(value) = env::var(REPLICATE_RETRY_DELAY_VAR) {
+            let millis: u64 = value
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid {} \"{}\"", REPLICATE_RETRY_DELAY_VAR, value))?;
+            policy.base_delay = Duration::from_millis(millis);
+        }
+        Ok(policy)
+    }
+
+    /// How long to wait before retry number `retry` (counting from 0)
+    pub fn delay(&self, retry: u32) -> Duration {
+        // xorshift32 seeded from the clock: only needs to differ between clients
+        let mut state = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0)
+            | 1;
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let jitter = 0.5 + (state >> 8) as f32 / (1 << 24) as f32;
+        self.base_delay
+            .saturating_mul(1 << retry.min(16))
+            .mul_f32(jitter)
+    }
+}
+
+/// Whether a response status is worth retrying: rate limited or a server error
+fn is_transient_status(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Directory where recordings are kept when transcription fails
+pub const FAILED_AUDIO_DIR: &str = "failed";
+
+/// Parse a list of extra HTTP headers
+///
+/// Entries take the form `Name: value` and are separated by `;` or newlines,
+/// e.g. `"X-API-Key: abc; X-Route: gpu"`. Blank entries are ignored.
+pub fn parse_headers(spec: &str) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+
+    for entry in spec.split([';', '\n']) {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+
+        let (name, value) = entry
+            .split_once(':')
+            .with_context(|| format!("Invalid header \"{}\" (expected \"Name: value\")", entry))?;
+
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .with_context(|| format!("Invalid header name \"{}\"", name.trim()))?;
+        let value = HeaderValue::from_str(value.trim())
+            .with_context(|| format!("Invalid value for header \"{}\"", name))?;
+
+        headers.append(name, value);
+    }
+
+    Ok(headers)
+}
+
+/// Read extra headers from an environment variable
+///
+/// Returns an empty map when the variable is not set.
+pub fn headers_from_env(var: &str) -> Result<HeaderMap> {
+    match env::var(var) {
+        Ok(spec) => parse_headers(&spec).with_context(|| format!("Failed to parse {}", var)),
+        Err(_) => Ok(HeaderMap::new()),
+    }
+}
+
+/// Save the WAV bytes of a failed transcription under `dir`
+///
+/// Files are named `recording-<unix millis>.wav` so repeated failures don't
+/// overwrite each other. Returns the path written so it can be reported.
+pub fn save_failed_audio(dir: &Path, wav_data: &[u8]) -> Result<PathBuf> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let path = dir.join(format!("recording-{}.wav", millis));
+
+    fs::write(&path, wav_data).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(path)
+}
+
+/// How long a download of audio to transcribe may take
+pub const AUDIO_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Download a WAV file to transcribe from `url`
+///
+/// Redirects are followed (up to reqwest's limit of 10) and the usual
+/// `HTTPS_PROXY`/`HTTP_PROXY` variables are honoured. A response that says
+/// it's something other than audio, e.g. an HTML error page behind a 200,
+/// is rejected rather than sent off for transcription, as is one that
+/// isn't a readable WAV file.
+pub fn download_audio(url: &str) -> Result<Vec<u8>> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(AUDIO_DOWNLOAD_TIMEOUT)
+        .build()?;
+    let response = client
+        .get(url)
+        .send()
+        .with_context(|| format!("Failed to download {}", url))?;
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("Failed to download {} ({})", url, status);
+    }
+    if let Some(content_type) = response.headers().get(reqwest::header::CONTENT_TYPE) {
+        let content_type = content_type.to_str().unwrap_or_default();
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        let audio = mime.starts_with("audio/")
+            || mime.eq_ignore_ascii_case("application/octet-stream")
+            || mime.eq_ignore_ascii_case("binary/octet-stream");
+        if !audio {
+            anyhow::bail!("{} is {}, not audio", url, content_type);
+        }
+    }
+    let bytes = response
+        .bytes()
+        .with_context(|| format!("Failed to download {}", url))?
+        .to_vec();
+    hound::WavReader::new(Cursor::new(&bytes))
+        .with_context(|| format!("{} is not a WAV file", url))?;
+    Ok(bytes)
+}
+
+/// Configuration for Whisper transcription service
+#[derive(Clone)]
+pub struct WhisperConfig {
+    pub endpoint: Option<String>,       // Local Fast Whisper endpoint
+    pub api_key: Option<String>,        // Replicate API key
+    pub openai_api_key: Option<String>, // OpenAI API key, used when there's no Replicate key
+    pub replicate_model: String,        // Replicate `owner/name:version`
+    pub local_headers: HeaderMap,       // Extra headers for the local endpoint (WHISPER_HEADERS)
+    pub replicate_headers: HeaderMap,   // Extra headers for Replicate (REPLICATE_HEADERS)
+    pub max_upload_bytes: usize,        // Larger recordings are re-encoded or rejected
+    pub prompt: Option<String>,         // Initial prompt biasing vocabulary and spelling
+    pub text_pointer: Option<String>,   // JSON pointer to the transcript in responses
+    pub replicate_timeout: Duration,    // Give up on a prediction that hasn't finished by then
+    pub retry: RetryPolicy,             // Retries of failed Replicate requests
+}
+
+impl WhisperConfig {
+    /// Read the configuration from `WHISPER_ENDPOINT`, `REPLICATE_API_KEY`, `OPENAI_API_KEY`,
+    /// `REPLICATE_MODEL`, `MAX_UPLOAD_BYTES`, `WHISPER_PROMPT`,
+    /// `WHISPER_TEXT_POINTER`, `REPLICATE_TIMEOUT`, the retry variables (see
+    /// `RetryPolicy::from_env`) and the extra-header variables. An empty API key
+    /// counts as unset.
+    pub fn from_env() -> Result<Self> {
+        let model =
+            env::var("REPLICATE_MODEL").unwrap_or_else(|_| DEFAULT_REPLICATE_MODEL.to_string());
+        Ok(Self {
+            endpoint: env::var("WHISPER_ENDPOINT").ok(),
+            api_key: env::var("REPLICATE_API_KEY")
+                .ok()
+                .filter(|key| !key.trim().is_empty()),
+            openai_api_key: env::var("OPENAI_API_KEY")
+                .ok()
+                .filter(|key| !key.trim().is_empty()),
+            replicate_model: resolve_replicate_model(&model).to_string(),
+            local_headers: headers_from_env(WHISPER_HEADERS_VAR)?,
+            replicate_headers: headers_from_env(REPLICATE_HEADERS_VAR)?,
+            max_upload_bytes: max_upload_bytes_from_env()?,
+            prompt: env::var("WHISPER_PROMPT")
+                .ok()
+                .filter(|p| !p.trim().is_empty()),
+            text_pointer: env::var(WHISPER_TEXT_POINTER_VAR)
+                .ok()
+                .map(|pointer| parse_text_pointer(&pointer))
+                .transpose()
+                .with_context(|| format!("Invalid {}", WHISPER_TEXT_POINTER_VAR))?,
+            replicate_timeout: replicate_timeout_from_env()?,
+            retry: RetryPolicy::from_env()?,
+        })
+    }
+
+    /// Whether any transcription service is configured
+    pub fn is_configured(&self) -> bool {
+        self.endpoint.is_some() || self.api_key.is_some() || self.openai_api_key.is_some()
+    }
+}
+
+/// How often a cancellable transcription checks whether to give up
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long to wait before first asking for a Replicate prediction's status
+pub(crate) const REPLICATE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Longest wait between status checks; the wait doubles up to this
+const REPLICATE_MAX_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The error (inside `anyhow::Error`) of a cancelled transcription
+///
+/// Check for it with `error.is::<Cancelled>()` to tell a cancellation
+/// apart from a failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Transcription cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Run `job` on its own thread, giving up as soon as `cancelled` returns true
+///
+/// A blocking request can't be interrupted, so a cancelled job runs on in
+/// the background and its result is thrown away.
+pub fn run_cancellable<T: Send + 'static>(
+    cancelled: impl Fn() -> bool,
+    job: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || tx.send(job()));
+    loop {
+        if cancelled() {
+            return Err(Cancelled.into());
+        }
+        match rx.recv_timeout(CANCEL_CHECK_INTERVAL) {
+            Ok(result) => return result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                anyhow::bail!("Transcription thread panicked")
+            }
+        }
+    }
+}
+
+/// A cap on how many transcriptions run at once
+///
+/// Every request in flight holds a whole recording in memory, and backends
+/// rate limit bursts, so work that transcribes clips in parallel takes a
+/// permit for each one: `acquire` waits its turn, `try_acquire` gives up
+/// straight away.
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    max: usize,
+    in_flight: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// A slot taken from a `ConcurrencyLimit`, given back when dropped
+#[derive(Debug)]
+pub struct ConcurrencyPermit<'a>(&'a ConcurrencyLimit);
+
+impl ConcurrencyLimit {
+    /// Allow `max` transcriptions at once (at least one)
+    pub fn new(max: usize) -> Self {
+        Self {
+            max: max.max(1),
+            in_flight: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// No limit at all
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    /// Wait until fewer than the maximum are running, then take a slot
+    pub fn acquire(&self) -> ConcurrencyPermit<'_> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        while *in_flight >= self.max {
+            in_flight = self.freed.wait(in_flight).unwrap();
+        }
+        *in_flight += 1;
+        ConcurrencyPermit(self)
+    }
+
+    /// Take a slot if one is free, without waiting
+    pub fn try_acquire(&self) -> Option<ConcurrencyPermit<'_>> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if *in_flight >= self.max {
+            return None;
+        }
+        *in_flight += 1;
+        Some(ConcurrencyPermit(self))
+    }
+
+    /// The most transcriptions allowed at once
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
+
+impl Drop for ConcurrencyPermit<'_> {
+    fn drop(&mut self) {
+        *self.0.in_flight.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// Transcribe like `transcribe_audio`, but give up as soon as `cancelled` returns true
+///
+/// A cancelled transcription fails with `Cancelled`. On Replicate the
+/// prediction is cancelled as well, so it stops using GPU time; other
+/// services have no way to stop, so their answer is just ignored.
+pub fn transcribe_audio_cancellable(
+    config: &WhisperConfig,
+    clip: AudioClip,
+    cancelled: impl Fn() -> bool,
+) -> Result<Transcription> {
+    let transcriber = config.transcriber()?;
+    if config.replicate_transcriber().is_some() {
+        return transcriber.transcribe_cancellable(clip.bytes, &cancelled);
+    }
+    run_cancellable(cancelled, move || {
+        transcriber.transcribe_segments(clip.bytes)
+    })
+}
+
+/// Transcribe audio using configured Whisper service
+///
+/// See `WhisperConfig::transcriber` for which service is used and how the
+/// clip is fitted to the upload limit.
+pub fn transcribe_audio(config: &WhisperConfig, clip: AudioClip) -> Result<String> {
+    config.transcriber()?.transcribe(clip.bytes)
+}
+
+/// Transcribe using local Fast Whisper endpoint
+///
+/// Shorthand for `LocalWhisperTranscriber`, which describes the request.
+pub fn transcribe_local_whisper(
+    endpoint: &str,
+    headers: &HeaderMap,
+    audio_data: Vec<u8>,
+    prompt: Option<&str>,
+    text_pointer: Option<&str>,
+) -> Result<String> {
+    LocalWhisperTranscriber {
+        endpoint: endpoint.to_string(),
+        headers: headers.clone(),
+        prompt: prompt.map(str::to_string),
+        text_pointer: text_pointer.map(str::to_string),
+    }
+    .transcribe(audio_data)
+}
+
+/// Build the URL and JSON body that create a Replicate prediction
+///
+/// A pinned `owner/name:version` goes to `/v1/predictions` with the version
+/// in the body; a bare `owner/name` runs the model's latest version. The
+/// audio is inlined as a data URL, and `prompt` is only included when set.
+pub fn replicate_request(
+    model: &str,
+    audio_data: &[u8],
+    prompt: Option<&str>,
+) -> (String, serde_json::Value) {
+    let data_url = format!("data:audio/wav;base64,{}", BASE64.encode(audio_data));
+    replicate_url_request(model, &data_url, prompt)
+}
+
+/// Like `replicate_request`, but for audio Replicate fetches itself from `audio_url`
+pub fn replicate_url_request(
+    model: &str,
+    audio_url: &str,
+    prompt: Option<&str>,
+) -> (String, serde_json::Value) {
+    let mut input = serde_json::json!({ "audio": audio_url });
+    if let Some(prompt) = prompt {
+        input["prompt"] = prompt.into();
+    }
+
+    match model.split_once(':') {
+        Some((_, version)) => (
+            "https://api.replicate.com/v1/predictions".to_string(),
+            serde_json::json!({ "version": version, "input": input }),
+        ),
+        None => (
+            format!("https://api.replicate.com/v1/models/{}/predictions", model),
+            serde_json::json!({ "input": input }),
+        ),
+    }
+}
+
+/// How `transcribe_replicate` and `transcribe_replicate_async` run a prediction
+///
+/// The default is the default model, with no extra headers or prompt, the
+/// default timeout, retries and upload limit.
+#[derive(Debug, Clone)]
+pub struct ReplicateOptions {
+    pub model: String, // `owner/name:version`, or `owner/name` for the latest version
+    pub headers: HeaderMap,
+    pub prompt: Option<String>,
+    pub text_pointer: Option<String>, // Where the transcript is in the prediction
+    pub timeout: Duration,
+    pub retry: RetryPolicy,
+    pub max_upload_bytes: usize, // Recordings are fitted to this with `fit_upload`
+}
+
+impl Default for ReplicateOptions {
+    fn default() -> Self {
+        Self {
+            model: resolve_replicate_model(DEFAULT_REPLICATE_MODEL).to_string(),
+            headers: HeaderMap::new(),
+            prompt: None,
+            text_pointer: None,
+            timeout: DEFAULT_REPLICATE_TIMEOUT,
+            retry: RetryPolicy::default(),
+            max_upload_bytes: DEFAULT_MAX_UPLOAD_BYTES,
+        }
+    }
+}
+
+/// Transcribe using Replicate API
+///
+/// Shorthand for `ReplicateTranscriber` behind an `UploadLimit`, which
+/// describe how predictions are run and read.
+pub fn transcribe_replicate(
+    api_key: &str,
+    audio_data: Vec<u8>,
+    options: &ReplicateOptions,
+) -> Result<String> {
+    let options = options.clone();
+    let transcriber = ReplicateTranscriber {
+        api_key: api_key.to_string(),
+        model: options.model,
+        headers: options.headers,
+        prompt: options.prompt,
+        text_pointer: options.text_pointer,
+        timeout: options.timeout,
+        retry: options.retry,
+    };
+    UploadLimit::new(Box::new(transcriber), options.max_upload_bytes).transcribe(audio_data)
+}
+
+/// Transcribe WAV bytes on Replicate without blocking the calling thread
+///
+/// The async counterpart of `transcribe_replicate`, with the default
+/// `ReplicateOptions`. Built on the non-blocking `reqwest::Client`, so a
+/// recording loop can carry on while the upload is in flight; it needs a
+/// Tokio runtime. See `transcribe_replicate_async` for the other options.
+pub async fn transcribe_audio_async(api_key: &str, audio: Vec<u8>) -> Result<String> {
+    transcribe_replicate_async(api_key, audio, &ReplicateOptions::default()).await
+}
+
+/// Transcribe using Replicate API, asynchronously
+///
+/// Sends the same request as `transcribe_replicate`, polls an unfinished
+/// prediction and retries transient failures the same way. The recording
+/// is fitted to the upload limit before any request is built, so one too
+/// big is refused without contacting Replicate.
+pub async fn transcribe_replicate_async(
+    api_key: &str,
+    audio_data: Vec<u8>,
+    options: &ReplicateOptions,
+) -> Result<String> {
+    let audio_data = fit_wav(audio_data, options.max_upload_bytes)?;
+    let retry = &options.retry;
+    let client = reqwest::Client::new();
+    let auth = format!("Bearer {}", api_key);
+    let request = |method: reqwest::Method, url: &str| {
+        client
+            .request(method, url)
+            .header("Authorization", &auth)
+            .headers(options.headers.clone())
+    };
+
+    let (url, body) = replicate_request(&options.model, &audio_data, options.prompt.as_deref());
+    let started = Instant::now();
+    let prediction = replicate_call_async(
+        request(reqwest::Method::POST, &url)
+            // Ask Replicate to hold the response until the prediction finishes
+            .header("Prefer", "wait")
+            .json(&body),
+        retry,
+    )
+    .await?;
+    let prediction = wait_for_prediction_async(
+        prediction,
+        request,
+        REPLICATE_POLL_INTERVAL,
+        options.timeout.saturating_sub(started.elapsed()),
+        retry,
+    )
+    .await?;
+    replicate_text(&prediction, options.text_pointer.as_deref())
+}
+
+/// The transcript of a finished Replicate prediction
+pub(crate) fn replicate_text(
+    prediction: &serde_json::Value,
+    text_pointer: Option<&str>,
+) -> Result<String> {
+    // Diarizing models return speaker-labelled segments, shown as speaker turns
+    let diarized = prediction
+        .get("output")
+        .and_then(parse_segments)
+        .filter(|segments| has_speakers(segments));
+    match diarized {
+        Some(segments) => Ok(format_speaker_turns(&segments)),
+        None => find_text(prediction, text_pointer),
+    }
+}
+
+/// Send a Replicate API request and return the prediction it answers with
+///
+/// Transient failures are retried according to `retry`.
+pub(crate) fn replicate_call(
+    request: reqwest::blocking::RequestBuilder,
+    retry: &RetryPolicy,
+) -> Result<serde_json::Value> {
+    let mut retries = 0;
+    loop {
+        let attempt = request
+            .try_clone()
+            .context("Replicate request can't be retried")?;
+        let error = match attempt.send() {
+            Ok(response) if response.status().is_success() => return Ok(response.json()?),
+            Ok(response) => {
+                let status = response.status();
+                let error_text = response.text().unwrap_or_default();
+                let error = anyhow::anyhow!("Replicate API error ({}): {}", status, error_text);
+                if !is_transient_status(status) {
+                    return Err(error);
+                }
+                error
+            }
+            Err(e) => {
+                let transient = e.is_connect() || e.is_timeout();
+                let error = anyhow::Error::new(e).context("Failed to send request to Replicate");
+                if !transient {
+                    return Err(error);
+                }
+                error
+            }
+        };
+        if retries >= retry.max_retries {
+            return Err(error);
+        }
+
+        let delay = retry.delay(retries);
+        retries += 1;
+        report_retry(&error, delay, retries, retry);
+        thread::sleep(delay);
+    }
+}
+
+/// Poll a Replicate prediction until it succeeds, and return it
+///
+/// The status is fetched from the prediction's `urls.get`, first after
+/// `interval` and then at doubling intervals up to
+/// `REPLICATE_MAX_POLL_INTERVAL`. A `failed` or `canceled` prediction is an
+/// error carrying Replicate's message. Past `timeout`, or once `cancelled`
+/// returns true (failing with `Cancelled`), the prediction's `cancel` URL
+/// is called (best effort) so it stops running. Each request is retried
+/// according to `retry`.
+pub(crate) fn wait_for_prediction(
+    mut prediction: serde_json::Value,
+    request: impl Fn(reqwest::Method, &str) -> reqwest::blocking::RequestBuilder,
+    interval: Duration,
+    timeout: Duration,
+    retry: &RetryPolicy,
+    cancelled: impl Fn() -> bool,
+) -> Result<serde_json::Value> {
+    let started = Instant::now();
+    let mut interval = interval;
+    loop {
+        if prediction_succeeded(&prediction)? {
+            return Ok(prediction);
+        }
+
+        let cancel_prediction = |prediction: &serde_json::Value| {
+            if let Some(cancel_url) = prediction
+                .pointer("/urls/cancel")
+                .and_then(|url| url.as_str())
+            {
+                if let Err(cancel_err) =
+                    replicate_call(request(reqwest::Method::POST, cancel_url), retry)
+                {
+                    eprintln!(
+                        "Failed to cancel the Replicate prediction: {:#}",
+                        cancel_err
+                    );
+                }
+            }
+        };
+        if started.elapsed() + interval > timeout {
+            cancel_prediction(&prediction);
+            return Err(prediction_timed_out(&prediction, timeout));
+        }
+
+        let poll = request(reqwest::Method::GET, poll_url(&prediction)?);
+        let waited = Instant::now();
+        let (wait, retry) = (interval, *retry);
+        let result = run_cancellable(&cancelled, move || {
+            thread::sleep(wait.saturating_sub(waited.elapsed()));
+            replicate_call(poll, &retry)
+        });
+        match result {
+            Ok(next) => prediction = next,
+            Err(e) => {
+                if e.is::<Cancelled>() {
+                    cancel_prediction(&prediction);
+                }
+                return Err(e);
+            }
+        }
+        interval = (interval * 2).min(REPLICATE_MAX_POLL_INTERVAL);
+    }
+}
+
+/// Whether a prediction has succeeded; an error if it failed or was cancelled
+fn prediction_succeeded(prediction: &serde_json::Value) -> Result<bool> {
+    match prediction["status"].as_str() {
+        Some("succeeded") => Ok(true),
+        Some(status @ ("failed" | "canceled")) => anyhow::bail!(
+            "Replicate prediction {}: {}",
+            status,
+            prediction["error"].as_str().unwrap_or("no details given")
+        ),
+        _ => Ok(false),
+    }
+}
+
+/// The URL a prediction's status is fetched from
+fn poll_url(prediction: &serde_json::Value) -> Result<&str> {
+    prediction
+        .pointer("/urls/get")
+        .and_then(|url| url.as_str())
+        .context("Replicate prediction has no status URL")
+}
+
+/// The error for a prediction that didn't finish within `timeout`
+fn prediction_timed_out(prediction: &serde_json::Value, timeout: Duration) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Replicate prediction still {} after {:.0}s",
+        prediction["status"].as_str().unwrap_or("pending"),
+        timeout.as_secs_f32()
+    )
+}
+
+/// Report a failed Replicate request that is about to be retried
+fn report_retry(error: &anyhow::Error, delay: Duration, retries: u32, retry: &RetryPolicy) {
+    eprintln!(
+        "{:#}; retrying in {:.1}s ({}/{})",
+        error,
+        delay.as_secs_f32(),
+        retries,
+        retry.max_retries
+    );
+}
+
+/// `replicate_call` on the non-blocking client
+async fn replicate_call_async(
+    request: reqwest::RequestBuilder,
+    retry: &RetryPolicy,
+) -> Result<serde_json::Value> {
+    let mut retries = 0;
+    loop {
+        let attempt = request
+            .try_clone()
+            .context("Replicate request can't be retried")?;
+        let error = match attempt.send().await {
+            Ok(response) if response.status().is_success() => return Ok(response.json().await?),
+            Ok(response) => {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+                let error = anyhow::anyhow!("Replicate API error ({}): {}", status, error_text);
+                if !is_transient_status(status) {
+                    return Err(error);
+                }
+                error
+            }
+            Err(e) => {
+                let transient = e.is_connect() || e.is_timeout();
+                let error = anyhow::Error::new(e).context("Failed to send request to Replicate");
+                if !transient {
+                    return Err(error);
+                }
+                error
+            }
+        };
+        if retries >= retry.max_retries {
+            return Err(error);
+        }
+
+        let delay = retry.delay(retries);
+        retries += 1;
+        report_retry(&error, delay, retries, retry);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// `wait_for_prediction` on the non-blocking client
+///
+/// Polls the same way and gives up after `timeout` the same way; to stop
+/// waiting sooner, drop the future (the prediction then runs on).
+pub(crate) async fn wait_for_prediction_async(
+    mut prediction: serde_json::Value,
+    request: impl Fn(reqwest::Method, &str) -> reqwest::RequestBuilder,
+    interval: Duration,
+    timeout: Duration,
+    retry: &RetryPolicy,
+) -> Result<serde_json::Value> {
+    let started = Instant::now();
+    let mut interval = interval;
+    while !prediction_succeeded(&prediction)? {
+        if started.elapsed() + interval > timeout {
+            if let Some(cancel_url) = prediction
+                .pointer("/urls/cancel")
+                .and_then(|url| url.as_str())
+            {
+                let cancel = request(reqwest::Method::POST, cancel_url);
+                if let Err(cancel_err) = replicate_call_async(cancel, retry).await {
+                    eprintln!(
+                        "Failed to cancel the Replicate prediction: {:#}",
+                        cancel_err
+                    );
+                }
+            }
+            return Err(prediction_timed_out(&prediction, timeout));
+        }
+
+        let poll = request(reqwest::Method::GET, poll_url(&prediction)?);
+        tokio::time::sleep(interval).await;
+        prediction = replicate_call_async(poll, retry).await?;
+        interval = (interval * 2).min(REPLICATE_MAX_POLL_INTERVAL);
+    }
+    Ok(prediction)
+}
+
+/// Serve `responses` (status code and JSON body) in order, one per connection, on `listener`
+///
+/// Returns a channel receiving each request's first line. Shared by the
+/// backend tests.
+#[cfg(test)]
+pub(crate) fn mock_server(
+    listener: std::net::TcpListener,
+    responses: Vec<(u16, serde_json::Value)>,
+) -> mpsc::Receiver<String> {
+    let responses = responses
+        .into_iter()
+        .map(|(status, body)| {
+            let body = body.to_string();
+            format!(
+                "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .into_bytes()
+        })
+        .collect();
+    mock_raw_server(listener, responses)
+}
+
+/// `mock_server` for responses written out in full, status line and headers included
+#[cfg(test)]
+pub(crate) fn mock_raw_server(
+    listener: std::net::TcpListener,
+    responses: Vec<Vec<u8>>,
+) -> mpsc::Receiver<String> {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            tx.send(line.trim().to_string()).unwrap();
+            // Skip the headers, then the body, so the client sees the whole response
+            let mut content_length = 0;
+            loop {
+                line.clear();
+                if reader.read_line(&mut line).unwrap() == 0 || line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            reader.read_exact(&mut vec![0; content_length]).unwrap();
+            stream.write_all(&response).unwrap();
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::samples_to_wav;
+
+    #[test]
+    fn test_parse_headers() {
+        let headers =
+            parse_headers("X-API-Key: abc; Authorization: Bearer xyz\nX-Route:gpu").unwrap();
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers["x-api-key"], "abc");
+        assert_eq!(headers["authorization"], "Bearer xyz");
+        assert_eq!(headers["x-route"], "gpu");
+    }
+
+    #[test]
+    fn test_parse_headers_rejects_malformed_entry() {
+        assert!(parse_headers("").unwrap().is_empty());
+        assert!(parse_headers("no-colon-here").is_err());
+        assert!(parse_headers("Bad Name: value").is_err());
+    }
+
+    #[test]
+    fn test_resolve_replicate_model() {
+        assert!(resolve_replicate_model("fast").starts_with("vaibhavs10/incredibly-fast-whisper:"));
+        assert!(resolve_replicate_model("large-v3").starts_with("openai/whisper:"));
+        assert_eq!(
+            resolve_replicate_model("me/my-whisper:abc123"),
+            "me/my-whisper:abc123"
+        );
+    }
+
+    #[test]
+    fn test_audio_clip_from_wav() {
+        let wav = samples_to_wav(&vec![0.1; 24000], 16000).unwrap();
+        let clip = AudioClip::from_wav(wav.clone()).unwrap();
+        assert_eq!(clip.sample_rate, 16000);
+        assert_eq!(clip.duration_s, 1.5);
+        assert_eq!(
+            clip,
+            AudioClip::from_samples(&vec![0.1; 24000], 16000).unwrap()
+        );
+        assert!(AudioClip::from_wav(b"not a wav".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_fit_upload() {
+        let clip = AudioClip::from_samples(&vec![0.1; 48000], 48000).unwrap();
+        let limit = clip.bytes.len();
+        assert_eq!(fit_upload(clip.clone(), limit).unwrap(), clip);
+
+        // Over the limit: re-encoded at 16 kHz, a third of the size
+        let fitted = fit_upload(clip.clone(), limit / 2).unwrap();
+        assert_eq!(fitted.sample_rate, 16000);
+        assert_eq!(fitted.duration_s, 1.0);
+
+        // Still too big after re-encoding: fail with the duration
+        let err = fit_upload(clip, 1000).unwrap_err().to_string();
+        assert!(err.contains("1.0s"), "{}", err);
+    }
+
+    #[test]
+    fn test_oversized_upload_is_never_sent() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let config = WhisperConfig {
+            endpoint: Some(format!("http://{}", listener.local_addr().unwrap())),
+            api_key: None,
+            openai_api_key: None,
+            replicate_model: DEFAULT_REPLICATE_MODEL.to_string(),
+            local_headers: HeaderMap::new(),
+            replicate_headers: HeaderMap::new(),
+            max_upload_bytes: 1000,
+            prompt: None,
+            text_pointer: None,
+            replicate_timeout: DEFAULT_REPLICATE_TIMEOUT,
+            retry: RetryPolicy::NONE,
+        };
+        let clip = AudioClip::from_samples(&vec![0.1; 16000], 16000).unwrap();
+
+        let err = transcribe_audio(&config, clip.clone())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("Recording too large (32044 bytes > 1000 limit)"),
+            "{}",
+            err
+        );
+        // Calling the transcriber directly gets the same check
+        let err = config
+            .transcriber()
+            .unwrap()
+            .transcribe_segments(clip.bytes)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("Recording too large"), "{}", err);
+        // The endpoint was never contacted
+        assert!(listener.accept().is_err());
+    }
+
+    #[test]
+    fn test_replicate_request() {
+        let (url, body) = replicate_request("openai/whisper:abc123", b"RIFF", Some("Kubernetes"));
+        assert_eq!(url, "https://api.replicate.com/v1/predictions");
+        assert_eq!(body["version"], "abc123");
+        assert_eq!(body["input"]["audio"], "data:audio/wav;base64,UklGRg==");
+        assert_eq!(body["input"]["prompt"], "Kubernetes");
+
+        // No prompt: the field is left out entirely
+        let (url, body) = replicate_request("me/my-whisper", b"RIFF", None);
+        assert_eq!(
+            url,
+            "https://api.replicate.com/v1/models/me/my-whisper/predictions"
+        );
+        assert!(body.get("version").is_none());
+        assert!(body["input"].get("prompt").is_none());
+
+        // Hosted audio is passed by URL, for Replicate to fetch
+        let (_, body) = replicate_url_request(
+            "openai/whisper:abc123",
+            "https://example.com/clip.wav",
+            None,
+        );
+        assert_eq!(body["input"]["audio"], "https://example.com/clip.wav");
+    }
+
+    #[test]
+    fn test_download_and_transcribe_url() {
+        let wav = samples_to_wav(&vec![0.1; 16000], 16000).unwrap();
+        let response = |status: &str, headers: &str, body: &[u8]| {
+            let mut response = format!(
+                "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                headers,
+                body.len()
+            )
+            .into_bytes();
+            response.extend_from_slice(body);
+            response
+        };
+        let files = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = format!("http://{}", files.local_addr().unwrap());
+        let requests = mock_raw_server(
+            files,
+            vec![
+                response(
+                    "302 Found",
+                    &format!("Location: {}/clip.wav\r\n", host),
+                    b"",
+                ),
+                response("200 OK", "Content-Type: audio/wav\r\n", &wav),
+                response(
+                    "200 OK",
+                    "Content-Type: text/html; charset=utf-8\r\n",
+                    b"<html>Sign in</html>",
+                ),
+            ],
+        );
+        let whisper = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let transcriber =
+            LocalWhisperTranscriber::new(&format!("http://{}", whisper.local_addr().unwrap()));
+        let _uploads = mock_server(
+            whisper,
+            vec![(200, serde_json::json!({ "text": "From the web." }))],
+        );
+
+        // The redirect is followed to the WAV, which is transcribed as usual
+        let downloaded = download_audio(&format!("{}/latest", host)).unwrap();
+        assert_eq!(downloaded, wav);
+        assert_eq!(transcriber.transcribe(downloaded).unwrap(), "From the web.");
+
+        // A page that isn't audio is refused
+        let err = download_audio(&format!("{}/login", host)).unwrap_err();
+        assert!(err.to_string().contains("text/html"), "{}", err);
+
+        let lines: Vec<String> = requests.try_iter().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "GET /latest HTTP/1.1",
+                "GET /clip.wav HTTP/1.1",
+                "GET /login HTTP/1.1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wait_for_prediction_polls_until_done() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let urls = serde_json::json!({
+            "get": format!("http://{}/v1/predictions/abc", listener.local_addr().unwrap()),
+        });
+        let prediction = |status: &str| serde_json::json!({ "status": status, "urls": urls });
+        let mut succeeded = prediction("succeeded");
+        succeeded["output"] = serde_json::json!({ "text": "hello" });
+        let requests = mock_server(
+            listener,
+            vec![
+                (200, prediction("processing")),
+                (200, prediction("processing")),
+                (200, succeeded),
+            ],
+        );
+        let client = reqwest::blocking::Client::new();
+        let request = |method, url: &str| client.request(method, url);
+
+        let done = wait_for_prediction(
+            prediction("starting"),
+            request,
+            Duration::from_millis(10),
+            Duration::from_secs(10),
+            &RetryPolicy::NONE,
+            || false,
+        )
+        .unwrap();
+        assert_eq!(replicate_text(&done, None).unwrap(), "hello");
+        let polls: Vec<String> = requests.try_iter().collect();
+        assert_eq!(polls, vec!["GET /v1/predictions/abc HTTP/1.1"; 3]);
+
+        // A failed prediction is an error with Replicate's message
+        let failed = serde_json::json!({ "status": "failed", "error": "CUDA out of memory" });
+        let err = wait_for_prediction(
+            failed,
+            request,
+            Duration::ZERO,
+            Duration::ZERO,
+            &RetryPolicy::NONE,
+            || false,
+        );
+        assert!(err.unwrap_err().to_string().contains("CUDA out of memory"));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_async_upload_is_never_sent() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let clip = AudioClip::from_samples(&vec![0.1; 16000], 16000).unwrap();
+        let options = ReplicateOptions {
+            max_upload_bytes: 1000,
+            retry: RetryPolicy::NONE,
+            ..Default::default()
+        };
+
+        let err = transcribe_replicate_async("r8_key", clip.bytes.clone(), &options)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("Recording too large (32044 bytes > 1000 limit)"),
+            "{}",
+            err
+        );
+        let err = transcribe_replicate("r8_key", clip.bytes, &options)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("Recording too large"), "{}", err);
+        // Nothing was sent anywhere
+        assert!(listener.accept().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_prediction_async_polls_until_done() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let urls = serde_json::json!({
+            "get": format!("http://{}/v1/predictions/abc", addr),
+            "cancel": format!("http://{}/v1/predictions/abc/cancel", addr),
+        });
+        let prediction = |status: &str| serde_json::json!({ "status": status, "urls": urls });
+        let mut succeeded = prediction("succeeded");
+        succeeded["output"] = serde_json::json!({ "text": "hello" });
+        let requests = mock_server(
+            listener,
+            vec![
+                // A transient failure is retried like any other request
+                (503, serde_json::json!({ "detail": "unavailable" })),
+                (200, prediction("processing")),
+                (200, succeeded),
+            ],
+        );
+        let client = reqwest::Client::new();
+        let request = |method, url: &str| client.request(method, url);
+        let retry = RetryPolicy {
+            max_retries: 1,
+            base_delay: Duration::from_millis(1),
+        };
+
+        let done = wait_for_prediction_async(
+            prediction("starting"),
+            request,
+            Duration::from_millis(10),
+            Duration::from_secs(10),
+            &retry,
+        )
+        .await
+        .unwrap();
+        assert_eq!(replicate_text(&done, None).unwrap(), "hello");
+        let polls: Vec<String> = requests.try_iter().collect();
+        assert_eq!(polls, vec!["GET /v1/predictions/abc HTTP/1.1"; 3]);
+
+        // Out of time, the prediction is cancelled
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let urls = serde_json::json!({
+            "get": format!("http://{}/v1/predictions/abc", listener.local_addr().unwrap()),
+            "cancel": format!("http://{}/v1/predictions/abc/cancel", listener.local_addr().unwrap()),
+        });
+        let requests = mock_server(listener, vec![(200, prediction("canceled"))]);
+        let pending = serde_json::json!({ "status": "processing", "urls": urls });
+        let err = wait_for_prediction_async(
+            pending,
+            request,
+            Duration::from_secs(1),
+            Duration::ZERO,
+            &RetryPolicy::NONE,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Replicate prediction still processing after 0s"
+        );
+        assert_eq!(
+            requests.recv().unwrap(),
+            "POST /v1/predictions/abc/cancel HTTP/1.1"
+        );
+
+        // A failed prediction is an error with Replicate's message
+        let failed = serde_json::json!({ "status": "failed", "error": "CUDA out of memory" });
+        let err = wait_for_prediction_async(
+            failed,
+            request,
+            Duration::ZERO,
+            Duration::ZERO,
+            &RetryPolicy::NONE,
+        )
+        .await;
+        assert!(err.unwrap_err().to_string().contains("CUDA out of memory"));
+    }
+
+    #[test]
+    fn test_replicate_call_retries_transient_failures() {
+        let retry = RetryPolicy {
+            max_retries: 2,
+            base_delay: Duration::from_millis(1),
+        };
+        let client = reqwest::blocking::Client::new();
+
+        // Rate limited, then a server error, then success
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/predictions", listener.local_addr().unwrap());
+        let requests = mock_server(
+            listener,
+            vec![
+                (429, serde_json::json!({ "detail": "slow down" })),
+                (503, serde_json::json!({ "detail": "unavailable" })),
+                (200, serde_json::json!({ "status": "succeeded" })),
+            ],
+        );
+        let prediction = replicate_call(client.get(&url), &retry).unwrap();
+        assert_eq!(prediction["status"], "succeeded");
+        assert_eq!(requests.try_iter().count(), 3);
+
+        // A bad request fails straight away
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/predictions", listener.local_addr().unwrap());
+        let requests = mock_server(
+            listener,
+            vec![(422, serde_json::json!({ "detail": "invalid input" }))],
+        );
+        let err = replicate_call(client.get(&url), &retry).unwrap_err();
+        assert!(err.to_string().contains("invalid input"), "{}", err);
+        assert_eq!(requests.try_iter().count(), 1);
+
+        // Nobody listening: retried, then the connection error is reported
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/predictions", closed.local_addr().unwrap());
+        drop(closed);
+        let err = replicate_call(client.get(&url), &retry).unwrap_err();
+        assert!(err.to_string().contains("Failed to send"), "{}", err);
+
+        // The delay doubles, give or take the jitter
+        let delay = RetryPolicy::default().delay(2);
+        assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_run_cancellable() {
+        assert_eq!(run_cancellable(|| false, || Ok(7)).unwrap(), 7);
+        assert!(
+            run_cancellable(|| false, || -> Result<()> { anyhow::bail!("boom") })
+                .unwrap_err()
+                .to_string()
+                .contains("boom")
+        );
+
+        // A slow job is abandoned as soon as it's cancelled
+        let started = Instant::now();
+        let error = run_cancellable(
+            || started.elapsed() > Duration::from_millis(100),
+            || {
+                thread::sleep(Duration::from_secs(10));
+                Ok(())
+            },
+        )
+        .unwrap_err();
+        assert!(error.is::<Cancelled>());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_transcribe_audio_cancellable() {
+        let clip = AudioClip::from_samples(&vec![0.1; 1600], 16000).unwrap();
+        let config = |endpoint: String| WhisperConfig {
+            endpoint: Some(endpoint),
+            api_key: None,
+            openai_api_key: None,
+            replicate_model: DEFAULT_REPLICATE_MODEL.to_string(),
+            local_headers: HeaderMap::new(),
+            replicate_headers: HeaderMap::new(),
+            max_upload_bytes: DEFAULT_MAX_UPLOAD_BYTES,
+            prompt: None,
+            text_pointer: None,
+            replicate_timeout: DEFAULT_REPLICATE_TIMEOUT,
+            retry: RetryPolicy::NONE,
+        };
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let requests = mock_server(
+            listener,
+            vec![(200, serde_json::json!({ "text": "hello" }))],
+        );
+        let transcription =
+            transcribe_audio_cancellable(&config(endpoint), clip.clone(), || false).unwrap();
+        assert_eq!(transcription.text, "hello");
+        assert!(requests.recv().unwrap().starts_with("POST "));
+
+        // An endpoint that never answers is given up on once cancelled
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let started = Instant::now();
+        let error = transcribe_audio_cancellable(&config(endpoint), clip, || {
+            started.elapsed() > Duration::from_millis(100)
+        })
+        .unwrap_err();
+        assert!(error.is::<Cancelled>());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_cancelled_prediction_is_cancelled_on_replicate() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let prediction = serde_json::json!({
+            "status": "starting",
+            "urls": {
+                "get": format!("http://{}/v1/predictions/abc", addr),
+                "cancel": format!("http://{}/v1/predictions/abc/cancel", addr),
+            },
+        });
+        let requests = mock_server(
+            listener,
+            vec![(200, serde_json::json!({ "status": "canceled" }))],
+        );
+        let client = reqwest::blocking::Client::new();
+        let request = |method, url: &str| client.request(method, url);
+
+        let error = wait_for_prediction(
+            prediction,
+            request,
+            // Long enough that no status poll is made first
+            Duration::from_secs(10),
+            Duration::from_secs(60),
+            &RetryPolicy::NONE,
+            || true,
+        )
+        .unwrap_err();
+        assert!(error.is::<Cancelled>());
+        assert_eq!(
+            requests.recv_timeout(Duration::from_secs(5)).unwrap(),
+            "POST /v1/predictions/abc/cancel HTTP/1.1"
+        );
+    }
+
+    #[test]
+    fn test_concurrency_limit() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let run = |limit: &ConcurrencyLimit| {
+            let running = AtomicUsize::new(0);
+            let most = AtomicUsize::new(0);
+            let finished = AtomicUsize::new(0);
+            thread::scope(|scope| {
+                for _ in 0..4 {
+                    scope.spawn(|| {
+                        let _permit = limit.acquire();
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(20));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        finished.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            });
+            (most.into_inner(), finished.into_inner())
+        };
+
+        // With one slot the others queue, and all of them still finish
+        assert_eq!(run(&ConcurrencyLimit::new(1)), (1, 4));
+        assert!(run(&ConcurrencyLimit::new(2)).0 <= 2);
+        assert_eq!(run(&ConcurrencyLimit::unlimited()).1, 4);
+
+        // Without waiting, a full limit turns the caller away
+        let limit = ConcurrencyLimit::new(1);
+        let permit = limit.try_acquire().unwrap();
+        assert!(limit.try_acquire().is_none());
+        drop(permit);
+        assert!(limit.try_acquire().is_some());
+    }
+
+    #[test]
+    fn test_save_failed_audio() {
+        let dir = env::temp_dir().join(format!("failed-audio-test-{}", std::process::id()));
+        let path = save_failed_audio(&dir, b"RIFF").unwrap();
+        assert!(path.starts_with(&dir));
+        assert_eq!(fs::read(&path).unwrap(), b"RIFF");
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,579 @@
+//! Pluggable transcription backends.
+//!
+//! Everything that turns a WAV file into text implements `Transcriber`:
+//! a local Whisper endpoint, Replicate, OpenAI, or your own service. Pick the
+//! configured one with `WhisperConfig::transcriber`, and use
+//! `MockTranscriber` to test code that transcribes without a network.
+
+use anyhow::{Context, Result};
+use reqwest::blocking::multipart;
+use reqwest::header::HeaderMap;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::segments::{parse_segments, Transcription};
+use crate::transcribe::{
+    fit_wav, replicate_call, replicate_request, replicate_text, replicate_url_request,
+    resolve_replicate_model, wait_for_prediction, Cancelled, RetryPolicy, WhisperConfig,
+    DEFAULT_REPLICATE_MODEL, DEFAULT_REPLICATE_TIMEOUT, REPLICATE_POLL_INTERVAL,
+};
+
+/// A speech-to-text service
+pub trait Transcriber: Send + Sync {
+    /// Transcribe a WAV file to text
+    fn transcribe(&self, wav: Vec<u8>) -> Result<String>;
+
+    /// The service, for messages and health checks, e.g. "Replicate"
+    fn name(&self) -> &str {
+        "custom"
+    }
+
+    /// Whether the service is configured well enough to try, e.g. has an API key
+    ///
+    /// For readiness checks, so it must be cheap: the built-in services only
+    /// look at their settings and don't contact the service. The default
+    /// is always ready.
+    fn check_ready(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Transcribe a WAV file, with timed segments if the service returns them
+    ///
+    /// The default has no segments; override it for services that time
+    /// their output.
+    fn transcribe_segments(&self, wav: Vec<u8>) -> Result<Transcription> {
+        Ok(Transcription {
+            text: self.transcribe(wav)?,
+            segments: Vec::new(),
+        })
+    }
+
+    /// Transcribe like `transcribe_segments`, stopping once `cancelled` returns true
+    ///
+    /// A cancelled transcription fails with `Cancelled`. The default only
+    /// checks before sending the audio; services that can stop work in
+    /// progress override it. To stop waiting on any service, use
+    /// `transcribe_audio_cancellable`.
+    fn transcribe_cancellable(
+        &self,
+        wav: Vec<u8>,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Result<Transcription> {
+        if cancelled() {
+            return Err(Cancelled.into());
+        }
+        self.transcribe_segments(wav)
+    }
+}
+
+/// A local (or self-hosted) Fast Whisper endpoint
+///
+/// The audio is posted as a `file` form field to `<endpoint>/transcribe`.
+/// A `prompt` is sent as a `prompt` form field, which OpenAI-compatible
+/// servers use as the initial prompt. The transcript is read from
+/// `text_pointer` in the response, or from the usual places (see
+/// `segments::find_text`).
+#[derive(Debug, Clone)]
+pub struct LocalWhisperTranscriber {
+    pub endpoint: String,
+    pub headers: HeaderMap,
+    pub prompt: Option<String>,
+    pub text_pointer: Option<String>,
+}
+
+impl LocalWhisperTranscriber {
+    /// Transcribe on `endpoint`, with no extra headers or prompt
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            headers: HeaderMap::new(),
+            prompt: None,
+            text_pointer: None,
+        }
+    }
+}
+
+impl Transcriber for LocalWhisperTranscriber {
+    fn transcribe(&self, wav: Vec<u8>) -> Result<String> {
+        Ok(self.transcribe_segments(wav)?.text)
+    }
+
+    fn name(&self) -> &str {
+        "local Whisper"
+    }
+
+    fn check_ready(&self) -> Result<()> {
+        reqwest::Url::parse(&self.endpoint)
+            .with_context(|| format!("Invalid Whisper endpoint \"{}\"", self.endpoint))?;
+        Ok(())
+    }
+
+    fn transcribe_segments(&self, wav: Vec<u8>) -> Result<Transcription> {
+        let client = reqwest::blocking::Client::new();
+
+        let part = multipart::Part::bytes(wav)
+            .file_name("audio.wav")
+            .mime_str("audio/wav")?;
+
+        let mut form = multipart::Form::new().part("file", part);
+        if let Some(ref prompt) = self.prompt {
+            form = form.text("prompt", prompt.clone());
+        }
+
+        let url = format!("{}/transcribe", self.endpoint);
+
+        let response = client
+            .post(&url)
+            .headers(self.headers.clone())
+            .multipart(form)
+            .send()
+            .context("Failed to send request to local Whisper endpoint")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().unwrap_or_default();
+            return Err(anyhow::anyhow!(
+                "Local Whisper API error ({}): {}",
+                status,
+                error_text
+            ));
+        }
+
+        let result: serde_json::Value = response.json()?;
+        Transcription::from_response(&result, self.text_pointer.as_deref())
+    }
+}
+
+/// Whisper on Replicate
+///
+/// Replicate holds the response for a while in the hope the prediction
+/// finishes; if it's still starting or processing, its status is polled for
+/// up to `timeout`. Requests that fail for transient reasons are retried
+/// according to `retry`. The transcript is read from `text_pointer` in the
+/// prediction, or from the usual places (see `segments::find_text`).
+#[derive(Debug, Clone)]
+pub struct ReplicateTranscriber {
+    pub api_key: String,
+    pub model: String, // `owner/name:version`, or `owner/name` for the latest version
+    pub headers: HeaderMap,
+    pub prompt: Option<String>,
+    pub text_pointer: Option<String>,
+    pub timeout: Duration,
+    pub retry: RetryPolicy,
+}
+
+impl ReplicateTranscriber {
+    /// Transcribe with the default model and settings
+    pub fn new(api_key: &str) -> Self {
+        Self {
+            api_key: api_key.to_string(),
+            model: resolve_replicate_model(DEFAULT_REPLICATE_MODEL).to_string(),
+            headers: HeaderMap::new(),
+            prompt: None,
+            text_pointer: None,
+            timeout: DEFAULT_REPLICATE_TIMEOUT,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Transcribe audio Replicate downloads from `url` itself
+    ///
+    /// Saves fetching hosted audio only to upload it again. Replicate
+    /// accepts any `http(s)` URL it can reach.
+    pub fn transcribe_url(&self, url: &str) -> Result<Transcription> {
+        let (url, body) = replicate_url_request(&self.model, url, self.prompt.as_deref());
+        self.transcription(self.predict(&url, body, None)?)
+    }
+
+    /// Run the prediction `body` describes and return it once it has succeeded
+    ///
+    /// Once `cancelled` returns true the prediction is cancelled on
+    /// Replicate too, so it stops using GPU time.
+    fn predict(
+        &self,
+        url: &str,
+        body: serde_json::Value,
+        cancelled: Option<&dyn Fn() -> bool>,
+    ) -> Result<serde_json::Value> {
+        let client = reqwest::blocking::Client::new();
+        let auth = format!("Bearer {}", self.api_key);
+        let request = |method: reqwest::Method, url: &str| {
+            client
+                .request(method, url)
+                .header("Authorization", &auth)
+                .headers(self.headers.clone())
+        };
+
+        let cancellable = cancelled.is_some();
+        let cancelled = || cancelled.is_some_and(|cancelled| cancelled());
+        if cancelled() {
+            return Err(Cancelled.into());
+        }
+        let started = Instant::now();
+        let mut create = request(reqwest::Method::POST, url).json(&body);
+        if !cancellable {
+            // Ask Replicate to hold the response until the prediction finishes
+            create = create.header("Prefer", "wait");
+        }
+        let prediction = replicate_call(create, &self.retry)?;
+        wait_for_prediction(
+            prediction,
+            request,
+            REPLICATE_POLL_INTERVAL,
+            self.timeout.saturating_sub(started.elapsed()),
+            &self.retry,
+            cancelled,
+        )
+    }
+
+    /// Run a prediction on `wav` and return it once it has succeeded
+    fn predict_wav(
+        &self,
+        wav: &[u8],
+        cancelled: Option<&dyn Fn() -> bool>,
+    ) -> Result<serde_json::Value> {
+        let (url, body) = replicate_request(&self.model, wav, self.prompt.as_deref());
+        self.predict(&url, body, cancelled)
+    }
+
+    /// The transcript and segments of a finished prediction
+    fn transcription(&self, prediction: serde_json::Value) -> Result<Transcription> {
+        Ok(Transcription {
+            text: replicate_text(&prediction, self.text_pointer.as_deref())?,
+            segments: prediction
+                .get("output")
+                .and_then(parse_segments)
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl Transcriber for ReplicateTranscriber {
+    fn transcribe(&self, wav: Vec<u8>) -> Result<String> {
+        replicate_text(&self.predict_wav(&wav, None)?, self.text_pointer.as_deref())
+    }
+
+    fn name(&self) -> &str {
+        "Replicate"
+    }
+
+    fn check_ready(&self) -> Result<()> {
+        if self.api_key.trim().is_empty() {
+            anyhow::bail!("No Replicate API key");
+        }
+        Ok(())
+    }
+
+    fn transcribe_segments(&self, wav: Vec<u8>) -> Result<Transcription> {
+        self.transcription(self.predict_wav(&wav, None)?)
+    }
+
+    /// Creates the prediction without waiting on it, then polls it until it
+    /// finishes or `cancelled` returns true, when it is cancelled on Replicate
+    fn transcribe_cancellable(
+        &self,
+        wav: Vec<u8>,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Result<Transcription> {
+        self.transcription(self.predict_wav(&wav, Some(cancelled))?)
+    }
+}
+
+/// OpenAI's hosted Whisper API
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// OpenAI transcription model used when none is chosen
+pub const DEFAULT_OPENAI_MODEL: &str = "whisper-1";
+
+/// OpenAI's `audio/transcriptions` endpoint
+///
+/// The audio is posted as a multipart `file` with the `model` (and
+/// `prompt`, if set), and the transcript read from the response's `text`.
+/// OpenAI reports failures as `{"error": {"message": ...}}`; the message is
+/// passed on in the error. `base_url` can point at any server that speaks
+/// the same API.
+#[derive(Debug, Clone)]
+pub struct OpenAiWhisperTranscriber {
+    pub api_key: String,
+    pub model: String,
+    pub base_url: String,
+    pub prompt: Option<String>,
+}
+
+impl OpenAiWhisperTranscriber {
+    /// Transcribe with `whisper-1` on OpenAI
+    pub fn new(api_key: &str) -> Self {
+        Self {
+            api_key: api_key.to_string(),
+            model: DEFAULT_OPENAI_MODEL.to_string(),
+            base_url: OPENAI_BASE_URL.to_string(),
+            prompt: None,
+        }
+    }
+}
+
+impl Transcriber for OpenAiWhisperTranscriber {
+    fn transcribe(&self, wav: Vec<u8>) -> Result<String> {
+        let client = reqwest::blocking::Client::new();
+
+        let part = multipart::Part::bytes(wav)
+            .file_name("audio.wav")
+            .mime_str("audio/wav")?;
+        let mut form = multipart::Form::new()
+            .part("file", part)
+            .text("model", self.model.clone());
+        if let Some(ref prompt) = self.prompt {
+            form = form.text("prompt", prompt.clone());
+        }
+
+        let url = format!(
+            "{}/audio/transcriptions",
+            self.base_url.trim_end_matches('/')
+        );
+
+        let response = client
+            .post(&url)
+            .bearer_auth(&self.api_key)
+            .multipart(form)
+            .send()
+            .context("Failed to send request to OpenAI")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response.text().unwrap_or_default();
+            // Prefer the message from OpenAI's error envelope over the raw body
+            let message = serde_json::from_str::<serde_json::Value>(&error_text)
+                .ok()
+                .and_then(|body| body.pointer("/error/message")?.as_str().map(str::to_string))
+                .unwrap_or(error_text);
+            anyhow::bail!("OpenAI API error ({}): {}", status, message);
+        }
+
+        let result: serde_json::Value = response.json()?;
+        result["text"]
+            .as_str()
+            .map(str::to_string)
+            .context("OpenAI response has no text")
+    }
+
+    fn name(&self) -> &str {
+        "OpenAI"
+    }
+
+    fn check_ready(&self) -> Result<()> {
+        if self.api_key.trim().is_empty() {
+            anyhow::bail!("No OpenAI API key");
+        }
+        Ok(())
+    }
+}
+
+/// A transcriber that plays back scripted replies, for tests
+///
+/// Each call takes the next reply: `Ok(text)` is returned as the
+/// transcript and `Err(message)` as a failure. Once the replies run out,
+/// every call fails.
+#[derive(Debug, Default)]
+pub struct MockTranscriber {
+    replies: Mutex<VecDeque<Result<String, String>>>,
+    calls: AtomicUsize,
+}
+
+impl MockTranscriber {
+    /// Reply with `replies`, in order
+    pub fn new<S: Into<String>>(replies: impl IntoIterator<Item = Result<S, S>>) -> Self {
+        Self {
+            replies: Mutex::new(
+                replies
+                    .into_iter()
+                    .map(|reply| reply.map(Into::into).map_err(Into::into))
+                    .collect(),
+            ),
+            calls: AtomicUsize::new(0),
+        }
+    }
+
+    /// How many times `transcribe` has been called
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::Relaxed)
+    }
+}
+
+impl Transcriber for MockTranscriber {
+    fn transcribe(&self, _wav: Vec<u8>) -> Result<String> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        match self.replies.lock().unwrap().pop_front() {
+            Some(Ok(text)) => Ok(text),
+            Some(Err(message)) => Err(anyhow::anyhow!(message)),
+            None => anyhow::bail!("MockTranscriber has no replies left"),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "mock"
+    }
+}
+
+/// Another transcriber, with recordings fitted to an upload limit first
+///
+/// Each WAV goes through `fit_upload`: one over `max_bytes` is re-encoded
+/// as 16 kHz mono, and one still too big is refused without `inner` ever
+/// seeing it.
+pub struct UploadLimit {
+    pub inner: Box<dyn Transcriber>,
+    pub max_bytes: usize,
+}
+
+impl UploadLimit {
+    /// Limit the recordings `inner` is sent to `max_bytes`
+    pub fn new(inner: Box<dyn Transcriber>, max_bytes: usize) -> Self {
+        Self { inner, max_bytes }
+    }
+
+    /// The WAV to send in place of `wav`
+    fn fit(&self, wav: Vec<u8>) -> Result<Vec<u8>> {
+        fit_wav(wav, self.max_bytes)
+    }
+}
+
+impl Transcriber for UploadLimit {
+    fn transcribe(&self, wav: Vec<u8>) -> Result<String> {
+        self.inner.transcribe(self.fit(wav)?)
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn check_ready(&self) -> Result<()> {
+        self.inner.check_ready()
+    }
+
+    fn transcribe_segments(&self, wav: Vec<u8>) -> Result<Transcription> {
+        self.inner.transcribe_segments(self.fit(wav)?)
+    }
+
+    fn transcribe_cancellable(
+        &self,
+        wav: Vec<u8>,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Result<Transcription> {
+        self.inner.transcribe_cancellable(self.fit(wav)?, cancelled)
+    }
+}
+
+impl WhisperConfig {
+    /// The configured backend: the local endpoint if set, otherwise
+    /// Replicate, otherwise OpenAI
+    ///
+    /// Recordings are held to `max_upload_bytes` (see `UploadLimit`).
+    pub fn transcriber(&self) -> Result<Box<dyn Transcriber>> {
+        let inner: Box<dyn Transcriber> = if let Some(ref endpoint) = self.endpoint {
+            Box::new(LocalWhisperTranscriber {
+                endpoint: endpoint.clone(),
+                headers: self.local_headers.clone(),
+                prompt: self.prompt.clone(),
+                text_pointer: self.text_pointer.clone(),
+            })
+        } else if let Some(replicate) = self.replicate_transcriber() {
+            Box::new(replicate)
+        } else if let Some(ref api_key) = self.openai_api_key {
+            Box::new(OpenAiWhisperTranscriber {
+                prompt: self.prompt.clone(),
+                ..OpenAiWhisperTranscriber::new(api_key)
+            })
+        } else {
+            anyhow::bail!("No transcription service configured")
+        };
+        Ok(Box::new(UploadLimit::new(inner, self.max_upload_bytes)))
+    }
+
+    /// Replicate, if that's the configured backend
+    ///
+    /// Unlike `transcriber`, this can transcribe audio by URL.
+    pub fn replicate_transcriber(&self) -> Option<ReplicateTranscriber> {
+        if self.endpoint.is_some() {
+            return None;
+        }
+        let api_key = self.api_key.as_ref()?;
+        Some(ReplicateTranscriber {
+            api_key: api_key.clone(),
+            model: self.replicate_model.clone(),
+            headers: self.replicate_headers.clone(),
+            prompt: self.prompt.clone(),
+            text_pointer: self.text_pointer.clone(),
+            timeout: self.replicate_timeout,
+            retry: self.retry,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcribe::mock_server;
+
+    #[test]
+    fn test_mock_transcriber_replays_script() {
+        let mock = MockTranscriber::new([Ok("hello"), Err("endpoint unreachable")]);
+        let transcriber: &dyn Transcriber = &mock;
+        assert_eq!(transcriber.transcribe(Vec::new()).unwrap(), "hello");
+        let err = transcriber.transcribe_segments(Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "endpoint unreachable");
+        assert!(transcriber.transcribe(Vec::new()).is_err());
+        assert_eq!(mock.calls(), 3);
+    }
+
+    #[test]
+    fn test_check_ready() {
+        assert!(ReplicateTranscriber::new("r8_key").check_ready().is_ok());
+        let err = ReplicateTranscriber::new(" ").check_ready().unwrap_err();
+        assert_eq!(err.to_string(), "No Replicate API key");
+        assert!(OpenAiWhisperTranscriber::new("").check_ready().is_err());
+        assert!(LocalWhisperTranscriber::new("http://localhost:8085")
+            .check_ready()
+            .is_ok());
+        assert!(LocalWhisperTranscriber::new("").check_ready().is_err());
+
+        // A limit is as ready as what it wraps
+        let limited = UploadLimit::new(Box::new(ReplicateTranscriber::new("")), 1000);
+        assert!(limited.check_ready().is_err());
+    }
+
+    #[test]
+    fn test_openai_transcriber() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let transcriber = OpenAiWhisperTranscriber {
+            base_url: format!("http://{}/v1", listener.local_addr().unwrap()),
+            ..OpenAiWhisperTranscriber::new("sk-test")
+        };
+        let requests = mock_server(
+            listener,
+            vec![
+                (200, serde_json::json!({ "text": "Hello there." })),
+                (
+                    401,
+                    serde_json::json!({ "error": {
+                        "message": "Incorrect API key provided: sk-test.",
+                        "type": "invalid_request_error",
+                    } }),
+                ),
+            ],
+        );
+
+        let wav = b"RIFF".to_vec();
+        assert_eq!(transcriber.transcribe(wav.clone()).unwrap(), "Hello there.");
+        let err = transcriber.transcribe(wav).unwrap_err().to_string();
+        assert!(err.contains("401"), "{}", err);
+        assert!(
+            err.ends_with("Incorrect API key provided: sk-test."),
+            "{}",
+            err
+        );
+
+        let lines: Vec<String> = requests.try_iter().collect();
+        assert_eq!(lines, vec!["POST /v1/audio/transcriptions HTTP/1.1"; 2]);
+    }
+}
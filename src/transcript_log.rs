@@ -0,0 +1,132 @@
+//! Append-only transcript file for the continuous modes
+//!
+//! Each transcript is written as one line and flushed straight away, so a
+//! crash loses at most the phrase being transcribed. With `durable` the line
+//! is also synced to disk before `append` returns. If the file is rotated or
+//! deleted while we hold it open, the next append starts a new file at the
+//! same path rather than writing into the old one.
+
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A transcript file that only ever grows by whole lines
+pub struct TranscriptLog {
+    path: PathBuf,
+    file: File,
+    durable: bool, // fsync after every line
+}
+
+impl TranscriptLog {
+    /// Open `path` for appending, creating it if needed
+    pub fn open(path: &Path, durable: bool) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: open_append(path)?,
+            durable,
+        })
+    }
+
+    /// Append `text` as one line, then flush (and sync, if durable)
+    ///
+    /// Line breaks inside `text`, e.g. between speaker turns, are kept.
+    pub fn append(&mut self, text: &str) -> Result<()> {
+        if self.replaced() {
+            self.file = open_append(&self.path)?;
+        }
+        let line = format!("{}\n", text.trim_end());
+        let write = || -> std::io::Result<()> {
+            // One write, so a reader never sees half a line from us
+            (&self.file).write_all(line.as_bytes())?;
+            (&self.file).flush()?;
+            if self.durable {
+                self.file.sync_data()?;
+            }
+            Ok(())
+        };
+        write().with_context(|| format!("Failed to write to {}", self.path.display()))
+    }
+
+    /// Whether the file at our path is no longer the one we have open
+    fn replaced(&self) -> bool {
+        match fs::metadata(&self.path) {
+            Err(_) => true, // Deleted, or moved away with nothing in its place yet
+            Ok(on_disk) => !same_file(&self.file, &on_disk),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn same_file(file: &File, on_disk: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    file.metadata()
+        .map(|open| (open.dev(), open.ino()) == (on_disk.dev(), on_disk.ino()))
+        .unwrap_or(true)
+}
+
+/// Elsewhere an open file can't be renamed or deleted, so it's always ours
+#[cfg(not(unix))]
+fn same_file(_file: &File, _on_disk: &fs::Metadata) -> bool {
+    true
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("transcript-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_segments_survive_without_close() {
+        let path = temp_path("crash.txt");
+        let mut log = TranscriptLog::open(&path, true).unwrap();
+        for text in [
+            "First phrase.",
+            "Second phrase.  ",
+            "Host: Third.\nGuest: Fourth.",
+        ] {
+            log.append(text).unwrap();
+        }
+        // No clean shutdown: the handle is never closed or dropped
+        std::mem::forget(log);
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "First phrase.\nSecond phrase.\nHost: Third.\nGuest: Fourth.\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reopens_after_rotation() {
+        let path = temp_path("rotated.txt");
+        let rotated = path.with_extension("txt.1");
+        let mut log = TranscriptLog::open(&path, false).unwrap();
+        log.append("Before.").unwrap();
+
+        fs::rename(&path, &rotated).unwrap();
+        log.append("After rotation.").unwrap();
+        assert_eq!(fs::read_to_string(&rotated).unwrap(), "Before.\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "After rotation.\n");
+
+        fs::remove_file(&path).unwrap();
+        log.append("After removal.").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "After removal.\n");
+        fs::remove_file(&rotated).unwrap();
+    }
+}
@@ -0,0 +1,299 @@
+//! Energy-based voice activity detection
+//!
+//! Used to split a continuous capture into separate utterances at pauses,
+//! and to skip wake word matching while nobody is speaking.
+
+use std::collections::VecDeque;
+
+/// Length of each VAD analysis frame
+const FRAME_MS: u32 = 20;
+
+/// Default silence needed after speech before an utterance is considered finished
+pub const END_SILENCE_MS: u32 = 600;
+
+/// Default minimum amount of speech for an utterance to be kept
+pub const MIN_UTTERANCE_MS: u32 = 150;
+
+/// Audio kept from before speech starts so the onset isn't clipped
+const PREROLL_MS: u32 = 200;
+
+/// Default RMS level above which a frame counts as speech
+pub const DEFAULT_VAD_THRESHOLD: f32 = 0.02;
+
+/// Default time `SpeechGate` stays open after the last speech frame
+pub const DEFAULT_HANGOVER_MS: u32 = 500;
+
+/// Root-mean-square level of a block of samples
+pub fn rms(frame: &[f32]) -> f32 {
+    if frame.is_empty() {
+        return 0.0;
+    }
+    (frame.iter().map(|x| x * x).sum::<f32>() / frame.len() as f32).sqrt()
+}
+
+/// Returns true when the frame's RMS level exceeds `threshold`
+pub fn is_speech(frame: &[f32], threshold: f32) -> bool {
+    rms(frame) > threshold
+}
+
+/// Splits a continuous mono stream into utterances separated by silence
+///
+/// Feed captured audio in with `push`; each time speech is followed by
+/// `END_SILENCE_MS` of silence the finished utterance is returned. Shorter
+/// pauses stay inside the utterance, and utterances with less than
+/// `MIN_UTTERANCE_MS` of speech (clicks, coughs) are dropped.
+pub struct UtteranceSplitter {
+    frame_len: usize,
+    threshold: f32,
+    end_silence_frames: usize,
+    min_speech_frames: usize,
+    preroll_frames: usize,
+    pending: Vec<f32>,           // Samples not yet forming a full frame
+    preroll: VecDeque<Vec<f32>>, // Recent silent frames before speech
+    current: Vec<f32>,           // Utterance being collected
+    in_speech: bool,
+    utterance_frames: usize, // Frames since speech onset
+    silent_frames: usize,
+}
+
+impl UtteranceSplitter {
+    /// Create a splitter for audio at `sample_rate` with the given VAD threshold
+    pub fn new(sample_rate: u32, threshold: f32) -> Self {
+        let frame_len = (sample_rate * FRAME_MS / 1000).max(1) as usize;
+        Self {
+            frame_len,
+            threshold,
+            end_silence_frames: ms_to_frames(END_SILENCE_MS),
+            min_speech_frames: ms_to_frames(MIN_UTTERANCE_MS),
+            preroll_frames: (PREROLL_MS / FRAME_MS) as usize,
+            pending: Vec::new(),
+            preroll: VecDeque::new(),
+            current: Vec::new(),
+            in_speech: false,
+            utterance_frames: 0,
+            silent_frames: 0,
+        }
+    }
+
+    /// Set how long a pause must be to end an utterance
+    pub fn set_min_silence_gap_ms(&mut self, ms: u32) {
+        self.end_silence_frames = ms_to_frames(ms).max(1);
+    }
+
+    /// Set the minimum amount of speech an utterance needs to be kept
+    pub fn set_min_utterance_ms(&mut self, ms: u32) {
+        self.min_speech_frames = ms_to_frames(ms);
+    }
+
+    /// Feed samples and return any utterances they complete
+    pub fn push(&mut self, samples: &[f32]) -> Vec<Vec<f32>> {
+        self.pending.extend_from_slice(samples);
+
+        let mut finished = Vec::new();
+        let mut offset = 0;
+        while self.pending.len() - offset >= self.frame_len {
+            let frame = self.pending[offset..offset + self.frame_len].to_vec();
+            offset += self.frame_len;
+            if let Some(utterance) = self.process_frame(frame) {
+                finished.push(utterance);
+            }
+        }
+        self.pending.drain(..offset);
+
+        finished
+    }
+
+    /// Return the utterance in progress, if any (e.g. when capture stops)
+    pub fn flush(&mut self) -> Option<Vec<f32>> {
+        if !self.in_speech {
+            return None;
+        }
+        self.finish_utterance()
+    }
+
+    fn process_frame(&mut self, frame: Vec<f32>) -> Option<Vec<f32>> {
+        let speech = is_speech(&frame, self.threshold);
+
+        if !self.in_speech {
+            if !speech {
+                self.preroll.push_back(frame);
+                if self.preroll.len() > self.preroll_frames {
+                    self.preroll.pop_front();
+                }
+                return None;
+            }
+            // Speech onset: start the utterance with the buffered lead-in
+            self.in_speech = true;
+            self.current = self.preroll.drain(..).flatten().collect();
+        }
+
+        self.current.extend_from_slice(&frame);
+        self.utterance_frames += 1;
+        if speech {
+            self.silent_frames = 0;
+            return None;
+        }
+
+        self.silent_frames += 1;
+        if self.silent_frames >= self.end_silence_frames {
+            return self.finish_utterance();
+        }
+        None
+    }
+
+    /// End the current utterance; `None` if it was too short to keep
+    fn finish_utterance(&mut self) -> Option<Vec<f32>> {
+        // Drop the trailing silence that ended the utterance
+        let trailing = self.silent_frames * self.frame_len;
+        let keep = self.current.len().saturating_sub(trailing);
+        self.current.truncate(keep);
+        let speech_frames = self.utterance_frames - self.silent_frames;
+
+        self.in_speech = false;
+        self.utterance_frames = 0;
+        self.silent_frames = 0;
+        let utterance = std::mem::take(&mut self.current);
+        (speech_frames >= self.min_speech_frames).then_some(utterance)
+    }
+}
+
+/// Tracks whether anyone has spoken recently, so work can be skipped on silence
+///
+/// Audio is judged in frames with `is_speech`. The gate opens on the first
+/// speech frame and stays open for a hangover after the last one, so the
+/// quieter tail of a word still gets looked at.
+pub struct SpeechGate {
+    frame_len: usize,
+    threshold: f32,
+    hangover_frames: usize,
+    pending: Vec<f32>,           // Samples not yet forming a full frame
+    since_speech: Option<usize>, // Frames since the last speech frame
+}
+
+impl SpeechGate {
+    /// Create a gate for audio at `sample_rate` with the given VAD threshold
+    pub fn new(sample_rate: u32, threshold: f32) -> Self {
+        Self {
+            frame_len: (sample_rate * FRAME_MS / 1000).max(1) as usize,
+            threshold,
+            hangover_frames: ms_to_frames(DEFAULT_HANGOVER_MS),
+            pending: Vec::new(),
+            since_speech: None,
+        }
+    }
+
+    /// Set how long the gate stays open after speech stops
+    pub fn set_hangover_ms(&mut self, ms: u32) {
+        self.hangover_frames = ms_to_frames(ms);
+    }
+
+    /// Feed samples; returns whether the gate is open afterwards
+    pub fn push(&mut self, samples: &[f32]) -> bool {
+        self.pending.extend_from_slice(samples);
+        let mut frames = self.pending.chunks_exact(self.frame_len);
+        for frame in &mut frames {
+            self.since_speech = if is_speech(frame, self.threshold) {
+                Some(0)
+            } else {
+                self.since_speech.map(|n| n + 1)
+            };
+        }
+        let used = self.pending.len() - frames.remainder().len();
+        self.pending.drain(..used);
+        self.is_open()
+    }
+
+    /// Whether there was speech within the hangover
+    pub fn is_open(&self) -> bool {
+        self.since_speech.is_some_and(|n| n <= self.hangover_frames)
+    }
+
+    /// Close the gate and forget buffered audio
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.since_speech = None;
+    }
+}
+
+fn ms_to_frames(ms: u32) -> usize {
+    (ms / FRAME_MS) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(samples: usize) -> Vec<f32> {
+        (0..samples).map(|i| (i as f32 * 0.1).sin() * 0.5).collect()
+    }
+
+    #[test]
+    fn test_is_speech() {
+        assert!(!is_speech(&vec![0.0; 320], DEFAULT_VAD_THRESHOLD));
+        assert!(is_speech(&tone(320), DEFAULT_VAD_THRESHOLD));
+    }
+
+    #[test]
+    fn test_speech_gate_hangover() {
+        let mut gate = SpeechGate::new(16000, DEFAULT_VAD_THRESHOLD);
+        gate.set_hangover_ms(200);
+        assert!(!gate.push(&vec![0.0; 16000]));
+
+        // Opens within the first frame of speech, in however small pieces it comes
+        assert!(!gate.push(&tone(100)));
+        assert!(gate.push(&tone(300)));
+
+        // Stays open through the hangover, then closes
+        assert!(gate.push(&vec![0.0; 16 * 200]));
+        assert!(!gate.push(&vec![0.0; 16 * 40]));
+        gate.push(&tone(320));
+        gate.reset();
+        assert!(!gate.is_open());
+    }
+
+    #[test]
+    fn test_splitter_emits_one_clip_per_utterance() {
+        let sample_rate = 16000;
+        let mut splitter = UtteranceSplitter::new(sample_rate, DEFAULT_VAD_THRESHOLD);
+        let silence = vec![0.0; sample_rate as usize];
+
+        let mut clips = splitter.push(&silence);
+        clips.extend(splitter.push(&tone(8000)));
+        clips.extend(splitter.push(&silence));
+        clips.extend(splitter.push(&tone(4000)));
+        clips.extend(splitter.push(&silence));
+
+        assert_eq!(clips.len(), 2);
+        // Each clip holds the speech plus a short lead-in, without the trailing gap
+        assert!(clips[0].len() >= 8000 && clips[0].len() < 8000 + 16000 / 2);
+        assert!(clips[1].len() >= 4000 && clips[1].len() < 4000 + 16000 / 2);
+        assert!(splitter.flush().is_none());
+    }
+
+    #[test]
+    fn test_splitter_bridges_short_pauses_and_drops_blips() {
+        let mut splitter = UtteranceSplitter::new(16000, DEFAULT_VAD_THRESHOLD);
+        splitter.set_min_silence_gap_ms(300);
+        splitter.set_min_utterance_ms(100);
+        let pause = |ms: usize| vec![0.0; 16 * ms];
+
+        // A 200 ms dip mid-word doesn't split; the 1 s pause after it does
+        let mut clips = splitter.push(&tone(4800));
+        clips.extend(splitter.push(&pause(200)));
+        clips.extend(splitter.push(&tone(4800)));
+        clips.extend(splitter.push(&pause(1000)));
+        assert_eq!(clips.len(), 1);
+        assert!(clips[0].len() >= 4800 * 2 + 16 * 200);
+
+        // A 40 ms click is ignored
+        assert!(splitter.push(&tone(640)).is_empty());
+        assert!(splitter.push(&pause(1000)).is_empty());
+    }
+
+    #[test]
+    fn test_splitter_flushes_unfinished_utterance() {
+        let mut splitter = UtteranceSplitter::new(16000, DEFAULT_VAD_THRESHOLD);
+        assert!(splitter.push(&tone(4000)).is_empty());
+        assert!(splitter.flush().is_some());
+    }
+}
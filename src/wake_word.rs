@@ -1,24 +1,125 @@
-/// Wake Word Detection Module
-/// 
-/// Implements a lightweight wake word detection system using MFCC features
-/// and Dynamic Time Warping (DTW) for pattern matching.
-/// 
-/// This is designed for low CPU/memory usage suitable for always-on operation.
-
-use anyhow::Result;
-use ndarray::{Array1, Array2};
-use rustfft::{FftPlanner, num_complex::Complex};
+//! Wake Word Detection Module
+//!
+//! Implements a lightweight wake word detection system using MFCC features
+//! and Dynamic Time Warping (DTW) for pattern matching.
+//!
+//! This is designed for low CPU/memory usage suitable for always-on operation.
+
+use anyhow::{Context, Result};
+use ndarray::{Array1, Array2, Axis};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
 use std::f32::consts::PI;
+use std::fmt;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::filter::{Bandpass, BandpassFilter};
+use crate::resample::resample_linear;
+use crate::wav::decode_wav_mono;
+
+/// Free-form metadata about a template, e.g. `room = office`
+pub type TemplateTags = BTreeMap<String, String>;
+
+/// Current version of the template file format
+///
+/// Version 2 added the MFCC config the template was trained with. Version 1
+/// files (just the frames, plus whichever optional fields they have) load
+/// with defaults for everything added since. New optional fields don't need
+/// a new version; it is for changes an older build would misread.
+const TEMPLATE_FORMAT_VERSION: u32 = 2;
+
+/// Current version of the `to_bytes` detector state format
+///
+/// Version 2 added the main template's name and the named wake words.
+const DETECTOR_STATE_VERSION: u32 = 2;
+
+/// What `detect` calls the main template until `set_name` renames it
+pub const DEFAULT_WORD_NAME: &str = "wake word";
+
+/// Fail unless this build reads `kind` files of `version` (1 to `newest`)
+fn check_format_version(kind: &str, version: u32, newest: u32) -> Result<()> {
+    if version == 0 || version > newest {
+        anyhow::bail!(
+            "{} version {} is not supported by this build (it reads versions 1 to {})",
+            kind,
+            version,
+            newest
+        );
+    }
+    Ok(())
+}
+
+/// `check_format_version` on the `version` field of `json`, before the rest is parsed
+///
+/// A newer format may have changed any other field, and parsing it in full
+/// would fail on that field instead of saying why. JSON without a readable
+/// version is left for the full parse to reject.
+fn check_json_version(json: &[u8], kind: &str, newest: u32) -> Result<()> {
+    #[derive(Deserialize)]
+    struct Versioned {
+        version: u32,
+    }
+    match serde_json::from_slice::<Versioned>(json) {
+        Ok(versioned) => check_format_version(kind, versioned.version, newest),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Interval between the windows checked by `scan`
+const SCAN_STEP_MS: usize = 100;
+
+/// Rate of the energy envelope used by the prefilter (one value per 20 ms)
+const ENVELOPE_RATE_HZ: usize = 50;
+
+/// Envelope similarity below which the prefilter skips MFCC + DTW
+const ENVELOPE_MIN_SIMILARITY: f32 = 0.8;
+
+/// Frames in a one-second template at the default 8 ms hop
+///
+/// Sets the confidence scale: an average frame distance of
+/// `sqrt(num_mfcc / REFERENCE_TEMPLATE_FRAMES)` counts as no match at all,
+/// the same as a whole-template distance of `sqrt(frames * num_mfcc)` over a
+/// template this long.
+const REFERENCE_TEMPLATE_FRAMES: f32 = 125.0;
+
+/// Frames either side used to estimate deltas (the usual HTK setting)
+pub const DELTA_WINDOW: usize = 2;
+
+/// Level (RMS, dBFS) below which a frame counts as silence
+///
+/// Such a frame gets a flat log spectrum at this level, the same as digital
+/// silence, so the faint noise of a quiet room can't give it spurious
+/// structure. Louder frames are well clear of the epsilon the log power
+/// spectrum is taken with.
+pub const SILENCE_FLOOR_DBFS: f32 = -80.0;
 
 /// MFCC feature extractor configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MfccConfig {
     pub sample_rate: u32,
-    pub frame_size: usize,      // Number of samples per frame (typically 512 or 1024)
-    pub hop_size: usize,        // Step size between frames (typically frame_size / 4)
-    pub num_mfcc: usize,        // Number of MFCC coefficients to extract (typically 13)
-    pub num_filters: usize,     // Number of mel filters (typically 26-40)
-    pub min_freq: f32,          // Minimum frequency for mel scale (typically 300 Hz)
-    pub max_freq: f32,          // Maximum frequency for mel scale (typically 8000 Hz)
+    pub frame_size: usize, // Number of samples per frame (typically 512 or 1024)
+    pub hop_size: usize,   // Step size between frames (typically frame_size / 4)
+    pub num_mfcc: usize,   // Number of MFCC coefficients to extract (typically 13)
+    pub num_filters: usize, // Number of mel filters (typically 26-40)
+    pub min_freq: f32,     // Minimum frequency for mel scale (typically 300 Hz)
+    pub max_freq: f32,     // Maximum frequency for mel scale (typically 8000 Hz)
+    pub bandpass: Option<Bandpass>, // Time-domain band applied before framing (e.g. 80-8000 Hz)
+    #[serde(default)]
+    pub template_frames: Option<usize>, // Frames per trained template (None = median sample length)
+    #[serde(default)]
+    pub include_deltas: bool, // Append delta and delta-delta coefficients (3 × num_mfcc columns)
+    #[serde(default)]
+    pub cmn: bool, // Subtract each coefficient's mean (cepstral mean normalization)
+    #[serde(default)]
+    pub dtw_band: Option<usize>, // Sakoe-Chiba band half-width in frames (None = full DTW)
+    #[serde(default)]
+    pub dtw_band_fraction: Option<f32>, // Band half-width as a fraction of the longer sequence (overrides dtw_band)
 }
 
 impl Default for MfccConfig {
@@ -31,322 +132,1972 @@ impl Default for MfccConfig {
             num_filters: 26,
             min_freq: 300.0,
             max_freq: 8000.0,
+            bandpass: None,
+            template_frames: None,
+            include_deltas: false,
+            cmn: false,
+            dtw_band: None,
+            dtw_band_fraction: None,
+        }
+    }
+}
+
+impl MfccConfig {
+    /// Columns per feature frame: `num_mfcc`, or three times that with deltas
+    pub fn feature_width(&self) -> usize {
+        if self.include_deltas {
+            self.num_mfcc * 3
+        } else {
+            self.num_mfcc
+        }
+    }
+
+    /// DTW band half-width for sequences of `n` and `m` frames, `None` for full DTW
+    ///
+    /// A fraction is worked out from the longer sequence, rounding up, and
+    /// takes precedence over an absolute `dtw_band`.
+    pub fn effective_dtw_band(&self, n: usize, m: usize) -> Option<usize> {
+        match self.dtw_band_fraction {
+            Some(fraction) => Some((fraction * n.max(m) as f32).ceil() as usize),
+            None => self.dtw_band,
+        }
+    }
+}
+
+/// DTW step pattern (which moves are allowed and how they're weighted)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum StepPattern {
+    /// Diagonal, vertical and horizontal steps all weighted equally
+    #[default]
+    Symmetric1,
+    /// Diagonal steps weighted 2, so warping (stretching one sequence)
+    /// is no longer cheaper than matching frame for frame
+    Symmetric2,
+}
+
+/// How much each template frame counts towards the DTW distance
+///
+/// The weights rise or fall linearly along the template and are scaled to
+/// average 1, so the confidence scale (and thresholds) stay comparable.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum TimeWeighting {
+    /// Every frame counts the same
+    #[default]
+    Uniform,
+    /// The first frame counts `1 + strength` times as much as the last
+    Onset { strength: f32 },
+    /// The last frame counts `1 + strength` times as much as the first
+    Ending { strength: f32 },
+}
+
+impl TimeWeighting {
+    /// Weight of each of `len` template frames
+    fn weights(&self, len: usize) -> Vec<f32> {
+        let ramp = |j: usize| j as f32 / (len.max(2) - 1) as f32; // 0.0 at the start, 1.0 at the end
+        let raw: Vec<f32> = (0..len)
+            .map(|j| match *self {
+                TimeWeighting::Uniform => 1.0,
+                TimeWeighting::Onset { strength } => 1.0 + strength.max(0.0) * (1.0 - ramp(j)),
+                TimeWeighting::Ending { strength } => 1.0 + strength.max(0.0) * ramp(j),
+            })
+            .collect();
+        let mean = raw.iter().sum::<f32>() / len.max(1) as f32;
+        raw.into_iter().map(|w| w / mean).collect()
+    }
+}
+
+/// How the distance between an input frame and a template frame is measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FrameDistance {
+    /// Every coefficient counts the same
+    #[default]
+    Euclidean,
+    /// Each squared difference is divided by how much that coefficient
+    /// varied across the training samples (a diagonal Mahalanobis
+    /// distance), so coefficients that vary naturally count less. Needs a
+    /// template from `train_template`; others fall back to Euclidean.
+    Mahalanobis,
+}
+
+/// Variance below this share of the average counts as this share, so a
+/// coefficient that happened not to vary in training can't dominate
+const VARIANCE_FLOOR: f32 = 0.01;
+
+/// Weight of each coefficient in a Mahalanobis distance: its inverse
+/// variance, scaled to average 1 so the confidence scale stays comparable
+fn inverse_variance_weights(variance: &[f32]) -> Vec<f32> {
+    let mean = variance.iter().sum::<f32>() / variance.len().max(1) as f32;
+    if mean.is_nan() || mean <= 0.0 {
+        // Nothing varied, e.g. a single training sample
+        return vec![1.0; variance.len()];
+    }
+    let inverse: Vec<f32> = variance
+        .iter()
+        .map(|v| 1.0 / v.max(mean * VARIANCE_FLOOR))
+        .collect();
+    let mean_inverse = inverse.iter().sum::<f32>() / inverse.len() as f32;
+    inverse.into_iter().map(|w| w / mean_inverse).collect()
+}
+
+/// How `train_template` lines samples up before averaging them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TemplateAlignment {
+    /// Warp each sample onto the median-length one with DTW, so the same
+    /// sounds are averaged together however each sample was paced
+    #[default]
+    Dtw,
+    /// Stretch each sample evenly to the template length
+    Linear,
+}
+
+/// Per-coefficient mean and standard deviation used to standardize features
+///
+/// Standardizing both the template and incoming features puts every
+/// coefficient on the same (unit variance) scale, so DTW distances, and hence
+/// thresholds, are comparable across wake words and recording conditions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeatureNormalization {
+    pub mean: Vec<f32>,
+    pub std: Vec<f32>,
+}
+
+impl FeatureNormalization {
+    /// Compute the statistics of each column (coefficient) of `features`
+    pub fn from_features(features: &Array2<f32>) -> Self {
+        let mean = features
+            .mean_axis(Axis(0))
+            .map(|m| m.to_vec())
+            .unwrap_or_else(|| vec![0.0; features.ncols()]);
+        // Floor the deviation so constant coefficients don't divide by zero
+        let std = features
+            .std_axis(Axis(0), 0.0)
+            .mapv(|s| s.max(1e-6))
+            .to_vec();
+        Self { mean, std }
+    }
+
+    /// Check statistics read from disk suit features `width` coefficients wide
+    ///
+    /// `apply` indexes `mean` and `std` by coefficient and divides by `std`,
+    /// so both must have one entry per coefficient and every deviation must
+    /// be positive.
+    pub fn check(&self, width: usize) -> Result<()> {
+        if self.mean.len() != width || self.std.len() != width {
+            anyhow::bail!(
+                "Normalization has {} means and {} deviations but the features have {} coefficients",
+                self.mean.len(),
+                self.std.len(),
+                width
+            );
+        }
+        if let Some(k) = self.std.iter().position(|s| !(*s > 0.0 && s.is_finite())) {
+            anyhow::bail!(
+                "Normalization deviation {} of coefficient {} is not positive",
+                self.std[k],
+                k
+            );
+        }
+        Ok(())
+    }
+
+    /// Standardize `features` in place: `(x - mean) / std` per coefficient
+    ///
+    /// `features` must have as many columns as there are statistics; see `check`.
+    pub fn apply(&self, features: &mut Array2<f32>) {
+        for mut row in features.rows_mut() {
+            for (k, x) in row.iter_mut().enumerate() {
+                *x = (*x - self.mean[k]) / self.std[k];
+            }
+        }
+    }
+}
+
+/// On-disk representation of a trained template
+#[derive(Serialize, Deserialize)]
+struct TemplateFile {
+    version: u32,
+    num_mfcc: usize,
+    frames: Vec<Vec<f32>>,
+    #[serde(default)]
+    normalization: Option<FeatureNormalization>,
+    #[serde(default)]
+    envelope: Option<Vec<f32>>,
+    #[serde(default)]
+    bandpass: Option<Bandpass>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    tags: TemplateTags,
+    #[serde(default)]
+    template_frames: Option<usize>,
+    #[serde(default)]
+    config: Option<MfccConfig>, // Missing from version 1 files
+    #[serde(default)]
+    variance: Option<Vec<f32>>, // Per coefficient, around the template
+}
+
+/// Fail unless features extracted with `current` are comparable with a
+/// template trained with `trained`
+///
+/// The band and template length are part of the template and get switched
+/// to it, so they aren't compared.
+fn check_feature_config(trained: &MfccConfig, current: &MfccConfig) -> Result<()> {
+    let settings = [
+        (
+            "sample rate (Hz)",
+            trained.sample_rate as f32,
+            current.sample_rate as f32,
+        ),
+        (
+            "frame size",
+            trained.frame_size as f32,
+            current.frame_size as f32,
+        ),
+        ("hop size", trained.hop_size as f32, current.hop_size as f32),
+        (
+            "mel filter count",
+            trained.num_filters as f32,
+            current.num_filters as f32,
+        ),
+        ("minimum frequency (Hz)", trained.min_freq, current.min_freq),
+        ("maximum frequency (Hz)", trained.max_freq, current.max_freq),
+    ];
+    for (name, trained, current) in settings {
+        if trained != current {
+            anyhow::bail!(
+                "Template was trained with a {} of {} but the detector uses {}",
+                name,
+                trained,
+                current
+            );
         }
     }
+    Ok(())
+}
+
+/// Serialized form of a whole detector, for `to_bytes` / `from_bytes`
+#[derive(Serialize, Deserialize)]
+struct DetectorState {
+    version: u32,
+    config: MfccConfig,
+    template: Option<TemplateFile>,
+    envelope_prefilter: bool,
+    threshold: f32,
+    release_threshold: f32,
+    armed: bool,
+    step_pattern: StepPattern,
+    #[serde(default)]
+    time_weighting: TimeWeighting,
+    #[serde(default)]
+    frame_distance: FrameDistance,
+    #[serde(default = "default_word_name")]
+    name: String, // Missing from version 1 states
+    #[serde(default)]
+    words: Vec<WordState>,
+}
+
+fn default_word_name() -> String {
+    DEFAULT_WORD_NAME.to_string()
+}
+
+/// A named wake word in a detector state
+#[derive(Serialize, Deserialize)]
+struct WordState {
+    name: String,
+    num_mfcc: usize,
+    frames: Vec<Vec<f32>>,
+    normalization: Option<FeatureNormalization>,
+    variance: Option<Vec<f32>>,
+    threshold: Option<f32>,
+}
+
+impl WordState {
+    fn new(word: &WordTemplate) -> Self {
+        Self {
+            name: word.name.clone(),
+            num_mfcc: word.template.ncols(),
+            frames: word
+                .template
+                .rows()
+                .into_iter()
+                .map(|row| row.to_vec())
+                .collect(),
+            normalization: word.normalization.clone(),
+            variance: word.variance.clone(),
+            threshold: word.threshold,
+        }
+    }
+
+    fn into_word(self) -> Result<WordTemplate> {
+        let rows = self.frames.len();
+        let values: Vec<f32> = self.frames.into_iter().flatten().collect();
+        let template =
+            Array2::from_shape_vec((rows, self.num_mfcc), values).with_context(|| {
+                format!("Wake word \"{}\" has inconsistent frame lengths", self.name)
+            })?;
+        if let Some(ref normalization) = self.normalization {
+            normalization
+                .check(self.num_mfcc)
+                .with_context(|| format!("Wake word \"{}\" is corrupt", self.name))?;
+        }
+        Ok(WordTemplate {
+            name: self.name,
+            template,
+            normalization: self.normalization,
+            variance: self.variance,
+            threshold: self.threshold,
+        })
+    }
+}
+
+/// A wake word occurrence found by `scan`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScanHit {
+    pub time_s: f32, // Start of the best-matching window, from the start of the audio
+    pub confidence: f32,
+}
+
+/// An extra wake word added with `add_template`
+struct WordTemplate {
+    name: String,
+    template: Array2<f32>,
+    normalization: Option<FeatureNormalization>, // From its template file, if it was normalized
+    variance: Option<Vec<f32>>,                  // From training, for Mahalanobis matching
+    threshold: Option<f32>,                      // None = the detector's threshold
+}
+
+/// What `build_template` makes of the training samples
+struct TrainedTemplate {
+    template: Array2<f32>,
+    envelope: Vec<f32>,
+    variance: Vec<f32>,
+}
+
+/// NaN and infinite values replaced in a detector's input
+///
+/// Driver glitches can put these in the audio. They are repaired rather
+/// than allowed to poison the match, and counted so the caller can report
+/// them; see `WakeWordDetector::take_repairs`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Repairs {
+    pub samples: usize, // Replaced with silence
+    pub frames: usize,  // Feature frames zeroed after overflowing
+}
+
+impl Repairs {
+    /// Whether nothing needed repairing
+    pub fn is_empty(&self) -> bool {
+        self.samples == 0 && self.frames == 0
+    }
+}
+
+impl fmt::Display for Repairs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "replaced {} NaN or infinite sample(s) with silence and zeroed {} feature frame(s)",
+            self.samples, self.frames
+        )
+    }
 }
 
 /// Wake word detector using MFCC + DTW
 pub struct WakeWordDetector {
     config: MfccConfig,
     template: Option<Array2<f32>>,
+    normalization: Option<FeatureNormalization>, // Applied to features before matching
+    envelope: Option<Vec<f32>>,                  // Template energy envelope, for the prefilter
+    variance: Option<Vec<f32>>,                  // Per coefficient, from training
+    tags: TemplateTags,                          // Saved with the template
+    name: String,                                // What `detect` calls the main template
+    words: Vec<WordTemplate>,                    // Named templates from `add_template`
+    envelope_prefilter: bool,
     threshold: f32,
+    release_threshold: f32, // Confidence must drop below this to re-arm
+    armed: bool,            // Whether `process` may fire on the next match
+    step_pattern: StepPattern,
+    time_weighting: TimeWeighting,
+    frame_distance: FrameDistance,
+    template_alignment: TemplateAlignment,
     mel_filterbank: Array2<f32>,
     dct_matrix: Array2<f32>,
+    fft: Arc<dyn Fft<f32>>,        // Planned once for the frame size
+    repaired_samples: AtomicUsize, // Since the last `take_repairs`
+    repaired_frames: AtomicUsize,
+}
+
+/// Rolling MFCC frames of a continuous stream, for `detect_cached`
+///
+/// Adjacent detection windows share most of their frames; keeping the
+/// frames between checks means each new frame is computed only once.
+pub struct FrameCache {
+    pending: Vec<f32>,              // Samples from the start of the next frame on
+    filter: Option<BandpassFilter>, // Carries the bandpass across chunks
+    frames: VecDeque<Array1<f32>>,  // Newest last
+    capacity: usize,
+}
+
+impl FrameCache {
+    /// Forget all audio, e.g. after a gap in the stream
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.frames.clear();
+        if let Some(filter) = &mut self.filter {
+            filter.reset();
+        }
+    }
+
+    /// Number of frames currently cached
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Whether no frames are cached yet
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
 }
 
 impl WakeWordDetector {
     /// Create a new wake word detector with default configuration
     pub fn new() -> Self {
-        let config = MfccConfig::default();
+        Self::with_config(MfccConfig::default())
+    }
+
+    /// Create a detector with a custom feature configuration
+    pub fn with_config(config: MfccConfig) -> Self {
         let mel_filterbank = create_mel_filterbank(&config);
         let dct_matrix = create_dct_matrix(config.num_filters, config.num_mfcc);
-        
+        let fft = FftPlanner::new().plan_fft_forward(config.frame_size);
+
         Self {
             config,
             template: None,
+            normalization: None,
+            envelope: None,
+            variance: None,
+            tags: TemplateTags::new(),
+            name: DEFAULT_WORD_NAME.to_string(),
+            words: Vec::new(),
+            envelope_prefilter: false,
             threshold: 0.7, // Default threshold (lower = more sensitive)
+            release_threshold: 0.7,
+            armed: true,
+            step_pattern: StepPattern::default(),
+            time_weighting: TimeWeighting::default(),
+            frame_distance: FrameDistance::default(),
+            template_alignment: TemplateAlignment::default(),
             mel_filterbank,
             dct_matrix,
+            fft,
+            repaired_samples: AtomicUsize::new(0),
+            repaired_frames: AtomicUsize::new(0),
+        }
+    }
+
+    /// The NaN and infinite values repaired since the last call, resetting the count
+    ///
+    /// Nothing is printed when input is repaired; check this after
+    /// detecting to warn about a misbehaving audio driver.
+    pub fn take_repairs(&self) -> Repairs {
+        Repairs {
+            samples: self.repaired_samples.swap(0, Ordering::Relaxed),
+            frames: self.repaired_frames.swap(0, Ordering::Relaxed),
+        }
+    }
+
+    /// `audio` with NaN and infinite samples (driver glitches) replaced by silence
+    ///
+    /// A single NaN sample would otherwise spread to every frame it falls in,
+    /// through the bandpass filter state to everything after it, and from
+    /// there to the whole DTW matrix. Replaced samples are counted towards
+    /// `take_repairs`.
+    fn silence_non_finite<'a>(&self, audio: &'a [f32]) -> Cow<'a, [f32]> {
+        let count = audio.iter().filter(|sample| !sample.is_finite()).count();
+        if count == 0 {
+            return Cow::Borrowed(audio);
         }
+        self.repaired_samples.fetch_add(count, Ordering::Relaxed);
+        Cow::Owned(
+            audio
+                .iter()
+                .map(|&sample| if sample.is_finite() { sample } else { 0.0 })
+                .collect(),
+        )
     }
-    
+
     /// Set the wake word template (pre-computed MFCC features)
     pub fn set_template(&mut self, template: Array2<f32>) {
         self.template = Some(template);
+        self.normalization = None;
+        self.envelope = None;
+        self.variance = None;
     }
-    
-    /// Set the detection threshold (0.0 = always trigger, 1.0 = never trigger)
-    pub fn set_threshold(&mut self, threshold: f32) {
-        self.threshold = threshold.clamp(0.0, 1.0);
+
+    /// Tag the template, e.g. with the room it was recorded in
+    ///
+    /// Tags are saved with the template, for picking one out of a
+    /// `TemplateBank`. They don't affect detection.
+    pub fn set_tag(&mut self, key: &str, value: &str) {
+        self.tags.insert(key.to_string(), value.to_string());
     }
-    
-    /// Extract MFCC features from audio samples
-    /// 
-    /// Returns a 2D array where each row is a frame and each column is an MFCC coefficient
-    pub fn extract_mfcc(&self, audio: &[f32]) -> Result<Array2<f32>> {
-        if audio.len() < self.config.frame_size {
-            return Ok(Array2::zeros((0, self.config.num_mfcc)));
-        }
-        
-        let num_frames = (audio.len() - self.config.frame_size) / self.config.hop_size + 1;
-        let mut mfcc_features = Array2::zeros((num_frames, self.config.num_mfcc));
-        
-        let mut planner = FftPlanner::new();
-        let fft = planner.plan_fft_forward(self.config.frame_size);
-        
-        for frame_idx in 0..num_frames {
-            let start = frame_idx * self.config.hop_size;
-            let end = start + self.config.frame_size;
-            
-            if end > audio.len() {
-                break;
-            }
-            
-            let frame = &audio[start..end];
-            
-            // Apply pre-emphasis filter (boost high frequencies)
-            let pre_emphasized = apply_pre_emphasis(frame, 0.97);
-            
-            // Apply Hamming window
-            let windowed = apply_hamming_window(&pre_emphasized);
-            
-            // Compute FFT
-            let mut buffer: Vec<Complex<f32>> = windowed
-                .iter()
-                .map(|&x| Complex::new(x, 0.0))
-                .collect();
-            fft.process(&mut buffer);
-            
-            // Compute power spectrum
-            let power_spectrum: Vec<f32> = buffer[..self.config.frame_size / 2]
-                .iter()
-                .map(|c| (c.norm_sqr() + 1e-10).ln())
-                .collect();
-            
-            // Apply mel filterbank
-            let mel_energies = self.mel_filterbank.dot(&Array1::from(power_spectrum));
-            
-            // Apply DCT to get MFCC coefficients
-            let mfcc = self.dct_matrix.dot(&mel_energies);
-            
-            // Store in output array
-            for i in 0..self.config.num_mfcc {
-                mfcc_features[[frame_idx, i]] = mfcc[i];
+
+    /// The template's tags
+    pub fn tags(&self) -> &TemplateTags {
+        &self.tags
+    }
+
+    /// Bandpass audio to `band` before extracting features, or `None` for no filtering
+    ///
+    /// The band is saved with the template, and loading a template restores
+    /// the band it was trained with, since features only match when both
+    /// sides were filtered alike. It comes on top of pre-emphasis, which only
+    /// tilts the spectrum; the low edge is itself a high-pass, so don't add
+    /// another one below it.
+    pub fn set_bandpass(&mut self, band: Option<Bandpass>) {
+        self.config.bandpass = band;
+    }
+
+    /// Resample trained templates to `frames` frames, or `None` for the median sample length
+    ///
+    /// A fixed length makes every template the same size and every DTW
+    /// check cost the same, whatever the length of the recordings. DTW
+    /// still stretches the template to fit the audio, so 40 frames (about
+    /// 0.3 s at the default hop) is plenty for a short word. The length is
+    /// saved with the template. Takes effect at the next `train_template`.
+    pub fn set_template_frames(&mut self, frames: Option<usize>) {
+        self.config.template_frames = frames;
+    }
+
+    /// How `train_template` aligns samples before averaging them
+    ///
+    /// DTW alignment (the default) keeps the template sharp when samples are
+    /// paced differently, e.g. a drawn-out and a clipped "computer". Linear
+    /// stretching is the older method, kept for comparison.
+    pub fn set_template_alignment(&mut self, alignment: TemplateAlignment) {
+        self.template_alignment = alignment;
+    }
+
+    /// Limit DTW to `band` frames either side of the diagonal, or `None` for full DTW
+    ///
+    /// Full DTW compares every input frame with every template frame; a
+    /// Sakoe-Chiba band only compares frames near the same relative
+    /// position, which is far cheaper and also rules out implausible
+    /// warps. The band needs to cover the largest speed difference you
+    /// expect between the template and a real utterance.
+    pub fn set_dtw_band(&mut self, band: Option<usize>) {
+        self.config.dtw_band = band;
+        self.config.dtw_band_fraction = None;
+    }
+
+    /// Limit DTW to a band that's `fraction` of the longer sequence either
+    /// side of the diagonal, or `None` to go back to `set_dtw_band`'s width
+    ///
+    /// Unlike a width in frames this suits short and long templates alike:
+    /// 0.1 allows a tenth of the word's length of warping. The fraction must
+    /// be above 0 and at most 1 (which is full DTW).
+    pub fn set_dtw_band_fraction(&mut self, fraction: Option<f32>) -> Result<()> {
+        if let Some(fraction) = fraction {
+            if !(fraction > 0.0 && fraction <= 1.0) {
+                anyhow::bail!("DTW band fraction must be in (0, 1], not {}", fraction);
             }
         }
-        
-        Ok(mfcc_features)
-    }
-    
-    /// Detect wake word in audio samples
-    /// 
-    /// Returns true if the wake word is detected, along with the confidence score
-    pub fn detect(&self, audio: &[f32]) -> Result<(bool, f32)> {
-        let template = match &self.template {
-            Some(t) => t,
-            None => return Ok((false, 0.0)),
-        };
-        
-        // Extract MFCC features from input audio
-        let features = self.extract_mfcc(audio)?;
-        
-        if features.nrows() == 0 {
-            return Ok((false, 0.0));
+        self.config.dtw_band_fraction = fraction;
+        Ok(())
+    }
+
+    /// Whether any template is loaded: the main one, or a named word from
+    /// `add_template`, `train_word` or `load_word`
+    ///
+    /// Without one `detect` never fires, so callers should check this at
+    /// startup rather than listen for a match that can't come.
+    pub fn is_trained(&self) -> bool {
+        self.has_main_template() || self.words.iter().any(|w| w.template.nrows() > 0)
+    }
+
+    /// Whether the main template is set, i.e. whether `confidence` can be nonzero
+    fn has_main_template(&self) -> bool {
+        self.template.as_ref().is_some_and(|t| t.nrows() > 0)
+    }
+
+    /// Add a named wake word template, replacing any with the same name
+    ///
+    /// Named templates are matched by `detect` along with the main one, and
+    /// it reports which of them fired; e.g. "computer" to start recording and "cancel" to abort.
+    /// The template must have the detector's feature width.
+    pub fn add_template(&mut self, name: String, template: Array2<f32>) {
+        self.add_word(WordTemplate {
+            name,
+            template,
+            normalization: None,
+            variance: None,
+            threshold: None,
+        });
+    }
+
+    /// Train a named wake word template from recordings, as `train_template` does
+    pub fn train_word(&mut self, name: &str, samples: &[Vec<f32>]) -> Result<()> {
+        let trained = self.build_template(samples)?;
+        self.add_word(WordTemplate {
+            name: name.to_string(),
+            template: trained.template,
+            normalization: None,
+            variance: Some(trained.variance),
+            threshold: None,
+        });
+        Ok(())
+    }
+
+    /// Add a named wake word from a template file written by `save_template`
+    ///
+    /// The file must use the same features (band, deltas, CMN) as this
+    /// detector. Its normalization, if any, is kept with the word.
+    pub fn load_word(&mut self, name: &str, path: &Path) -> Result<()> {
+        let mut loaded = Self::with_config(self.config.clone());
+        loaded.load_template(path)?;
+        let same_features = loaded.config.bandpass == self.config.bandpass
+            && loaded.config.include_deltas == self.config.include_deltas
+            && loaded.config.cmn == self.config.cmn;
+        if !same_features {
+            anyhow::bail!(
+                "{} was trained with different features (band, deltas or CMN) than this detector",
+                path.display()
+            );
         }
-        
-        // Compute DTW distance between features and template
-        let distance = dtw_distance(&features, template);
-        
-        // Normalize distance to 0-1 range (approximate)
-        let max_distance = (template.nrows() as f32 * self.config.num_mfcc as f32).sqrt();
-        let normalized_distance = (distance / max_distance).min(1.0);
-        
-        // Convert distance to similarity (1 - distance)
-        let similarity = 1.0 - normalized_distance;
-        
-        // Check if similarity exceeds threshold
-        let detected = similarity >= self.threshold;
-        
-        Ok((detected, similarity))
-    }
-    
-    /// Train a template from multiple audio samples
-    /// 
-    /// This averages the MFCC features from multiple recordings
-    /// to create a robust template
-    pub fn train_template(&mut self, samples: &[Vec<f32>]) -> Result<()> {
-        if samples.is_empty() {
-            anyhow::bail!("Need at least one sample to train");
+        self.add_word(WordTemplate {
+            name: name.to_string(),
+            template: loaded.template.context("No template loaded")?,
+            normalization: loaded.normalization,
+            variance: loaded.variance,
+            threshold: None,
+        });
+        Ok(())
+    }
+
+    fn add_word(&mut self, word: WordTemplate) {
+        self.words.retain(|w| w.name != word.name);
+        self.words.push(word);
+    }
+
+    /// Give the named wake word its own threshold instead of the detector's
+    pub fn set_word_threshold(&mut self, name: &str, threshold: f32) -> Result<()> {
+        let word = self
+            .words
+            .iter_mut()
+            .find(|w| w.name == name)
+            .with_context(|| format!("No wake word named \"{}\"", name))?;
+        word.threshold = Some(threshold.clamp(0.0, 1.0));
+        Ok(())
+    }
+
+    /// Rename the main template, as `detect` reports it (default `DEFAULT_WORD_NAME`)
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    /// What `detect` calls the main template
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Names of the templates added with `add_template`, in the order added
+    pub fn word_names(&self) -> Vec<&str> {
+        self.words.iter().map(|w| w.name.as_str()).collect()
+    }
+
+    /// Standardize the template to zero mean / unit variance per coefficient
+    ///
+    /// The statistics are kept and applied to incoming features in `detect`,
+    /// and are stored by `save_template`. Call this before saving so the
+    /// template's threshold scale carries over to other wake words.
+    pub fn normalize_template(&mut self) -> Result<()> {
+        if self.normalization.is_some() {
+            return Ok(());
         }
-        
-        // Extract MFCC from all samples
-        let mut all_features = Vec::new();
-        for sample in samples {
-            let features = self.extract_mfcc(sample)?;
-            if features.nrows() > 0 {
-                all_features.push(features);
+        let template = self.template.as_mut().context("No template to normalize")?;
+        let normalization = FeatureNormalization::from_features(template);
+        normalization.apply(template);
+        // The variance has to be on the features' new scale too
+        if let Some(variance) = &mut self.variance {
+            for (v, std) in variance.iter_mut().zip(&normalization.std) {
+                *v /= std * std;
             }
         }
-        
-        if all_features.is_empty() {
-            anyhow::bail!("No valid features extracted from samples");
+        self.normalization = Some(normalization);
+        Ok(())
+    }
+
+    /// Normalization statistics of the current template, if it was normalized
+    pub fn normalization(&self) -> Option<&FeatureNormalization> {
+        self.normalization.as_ref()
+    }
+
+    /// Save the template (and its normalization statistics) as JSON
+    pub fn save_template(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string(&self.template_file()?)?;
+        fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Load a template written by `save_template`
+    pub fn load_template(&mut self, path: &Path) -> Result<()> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        check_json_version(json.as_bytes(), "Template", TEMPLATE_FORMAT_VERSION)?;
+        let file: TemplateFile = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid template file {}", path.display()))?;
+        self.apply_template_file(file)
+    }
+
+    /// Serialize the whole detector: configuration, template, thresholds and settings
+    ///
+    /// A superset of `save_template`, named wake words included, for apps
+    /// that hot-reload their setup:
+    /// `from_bytes` gives back a detector that scores every input exactly as
+    /// this one does, including where `process` is in its fire/re-arm cycle.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let state = DetectorState {
+            version: DETECTOR_STATE_VERSION,
+            config: self.config.clone(),
+            template: self
+                .template
+                .is_some()
+                .then(|| self.template_file())
+                .transpose()?,
+            envelope_prefilter: self.envelope_prefilter,
+            threshold: self.threshold,
+            release_threshold: self.release_threshold,
+            armed: self.armed,
+            step_pattern: self.step_pattern,
+            time_weighting: self.time_weighting,
+            frame_distance: self.frame_distance,
+            name: self.name.clone(),
+            words: self.words.iter().map(WordState::new).collect(),
+        };
+        Ok(serde_json::to_vec(&state)?)
+    }
+
+    /// Restore a detector serialized with `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        check_json_version(bytes, "Detector state", DETECTOR_STATE_VERSION)?;
+        let state: DetectorState =
+            serde_json::from_slice(bytes).context("Invalid detector state")?;
+
+        let mut detector = Self::with_config(state.config);
+        if let Some(file) = state.template {
+            detector.apply_template_file(file)?;
         }
-        
-        // Use the median length to avoid outliers
-        let mut lengths: Vec<usize> = all_features.iter().map(|f| f.nrows()).collect();
-        lengths.sort_unstable();
-        let target_length = lengths[lengths.len() / 2];
-        
-        // Average features (time-align using DTW first would be better, but simple average works)
-        let mut template = Array2::zeros((target_length, self.config.num_mfcc));
-        let mut count = 0;
-        
-        for features in all_features {
-            // Simple linear interpolation to match target length
-            for i in 0..target_length {
-                let src_idx = (i as f32 * (features.nrows() - 1) as f32 / (target_length - 1) as f32) as usize;
-                let src_idx = src_idx.min(features.nrows() - 1);
-                for j in 0..self.config.num_mfcc {
-                    template[[i, j]] += features[[src_idx, j]];
-                }
+        detector.envelope_prefilter = state.envelope_prefilter;
+        detector.threshold = state.threshold;
+        detector.release_threshold = state.release_threshold;
+        detector.armed = state.armed;
+        detector.step_pattern = state.step_pattern;
+        detector.time_weighting = state.time_weighting;
+        detector.frame_distance = state.frame_distance;
+        detector.name = state.name;
+        for word in state.words {
+            let word = word.into_word()?;
+            if word.template.ncols() != detector.config.feature_width() {
+                anyhow::bail!(
+                    "Wake word \"{}\" has {} features per frame but the detector extracts {}",
+                    word.name,
+                    word.template.ncols(),
+                    detector.config.feature_width()
+                );
             }
-            count += 1;
+            detector.add_word(word);
+        }
+        Ok(detector)
+    }
+
+    /// The current template in its on-disk form
+    fn template_file(&self) -> Result<TemplateFile> {
+        let template = self.template.as_ref().context("No template to save")?;
+        Ok(TemplateFile {
+            version: TEMPLATE_FORMAT_VERSION,
+            num_mfcc: template.ncols(),
+            frames: template
+                .rows()
+                .into_iter()
+                .map(|row| row.to_vec())
+                .collect(),
+            normalization: self.normalization.clone(),
+            envelope: self.envelope.clone(),
+            bandpass: self.config.bandpass,
+            tags: self.tags.clone(),
+            template_frames: self.config.template_frames,
+            config: Some(self.config.clone()),
+            variance: self.variance.clone(),
+        })
+    }
+
+    /// Install a template read from disk, after checking it suits this detector
+    fn apply_template_file(&mut self, file: TemplateFile) -> Result<()> {
+        // Also reached for templates inside a detector state
+        check_format_version("Template", file.version, TEMPLATE_FORMAT_VERSION)?;
+        // Deltas and CMN are part of the template, like the band
+        let mut config = self.config.clone();
+        if let Some(ref trained) = file.config {
+            config.include_deltas = trained.include_deltas;
+            config.cmn = trained.cmn;
+        }
+        if file.num_mfcc != config.feature_width() {
+            anyhow::bail!(
+                "Template has {} MFCC coefficients but the detector uses {}",
+                file.num_mfcc,
+                config.feature_width()
+            );
+        }
+        if let Some(ref trained) = file.config {
+            check_feature_config(trained, &self.config)?;
+        }
+
+        let rows = file.frames.len();
+        let values: Vec<f32> = file.frames.into_iter().flatten().collect();
+        let template = Array2::from_shape_vec((rows, file.num_mfcc), values)
+            .context("Template frames have inconsistent lengths")?;
+        if let Some(ref normalization) = file.normalization {
+            normalization
+                .check(file.num_mfcc)
+                .context("Template is corrupt")?;
         }
-        
-        // Normalize by count
-        template /= count as f32;
-        
+
         self.template = Some(template);
-        
+        self.normalization = file.normalization;
+        self.envelope = file.envelope;
+        self.variance = file.variance.filter(|v| v.len() == file.num_mfcc);
+        self.config.bandpass = file.bandpass;
+        self.tags = file.tags;
+        self.config.template_frames = file.template_frames;
+        self.config.include_deltas = config.include_deltas;
+        self.config.cmn = config.cmn;
         Ok(())
     }
-}
 
-impl Default for WakeWordDetector {
-    fn default() -> Self {
-        Self::new()
+    /// Set the detection threshold (0.0 = always trigger, 1.0 = never trigger)
+    ///
+    /// Also sets the release threshold used by `process` to the same value.
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold.clamp(0.0, 1.0);
+        self.release_threshold = self.threshold;
     }
-}
 
-/// Apply pre-emphasis filter to boost high frequencies
-fn apply_pre_emphasis(signal: &[f32], alpha: f32) -> Vec<f32> {
-    let mut result = vec![0.0; signal.len()];
-    result[0] = signal[0];
-    for i in 1..signal.len() {
-        result[i] = signal[i] - alpha * signal[i - 1];
+    /// Set the threshold that best separates recordings of the wake word
+    /// (`positive`) from other sounds (`negative`), and return it
+    ///
+    /// Every recording is scored with `detect`, and the threshold goes
+    /// midway between two adjacent scores where it gives the best F1 score:
+    /// as many positives firing and as few negatives as possible. With
+    /// cleanly separated sets, that's midway between the best-scoring
+    /// negative and the worst-scoring positive. Record the negatives in the
+    /// room where the detector will run: speech, music, typing.
+    pub fn calibrate_threshold(
+        &mut self,
+        positive: &[Vec<f32>],
+        negative: &[Vec<f32>],
+    ) -> Result<f32> {
+        if positive.is_empty() {
+            anyhow::bail!("Need at least one recording of the wake word to calibrate");
+        }
+        let score = |audio: &Vec<f32>| self.confidence(audio);
+        let positive: Vec<f32> = positive.iter().map(score).collect::<Result<_>>()?;
+        let negative: Vec<f32> = negative.iter().map(score).collect::<Result<_>>()?;
+        let threshold = best_threshold(&positive, &negative);
+        self.set_threshold(threshold);
+        Ok(threshold)
     }
-    result
-}
 
-/// Apply Hamming window to reduce spectral leakage
-fn apply_hamming_window(signal: &[f32]) -> Vec<f32> {
-    let n = signal.len();
-    signal
-        .iter()
-        .enumerate()
-        .map(|(i, &x)| {
-            let window = 0.54 - 0.46 * (2.0 * PI * i as f32 / (n - 1) as f32).cos();
-            x * window
-        })
-        .collect()
-}
+    /// Sample rate the detector expects audio at
+    pub fn sample_rate(&self) -> u32 {
+        self.config.sample_rate
+    }
 
-/// Create mel filterbank matrix
-fn create_mel_filterbank(config: &MfccConfig) -> Array2<f32> {
-    let num_fft_bins = config.frame_size / 2;
-    let mut filterbank = Array2::zeros((config.num_filters, num_fft_bins));
-    
-    // Convert Hz to Mel scale
-    let hz_to_mel = |hz: f32| 2595.0 * (1.0 + hz / 700.0).log10();
-    let mel_to_hz = |mel: f32| 700.0 * (10.0_f32.powf(mel / 2595.0) - 1.0);
-    
-    let min_mel = hz_to_mel(config.min_freq);
+    /// Current detection threshold
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    /// Set separate fire (`high`) and re-arm (`low`) thresholds for `process`
+    ///
+    /// Once `process` fires, it won't fire again until confidence has dropped
+    /// below `low`, so a confidence hovering around `high` triggers only once.
+    /// `low` is capped at `high`.
+    pub fn set_thresholds(&mut self, high: f32, low: f32) {
+        self.threshold = high.clamp(0.0, 1.0);
+        self.release_threshold = low.clamp(0.0, self.threshold);
+    }
+
+    /// Skip MFCC + DTW on audio whose energy envelope doesn't resemble the template's
+    ///
+    /// The envelope (RMS per 20 ms) costs a fraction of MFCC extraction, so
+    /// with the prefilter on, silence and sounds of the wrong shape are
+    /// rejected cheaply and `detect` reports a confidence of 0 for them. Only
+    /// templates built by `train_template` carry an envelope; with any other
+    /// template every window goes through to MFCC + DTW.
+    pub fn enable_envelope_prefilter(&mut self, enabled: bool) {
+        self.envelope_prefilter = enabled;
+    }
+
+    /// Set the DTW step pattern used when matching against the template
+    pub fn set_step_pattern(&mut self, step_pattern: StepPattern) {
+        self.step_pattern = step_pattern;
+    }
+
+    /// Weight the start or end of the template more heavily in DTW
+    ///
+    /// Use it to emphasize the most distinctive part of the wake word, often
+    /// its onset. Uniform (the default) weights every frame the same.
+    pub fn set_time_weighting(&mut self, time_weighting: TimeWeighting) {
+        self.time_weighting = time_weighting;
+    }
+
+    /// How frames are compared in DTW; see `FrameDistance`
+    ///
+    /// Mahalanobis uses the variance `train_template` measured, which is
+    /// saved with the template.
+    pub fn set_frame_distance(&mut self, frame_distance: FrameDistance) {
+        self.frame_distance = frame_distance;
+    }
+
+    /// How much each coefficient varied around the template in training
+    ///
+    /// Measured along the DTW path from each sample to the finished
+    /// template, and kept per coefficient rather than per frame: a handful
+    /// of samples gives too few values at each frame for a stable estimate.
+    /// `None` for templates that weren't trained here.
+    pub fn template_variance(&self) -> Option<&[f32]> {
+        self.variance.as_deref()
+    }
+
+    /// Extract MFCC features from audio samples
+    ///
+    /// Returns a 2D array where each row is a frame and each column is an MFCC coefficient,
+    /// followed by the deltas and delta-deltas when `include_deltas` is set.
+    /// With `cmn` set, each coefficient's mean over the frames is subtracted.
+    pub fn extract_mfcc(&self, audio: &[f32]) -> Result<Array2<f32>> {
+        let features = self.extract_frames(audio, self.config.num_mfcc, Self::mfcc_frame)?;
+        Ok(self.finish_features(features))
+    }
+
+    /// Apply CMN and append delta and delta-delta columns to static MFCCs, as configured
+    fn finish_features(&self, mut features: Array2<f32>) -> Array2<f32> {
+        if self.config.cmn {
+            let silence = self.dct_matrix.dot(&self.silent_log_mel());
+            apply_cmn_except(&mut features, &silence);
+        }
+        if !self.config.include_deltas || features.nrows() == 0 {
+            return features;
+        }
+        let deltas = compute_deltas(&features, DELTA_WINDOW);
+        let delta_deltas = compute_deltas(&deltas, DELTA_WINDOW);
+        ndarray::concatenate(
+            Axis(1),
+            &[features.view(), deltas.view(), delta_deltas.view()],
+        )
+        .expect("delta columns have the same number of rows")
+    }
+
+    /// Extract the log-mel spectrogram the MFCCs are computed from
+    ///
+    /// Returns a 2D array where each row is a frame and each column a mel
+    /// band, lowest first. Useful for looking at what the detector hears.
+    pub fn extract_log_mel(&self, audio: &[f32]) -> Result<Array2<f32>> {
+        self.extract_frames(audio, self.config.num_filters, Self::log_mel_frame)
+    }
+
+    /// Apply `frame_fn` to every `frame_size` frame, `hop_size` apart
+    fn extract_frames(
+        &self,
+        audio: &[f32],
+        width: usize,
+        frame_fn: fn(&Self, &[f32]) -> Array1<f32>,
+    ) -> Result<Array2<f32>> {
+        let audio = self.silence_non_finite(audio);
+        let filtered;
+        let audio = match self.config.bandpass {
+            Some(band) => {
+                filtered = band.apply(&audio, self.config.sample_rate);
+                &filtered[..]
+            }
+            None => &audio[..],
+        };
+        if audio.len() < self.config.frame_size {
+            return Ok(Array2::zeros((0, width)));
+        }
+
+        let num_frames = (audio.len() - self.config.frame_size) / self.config.hop_size + 1;
+        let mut features = Array2::zeros((num_frames, width));
+        let mut corrupt_frames = 0;
+
+        for frame_idx in 0..num_frames {
+            let start = frame_idx * self.config.hop_size;
+            let end = start + self.config.frame_size;
+
+            if end > audio.len() {
+                break;
+            }
+
+            // Store in output array
+            let mut frame = frame_fn(self, &audio[start..end]);
+            if zero_non_finite(&mut frame) {
+                corrupt_frames += 1;
+            }
+            features.row_mut(frame_idx).assign(&frame);
+        }
+        self.repaired_frames
+            .fetch_add(corrupt_frames, Ordering::Relaxed);
+
+        Ok(features)
+    }
+
+    /// MFCC coefficients of a single `frame_size` frame
+    fn mfcc_frame(&self, frame: &[f32]) -> Array1<f32> {
+        // Apply DCT to get MFCC coefficients
+        self.dct_matrix.dot(&self.log_mel_frame(frame))
+    }
+
+    /// Mel band energies of a single `frame_size` frame, from the log power spectrum
+    fn log_mel_frame(&self, frame: &[f32]) -> Array1<f32> {
+        let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len().max(1) as f32).sqrt();
+        if rms < 10f32.powf(SILENCE_FLOOR_DBFS / 20.0) {
+            return self.silent_log_mel();
+        }
+
+        // Apply pre-emphasis filter (boost high frequencies)
+        let pre_emphasized = apply_pre_emphasis(frame, 0.97);
+
+        // Apply Hamming window
+        let windowed = apply_hamming_window(&pre_emphasized);
+
+        // Compute FFT
+        let mut buffer: Vec<Complex<f32>> =
+            windowed.iter().map(|&x| Complex::new(x, 0.0)).collect();
+        self.fft.process(&mut buffer);
+
+        // Compute power spectrum
+        let power_spectrum: Vec<f32> = buffer[..self.config.frame_size / 2]
+            .iter()
+            .map(|c| (c.norm_sqr() + 1e-10).ln())
+            .collect();
+
+        // Apply mel filterbank
+        self.mel_filterbank.dot(&Array1::from(power_spectrum))
+    }
+
+    /// Power per FFT bin of white noise at `SILENCE_FLOOR_DBFS` (window aside)
+    fn power_floor(&self) -> f32 {
+        let rms = 10f32.powf(SILENCE_FLOOR_DBFS / 20.0);
+        rms * rms * self.config.frame_size as f32
+    }
+
+    /// Mel band energies of a frame below the silence floor
+    fn silent_log_mel(&self) -> Array1<f32> {
+        let flat = Array1::from_elem(self.config.frame_size / 2, self.power_floor().ln());
+        self.mel_filterbank.dot(&flat)
+    }
+
+    /// An empty frame cache holding as many frames as the template
+    pub fn frame_cache(&self) -> FrameCache {
+        let capacity = self
+            .template
+            .iter()
+            .chain(self.words.iter().map(|w| &w.template))
+            .map(|t| t.nrows())
+            .max()
+            .unwrap_or(0);
+        FrameCache {
+            pending: Vec::new(),
+            filter: self
+                .config
+                .bandpass
+                .map(|band| BandpassFilter::new(band, self.config.sample_rate)),
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Compute the MFCC frames completed by `audio` and add them to `cache`
+    ///
+    /// Frames are cut every `hop_size` samples from the start of the stream,
+    /// and only the newest `capacity` frames are kept. Returns the number of
+    /// new frames.
+    pub fn update_cache(&self, cache: &mut FrameCache, audio: &[f32]) -> usize {
+        let frame_size = self.config.frame_size;
+        let hop_size = self.config.hop_size;
+        let audio = self.silence_non_finite(audio);
+        match &mut cache.filter {
+            Some(filter) => cache.pending.extend(filter.process(&audio)),
+            None => cache.pending.extend_from_slice(&audio),
+        }
+
+        let mut start = 0;
+        let mut new_frames = 0;
+        let mut corrupt_frames = 0;
+        while start + frame_size <= cache.pending.len() {
+            let mut mfcc = self.mfcc_frame(&cache.pending[start..start + frame_size]);
+            if zero_non_finite(&mut mfcc) {
+                corrupt_frames += 1;
+            }
+            cache.frames.push_back(mfcc);
+            start += hop_size;
+            new_frames += 1;
+        }
+        self.repaired_frames
+            .fetch_add(corrupt_frames, Ordering::Relaxed);
+        cache.pending.drain(..start.min(cache.pending.len()));
+
+        let excess = cache.frames.len().saturating_sub(cache.capacity);
+        cache.frames.drain(..excess);
+        new_frames
+    }
+
+    /// The newest `len` cached frames as features, or `None` if too few are cached
+    fn newest_features(&self, cache: &FrameCache, len: usize) -> Option<Array2<f32>> {
+        if len == 0 || cache.frames.len() < len {
+            return None;
+        }
+        let mut features = Array2::zeros((len, self.config.num_mfcc));
+        let newest = cache.frames.range(cache.frames.len() - len..);
+        for (mut row, frame) in features.rows_mut().into_iter().zip(newest) {
+            row.assign(frame);
+        }
+        Some(self.finish_features(features))
+    }
+
+    /// Confidence of the main template in the newest template-length run of cached frames
+    ///
+    /// Gives the same result as `confidence` on the samples those frames
+    /// cover, without recomputing them. The envelope prefilter doesn't apply
+    /// here.
+    pub fn confidence_cached(&self, cache: &FrameCache) -> Result<f32> {
+        let template_len = match &self.template {
+            Some(t) => t.nrows(),
+            None => return Ok(0.0),
+        };
+        match self.newest_features(cache, template_len) {
+            Some(features) => self.match_features(features),
+            None => Ok(0.0),
+        }
+    }
+
+    /// `detect` on cached frames: each template is matched against the
+    /// newest run of frames as long as it
+    pub fn detect_cached(&self, cache: &FrameCache) -> Result<Option<(String, f32)>> {
+        let main = match &self.template {
+            Some(_) => Some(self.confidence_cached(cache)?),
+            None => None,
+        };
+        self.best_match(main, |word| {
+            self.newest_features(cache, word.template.nrows())
+        })
+    }
+
+    /// Find which wake word, if any, fired in `audio`
+    ///
+    /// The main template (`train_template` / `load_template`, reported under
+    /// `name`) and every named template from `add_template` are matched; the
+    /// result is the most confident one at or above its threshold, with its
+    /// confidence.
+    pub fn detect(&self, audio: &[f32]) -> Result<Option<(String, f32)>> {
+        if !self.is_trained() {
+            return Ok(None);
+        }
+        let features = self.extract_mfcc(audio)?;
+        if features.nrows() == 0 {
+            return Ok(None);
+        }
+        let main = match &self.template {
+            Some(_) if self.passes_prefilter(audio) => Some(self.match_features(features.clone())?),
+            _ => None,
+        };
+        self.best_match(main, |_| Some(features.clone()))
+    }
+
+    /// Confidence (0.0 to 1.0) that `audio` holds the main wake word
+    ///
+    /// Scored whether or not it reaches the threshold, e.g. for a level
+    /// display or calibration. 0.0 without a template, or when the envelope
+    /// prefilter rejects the audio.
+    pub fn confidence(&self, audio: &[f32]) -> Result<f32> {
+        if !self.has_main_template() || !self.passes_prefilter(audio) {
+            return Ok(0.0);
+        }
+
+        // Extract MFCC features from input audio
+        let features = self.extract_mfcc(audio)?;
+
+        if features.nrows() == 0 {
+            return Ok(0.0);
+        }
+
+        self.match_features(features)
+    }
+
+    /// Whether `audio` gets past the envelope prefilter, if it is enabled
+    fn passes_prefilter(&self, audio: &[f32]) -> bool {
+        match (self.envelope_prefilter, &self.envelope) {
+            (true, Some(envelope)) => {
+                let audio_envelope = energy_envelope(audio, self.config.sample_rate);
+                envelope_similarity(&audio_envelope, envelope) >= ENVELOPE_MIN_SIMILARITY
+            }
+            _ => true,
+        }
+    }
+
+    /// The most confident match at or above its threshold: the main
+    /// template's confidence `main`, if it was scored, and each named word
+    /// on the features `features_for` gives it
+    fn best_match(
+        &self,
+        main: Option<f32>,
+        features_for: impl Fn(&WordTemplate) -> Option<Array2<f32>>,
+    ) -> Result<Option<(String, f32)>> {
+        let mut best: Option<(&str, f32)> = main
+            .filter(|&confidence| confidence >= self.threshold)
+            .map(|confidence| (self.name.as_str(), confidence));
+        for word in &self.words {
+            let Some(features) = features_for(word) else {
+                continue;
+            };
+            let similarity = self.similarity(
+                features,
+                &word.template,
+                word.normalization.as_ref(),
+                word.variance.as_deref(),
+            )?;
+            let fired = similarity >= word.threshold.unwrap_or(self.threshold);
+            if fired && best.is_none_or(|(_, confidence)| similarity > confidence) {
+                best = Some((&word.name, similarity));
+            }
+        }
+        Ok(best.map(|(name, confidence)| (name.to_string(), confidence)))
+    }
+
+    /// Similarity of extracted features to the main template
+    fn match_features(&self, features: Array2<f32>) -> Result<f32> {
+        let template = self.template.as_ref().context("No template loaded")?;
+        self.similarity(
+            features,
+            template,
+            self.normalization.as_ref(),
+            self.variance.as_deref(),
+        )
+    }
+
+    /// Similarity (0.0 to 1.0) of extracted features to `template`
+    fn similarity(
+        &self,
+        mut features: Array2<f32>,
+        template: &Array2<f32>,
+        normalization: Option<&FeatureNormalization>,
+        variance: Option<&[f32]>,
+    ) -> Result<f32> {
+        if template.ncols() != features.ncols() {
+            anyhow::bail!(
+                "Template has {} features per frame but the detector extracts {}",
+                template.ncols(),
+                features.ncols()
+            );
+        }
+
+        // Put the features on the same scale as a normalized template
+        if let Some(normalization) = normalization {
+            normalization.apply(&mut features);
+        }
+
+        // Scaling each coefficient by the square root of its weight makes the
+        // Euclidean distance below the weighted (Mahalanobis) one
+        let weighted;
+        let template = match (self.frame_distance, variance) {
+            (FrameDistance::Mahalanobis, Some(variance)) if variance.len() == template.ncols() => {
+                let scale = Array1::from(inverse_variance_weights(variance)).mapv(f32::sqrt);
+                features *= &scale;
+                weighted = template * &scale;
+                &weighted
+            }
+            _ => template,
+        };
+
+        // Compute DTW distance between features and template
+        let band = self
+            .config
+            .effective_dtw_band(features.nrows(), template.nrows());
+        let (cost, path_len) = match band {
+            Some(band) => dtw_distance_banded(
+                &features,
+                template,
+                band,
+                self.step_pattern,
+                self.time_weighting,
+            ),
+            None => dtw_distance(&features, template, self.step_pattern, self.time_weighting),
+        };
+
+        // Average per frame on the path, so short and long templates (and
+        // both step patterns) share one confidence scale
+        let frame_distance = cost / path_len.max(1) as f32;
+        let max_distance = (template.ncols() as f32 / REFERENCE_TEMPLATE_FRAMES).sqrt();
+        let normalized_distance = (frame_distance / max_distance).min(1.0);
+
+        // Convert distance to similarity (1 - distance)
+        Ok(1.0 - normalized_distance)
+    }
+
+    /// Detect the wake word in a stream of audio windows, with hysteresis
+    ///
+    /// Unlike `detect`, which reports every window above the threshold, this
+    /// fires once when the main template's confidence reaches the high
+    /// threshold and then stays disarmed until confidence falls below the
+    /// low threshold (see `set_thresholds`). Returns whether it fired, and
+    /// the confidence.
+    pub fn process(&mut self, audio: &[f32]) -> Result<(bool, f32)> {
+        let similarity = self.confidence(audio)?;
+        Ok((self.update_armed(similarity), similarity))
+    }
+
+    /// Advance the hysteresis state machine; returns true when it fires
+    fn update_armed(&mut self, similarity: f32) -> bool {
+        if self.armed && similarity >= self.threshold {
+            self.armed = false;
+            return true;
+        }
+        if !self.armed && similarity < self.release_threshold {
+            self.armed = true;
+        }
+        false
+    }
+
+    /// Find every occurrence of the wake word in a recording
+    ///
+    /// Slides a template-length window over `audio` (at the configured sample
+    /// rate) in `SCAN_STEP_MS` steps. Consecutive windows above the threshold
+    /// count as one occurrence, reported at its most confident window.
+    pub fn scan(&self, audio: &[f32]) -> Result<Vec<ScanHit>> {
+        let template = self.template.as_ref().context("No template loaded")?;
+        let sample_rate = self.config.sample_rate as usize;
+        let window =
+            template.nrows().saturating_sub(1) * self.config.hop_size + self.config.frame_size;
+        let step = (sample_rate * SCAN_STEP_MS / 1000).max(1);
+
+        let mut hits = Vec::new();
+        let mut current: Option<ScanHit> = None;
+        let mut start = 0;
+        while start < audio.len() {
+            let end = (start + window).min(audio.len());
+            let confidence = self.confidence(&audio[start..end])?;
+            if confidence >= self.threshold {
+                let hit = ScanHit {
+                    time_s: start as f32 / sample_rate as f32,
+                    confidence,
+                };
+                current = match current {
+                    Some(best) if best.confidence >= confidence => Some(best),
+                    _ => Some(hit),
+                };
+            } else if let Some(best) = current.take() {
+                hits.push(best);
+            }
+            if end == audio.len() {
+                break;
+            }
+            start += step;
+        }
+        hits.extend(current);
+
+        Ok(hits)
+    }
+
+    /// Find every occurrence of the wake word in a WAV file
+    ///
+    /// Any bit depth or channel count is accepted; the audio is mixed to mono
+    /// and resampled to the detector's sample rate before scanning.
+    pub fn scan_file(&self, path: &Path) -> Result<Vec<ScanHit>> {
+        let wav_data =
+            fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let (samples, sample_rate) = decode_wav_mono(&wav_data)
+            .with_context(|| format!("Failed to decode {}", path.display()))?;
+        let samples = resample_linear(&samples, sample_rate, self.config.sample_rate);
+        self.scan(&samples)
+    }
+
+    /// Train a template from multiple audio samples
+    ///
+    /// This averages the MFCC features from multiple recordings
+    /// to create a robust template
+    pub fn train_template(&mut self, samples: &[Vec<f32>]) -> Result<()> {
+        let trained = self.build_template(samples)?;
+        self.template = Some(trained.template);
+        self.normalization = None;
+        self.envelope = Some(trained.envelope);
+        self.variance = Some(trained.variance);
+
+        Ok(())
+    }
+
+    /// Average `samples` into a template, with its energy envelope and variance
+    fn build_template(&self, samples: &[Vec<f32>]) -> Result<TrainedTemplate> {
+        if samples.is_empty() {
+            anyhow::bail!("Need at least one sample to train");
+        }
+
+        // Extract MFCC from all samples
+        let mut all_features = Vec::new();
+        for sample in samples {
+            let features = self.extract_mfcc(sample)?;
+            if features.nrows() > 0 {
+                all_features.push(features);
+            }
+        }
+
+        if all_features.is_empty() {
+            anyhow::bail!("No valid features extracted from samples");
+        }
+
+        // Use the configured length, or else the median to avoid outliers
+        let target_length = match self.config.template_frames {
+            Some(frames) if frames < 2 => {
+                anyhow::bail!("A template needs at least 2 frames, not {}", frames)
+            }
+            Some(frames) => frames,
+            None => {
+                let mut lengths: Vec<usize> = all_features.iter().map(|f| f.nrows()).collect();
+                lengths.sort_unstable();
+                lengths[lengths.len() / 2]
+            }
+        };
+
+        let template = match self.template_alignment {
+            TemplateAlignment::Dtw => stretch_linear(&dtw_barycenter(&all_features), target_length),
+            TemplateAlignment::Linear => {
+                let mut template = Array2::zeros((target_length, self.config.feature_width()));
+                for features in &all_features {
+                    template += &stretch_linear(features, target_length);
+                }
+                template / all_features.len() as f32
+            }
+        };
+
+        // Average the energy envelopes the same way, for the prefilter
+        let envelopes: Vec<Vec<f32>> = samples
+            .iter()
+            .map(|sample| energy_envelope(sample, self.config.sample_rate))
+            .filter(|envelope| !envelope.is_empty())
+            .collect();
+        if envelopes.is_empty() {
+            anyhow::bail!(
+                "Samples too short to train on: need at least {} ms",
+                1000 / ENVELOPE_RATE_HZ
+            );
+        }
+        let mut lengths: Vec<usize> = envelopes.iter().map(|e| e.len()).collect();
+        lengths.sort_unstable();
+        let envelope_length = lengths[lengths.len() / 2];
+        let mut envelope = vec![0.0; envelope_length];
+        for source in &envelopes {
+            for (i, value) in envelope.iter_mut().enumerate() {
+                let src_idx = i * source.len() / envelope_length;
+                *value += source[src_idx] / envelopes.len() as f32;
+            }
+        }
+
+        let variance = coefficient_variance(&all_features, &template);
+        Ok(TrainedTemplate {
+            template,
+            envelope,
+            variance,
+        })
+    }
+}
+
+impl Default for WakeWordDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The threshold with the best F1 score for the given positive and negative scores
+///
+/// Candidates are the lowest score (everything fires) and the midpoints
+/// between adjacent distinct scores; the first with the highest F1 wins.
+fn best_threshold(positive: &[f32], negative: &[f32]) -> f32 {
+    let mut scores: Vec<f32> = positive.iter().chain(negative).copied().collect();
+    scores.sort_by(f32::total_cmp);
+    scores.dedup();
+    let Some(&lowest) = scores.first() else {
+        return 0.0;
+    };
+
+    let f1 = |threshold: f32| {
+        let hits = positive.iter().filter(|&&s| s >= threshold).count() as f32;
+        let false_alarms = negative.iter().filter(|&&s| s >= threshold).count() as f32;
+        let misses = positive.len() as f32 - hits;
+        2.0 * hits / (2.0 * hits + false_alarms + misses).max(1.0)
+    };
+    let candidates = std::iter::once(lowest).chain(scores.windows(2).map(|w| (w[0] + w[1]) / 2.0));
+    let mut best = (lowest, f1(lowest));
+    for threshold in candidates {
+        let score = f1(threshold);
+        if score > best.1 {
+            best = (threshold, score);
+        }
+    }
+    best.0
+}
+
+/// Express a confidence as a match level in dB: `-20 * log10(1 - confidence)`
+///
+/// Good matches crowd together just below 1.0 on the linear scale. In dB
+/// every 20 dB is ten times closer to the template (0.9 = 20 dB, 0.99 =
+/// 40 dB, 0.999 = 60 dB), which gives thresholds more resolution where it
+/// matters. A confidence of 0 is 0 dB; a perfect match is capped at 120 dB.
+pub fn confidence_to_db(confidence: f32) -> f32 {
+    let distance = (1.0 - confidence).clamp(1e-6, 1.0);
+    -20.0 * distance.log10()
+}
+
+/// Convert a match level in dB back to a linear confidence (see `confidence_to_db`)
+pub fn db_to_confidence(db: f32) -> f32 {
+    1.0 - 10f32.powf(-db.max(0.0) / 20.0)
+}
+
+/// Environment variable setting how many decimals confidences are printed with
+pub const CONFIDENCE_PRECISION_VAR: &str = "CONFIDENCE_PRECISION";
+
+/// Decimals of a printed confidence when `CONFIDENCE_PRECISION` is unset
+pub const DEFAULT_CONFIDENCE_PRECISION: usize = 1;
+
+/// Decimals to print confidences with: `CONFIDENCE_PRECISION`, or the default
+///
+/// An unparsable value falls back to the default, since it only affects
+/// how numbers look.
+pub fn confidence_precision() -> usize {
+    std::env::var(CONFIDENCE_PRECISION_VAR)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_CONFIDENCE_PRECISION)
+}
+
+/// Format a confidence (0.0-1.0) for display: always a percentage, e.g. `87.5%`
+///
+/// Every tool prints detection confidences through this, so their output
+/// can be compared directly. Use `confidence_precision()` for `precision`
+/// unless there's a reason not to.
+pub fn format_confidence(confidence: f32, precision: usize) -> String {
+    format!("{:.*}%", precision, confidence * 100.0)
+}
+
+/// RMS level of each `1 / ENVELOPE_RATE_HZ` second block of `audio`
+fn energy_envelope(audio: &[f32], sample_rate: u32) -> Vec<f32> {
+    let block = (sample_rate as usize / ENVELOPE_RATE_HZ).max(1);
+    audio
+        .chunks_exact(block)
+        .map(|chunk| (chunk.iter().map(|x| x * x).sum::<f32>() / block as f32).sqrt())
+        .collect()
+}
+
+/// Best cosine similarity of `template` against any aligned stretch of `envelope`
+///
+/// Envelopes are non-negative, so this is 1.0 for the same shape at any
+/// loudness and 0.0 against silence. Audio shorter than the template is
+/// compared with the matching part of the template.
+fn envelope_similarity(envelope: &[f32], template: &[f32]) -> f32 {
+    let len = envelope.len().min(template.len());
+    if len == 0 {
+        return 0.0;
+    }
+    let template = &template[..len];
+    let template_norm = template.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    envelope
+        .windows(len)
+        .map(|window| {
+            let dot: f32 = window.iter().zip(template).map(|(a, b)| a * b).sum();
+            let norm = window.iter().map(|x| x * x).sum::<f32>().sqrt() * template_norm;
+            if norm > 0.0 {
+                dot / norm
+            } else {
+                0.0
+            }
+        })
+        .fold(0.0, f32::max)
+}
+
+/// Replace NaN and infinite feature values with zeros; returns whether there were any
+///
+/// Finite audio can still overflow, e.g. samples far outside -1.0..1.0. A
+/// zeroed frame only costs that frame's share of the match.
+fn zero_non_finite(frame: &mut Array1<f32>) -> bool {
+    let mut found = false;
+    for value in frame.iter_mut().filter(|value| !value.is_finite()) {
+        *value = 0.0;
+        found = true;
+    }
+    found
+}
+
+/// Apply pre-emphasis filter to boost high frequencies
+fn apply_pre_emphasis(signal: &[f32], alpha: f32) -> Vec<f32> {
+    let mut result = vec![0.0; signal.len()];
+    result[0] = signal[0];
+    for i in 1..signal.len() {
+        result[i] = signal[i] - alpha * signal[i - 1];
+    }
+    result
+}
+
+/// Apply Hamming window to reduce spectral leakage
+fn apply_hamming_window(signal: &[f32]) -> Vec<f32> {
+    let n = signal.len();
+    signal
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| {
+            let window = 0.54 - 0.46 * (2.0 * PI * i as f32 / (n - 1) as f32).cos();
+            x * window
+        })
+        .collect()
+}
+
+/// Create mel filterbank matrix
+fn create_mel_filterbank(config: &MfccConfig) -> Array2<f32> {
+    let num_fft_bins = config.frame_size / 2;
+    let mut filterbank = Array2::zeros((config.num_filters, num_fft_bins));
+
+    // Convert Hz to Mel scale
+    let hz_to_mel = |hz: f32| 2595.0 * (1.0 + hz / 700.0).log10();
+    let mel_to_hz = |mel: f32| 700.0 * (10.0_f32.powf(mel / 2595.0) - 1.0);
+
+    let min_mel = hz_to_mel(config.min_freq);
     let max_mel = hz_to_mel(config.max_freq);
-    
+
     // Create evenly spaced mel points
     let mel_points: Vec<f32> = (0..=config.num_filters + 1)
         .map(|i| min_mel + (max_mel - min_mel) * i as f32 / (config.num_filters + 1) as f32)
         .map(mel_to_hz)
         .collect();
-    
+
     // Convert Hz points to FFT bin indices
     let bin_points: Vec<usize> = mel_points
         .iter()
         .map(|&hz| ((hz * config.frame_size as f32) / config.sample_rate as f32).floor() as usize)
         .collect();
-    
+
     // Create triangular filters
     for i in 0..config.num_filters {
         let start = bin_points[i];
         let center = bin_points[i + 1];
         let end = bin_points[i + 2];
-        
+
         // Rising slope
         for j in start..center {
             if j < num_fft_bins {
                 filterbank[[i, j]] = (j - start) as f32 / (center - start) as f32;
             }
         }
-        
-        // Falling slope
-        for j in center..end {
-            if j < num_fft_bins {
-                filterbank[[i, j]] = (end - j) as f32 / (end - center) as f32;
-            }
+
+        // Falling slope
+        for j in center..end {
+            if j < num_fft_bins {
+                filterbank[[i, j]] = (end - j) as f32 / (end - center) as f32;
+            }
+        }
+    }
+
+    filterbank
+}
+
+/// Create DCT (Discrete Cosine Transform) matrix for MFCC computation
+fn create_dct_matrix(num_filters: usize, num_mfcc: usize) -> Array2<f32> {
+    let mut dct = Array2::zeros((num_mfcc, num_filters));
+
+    for i in 0..num_mfcc {
+        for j in 0..num_filters {
+            dct[[i, j]] = (PI * i as f32 * (j as f32 + 0.5) / num_filters as f32).cos();
+            if i == 0 {
+                dct[[i, j]] *= (1.0 / num_filters as f32).sqrt();
+            } else {
+                dct[[i, j]] *= (2.0 / num_filters as f32).sqrt();
+            }
+        }
+    }
+
+    dct
+}
+
+/// Cepstral mean normalization: subtract each column's mean over all frames
+///
+/// A microphone or room filters everything it records the same way, which
+/// shows up as a constant offset in the cepstrum. Removing the mean cancels
+/// it, so templates carry over between devices.
+pub fn apply_cmn(features: &mut Array2<f32>) {
+    if let Some(mean) = features.mean_axis(Axis(0)) {
+        *features -= &mean;
+    }
+}
+
+/// `apply_cmn` over every frame except those equal to `silence`
+///
+/// Silent frames would drag the mean towards silence, and once normalized
+/// they'd sit at the average frame, close to any speech. Left as they are,
+/// they stay far from it.
+fn apply_cmn_except(features: &mut Array2<f32>, silence: &Array1<f32>) {
+    let voiced: Vec<usize> = (0..features.nrows())
+        .filter(|&t| features.row(t) != *silence)
+        .collect();
+    if voiced.is_empty() {
+        return;
+    }
+    let mean = features
+        .select(Axis(0), &voiced)
+        .mean_axis(Axis(0))
+        .unwrap();
+    for t in voiced {
+        let mut row = features.row_mut(t);
+        row -= &mean;
+    }
+}
+
+/// Estimate the rate of change of each column of `features` over time
+///
+/// Uses the standard regression over `window` frames either side,
+/// `d[t] = sum(n * (c[t+n] - c[t-n])) / (2 * sum(n^2))`, repeating the first
+/// and last frames at the edges. Apply it twice for delta-deltas.
+pub fn compute_deltas(features: &Array2<f32>, window: usize) -> Array2<f32> {
+    let frames = features.nrows();
+    let mut deltas = Array2::zeros(features.raw_dim());
+    if frames == 0 || window == 0 {
+        return deltas;
+    }
+
+    let denominator = 2.0 * (1..=window).map(|n| (n * n) as f32).sum::<f32>();
+    for t in 0..frames {
+        let mut row = deltas.row_mut(t);
+        for n in 1..=window {
+            let next = features.row((t + n).min(frames - 1));
+            let prev = features.row(t.saturating_sub(n));
+            row.scaled_add(n as f32 / denominator, &(&next - &prev));
+        }
+    }
+    deltas
+}
+
+/// Resample `features` to `length` frames by picking the nearest earlier frame
+fn stretch_linear(features: &Array2<f32>, length: usize) -> Array2<f32> {
+    if features.nrows() == length {
+        return features.clone();
+    }
+    let mut stretched = Array2::zeros((length, features.ncols()));
+    for (i, mut row) in stretched.rows_mut().into_iter().enumerate() {
+        let src_idx =
+            (i as f32 * (features.nrows() - 1) as f32 / (length.max(2) - 1) as f32) as usize;
+        row.assign(&features.row(src_idx.min(features.nrows() - 1)));
+    }
+    stretched
+}
+
+/// Average sequences frame by frame after warping each onto the median-length one
+///
+/// A single round of DTW barycenter averaging: every frame of every sequence
+/// is added to the reference frame DTW matches it with. The result has the
+/// reference's length.
+fn dtw_barycenter(sequences: &[Array2<f32>]) -> Array2<f32> {
+    let mut by_length: Vec<&Array2<f32>> = sequences.iter().collect();
+    by_length.sort_by_key(|features| features.nrows());
+    let reference = by_length[by_length.len() / 2];
+
+    let mut sum = Array2::<f32>::zeros(reference.raw_dim());
+    let mut counts = vec![0usize; reference.nrows()];
+    for features in sequences {
+        for (i, j) in dtw_path(features, reference) {
+            let mut row = sum.row_mut(j);
+            row += &features.row(i);
+            counts[j] += 1;
+        }
+    }
+    for (mut row, count) in sum.rows_mut().into_iter().zip(counts) {
+        row /= count.max(1) as f32;
+    }
+    sum
+}
+
+/// Variance of each coefficient of `sequences` around `template`
+///
+/// Every frame is compared with the template frame DTW matches it with.
+fn coefficient_variance(sequences: &[Array2<f32>], template: &Array2<f32>) -> Vec<f32> {
+    let mut sum = Array1::<f32>::zeros(template.ncols());
+    let mut count = 0;
+    for features in sequences {
+        for (i, j) in dtw_path(features, template) {
+            let diff = &features.row(i) - &template.row(j);
+            sum += &(&diff * &diff);
+            count += 1;
         }
     }
-    
-    filterbank
+    (sum / count.max(1) as f32).to_vec()
 }
 
-/// Create DCT (Discrete Cosine Transform) matrix for MFCC computation
-fn create_dct_matrix(num_filters: usize, num_mfcc: usize) -> Array2<f32> {
-    let mut dct = Array2::zeros((num_mfcc, num_filters));
-    
-    for i in 0..num_mfcc {
-        for j in 0..num_filters {
-            dct[[i, j]] = (PI * i as f32 * (j as f32 + 0.5) / num_filters as f32).cos();
-            if i == 0 {
-                dct[[i, j]] *= (1.0 / num_filters as f32).sqrt();
-            } else {
-                dct[[i, j]] *= (2.0 / num_filters as f32).sqrt();
-            }
+/// The frame pairs `(i, j)` on the best DTW path between `seq1` and `seq2`
+///
+/// Full DTW with the Symmetric1 step pattern, from `(0, 0)` to the last
+/// frame of each. Every frame of both sequences appears at least once.
+fn dtw_path(seq1: &Array2<f32>, seq2: &Array2<f32>) -> Vec<(usize, usize)> {
+    let (n, m) = (seq1.nrows(), seq2.nrows());
+    if n == 0 || m == 0 {
+        return Vec::new();
+    }
+
+    let mut dtw = Array2::from_elem((n + 1, m + 1), f32::MAX);
+    dtw[[0, 0]] = 0.0;
+    for i in 1..=n {
+        for j in 1..=m {
+            let diff = &seq1.row(i - 1) - &seq2.row(j - 1);
+            let dist = diff.dot(&diff).sqrt();
+            let best = dtw[[i - 1, j - 1]]
+                .min(dtw[[i - 1, j]])
+                .min(dtw[[i, j - 1]]);
+            dtw[[i, j]] = best + dist;
         }
     }
-    
-    dct
+
+    // Walk back from the end, always to the cheapest predecessor
+    let mut path = vec![(n - 1, m - 1)];
+    let (mut i, mut j) = (n, m);
+    while (i, j) != (1, 1) {
+        let moves = [(i - 1, j - 1), (i - 1, j), (i, j - 1)];
+        (i, j) = moves
+            .into_iter()
+            .filter(|&(a, b)| a >= 1 && b >= 1)
+            .min_by(|a, b| dtw[[a.0, a.1]].total_cmp(&dtw[[b.0, b.1]]))
+            .unwrap();
+        path.push((i - 1, j - 1));
+    }
+    path.reverse();
+    path
 }
 
 /// Compute Dynamic Time Warping distance between two sequences
-/// 
+///
 /// This allows matching patterns even when they're spoken at different speeds
-fn dtw_distance(seq1: &Array2<f32>, seq2: &Array2<f32>) -> f32 {
+///
+/// `time_weighting` scales the cost of matching each frame of `seq2` (the template).
+/// Returns the accumulated cost and the length of the warping path, in cells;
+/// Symmetric2's double-weighted diagonal steps count twice, which makes its
+/// length always `n + m`. Cost over length is the average frame distance.
+fn dtw_distance(
+    seq1: &Array2<f32>,
+    seq2: &Array2<f32>,
+    step_pattern: StepPattern,
+    time_weighting: TimeWeighting,
+) -> (f32, usize) {
+    let full_band = seq1.nrows().max(seq2.nrows());
+    dtw_distance_banded(seq1, seq2, full_band, step_pattern, time_weighting)
+}
+
+/// Columns of row `i` (1-based) of an `n` x `m` DTW matrix inside the band
+///
+/// That's within `band_width` frames of the diagonal from the first to the
+/// last frame pair; for sequences of equal length, `|i - j| <= band_width`.
+/// The band is widened, if needed, to the slope of the diagonal so a path
+/// always exists.
+fn band_columns(i: usize, n: usize, m: usize, band_width: usize) -> RangeInclusive<usize> {
+    let slope = m as f32 / n as f32;
+    let band = band_width.max(slope.max(1.0 / slope).ceil() as usize) as f32;
+    let centre = i as f32 * slope;
+    let first = ((centre - band).ceil().max(1.0)) as usize;
+    let last = ((centre + band).floor() as usize).min(m);
+    first..=last
+}
+
+/// DTW restricted to a Sakoe-Chiba band around the diagonal
+///
+/// Only the cells in `band_columns` are computed, and only two rows of the
+/// band are kept, so memory is proportional to the band's width rather than
+/// `n * m`. The same as `dtw_distance` when the band is wide enough for its
+/// best path.
+fn dtw_distance_banded(
+    seq1: &Array2<f32>,
+    seq2: &Array2<f32>,
+    band_width: usize,
+    step_pattern: StepPattern,
+    time_weighting: TimeWeighting,
+) -> (f32, usize) {
     let n = seq1.nrows();
     let m = seq2.nrows();
     let dim = seq1.ncols();
-    
-    if n == 0 || m == 0 {
-        return f32::MAX;
+
+    // Sequences of different feature dimension can't be compared frame by frame
+    if n == 0 || m == 0 || seq2.ncols() != dim {
+        return (f32::MAX, 0);
     }
-    
-    // Initialize DTW matrix with infinity
-    let mut dtw = Array2::from_elem((n + 1, m + 1), f32::MAX);
-    dtw[[0, 0]] = 0.0;
-    
-    // Fill DTW matrix
+
+    let weights = time_weighting.weights(m);
+    let diagonal_weight = match step_pattern {
+        StepPattern::Symmetric1 => 1,
+        StepPattern::Symmetric2 => 2,
+    };
+
+    // (cost, path length) of the band cells of the previous and current row,
+    // from column `*_first`; cells outside the band are unreachable
+    let (mut previous, mut previous_first) = (vec![(0.0f32, 0usize)], 0);
+    let mut current = Vec::new();
+    let cell = |row: &[(f32, usize)], first: usize, j: usize| {
+        j.checked_sub(first)
+            .and_then(|offset| row.get(offset).copied())
+            .unwrap_or((f32::MAX, 0))
+    };
+
     for i in 1..=n {
-        for j in 1..=m {
+        let columns = band_columns(i, n, m, band_width);
+        let first = *columns.start();
+        current.clear();
+        for j in columns {
             // Compute Euclidean distance between frames
             let mut dist = 0.0;
             for k in 0..dim {
                 let diff = seq1[[i - 1, k]] - seq2[[j - 1, k]];
                 dist += diff * diff;
             }
-            dist = dist.sqrt();
-            
-            // DTW recurrence relation
-            let cost = dist + dtw[[i - 1, j - 1]].min(dtw[[i - 1, j]]).min(dtw[[i, j - 1]]);
-            dtw[[i, j]] = cost;
+            dist = dist.sqrt() * weights[j - 1];
+
+            // DTW recurrence relation: (cost, path length) via each predecessor
+            let diagonal = cell(&previous, previous_first, j - 1);
+            let above = cell(&previous, previous_first, j);
+            let left = cell(&current, first, j - 1);
+            let moves = [
+                (
+                    diagonal.0 + diagonal_weight as f32 * dist,
+                    diagonal.1 + diagonal_weight,
+                ),
+                (above.0 + dist, above.1 + 1),
+                (left.0 + dist, left.1 + 1),
+            ];
+            current.push(
+                moves
+                    .into_iter()
+                    .min_by(|a, b| a.0.total_cmp(&b.0))
+                    .unwrap(),
+            );
         }
+        std::mem::swap(&mut previous, &mut current);
+        previous_first = first;
     }
-    
-    dtw[[n, m]]
+
+    cell(&previous, previous_first, m)
+}
+
+/// A frequency sweep from `freq_start` to `freq_end` Hz lasting `secs`, at
+/// 16 kHz, standing in for a spoken word. Shared by the detector tests.
+#[cfg(test)]
+pub(crate) fn chirp(freq_start: f32, freq_end: f32, secs: f32) -> Vec<f32> {
+    (0..(16000.0 * secs) as usize)
+        .map(|i| {
+            let t = i as f32 / 16000.0;
+            (2.0 * PI * (freq_start + (freq_end - freq_start) * t / secs) * t).sin() * 0.5
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+
     #[test]
     fn test_pre_emphasis() {
         let signal = vec![1.0, 2.0, 3.0, 4.0];
@@ -354,7 +2105,7 @@ mod tests {
         assert_eq!(result.len(), signal.len());
         assert_eq!(result[0], signal[0]);
     }
-    
+
     #[test]
     fn test_hamming_window() {
         let signal = vec![1.0; 256];
@@ -364,7 +2115,7 @@ mod tests {
         assert!(result[0] < result[128]);
         assert!(result[255] < result[128]);
     }
-    
+
     #[test]
     fn test_mfcc_extraction() {
         let detector = WakeWordDetector::new();
@@ -375,17 +2126,1076 @@ mod tests {
         let samples: Vec<f32> = (0..(sample_rate as f32 * duration) as usize)
             .map(|i| (2.0 * PI * frequency * i as f32 / sample_rate as f32).sin())
             .collect();
-        
+
         let mfcc = detector.extract_mfcc(&samples).unwrap();
         assert!(mfcc.nrows() > 0);
         assert_eq!(mfcc.ncols(), 13);
     }
-    
+
+    #[test]
+    fn test_non_finite_samples_are_contained() {
+        let chirp = chirp(300.0, 1500.0, 1.0);
+        let mut corrupt = chirp.clone();
+        corrupt[8000] = f32::NAN;
+        corrupt[12000] = f32::INFINITY;
+
+        let mut detector = WakeWordDetector::new();
+        detector
+            .train_template(std::slice::from_ref(&chirp))
+            .unwrap();
+        assert!(detector.take_repairs().is_empty());
+        let features = detector.extract_mfcc(&corrupt).unwrap();
+        assert!(features.iter().all(|value| value.is_finite()));
+        assert_eq!(
+            detector.take_repairs(),
+            Repairs {
+                samples: 2,
+                frames: 0
+            }
+        );
+        assert!(detector.take_repairs().is_empty());
+
+        detector.set_threshold(0.9);
+        assert!(detector.detect(&corrupt).unwrap().is_some());
+
+        // Samples too loud for the features are contained as well
+        let mut overflowing = chirp.clone();
+        overflowing[8000] = 1e30;
+        detector.take_repairs();
+        let features = detector.extract_mfcc(&overflowing).unwrap();
+        assert!(features.iter().all(|value| value.is_finite()));
+        assert!(features.iter().any(|&value| value != 0.0));
+        let repairs = detector.take_repairs();
+        assert_eq!(repairs.samples, 0);
+        assert!(repairs.frames > 0);
+
+        // A streaming bandpass recovers too
+        detector.set_bandpass(Some(crate::filter::SPEECH_BAND));
+        let mut cache = detector.frame_cache();
+        detector.update_cache(&mut cache, &corrupt[..8192]);
+        detector.update_cache(&mut cache, &corrupt[8192..]);
+        assert!(cache.frames.iter().flatten().all(|value| value.is_finite()));
+    }
+
+    #[test]
+    fn test_log_mel_follows_pitch() {
+        let detector = WakeWordDetector::new();
+        let tone = |frequency: f32| -> Vec<f32> {
+            (0..16000)
+                .map(|i| (2.0 * PI * frequency * i as f32 / 16000.0).sin())
+                .collect()
+        };
+        let low = detector.extract_log_mel(&tone(440.0)).unwrap();
+        let high = detector.extract_log_mel(&tone(3000.0)).unwrap();
+        assert_eq!(
+            low.dim(),
+            (detector.extract_mfcc(&tone(440.0)).unwrap().nrows(), 26)
+        );
+
+        // The band where the higher tone gains most lies above the one where it loses most
+        let difference = (&high - &low).mean_axis(ndarray::Axis(0)).unwrap();
+        let argmax = |values: &Array1<f32>| {
+            (0..values.len())
+                .max_by(|&a, &b| values[a].total_cmp(&values[b]))
+                .unwrap()
+        };
+        assert!(argmax(&difference) > argmax(&-&difference));
+    }
+
     #[test]
     fn test_dtw_distance() {
         let seq1 = Array2::from_shape_vec((3, 2), vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
         let seq2 = Array2::from_shape_vec((3, 2), vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
-        let dist = dtw_distance(&seq1, &seq2);
+        let (dist, path_len) = dtw_distance(
+            &seq1,
+            &seq2,
+            StepPattern::Symmetric1,
+            TimeWeighting::Uniform,
+        );
         assert!(dist < 0.1); // Should be very close to 0 for identical sequences
+        assert_eq!(path_len, 3); // Straight down the diagonal
+    }
+
+    #[test]
+    fn test_detect_rejects_template_dimension_mismatch() {
+        let mut detector = WakeWordDetector::new();
+        detector.set_template(Array2::zeros((10, 39)));
+        let samples: Vec<f32> = (0..16000).map(|i| (i as f32 * 0.05).sin()).collect();
+        assert!(detector.detect(&samples).is_err());
+        assert!(detector.confidence(&samples).is_err());
+
+        let seq1 = Array2::<f32>::zeros((3, 13));
+        let seq2 = Array2::<f32>::zeros((3, 39));
+        assert_eq!(
+            dtw_distance(
+                &seq1,
+                &seq2,
+                StepPattern::Symmetric1,
+                TimeWeighting::Uniform
+            )
+            .0,
+            f32::MAX
+        );
+    }
+
+    #[test]
+    fn test_is_trained_needs_a_template() {
+        let mut detector = WakeWordDetector::new();
+        assert!(!detector.is_trained());
+        assert_eq!(detector.detect(&[0.1; 16000]).unwrap(), None);
+        assert_eq!(detector.confidence(&[0.1; 16000]).unwrap(), 0.0);
+
+        detector.set_template(Array2::zeros((0, 13)));
+        assert!(!detector.is_trained());
+        detector.set_template(Array2::zeros((50, 13)));
+        assert!(detector.is_trained());
+
+        // A named word alone is enough for `detect`, though not for `confidence`
+        let mut detector = WakeWordDetector::new();
+        detector.add_template("cancel".to_string(), Array2::zeros((0, 13)));
+        assert!(!detector.is_trained());
+        detector.add_template("computer".to_string(), Array2::zeros((50, 13)));
+        assert!(detector.is_trained());
+        assert_eq!(detector.confidence(&[0.1; 16000]).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_banded_dtw() {
+        let sequence = |len: usize, phase: f32| {
+            Array2::from_shape_fn((len, 13), |(t, k)| {
+                ((t as f32 * 0.07 + phase) * (k + 1) as f32).sin()
+            })
+        };
+        let (input, template) = (sequence(400, 0.0), sequence(380, 0.3));
+        let full = || {
+            dtw_distance(
+                &input,
+                &template,
+                StepPattern::Symmetric1,
+                TimeWeighting::Uniform,
+            )
+        };
+        let banded = |band| {
+            dtw_distance_banded(
+                &input,
+                &template,
+                band,
+                StepPattern::Symmetric1,
+                TimeWeighting::Uniform,
+            )
+        };
+
+        // A band wide enough for the best path changes nothing
+        assert_eq!(banded(400), full());
+        assert_eq!(banded(60), full());
+        // A narrow one can only do worse, never better
+        assert!(banded(2).0 >= full().0);
+
+        // ...but it evaluates far fewer cells
+        let cells = |band| {
+            (1..=400)
+                .map(|i| band_columns(i, 400, 380, band).count())
+                .sum::<usize>()
+        };
+        assert_eq!(cells(400), 400 * 380);
+        assert!(cells(10) * 15 < cells(400), "{}", cells(10));
+
+        // Sequences of very different length still have a path
+        let short = sequence(40, 0.3);
+        let (cost, len) = dtw_distance_banded(
+            &input,
+            &short,
+            1,
+            StepPattern::Symmetric1,
+            TimeWeighting::Uniform,
+        );
+        assert!(cost < f32::MAX && len >= 400, "{} {}", cost, len);
+    }
+
+    #[test]
+    fn test_dtw_band_fraction() {
+        let mut detector = WakeWordDetector::new();
+        assert!(detector.set_dtw_band_fraction(Some(0.0)).is_err());
+        assert!(detector.set_dtw_band_fraction(Some(1.5)).is_err());
+        assert!(detector.set_dtw_band_fraction(Some(f32::NAN)).is_err());
+
+        // 10% of two 100-frame sequences is 10 frames, whatever the absolute width
+        detector.set_dtw_band(Some(3));
+        detector.set_dtw_band_fraction(Some(0.1)).unwrap();
+        assert_eq!(detector.config.effective_dtw_band(100, 100), Some(10));
+        assert_eq!(detector.config.effective_dtw_band(100, 250), Some(25));
+        detector.set_dtw_band_fraction(None).unwrap();
+        assert_eq!(detector.config.effective_dtw_band(100, 100), Some(3));
+
+        // 100% is unconstrained DTW
+        detector.set_dtw_band_fraction(Some(1.0)).unwrap();
+        let sequence = |len: usize, phase: f32| {
+            Array2::from_shape_fn((len, 13), |(t, k)| {
+                ((t as f32 * 0.11 + phase) * (k + 1) as f32).sin()
+            })
+        };
+        let (input, template) = (sequence(90, 0.0), sequence(60, 0.4));
+        let band = detector
+            .config
+            .effective_dtw_band(input.nrows(), template.nrows())
+            .unwrap();
+        assert_eq!(
+            dtw_distance_banded(
+                &input,
+                &template,
+                band,
+                StepPattern::Symmetric1,
+                TimeWeighting::Uniform
+            ),
+            dtw_distance(
+                &input,
+                &template,
+                StepPattern::Symmetric1,
+                TimeWeighting::Uniform
+            )
+        );
+    }
+
+    #[test]
+    fn test_dtw_step_patterns() {
+        let seq = Array2::from_shape_vec((3, 1), vec![0.0, 1.0, 2.0]).unwrap();
+        assert_eq!(
+            dtw_distance(&seq, &seq, StepPattern::Symmetric1, TimeWeighting::Uniform),
+            (0.0, 3)
+        );
+        assert_eq!(
+            dtw_distance(&seq, &seq, StepPattern::Symmetric2, TimeWeighting::Uniform),
+            (0.0, 6)
+        );
+
+        // Matching one frame against three forces a diagonal step then two
+        // horizontal ones; only the diagonal is weighted double under Symmetric2
+        let short = Array2::from_shape_vec((1, 1), vec![0.0]).unwrap();
+        let long = Array2::from_shape_vec((3, 1), vec![1.0, 1.0, 1.0]).unwrap();
+        assert_eq!(
+            dtw_distance(
+                &short,
+                &long,
+                StepPattern::Symmetric1,
+                TimeWeighting::Uniform
+            ),
+            (3.0, 3)
+        );
+        assert_eq!(
+            dtw_distance(
+                &short,
+                &long,
+                StepPattern::Symmetric2,
+                TimeWeighting::Uniform
+            ),
+            (4.0, 4)
+        );
+    }
+
+    #[test]
+    fn test_confidence_does_not_depend_on_template_length() {
+        // The same contour as a short and a long template, each heard with
+        // the same small offset in every frame
+        let similarity = |frames: usize, step_pattern| {
+            let template = Array2::from_shape_fn((frames, 13), |(i, k)| {
+                (i as f32 / frames as f32 * 6.0 + k as f32).sin()
+            });
+            let heard = &template + 0.05;
+            let mut detector = WakeWordDetector::new();
+            detector.set_template(template);
+            detector.set_step_pattern(step_pattern);
+            detector.match_features(heard).unwrap()
+        };
+        for step_pattern in [StepPattern::Symmetric1, StepPattern::Symmetric2] {
+            let (short, long) = (similarity(40, step_pattern), similarity(200, step_pattern));
+            assert!(short > 0.3 && short < 0.9, "{}", short);
+            assert!((short - long).abs() < 0.01, "{} vs {}", short, long);
+        }
+    }
+
+    #[test]
+    fn test_time_weighting_emphasizes_one_end() {
+        let template = Array2::zeros((4, 1));
+        let early = Array2::from_shape_vec((4, 1), vec![1.0, 0.0, 0.0, 0.0]).unwrap();
+        let late = Array2::from_shape_vec((4, 1), vec![0.0, 0.0, 0.0, 1.0]).unwrap();
+        let distance = |seq: &Array2<f32>, weighting| {
+            dtw_distance(seq, &template, StepPattern::Symmetric1, weighting).0
+        };
+
+        assert_eq!(
+            distance(&early, TimeWeighting::Uniform),
+            distance(&late, TimeWeighting::Uniform)
+        );
+
+        // A mismatch at the emphasized end costs more than one at the other end
+        let onset = TimeWeighting::Onset { strength: 2.0 };
+        assert!(distance(&early, onset) > distance(&early, TimeWeighting::Uniform));
+        assert!(distance(&early, onset) > distance(&late, onset));
+        let ending = TimeWeighting::Ending { strength: 2.0 };
+        assert!(distance(&late, ending) > distance(&early, ending));
+
+        // Weights average 1, so an even mismatch costs the same either way
+        let even = Array2::from_elem((4, 1), 1.0);
+        assert!((distance(&even, onset) - distance(&even, TimeWeighting::Uniform)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_fixed_template_frames() {
+        // The same sweep spoken quickly and slowly
+        let (fast, slow) = (chirp(300.0, 1500.0, 0.8), chirp(300.0, 1500.0, 1.2));
+
+        let mut detector = WakeWordDetector::new();
+        detector.set_template_frames(Some(40));
+        detector
+            .train_template(&[fast.clone(), slow.clone()])
+            .unwrap();
+        assert_eq!(detector.template.as_ref().unwrap().nrows(), 40);
+
+        // DTW still lines the template up with the sweep at any speed
+        let distance = |audio: &[f32]| {
+            let features = detector.extract_mfcc(audio).unwrap();
+            let template = detector.template.as_ref().unwrap();
+            dtw_distance(
+                &features,
+                template,
+                StepPattern::Symmetric1,
+                TimeWeighting::Uniform,
+            )
+            .0
+        };
+        let reversed: Vec<f32> = chirp(300.0, 1500.0, 1.0).into_iter().rev().collect();
+        for input in [&fast, &slow, &chirp(300.0, 1500.0, 1.0)] {
+            assert!(distance(input) < distance(&reversed) * 0.8);
+        }
+
+        // The length is kept with the template
+        let path = std::env::temp_dir().join(format!("frames-test-{}.json", std::process::id()));
+        detector.save_template(&path).unwrap();
+        let mut loaded = WakeWordDetector::new();
+        loaded.load_template(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(loaded.config.template_frames, Some(40));
+
+        detector.set_template_frames(Some(1));
+        assert!(detector.train_template(&[fast]).is_err());
+    }
+
+    #[test]
+    fn test_training_on_too_short_samples_fails() {
+        // Long enough for an MFCC frame, but not for an envelope block
+        let mut detector = WakeWordDetector::with_config(MfccConfig {
+            sample_rate: 48000,
+            ..MfccConfig::default()
+        });
+        let blip: Vec<f32> = (0..700).map(|i| (i as f32 * 0.1).sin() * 0.5).collect();
+        assert!(detector.extract_mfcc(&blip).unwrap().nrows() > 0);
+        let err = detector.train_template(&[blip]).unwrap_err();
+        assert!(err.to_string().contains("too short"), "{}", err);
+        assert!(detector.train_template(&[]).is_err());
+    }
+
+    #[test]
+    fn test_dtw_alignment_sharpens_template() {
+        // Two tones, with the change at a different point in each sample
+        let two_tones = |split: f32| -> Vec<f32> {
+            (0..9600)
+                .map(|i| {
+                    let t = i as f32 / 16000.0;
+                    let freq = if t < split { 500.0 } else { 2000.0 };
+                    (2.0 * PI * freq * t).sin() * 0.5
+                })
+                .collect()
+        };
+        let samples = vec![two_tones(0.15), two_tones(0.3), two_tones(0.45)];
+
+        let mean_confidence = |alignment: TemplateAlignment| {
+            let mut detector = WakeWordDetector::new();
+            detector.set_template_alignment(alignment);
+            detector.train_template(&samples).unwrap();
+            detector.normalize_template().unwrap();
+            let total: f32 = samples
+                .iter()
+                .map(|s| detector.confidence(s).unwrap())
+                .sum();
+            total / samples.len() as f32
+        };
+        let dtw = mean_confidence(TemplateAlignment::Dtw);
+        let linear = mean_confidence(TemplateAlignment::Linear);
+        assert!(dtw > linear, "DTW {} vs linear {}", dtw, linear);
+
+        // The path covers both sequences from end to end
+        let seq =
+            |values: &[f32]| Array2::from_shape_vec((values.len(), 1), values.to_vec()).unwrap();
+        let path = dtw_path(&seq(&[0.0, 1.0, 1.0, 2.0]), &seq(&[0.0, 1.0, 2.0]));
+        assert_eq!(path, vec![(0, 0), (1, 1), (2, 1), (3, 2)]);
+    }
+
+    #[test]
+    fn test_detect_picks_the_closest_template() {
+        let (rising, falling) = (chirp(300.0, 3000.0, 0.5), chirp(3000.0, 300.0, 0.5));
+
+        let mut detector = WakeWordDetector::new();
+        detector.set_threshold(0.0);
+        assert_eq!(detector.detect(&rising).unwrap(), None);
+        detector.add_template("computer".into(), detector.extract_mfcc(&rising).unwrap());
+        detector.add_template("cancel".into(), detector.extract_mfcc(&falling).unwrap());
+        assert_eq!(detector.word_names(), vec!["computer", "cancel"]);
+
+        let (word, confidence) = detector.detect(&rising).unwrap().unwrap();
+        assert_eq!(word, "computer");
+        assert!(confidence > 0.99);
+        assert_eq!(detector.detect(&falling).unwrap().unwrap().0, "cancel");
+
+        // A word below its own threshold doesn't fire, however close it is
+        let noisy: Vec<f32> = rising
+            .iter()
+            .enumerate()
+            .map(|(i, s)| s + 0.01 * (i as f32).sin())
+            .collect();
+        assert_eq!(detector.detect(&noisy).unwrap().unwrap().0, "computer");
+        detector.set_word_threshold("computer", 1.0).unwrap();
+        assert_eq!(detector.detect(&noisy).unwrap().unwrap().0, "cancel");
+        assert!(detector.set_word_threshold("stop", 0.5).is_err());
+
+        // The main template competes with the named ones, under its own name
+        detector.set_template(detector.extract_mfcc(&noisy).unwrap());
+        detector.set_name("hey jarvis");
+        assert_eq!(detector.detect(&noisy).unwrap().unwrap().0, "hey jarvis");
+        assert_eq!(detector.detect(&falling).unwrap().unwrap().0, "cancel");
+    }
+
+    #[test]
+    fn test_silent_frames_score_low() {
+        // A quiet room: a little noise, well under the silence floor
+        let hiss: Vec<f32> = (0..8000)
+            .map(|i| 1e-5 * ((i as f32 * 12.9898).sin() * 43758.547).fract())
+            .collect();
+
+        for cmn in [false, true] {
+            let mut detector = WakeWordDetector::with_config(MfccConfig {
+                cmn,
+                ..MfccConfig::default()
+            });
+            detector
+                .train_template(&[chirp(300.0, 3000.0, 0.5)])
+                .unwrap();
+            detector.normalize_template().unwrap();
+            let template = detector.template.clone().unwrap();
+            let distance = |audio: &[f32]| {
+                let mut features = detector.extract_mfcc(audio).unwrap();
+                detector
+                    .normalization
+                    .as_ref()
+                    .unwrap()
+                    .apply(&mut features);
+                let (cost, len) = dtw_distance(
+                    &features,
+                    &template,
+                    StepPattern::Symmetric1,
+                    TimeWeighting::Uniform,
+                );
+                cost / len as f32
+            };
+
+            // Silence is no closer to the word than a different word is
+            let other_word = distance(&chirp(3000.0, 300.0, 0.5));
+            for silence in [vec![0.0; 8000], hiss.clone()] {
+                assert!(
+                    distance(&silence) > other_word,
+                    "cmn {}: {} vs {}",
+                    cmn,
+                    distance(&silence),
+                    other_word
+                );
+            }
+            // ...and hiss under the floor is no different from digital silence
+            assert_eq!(
+                detector.extract_mfcc(&hiss).unwrap(),
+                detector.extract_mfcc(&[0.0; 8000]).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_mahalanobis_discounts_variable_coefficients() {
+        let mut detector = WakeWordDetector::new();
+        let template = Array2::from_shape_fn((40, 13), |(t, k)| ((t * 3 + k) as f32 * 0.1).sin());
+        detector.set_template(template.clone());
+        // Coefficient 0 varied a lot in training, coefficient 1 hardly at all
+        let mut variance = vec![1.0; 13];
+        variance[0] = 4.0;
+        variance[1] = 0.25;
+        detector.variance = Some(variance);
+        let nudged = |k: usize| {
+            let mut features = template.clone();
+            features.column_mut(k).mapv_inplace(|x| x + 0.1);
+            features
+        };
+        let score = |detector: &WakeWordDetector, k| detector.match_features(nudged(k)).unwrap();
+
+        // The same deviation costs less on the variable coefficient
+        assert_eq!(score(&detector, 0), score(&detector, 1));
+        detector.set_frame_distance(FrameDistance::Mahalanobis);
+        assert!(
+            score(&detector, 0) > score(&detector, 2),
+            "{}",
+            score(&detector, 0)
+        );
+        assert!(
+            score(&detector, 2) > score(&detector, 1),
+            "{}",
+            score(&detector, 1)
+        );
+
+        // The setting and the variance survive a round trip
+        let restored = WakeWordDetector::from_bytes(&detector.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.template_variance(), detector.template_variance());
+        assert_eq!(score(&restored, 1), score(&detector, 1));
+
+        // Training measures it, one value per coefficient
+        let tone = |hz: f32, wobble: f32| -> Vec<f32> {
+            (0..8000)
+                .map(|i| {
+                    (2.0 * PI * (hz + wobble * (i as f32 * 0.003).sin()) * i as f32 / 16000.0).sin()
+                        * 0.5
+                })
+                .collect()
+        };
+        detector
+            .train_template(&[tone(800.0, 0.0), tone(800.0, 40.0), tone(820.0, 20.0)])
+            .unwrap();
+        let variance = detector.template_variance().unwrap();
+        assert_eq!(variance.len(), 13);
+        assert!(
+            variance.iter().all(|v| v.is_finite() && *v >= 0.0)
+                && variance.iter().any(|v| *v > 0.0)
+        );
+    }
+
+    #[test]
+    fn test_calibrate_threshold() {
+        // Sweeps over a little noise, a different take of the noise each time
+        let sweep = |from_hz, to_hz, seed: f32| -> Vec<f32> {
+            chirp(from_hz, to_hz, 0.5)
+                .into_iter()
+                .enumerate()
+                .map(|(i, tone)| tone + 0.02 * ((i as f32 + seed) * 12.9898).sin().fract())
+                .collect()
+        };
+        let positive: Vec<Vec<f32>> = [1.0, 2.0, 3.0]
+            .iter()
+            .map(|&seed| sweep(300.0, 3000.0, seed * 1e4))
+            .collect();
+        let negative = vec![sweep(3000.0, 300.0, 4e4), sweep(1000.0, 1000.0, 5e4)];
+
+        let mut detector = WakeWordDetector::new();
+        detector.train_template(&positive).unwrap();
+        detector.normalize_template().unwrap();
+        let threshold = detector.calibrate_threshold(&positive, &negative).unwrap();
+        assert_eq!(detector.threshold(), threshold);
+        assert!(positive
+            .iter()
+            .all(|p| detector.detect(p).unwrap().is_some()));
+        assert!(negative
+            .iter()
+            .all(|n| detector.detect(n).unwrap().is_none()));
+
+        // Overlapping scores: missing no positive beats missing one to drop a false alarm
+        let threshold = best_threshold(&[0.9, 0.8, 0.4], &[0.5, 0.2]);
+        assert!((threshold - 0.3).abs() < 1e-6, "{}", threshold);
+        assert!(detector.calibrate_threshold(&[], &negative).is_err());
+    }
+
+    #[test]
+    fn test_detector_state_round_trip() {
+        let word = chirp(300.0, 1500.0, 1.0);
+        let mut detector = WakeWordDetector::with_config(MfccConfig {
+            bandpass: Some(crate::filter::SPEECH_BAND),
+            template_frames: Some(60),
+            ..MfccConfig::default()
+        });
+        detector
+            .train_template(std::slice::from_ref(&word))
+            .unwrap();
+        detector.normalize_template().unwrap();
+        detector.set_thresholds(0.6, 0.4);
+        detector.set_step_pattern(StepPattern::Symmetric2);
+        detector.set_time_weighting(TimeWeighting::Onset { strength: 1.0 });
+        detector.enable_envelope_prefilter(true);
+        detector.set_name("computer");
+        let (rising, falling) = (chirp(400.0, 3000.0, 1.0), chirp(3000.0, 400.0, 1.0));
+        detector.add_template("lights".into(), detector.extract_mfcc(&rising).unwrap());
+        detector.add_template("cancel".into(), detector.extract_mfcc(&falling).unwrap());
+        detector.set_word_threshold("cancel", 0.65).unwrap();
+        detector
+            .train_word("dim", &[rising.clone(), falling.clone()])
+            .unwrap();
+        detector.set_word_threshold("dim", 1.0).unwrap();
+
+        let restored = WakeWordDetector::from_bytes(&detector.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.config, detector.config);
+        assert_eq!(restored.word_names(), vec!["lights", "cancel", "dim"]);
+        assert_eq!(restored.name(), "computer");
+        assert!(restored.words[2].variance.is_some());
+        assert_eq!(restored.words[2].variance, detector.words[2].variance);
+        // Both named words still fire, with the same confidence as before
+        for (input, name) in [(&rising, "lights"), (&falling, "cancel")] {
+            let found = restored.detect(input).unwrap().unwrap();
+            assert_eq!(found.0, name);
+            assert_eq!(Some(found), detector.detect(input).unwrap());
+        }
+        assert_eq!(restored.threshold(), 0.6);
+        assert_eq!(restored.release_threshold, 0.4);
+        assert!(restored.envelope_prefilter);
+        assert_eq!(restored.time_weighting, detector.time_weighting);
+
+        // Fixed inputs score exactly the same
+        let reversed: Vec<f32> = word.iter().rev().copied().collect();
+        let faded: Vec<f32> = word.iter().map(|s| s * 0.3).collect();
+        for input in [&word, &reversed, &faded] {
+            assert_eq!(
+                restored.confidence(input).unwrap(),
+                detector.confidence(input).unwrap()
+            );
+            assert_eq!(
+                restored.detect(input).unwrap(),
+                detector.detect(input).unwrap()
+            );
+        }
+
+        // Version 1 states had no words, and the main template no name
+        let mut state: serde_json::Value =
+            serde_json::from_slice(&detector.to_bytes().unwrap()).unwrap();
+        state["version"] = 1.into();
+        state.as_object_mut().unwrap().remove("name");
+        state.as_object_mut().unwrap().remove("words");
+        let old = WakeWordDetector::from_bytes(state.to_string().as_bytes()).unwrap();
+        assert_eq!(old.name(), DEFAULT_WORD_NAME);
+        assert!(old.word_names().is_empty());
+        assert_eq!(
+            old.confidence(&word).unwrap(),
+            detector.confidence(&word).unwrap()
+        );
+
+        // An untrained detector round-trips too
+        let untrained = WakeWordDetector::from_bytes(&WakeWordDetector::new().to_bytes().unwrap());
+        assert!(!untrained.unwrap().is_trained());
+        assert!(WakeWordDetector::from_bytes(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_deltas() {
+        // A ramp has a constant slope, and no curvature, away from the edges
+        let ramp = Array2::from_shape_fn((10, 2), |(t, k)| (t * (k + 1)) as f32);
+        let deltas = compute_deltas(&ramp, DELTA_WINDOW);
+        for t in DELTA_WINDOW..10 - DELTA_WINDOW {
+            assert!((deltas[[t, 0]] - 1.0).abs() < 1e-6);
+            assert!((deltas[[t, 1]] - 2.0).abs() < 1e-6);
+        }
+
+        let chirp = chirp(300.0, 1500.0, 1.0);
+        let mut detector = WakeWordDetector::with_config(MfccConfig {
+            include_deltas: true,
+            ..MfccConfig::default()
+        });
+        assert_eq!(detector.extract_mfcc(&chirp).unwrap().ncols(), 39);
+
+        detector
+            .train_template(std::slice::from_ref(&chirp))
+            .unwrap();
+        assert_eq!(detector.template.as_ref().unwrap().ncols(), 39);
+        let confidence = detector.confidence(&chirp).unwrap();
+        assert!(confidence > 0.9, "confidence {}", confidence);
+
+        // Loading the template switches a plain detector to deltas too
+        let mut plain = WakeWordDetector::new();
+        plain
+            .apply_template_file(detector.template_file().unwrap())
+            .unwrap();
+        assert!(plain.config.include_deltas);
+        assert_eq!(plain.confidence(&chirp).unwrap(), confidence);
+    }
+
+    #[test]
+    fn test_cmn_removes_channel_offset() {
+        let features = Array2::from_shape_fn((20, 13), |(t, k)| ((t * 7 + k * 3) % 11) as f32);
+        let mut normalized = features.clone();
+        apply_cmn(&mut normalized);
+        let mut offset = features + &Array1::from_shape_fn(13, |k| k as f32 * 2.5 - 4.0);
+        apply_cmn(&mut offset);
+        for (a, b) in normalized.iter().zip(offset.iter()) {
+            assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+        }
+
+        // A gain change is a constant offset in the cepstrum, so CMN hides it
+        let chirp = chirp(300.0, 1500.0, 1.0);
+        let quiet: Vec<f32> = chirp.iter().map(|s| s * 0.5).collect();
+        let detector = WakeWordDetector::with_config(MfccConfig {
+            cmn: true,
+            ..MfccConfig::default()
+        });
+        let loud = detector.extract_mfcc(&chirp).unwrap();
+        let soft = detector.extract_mfcc(&quiet).unwrap();
+        let mean = loud.mean_axis(Axis(0)).unwrap();
+        assert!(mean.iter().all(|m| m.abs() < 1e-3));
+        let worst = loud
+            .iter()
+            .zip(soft.iter())
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        assert!(worst < 0.05, "worst {}", worst);
+    }
+
+    #[test]
+    fn test_hysteresis_suppresses_flicker() {
+        let mut detector = WakeWordDetector::new();
+        detector.set_thresholds(0.7, 0.5);
+
+        // Confidence hovering around the high threshold fires only once
+        let hovering = [0.69, 0.71, 0.68, 0.72, 0.69, 0.71];
+        let fired: Vec<bool> = hovering.iter().map(|&c| detector.update_armed(c)).collect();
+        assert_eq!(fired, vec![false, true, false, false, false, false]);
+
+        // Dropping below the low threshold re-arms it
+        assert!(!detector.update_armed(0.4));
+        assert!(detector.update_armed(0.75));
+
+        // With a single threshold it behaves as a rising-edge trigger
+        detector.set_threshold(0.7);
+        assert!(!detector.update_armed(0.69));
+        assert!(detector.update_armed(0.71));
+        assert!(!detector.update_armed(0.72));
+    }
+
+    #[test]
+    fn test_normalized_template_round_trip() {
+        let chirp = chirp(300.0, 1500.0, 1.0);
+
+        let mut detector = WakeWordDetector::new();
+        detector
+            .train_template(std::slice::from_ref(&chirp))
+            .unwrap();
+        detector.normalize_template().unwrap();
+
+        // Each coefficient of the stored template is standardized
+        let template = detector.template.as_ref().unwrap();
+        for column in template.columns() {
+            assert!(column.mean().unwrap().abs() < 1e-3);
+        }
+        let confidence = detector.confidence(&chirp).unwrap();
+
+        let path = std::env::temp_dir().join(format!("template-test-{}.json", std::process::id()));
+        detector.save_template(&path).unwrap();
+        let mut loaded = WakeWordDetector::new();
+        loaded.load_template(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.normalization(), detector.normalization());
+        let loaded_confidence = loaded.confidence(&chirp).unwrap();
+        assert_eq!(loaded_confidence, confidence);
+    }
+
+    #[test]
+    fn test_template_file_records_feature_config() {
+        let chirp = chirp(300.0, 1500.0, 1.0);
+        let mut detector = WakeWordDetector::new();
+        detector
+            .train_template(std::slice::from_ref(&chirp))
+            .unwrap();
+        let path =
+            std::env::temp_dir().join(format!("template-config-{}.json", std::process::id()));
+        detector.save_template(&path).unwrap();
+
+        // Features framed differently can't be compared with the template
+        let mut other_hop = WakeWordDetector::with_config(MfccConfig {
+            hop_size: 256,
+            ..MfccConfig::default()
+        });
+        let error = other_hop.load_template(&path).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("hop size of 128 but the detector uses 256"),
+            "{}",
+            error
+        );
+        assert!(!other_hop.is_trained());
+        let mut other_mfcc = WakeWordDetector::with_config(MfccConfig {
+            num_mfcc: 20,
+            ..MfccConfig::default()
+        });
+        let error = other_mfcc.load_template(&path).unwrap_err();
+        assert!(
+            error.to_string().contains("13 MFCC coefficients"),
+            "{}",
+            error
+        );
+
+        // Version 1 files have no config and load as before; newer ones are refused
+        let mut file: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(file["config"]["sample_rate"], 16000);
+        file.as_object_mut().unwrap().remove("config");
+        file["version"] = 1.into();
+        std::fs::write(&path, file.to_string()).unwrap();
+        other_hop.load_template(&path).unwrap();
+        file["version"] = 99.into();
+        std::fs::write(&path, file.to_string()).unwrap();
+        let error = WakeWordDetector::new().load_template(&path).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Template version 99 is not supported by this build (it reads versions 1 to 2)"
+        );
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_template_normalization_is_checked_on_load() {
+        let path = std::env::temp_dir().join(format!(
+            "template-normalization-{}.json",
+            std::process::id()
+        ));
+        let load = |mean: Vec<f32>, std: Vec<f32>| {
+            let file = serde_json::json!({
+                "version": 1,
+                "num_mfcc": 13,
+                "frames": vec![vec![0.5f32; 13]; 4],
+                "normalization": { "mean": mean, "std": std },
+            });
+            std::fs::write(&path, file.to_string()).unwrap();
+            let mut detector = WakeWordDetector::new();
+            detector.load_template(&path).map(|()| detector)
+        };
+
+        // Too few statistics would index out of bounds in `detect`
+        let error = load(vec![0.0; 12], vec![1.0; 13]).err().unwrap();
+        assert_eq!(
+            format!("{:#}", error),
+            "Template is corrupt: Normalization has 12 means and 13 deviations but the features have 13 coefficients"
+        );
+        assert!(load(vec![0.0; 13], vec![1.0; 40]).is_err());
+        // A zero deviation would divide by zero
+        let mut std = vec![1.0; 13];
+        std[4] = 0.0;
+        let error = load(vec![0.0; 13], std).err().unwrap();
+        assert!(
+            format!("{:#}", error).contains("deviation 0 of coefficient 4"),
+            "{:#}",
+            error
+        );
+
+        let detector = load(vec![0.0; 13], vec![1.0; 13]).unwrap();
+        assert!(detector.detect(&chirp(300.0, 1500.0, 1.0)).is_ok());
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_template_format_versions() {
+        let path =
+            std::env::temp_dir().join(format!("template-version-{}.json", std::process::id()));
+
+        // A version 1 file with nothing but the frames gets defaults for the rest
+        let frames = vec![vec![0.5f32; 13]; 4];
+        let v1 = serde_json::json!({ "version": 1, "num_mfcc": 13, "frames": frames });
+        std::fs::write(&path, v1.to_string()).unwrap();
+        let mut detector = WakeWordDetector::new();
+        detector.load_template(&path).unwrap();
+        assert!(detector.is_trained());
+        assert_eq!(detector.template.as_ref().unwrap().dim(), (4, 13));
+        assert!(detector.tags().is_empty());
+        assert_eq!(detector.template_variance(), None);
+        // Saving it upgrades it to the current version
+        detector.save_template(&path).unwrap();
+        let saved: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["version"], TEMPLATE_FORMAT_VERSION);
+
+        // A future format is refused for its version, whatever else changed
+        let future = serde_json::json!({ "version": 3, "frames": "zstd:KLUv/QBY" });
+        std::fs::write(&path, future.to_string()).unwrap();
+        let error = WakeWordDetector::new().load_template(&path).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("Template version 3 is not supported by this build"),
+            "{}",
+            error
+        );
+        std::fs::write(
+            &path,
+            v1.to_string().replace("\"version\":1", "\"version\":0"),
+        )
+        .unwrap();
+        assert!(WakeWordDetector::new().load_template(&path).is_err());
+        std::fs::remove_file(&path).ok();
+
+        let error = WakeWordDetector::from_bytes(br#"{"version":7,"config":null}"#)
+            .err()
+            .unwrap();
+        assert!(
+            error
+                .to_string()
+                .starts_with("Detector state version 7 is not supported"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_bandpass_is_stored_with_template() {
+        let chirp = chirp(300.0, 1500.0, 1.0);
+        // The same chirp over a faint rumble, below the speech band
+        let hummed: Vec<f32> = chirp
+            .iter()
+            .enumerate()
+            .map(|(i, s)| s + (2.0 * PI * 20.0 * i as f32 / 16000.0).sin() * 0.003)
+            .collect();
+
+        let mut plain = WakeWordDetector::new();
+        plain.train_template(std::slice::from_ref(&chirp)).unwrap();
+        let plain_confidence = plain.confidence(&hummed).unwrap();
+
+        let mut filtered = WakeWordDetector::with_config(MfccConfig {
+            bandpass: Some(crate::filter::SPEECH_BAND),
+            ..MfccConfig::default()
+        });
+        filtered
+            .train_template(std::slice::from_ref(&chirp))
+            .unwrap();
+        let filtered_confidence = filtered.confidence(&hummed).unwrap();
+        assert!(filtered_confidence > plain_confidence + 0.5);
+
+        // Loading the template brings its band along
+        let path = std::env::temp_dir().join(format!("bandpass-test-{}.json", std::process::id()));
+        filtered.save_template(&path).unwrap();
+        let mut loaded = WakeWordDetector::new();
+        loaded.load_template(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(loaded.config.bandpass, Some(crate::filter::SPEECH_BAND));
+        assert_eq!(loaded.confidence(&hummed).unwrap(), filtered_confidence);
+    }
+
+    #[test]
+    fn test_scan_reports_each_occurrence_once() {
+        let sample_rate = 16000;
+        let chirp = chirp(300.0, 1500.0, 1.0);
+
+        let mut detector = WakeWordDetector::new();
+        detector
+            .train_template(std::slice::from_ref(&chirp))
+            .unwrap();
+
+        let silence = vec![0.0; sample_rate];
+        let audio = [
+            silence.clone(),
+            chirp.clone(),
+            silence.clone(),
+            chirp,
+            silence,
+        ]
+        .concat();
+        let hits = detector.scan(&audio).unwrap();
+
+        assert_eq!(hits.len(), 2, "{:?}", hits);
+        assert!((hits[0].time_s - 1.0).abs() < 0.15);
+        assert!((hits[1].time_s - 3.0).abs() < 0.15);
+    }
+
+    #[test]
+    fn test_cached_detection_matches_recompute() {
+        let chirp = chirp(300.0, 1500.0, 1.0);
+        let mut detector = WakeWordDetector::new();
+        detector
+            .train_template(std::slice::from_ref(&chirp))
+            .unwrap();
+        let template_len = detector.template.as_ref().unwrap().nrows();
+        let (frame_size, hop_size) = (detector.config.frame_size, detector.config.hop_size);
+
+        // Stream silence then the chirp in uneven chunks; the silence is a
+        // whole number of hops so the last frames line up with the chirp
+        let stream = [vec![0.0; 55 * hop_size], chirp].concat();
+        let mut cache = detector.frame_cache();
+        let mut fed = 0;
+        for chunk in stream.chunks(333) {
+            detector.update_cache(&mut cache, chunk);
+            fed += chunk.len();
+
+            // The samples behind the cached frames, recomputed from scratch
+            let frames = (fed.saturating_sub(frame_size) / hop_size + 1).min(template_len);
+            if fed < frame_size || frames < template_len {
+                assert_eq!(detector.confidence_cached(&cache).unwrap(), 0.0);
+                assert_eq!(detector.detect_cached(&cache).unwrap(), None);
+                continue;
+            }
+            let last_start = (fed - frame_size) / hop_size * hop_size;
+            let start = last_start - (template_len - 1) * hop_size;
+            let window = &stream[start..last_start + frame_size];
+            assert_eq!(
+                detector.confidence_cached(&cache).unwrap(),
+                detector.confidence(window).unwrap()
+            );
+            assert_eq!(
+                detector.detect_cached(&cache).unwrap(),
+                detector.detect(window).unwrap()
+            );
+        }
+        assert_eq!(cache.len(), template_len);
+        assert!(detector.detect_cached(&cache).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_confidence_db_mapping() {
+        assert_eq!(confidence_to_db(0.0), 0.0);
+        assert!((confidence_to_db(0.9) - 20.0).abs() < 1e-3);
+        assert!((confidence_to_db(0.99) - 40.0).abs() < 1e-2);
+        assert!((confidence_to_db(1.0) - 120.0).abs() < 1e-3);
+        for confidence in [0.25, 0.65, 0.9, 0.995] {
+            assert!((db_to_confidence(confidence_to_db(confidence)) - confidence).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_format_confidence() {
+        assert_eq!(format_confidence(0.875, 1), "87.5%");
+        assert_eq!(format_confidence(0.875, 0), "88%");
+        assert_eq!(format_confidence(0.65, 2), "65.00%");
+        assert_eq!(format_confidence(1.0, 1), "100.0%");
+        assert_eq!(format_confidence(0.0, 3), "0.000%");
+        assert_eq!(
+            format_confidence(0.12345, DEFAULT_CONFIDENCE_PRECISION),
+            "12.3%"
+        );
+    }
+
+    #[test]
+    fn test_envelope_prefilter_rejects_wrong_shape() {
+        // Same tone with different loudness contours
+        let tone = |gain: fn(f32) -> f32| -> Vec<f32> {
+            (0..16000)
+                .map(|i| {
+                    let t = i as f32 / 16000.0;
+                    (2.0 * PI * 600.0 * t).sin() * 0.5 * gain(t)
+                })
+                .collect()
+        };
+        let swell = tone(|t| t);
+
+        let mut detector = WakeWordDetector::new();
+        detector
+            .train_template(std::slice::from_ref(&swell))
+            .unwrap();
+        let template = detector.envelope.clone().unwrap();
+        let similarity =
+            |audio: &[f32]| envelope_similarity(&energy_envelope(audio, 16000), &template);
+
+        // A quieter swell still matches; a fade or silence doesn't
+        assert!(similarity(&tone(|t| t * 0.3)) > 0.99);
+        assert!(similarity(&tone(|t| 1.0 - t)) < ENVELOPE_MIN_SIMILARITY);
+        assert_eq!(similarity(&vec![0.0; 16000]), 0.0);
+
+        // The trained audio still reaches MFCC + DTW with the prefilter on
+        detector.enable_envelope_prefilter(true);
+        let (_, confidence) = detector.detect(&swell).unwrap().unwrap();
+        assert!(confidence > 0.9);
     }
 }
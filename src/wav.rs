@@ -0,0 +1,574 @@
+//! WAV encoding helpers shared by the recorder, the CLI and the examples.
+
+use crate::convert::{
+    deinterleave, f32_to_i16, f32_to_i16_truncated, f32_to_int, i16_to_f32, to_mono,
+};
+use anyhow::{bail, Result};
+use hound::{WavReader, WavSpec, WavWriter};
+use std::io::{Cursor, Seek, Write};
+use std::str::FromStr;
+
+/// How f32 samples are turned into 16-bit integers
+///
+/// `Truncate` drops the fraction, with no noise added, so the same samples
+/// always give the same file bit for bit. `RoundDither` adds ±1 LSB of triangular (TPDF) dither
+/// and rounds: it avoids truncation's bias towards zero and turns the
+/// distortion of quantizing quiet audio into a faint, constant noise floor.
+/// The dither is seeded the same way on every call, so its output is
+/// repeatable too, just not identical to `Truncate`'s. Both use the scale
+/// of the `convert` helpers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quantization {
+    Truncate,
+    #[default]
+    RoundDither,
+}
+
+impl Quantization {
+    /// Convert samples (-1.0..1.0) to 16-bit integers
+    pub fn quantize(&self, samples: &[f32]) -> Vec<i16> {
+        match self {
+            Self::Truncate => samples.iter().map(|&s| f32_to_i16_truncated(s)).collect(),
+            Self::RoundDither => {
+                // xorshift32: cheap, and plenty random for dither
+                let mut state: u32 = 0x9E37_79B9;
+                let mut uniform = move || {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    (state >> 8) as f32 / (1 << 24) as f32
+                };
+                samples
+                    .iter()
+                    .map(|&sample| {
+                        let dither = (uniform() - uniform()) / 32768.0;
+                        f32_to_i16(sample.clamp(-1.0, 1.0) + dither)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+impl FromStr for Quantization {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode.trim().to_lowercase().as_str() {
+            "truncate" => Ok(Self::Truncate),
+            "round-dither" | "dither" => Ok(Self::RoundDither),
+            other => bail!(
+                "Unknown quantization \"{}\" (use truncate or round-dither)",
+                other
+            ),
+        }
+    }
+}
+
+/// Sample encoding of a WAV file: bit depth and integer vs float
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavEncoding {
+    bits_per_sample: u16,
+    float: bool,
+}
+
+impl WavEncoding {
+    /// 16-bit integer PCM, what the transcription services expect
+    pub const PCM16: Self = Self {
+        bits_per_sample: 16,
+        float: false,
+    };
+
+    /// Validate a bit depth / sample type combination
+    ///
+    /// Integer samples can be 16, 24 or 32 bits; float samples only 32.
+    pub fn new(bits_per_sample: u16, float: bool) -> Result<Self> {
+        match (bits_per_sample, float) {
+            (16 | 24 | 32, false) | (32, true) => Ok(Self {
+                bits_per_sample,
+                float,
+            }),
+            (bits, true) => bail!("{}-bit float WAV is not supported (use 32)", bits),
+            (bits, false) => bail!(
+                "{}-bit integer WAV is not supported (use 16, 24 or 32)",
+                bits
+            ),
+        }
+    }
+
+    pub fn bits_per_sample(&self) -> u16 {
+        self.bits_per_sample
+    }
+
+    pub fn is_float(&self) -> bool {
+        self.float
+    }
+
+    /// WAV header for `channels` channels at `sample_rate` in this encoding
+    pub fn spec(&self, channels: u16, sample_rate: u32) -> WavSpec {
+        WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: self.bits_per_sample,
+            sample_format: if self.float {
+                hound::SampleFormat::Float
+            } else {
+                hound::SampleFormat::Int
+            },
+        }
+    }
+}
+
+impl Default for WavEncoding {
+    fn default() -> Self {
+        Self::PCM16
+    }
+}
+
+/// Encode mono f32 samples (-1.0..1.0) as WAV bytes with the given encoding
+///
+/// Used for recordings kept on disk; anything sent for transcription should
+/// stay 16-bit via `samples_to_wav`. 16-bit output is quantized with `mode`.
+pub fn samples_to_wav_as(
+    samples: &[f32],
+    sample_rate: u32,
+    encoding: WavEncoding,
+    mode: Quantization,
+) -> Result<Vec<u8>> {
+    if encoding == WavEncoding::PCM16 {
+        return samples_to_wav_quantized(samples, sample_rate, mode);
+    }
+
+    let mut cursor = Cursor::new(Vec::new());
+    {
+        let mut writer = WavWriter::new(&mut cursor, encoding.spec(1, sample_rate))?;
+        for &sample in samples {
+            write_sample_as(&mut writer, sample, encoding)?;
+        }
+        writer.finalize()?;
+    }
+
+    Ok(cursor.into_inner())
+}
+
+/// Write an f32 sample (-1.0..1.0) in the writer's encoding
+pub fn write_sample_as<W: Write + Seek>(
+    writer: &mut WavWriter<W>,
+    sample: f32,
+    encoding: WavEncoding,
+) -> hound::Result<()> {
+    if encoding.float {
+        return writer.write_sample(sample);
+    }
+    writer.write_sample(f32_to_int(sample, encoding.bits_per_sample))
+}
+
+/// Write an i16 sample in the writer's encoding, exactly for integer depths
+pub fn write_i16_as<W: Write + Seek>(
+    writer: &mut WavWriter<W>,
+    sample: i16,
+    encoding: WavEncoding,
+) -> hound::Result<()> {
+    if encoding.float {
+        writer.write_sample(i16_to_f32(sample))
+    } else {
+        writer.write_sample((sample as i32) << (encoding.bits_per_sample - 16))
+    }
+}
+
+/// How a recording written to a pipe is framed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PipeFormat {
+    /// A complete WAV file, header included
+    #[default]
+    Wav,
+    /// Bare little-endian samples, interleaved, in the recording's encoding
+    Raw,
+}
+
+impl FromStr for PipeFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format.trim().to_lowercase().as_str() {
+            "wav" => Ok(Self::Wav),
+            "raw" | "pcm" => Ok(Self::Raw),
+            other => bail!("Unknown audio format \"{}\" (use wav or raw)", other),
+        }
+    }
+}
+
+/// Write a WAV recording to `out`, e.g. stdout, as `format`; returns the bytes written
+///
+/// Raw output has no header, so the reader has to be told the layout (see
+/// `raw_pcm_layout`).
+pub fn write_piped(out: &mut impl Write, wav_data: &[u8], format: PipeFormat) -> Result<usize> {
+    let bytes = match format {
+        PipeFormat::Wav => wav_data.to_vec(),
+        PipeFormat::Raw => to_raw_pcm(wav_data)?,
+    };
+    out.write_all(&bytes)?;
+    Ok(bytes.len())
+}
+
+/// The samples of WAV bytes as bare little-endian PCM, interleaved
+pub fn to_raw_pcm(wav_data: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = WavReader::new(Cursor::new(wav_data))?;
+    let spec = reader.spec();
+    let width = (spec.bits_per_sample / 8) as usize;
+    let mut raw = Vec::with_capacity(reader.len() as usize * width);
+    match spec.sample_format {
+        hound::SampleFormat::Float => {
+            for sample in reader.samples::<f32>() {
+                raw.extend_from_slice(&sample?.to_le_bytes());
+            }
+        }
+        hound::SampleFormat::Int => {
+            for sample in reader.samples::<i32>() {
+                raw.extend_from_slice(&sample?.to_le_bytes()[..width]);
+            }
+        }
+    }
+    Ok(raw)
+}
+
+/// How `to_raw_pcm` lays out WAV bytes, named as ffmpeg does, e.g. `s16le, 48000 Hz, 2 ch`
+pub fn raw_pcm_layout(wav_data: &[u8]) -> Result<String> {
+    let spec = WavReader::new(Cursor::new(wav_data))?.spec();
+    let kind = match spec.sample_format {
+        hound::SampleFormat::Float => 'f',
+        hound::SampleFormat::Int => 's',
+    };
+    Ok(format!(
+        "{}{}le, {} Hz, {} ch",
+        kind, spec.bits_per_sample, spec.sample_rate, spec.channels
+    ))
+}
+
+/// Convert WAV bytes of any supported encoding to 16-bit integer PCM
+///
+/// Channels and sample rate are kept. Recordings kept at a higher bit depth
+/// go through this before being sent for transcription.
+pub fn to_pcm16(wav_data: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = WavReader::new(Cursor::new(wav_data))?;
+    let spec = reader.spec();
+    if spec.bits_per_sample == 16 && spec.sample_format == hound::SampleFormat::Int {
+        return Ok(wav_data.to_vec());
+    }
+
+    let samples: Vec<i16> = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .map(|s| s.map(f32_to_i16))
+            .collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let shift = spec.bits_per_sample.saturating_sub(16);
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| (s >> shift) as i16))
+                .collect::<Result<_, _>>()?
+        }
+    };
+
+    let mut cursor = Cursor::new(Vec::new());
+    {
+        let mut writer = WavWriter::new(
+            &mut cursor,
+            WavEncoding::PCM16.spec(spec.channels, spec.sample_rate),
+        )?;
+        for sample in samples {
+            writer.write_sample(sample)?;
+        }
+        writer.finalize()?;
+    }
+
+    Ok(cursor.into_inner())
+}
+
+/// Decode WAV bytes of any supported encoding to mono f32 samples
+///
+/// Channels are averaged. Returns the samples and the sample rate.
+pub fn decode_wav_mono(wav_data: &[u8]) -> Result<(Vec<f32>, u32)> {
+    let (interleaved, channels, sample_rate) = decode_wav_interleaved(wav_data)?;
+    Ok((to_mono(&interleaved, channels), sample_rate))
+}
+
+/// Decode WAV bytes of any supported encoding to one f32 track per channel
+///
+/// Returns the tracks, in channel order, and the sample rate.
+pub fn decode_wav_channels(wav_data: &[u8]) -> Result<(Vec<Vec<f32>>, u32)> {
+    let (interleaved, channels, sample_rate) = decode_wav_interleaved(wav_data)?;
+    Ok((deinterleave(&interleaved, channels), sample_rate))
+}
+
+/// Decode WAV bytes to interleaved f32 samples, the channel count and the sample rate
+fn decode_wav_interleaved(wav_data: &[u8]) -> Result<(Vec<f32>, usize, u32)> {
+    let mut reader = WavReader::new(Cursor::new(wav_data))?;
+    let spec = reader.spec();
+
+    let interleaved: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    Ok((interleaved, spec.channels.max(1) as usize, spec.sample_rate))
+}
+
+/// Encode mono f32 samples (-1.0..1.0) as 16-bit PCM WAV bytes
+///
+/// Quantizes with the default `Quantization`; see `samples_to_wav_quantized`.
+pub fn samples_to_wav(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>> {
+    samples_to_wav_quantized(samples, sample_rate, Quantization::default())
+}
+
+/// Encode mono f32 samples (-1.0..1.0) as 16-bit PCM WAV bytes, quantized with `mode`
+pub fn samples_to_wav_quantized(
+    samples: &[f32],
+    sample_rate: u32,
+    mode: Quantization,
+) -> Result<Vec<u8>> {
+    write_pcm16(mode.quantize(samples).into_iter(), sample_rate)
+}
+
+/// Encode mono i16 samples as 16-bit PCM WAV bytes
+///
+/// Use this when the device already delivers i16: the samples are written
+/// as-is, avoiding the precision loss of a round trip through f32.
+pub fn samples_i16_to_wav(samples: &[i16], sample_rate: u32) -> Result<Vec<u8>> {
+    write_pcm16(samples.iter().copied(), sample_rate)
+}
+
+fn write_pcm16(samples: impl Iterator<Item = i16>, sample_rate: u32) -> Result<Vec<u8>> {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    let mut cursor = Cursor::new(Vec::new());
+    {
+        let mut writer = WavWriter::new(&mut cursor, spec)?;
+        for sample in samples {
+            writer.write_sample(sample)?;
+        }
+        writer.finalize()?;
+    }
+
+    Ok(cursor.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_to_wav_round_trip() {
+        let samples = vec![0.0, 0.5, -0.5, 1.0, -1.0];
+        let wav = samples_to_wav_quantized(&samples, 16000, Quantization::Truncate).unwrap();
+
+        let mut reader = hound::WavReader::new(Cursor::new(wav)).unwrap();
+        let spec = reader.spec();
+        assert_eq!(spec.channels, 1);
+        assert_eq!(spec.sample_rate, 16000);
+        assert_eq!(spec.bits_per_sample, 16);
+
+        let decoded: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+        assert_eq!(decoded, vec![0, 16384, -16384, i16::MAX, i16::MIN]);
+    }
+
+    #[test]
+    fn test_round_dither_is_unbiased() {
+        // A steady level of 0.3 LSB: truncation flattens it to zero...
+        let lsb = 1.0 / 32768.0;
+        let samples = vec![0.3 * lsb; 20000];
+        assert!(Quantization::Truncate
+            .quantize(&samples)
+            .iter()
+            .all(|&s| s == 0));
+
+        // ...while dithered rounding keeps it on average, within a step or two
+        let dithered = Quantization::RoundDither.quantize(&samples);
+        let mean = dithered.iter().map(|&s| s as f32).sum::<f32>() / dithered.len() as f32;
+        assert!((mean - 0.3).abs() < 0.05, "mean {}", mean);
+        assert!(dithered.iter().all(|&s| (-1..=2).contains(&s)));
+        assert_eq!(Quantization::RoundDither.quantize(&samples), dithered);
+
+        // Full scale and beyond stay in range
+        let loud = Quantization::RoundDither.quantize(&[1.0, -1.5]);
+        assert!(loud[0] >= i16::MAX - 1 && loud[1] <= -i16::MAX + 1);
+        assert_eq!(
+            "Truncate".parse::<Quantization>().unwrap(),
+            Quantization::Truncate
+        );
+        assert!("nearest".parse::<Quantization>().is_err());
+    }
+
+    #[test]
+    fn test_samples_i16_to_wav_is_bit_exact() {
+        let samples = vec![i16::MIN, -1, 0, 1, 12345, i16::MAX];
+        let wav = samples_i16_to_wav(&samples, 48000).unwrap();
+
+        let mut reader = hound::WavReader::new(Cursor::new(wav)).unwrap();
+        assert_eq!(reader.spec().sample_rate, 48000);
+        let decoded: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+        assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn test_samples_to_wav_as_higher_depths() {
+        let samples = vec![0.0, 0.5, -1.0, 1.0];
+
+        let wav = samples_to_wav_as(
+            &samples,
+            44100,
+            WavEncoding::new(24, false).unwrap(),
+            Quantization::default(),
+        )
+        .unwrap();
+        let mut reader = hound::WavReader::new(Cursor::new(wav)).unwrap();
+        assert_eq!(reader.spec().bits_per_sample, 24);
+        let decoded: Vec<i32> = reader.samples::<i32>().map(|s| s.unwrap()).collect();
+        assert_eq!(decoded, vec![0, 4194304, -8388608, 8388607]);
+
+        let wav = samples_to_wav_as(
+            &samples,
+            44100,
+            WavEncoding::new(32, true).unwrap(),
+            Quantization::default(),
+        )
+        .unwrap();
+        let mut reader = hound::WavReader::new(Cursor::new(wav)).unwrap();
+        assert_eq!(reader.spec().sample_format, hound::SampleFormat::Float);
+        let decoded: Vec<f32> = reader.samples::<f32>().map(|s| s.unwrap()).collect();
+        assert_eq!(decoded, samples);
+
+        // Downconverting for transcription gives plain 16-bit PCM
+        let wav = samples_to_wav_as(
+            &samples,
+            44100,
+            WavEncoding::new(24, false).unwrap(),
+            Quantization::default(),
+        )
+        .unwrap();
+        let mut reader = hound::WavReader::new(Cursor::new(to_pcm16(&wav).unwrap())).unwrap();
+        assert_eq!(reader.spec().bits_per_sample, 16);
+        let decoded: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+        assert_eq!(decoded, vec![0, 16384, i16::MIN, i16::MAX]);
+    }
+
+    #[test]
+    fn test_piped_recording() {
+        let samples = vec![0.0, 0.5, -0.5, 1.0, -1.0];
+        let wav = samples_to_wav_quantized(&samples, 16000, Quantization::Truncate).unwrap();
+
+        // What goes down the pipe as WAV is a file hound can read back
+        let mut piped = Vec::new();
+        let written = write_piped(&mut piped, &wav, PipeFormat::Wav).unwrap();
+        assert_eq!(written, piped.len());
+        let mut reader = hound::WavReader::new(Cursor::new(&piped)).unwrap();
+        assert_eq!(reader.spec(), WavEncoding::PCM16.spec(1, 16000));
+        let decoded: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+        assert_eq!(decoded, vec![0, 16384, -16384, i16::MAX, i16::MIN]);
+
+        // Raw is the same samples with no header
+        let mut raw = Vec::new();
+        write_piped(&mut raw, &wav, PipeFormat::Raw).unwrap();
+        let expected: Vec<u8> = decoded.iter().flat_map(|s| s.to_le_bytes()).collect();
+        assert_eq!(raw, expected);
+        assert_eq!(raw_pcm_layout(&wav).unwrap(), "s16le, 16000 Hz, 1 ch");
+
+        let wav24 = samples_to_wav_as(
+            &samples,
+            44100,
+            WavEncoding::new(24, false).unwrap(),
+            Quantization::default(),
+        )
+        .unwrap();
+        assert_eq!(to_raw_pcm(&wav24).unwrap()[3..6], [0x00, 0x00, 0x40]);
+        assert_eq!("RAW".parse::<PipeFormat>().unwrap(), PipeFormat::Raw);
+    }
+
+    #[test]
+    fn test_decode_wav_mono_averages_channels() {
+        let spec = WavEncoding::PCM16.spec(2, 8000);
+        let mut cursor = Cursor::new(Vec::new());
+        {
+            let mut writer = WavWriter::new(&mut cursor, spec).unwrap();
+            for sample in [16384i16, 0, -16384, -16384] {
+                writer.write_sample(sample).unwrap();
+            }
+            writer.finalize().unwrap();
+        }
+
+        let wav = cursor.into_inner();
+        let (mono, sample_rate) = decode_wav_mono(&wav).unwrap();
+        assert_eq!(sample_rate, 8000);
+        assert_eq!(mono, vec![0.25, -0.5]);
+
+        // Or kept apart, one track per channel
+        let (tracks, _) = decode_wav_channels(&wav).unwrap();
+        assert_eq!(tracks, vec![vec![0.5, -0.5], vec![0.0, -0.5]]);
+    }
+
+    #[test]
+    fn test_every_path_writes_the_same_samples() {
+        let write = |encoding: WavEncoding, f32_path: bool| {
+            let mut cursor = Cursor::new(Vec::new());
+            {
+                let spec = encoding.spec(1, 16000);
+                let mut writer = WavWriter::new(&mut cursor, spec).unwrap();
+                for sample in i16::MIN..=i16::MAX {
+                    if f32_path {
+                        write_sample_as(&mut writer, i16_to_f32(sample), encoding).unwrap();
+                    } else {
+                        write_i16_as(&mut writer, sample, encoding).unwrap();
+                    }
+                }
+                writer.finalize().unwrap();
+            }
+            cursor.into_inner()
+        };
+
+        // Every i16 level, however it reaches the file, comes out the same
+        let levels: Vec<i16> = (i16::MIN..=i16::MAX).collect();
+        let floats: Vec<f32> = levels.iter().map(|&s| i16_to_f32(s)).collect();
+        let pcm16 = samples_i16_to_wav(&levels, 16000).unwrap();
+        assert_eq!(
+            samples_to_wav_quantized(&floats, 16000, Quantization::Truncate).unwrap(),
+            pcm16
+        );
+        for bits in [16, 24, 32] {
+            let encoding = WavEncoding::new(bits, false).unwrap();
+            assert_eq!(
+                write(encoding, true),
+                write(encoding, false),
+                "{} bits",
+                bits
+            );
+        }
+        let float = WavEncoding::new(32, true).unwrap();
+        assert_eq!(write(float, true), write(float, false));
+
+        // Dither moves a sample by a step at most
+        let dithered = Quantization::RoundDither.quantize(&floats);
+        assert!(levels
+            .iter()
+            .zip(&dithered)
+            .all(|(&level, &s)| (level as i32 - s as i32).abs() <= 1));
+    }
+
+    #[test]
+    fn test_wav_encoding_rejects_invalid_combinations() {
+        assert_eq!(WavEncoding::new(16, false).unwrap(), WavEncoding::default());
+        assert!(WavEncoding::new(32, false).is_ok());
+        assert!(WavEncoding::new(24, true).is_err());
+        assert!(WavEncoding::new(16, true).is_err());
+        assert!(WavEncoding::new(8, false).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+//! End-to-end runs of the `audio-transcribe-cli` binary.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::process::Command;
+use std::thread;
+
+use audio_transcribe_cli::transcribe::AudioClip;
+
+/// Serve one response per connection, reading each request in full first
+fn serve(listener: TcpListener, responses: Vec<(&'static str, Vec<u8>)>) {
+    thread::spawn(move || {
+        for (content_type, body) in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            let mut line = String::new();
+            loop {
+                line.clear();
+                if reader.read_line(&mut line).unwrap() == 0 || line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            reader.read_exact(&mut vec![0; content_length]).unwrap();
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                content_type,
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+}
+
+#[test]
+fn test_json_format_keeps_stdout_clean() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let wav = AudioClip::from_samples(&vec![0.1; 32000], 16000)
+        .unwrap()
+        .bytes;
+    serve(
+        listener,
+        vec![
+            ("audio/wav", wav),
+            ("application/json", br#"{"text": " hello world "}"#.to_vec()),
+        ],
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_audio-transcribe-cli"))
+        .args(["--format", "json", "--input-url"])
+        .arg(format!("http://{}/clip.wav", addr))
+        .env("WHISPER_ENDPOINT", format!("http://{}/transcribe", addr))
+        .env_remove("REPLICATE_API_KEY")
+        .env_remove("OPENAI_API_KEY")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // stdout is exactly one JSON object; the progress messages went to stderr
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(report["text"], "hello world");
+    assert_eq!(report["duration_secs"], 2.0);
+    assert_eq!(report["sample_rate"], 16000);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("for transcription"));
+}